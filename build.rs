@@ -1,17 +1,48 @@
 extern crate bindgen;
+use std::env;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
 
 fn main() {
-    println!("cargo:rustc-link-lib=traildb");
+    // musl binaries are linked statically, so there is no shared
+    // libtraildb to load at runtime.
+    let musl = env::var("CARGO_CFG_TARGET_ENV").as_deref() == Ok("musl");
+    if musl {
+        link_static();
+    } else {
+        println!("cargo:rustc-link-lib=traildb");
+    }
 
-    let _ = bindgen::builder()
+    let builder = bindgen::builder()
         .header("src/ffi/include/traildb.h")
         .no_unstable_rust()
-        .emit_builtins()
-        .link("traildb")
-        .generate()
+        .emit_builtins();
+    // A `#[link]` attribute would ask for the shared library.
+    let builder = if musl { builder } else { builder.link("traildb") };
+    let _ = builder.generate()
         .expect("Unable to generate bindings")
         .write_to_file(Path::new("src/ffi/mod.rs"));
 }
+
+/// Link libtraildb, Judy and libarchive statically, for musl targets.
+///
+/// The static libraries, built for the target beforehand, are looked up in
+/// `TRAILDB_STATIC_LIB_DIR`, if set, and the usual library paths. Whatever
+/// libarchive was built against (zlib, liblzma, ...) has to be named in
+/// `TRAILDB_STATIC_LIBS`, separated by commas.
+fn link_static() {
+    println!("cargo:rerun-if-env-changed=TRAILDB_STATIC_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=TRAILDB_STATIC_LIBS");
+    if let Some(dir) = env::var_os("TRAILDB_STATIC_LIB_DIR") {
+        println!("cargo:rustc-link-search=native={}", Path::new(&dir).display());
+    }
+    println!("cargo:rustc-link-lib=static=traildb");
+    println!("cargo:rustc-link-lib=static=Judy");
+    println!("cargo:rustc-link-lib=static=archive");
+    if let Ok(libs) = env::var("TRAILDB_STATIC_LIBS") {
+        for lib in libs.split(',').map(str::trim).filter(|l| !l.is_empty()) {
+            println!("cargo:rustc-link-lib=static={}", lib);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Grouped aggregation.
+//!
+//! An `Aggregator` groups events by the value of one field and evaluates a
+//! set of accumulators for every group in a single scan. Groups are keyed by
+//! value id, so no values are resolved while scanning.
+
+use super::super::{Db, Error, EventFilter, Field, Timestamp, TrailId, Value};
+
+/// A single-scan group-by over the events of a `Db`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::analytics::aggregate::Aggregator;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let action = db.get_field("action").unwrap();
+/// let groups = Aggregator::group_by(action)
+///     .count()
+///     .distinct_trails()
+///     .min_max_timestamp()
+///     .run(&db)
+///     .unwrap();
+/// for group in &groups {
+///     println!("{:?}: {:?} events by {:?} trails",
+///              db.get_value(action, group.value),
+///              group.events,
+///              group.trails);
+/// }
+/// ```
+pub struct Aggregator<'f> {
+    field: Field,
+    filter: Option<&'f EventFilter>,
+    count: bool,
+    distinct_trails: bool,
+    min_max_timestamp: bool,
+}
+
+/// The accumulators of one group. Accumulators that weren't requested are
+/// `None`.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Group {
+    /// The value id of the group; 0 is the empty value.
+    pub value: Value,
+    /// The number of events in the group.
+    pub events: Option<u64>,
+    /// The number of trails with at least one event in the group.
+    pub trails: Option<u64>,
+    /// The earliest and latest timestamp in the group.
+    pub timestamps: Option<(Timestamp, Timestamp)>,
+}
+
+/// Per group state while scanning.
+#[derive(Clone)]
+struct Accumulator {
+    events: u64,
+    trails: u64,
+    last_trail: TrailId,
+    min_timestamp: Timestamp,
+    max_timestamp: Timestamp,
+}
+
+impl<'f> Aggregator<'f> {
+    /// Group events by the value of `field`.
+    pub fn group_by(field: Field) -> Self {
+        Aggregator {
+            field: field,
+            filter: None,
+            count: false,
+            distinct_trails: false,
+            min_max_timestamp: false,
+        }
+    }
+
+    /// Only aggregate events matching `filter`.
+    pub fn filter(mut self, filter: &'f EventFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Count the events in each group.
+    pub fn count(mut self) -> Self {
+        self.count = true;
+        self
+    }
+
+    /// Count the distinct trails in each group.
+    pub fn distinct_trails(mut self) -> Self {
+        self.distinct_trails = true;
+        self
+    }
+
+    /// Track the earliest and latest timestamp in each group.
+    pub fn min_max_timestamp(mut self) -> Self {
+        self.min_max_timestamp = true;
+        self
+    }
+
+    /// Scan `db` and return the non-empty groups, ordered by value id.
+    ///
+    /// Fails with `Error::UnknownField` if the grouping field is the
+    /// timestamp or doesn't exist in `db`.
+    pub fn run(&self, db: &Db) -> Result<Vec<Group>, Error> {
+        if self.field == 0 || self.field as u64 >= db.num_fields() {
+            return Err(Error::UnknownField);
+        }
+        let index = self.field as usize - 1;
+        let empty = Accumulator {
+            events: 0,
+            trails: 0,
+            last_trail: TrailId::max_value(),
+            min_timestamp: Timestamp::max_value(),
+            max_timestamp: 0,
+        };
+        let mut groups = vec![empty.clone(); db.lexicon_size(self.field) as usize];
+        let mut cursor = db.cursor();
+        if let Some(filter) = self.filter {
+            cursor.set_event_filter(filter)?;
+        }
+        for trail_id in db.scan_trails(self.filter) {
+            cursor.get_trail(trail_id)?;
+            for event in &mut cursor {
+                let value = event.items[index].value() as usize;
+                if value >= groups.len() {
+                    groups.resize(value + 1, empty.clone());
+                }
+                let group = &mut groups[value];
+                group.events += 1;
+                if group.last_trail != trail_id {
+                    group.last_trail = trail_id;
+                    group.trails += 1;
+                }
+                group.min_timestamp = group.min_timestamp.min(event.timestamp);
+                group.max_timestamp = group.max_timestamp.max(event.timestamp);
+            }
+        }
+        Ok(groups.into_iter()
+            .enumerate()
+            .filter(|&(_, ref group)| group.events > 0)
+            .map(|(value, group)| {
+                Group {
+                    value: value as Value,
+                    events: if self.count { Some(group.events) } else { None },
+                    trails: if self.distinct_trails { Some(group.trails) } else { None },
+                    timestamps: if self.min_max_timestamp {
+                        Some((group.min_timestamp, group.max_timestamp))
+                    } else {
+                        None
+                    },
+                }
+            })
+            .collect())
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_aggregate {
+    use super::Aggregator;
+    use super::super::super::{Constructor, Db, EventFilter};
+    use std::path::Path;
+
+    #[test]
+    fn test_group_by() {
+        let db_path = Path::new("test_group_by");
+        let mut cons = Constructor::new(db_path, &["user", "action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["alice", "login"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 5, &["alice", "login"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 3, &["bob", "login"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 4, &["bob", "logout"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let action = db.get_field("action").unwrap();
+        let login = db.get_item(action, "login").unwrap().value();
+        let groups = Aggregator::group_by(action)
+            .count()
+            .distinct_trails()
+            .min_max_timestamp()
+            .run(&db)
+            .unwrap();
+        assert_eq!(groups.len(), 2);
+        let group = groups.iter().find(|g| g.value == login).unwrap();
+        assert_eq!(group.events, Some(3));
+        assert_eq!(group.trails, Some(2));
+        assert_eq!(group.timestamps, Some((1, 5)));
+
+        let user = db.get_field("user").unwrap();
+        let mut filter = EventFilter::new();
+        filter.add_term(db.get_item(user, "bob").unwrap(), false).unwrap();
+        let groups = Aggregator::group_by(action).filter(&filter).count().run(&db).unwrap();
+        assert!(groups.iter().all(|g| g.events == Some(1) && g.trails.is_none()));
+        assert!(Aggregator::group_by(0).run(&db).is_err());
+    }
+}
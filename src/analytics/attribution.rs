@@ -0,0 +1,182 @@
+//! Touch attribution.
+//!
+//! Every conversion, an event matching the conversion filter, is credited to
+//! one touch, an event matching the touch filter in the same trail at or
+//! before the conversion and within the lookback window. Credit goes to the
+//! touch's value of the attribution field, e.g. a campaign.
+
+use std::collections::HashMap;
+
+use super::super::{Db, Error, EventFilter, Field, Timestamp, Value};
+
+/// Which touch in the lookback window gets the credit.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum TouchModel {
+    /// The earliest touch in the window.
+    FirstTouch,
+    /// The latest touch at or before the conversion.
+    LastTouch,
+}
+
+/// An attribution query, evaluated with `run`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::{Db, EventFilter};
+/// use traildb::analytics::attribution::{Attribution, TouchModel};
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let action = db.get_field("action").unwrap();
+/// let campaign = db.get_field("campaign").unwrap();
+/// let mut touch = EventFilter::new();
+/// touch.add_term(db.get_item(action, "ad_click").unwrap(), false).unwrap();
+/// let mut conversion = EventFilter::new();
+/// conversion.add_term(db.get_item(action, "purchase").unwrap(), false).unwrap();
+///
+/// let report = Attribution::new(&touch, &conversion, campaign)
+///     .lookback(7 * 86400)
+///     .model(TouchModel::FirstTouch)
+///     .run(&db)
+///     .unwrap();
+/// for (value, conversions) in &report.credited {
+///     println!("{:?}: {}", db.get_value(campaign, *value), conversions);
+/// }
+/// println!("unattributed: {}", report.unattributed);
+/// ```
+pub struct Attribution<'f> {
+    touch: &'f EventFilter,
+    conversion: &'f EventFilter,
+    field: Field,
+    lookback: Timestamp,
+    model: TouchModel,
+}
+
+/// The result of running an `Attribution`.
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct AttributionReport {
+    /// The number of conversions seen.
+    pub conversions: u64,
+    /// The number of conversions credited to each value id of the
+    /// attribution field.
+    pub credited: HashMap<Value, u64>,
+    /// The number of conversions without a touch in their window.
+    pub unattributed: u64,
+}
+
+impl<'f> Attribution<'f> {
+    /// Credit conversions matching `conversion` to the `field` value of
+    /// touches matching `touch`. Defaults to last touch with an unlimited
+    /// lookback.
+    pub fn new(touch: &'f EventFilter, conversion: &'f EventFilter, field: Field) -> Self {
+        Attribution {
+            touch: touch,
+            conversion: conversion,
+            field: field,
+            lookback: Timestamp::max_value(),
+            model: TouchModel::LastTouch,
+        }
+    }
+
+    /// Only touches at most `window` before a conversion are credited.
+    pub fn lookback(mut self, window: Timestamp) -> Self {
+        self.lookback = window;
+        self
+    }
+
+    pub fn model(mut self, model: TouchModel) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Evaluate the attribution over every trail in `db`.
+    ///
+    /// Fails with `Error::UnknownField` if the attribution field is the
+    /// timestamp or doesn't exist in `db`.
+    pub fn run(&self, db: &Db) -> Result<AttributionReport, Error> {
+        if self.field == 0 || self.field as u64 >= db.num_fields() {
+            return Err(Error::UnknownField);
+        }
+        let index = self.field as usize - 1;
+        let mut touches = db.cursor();
+        touches.set_event_filter(self.touch)?;
+        let mut conversions = db.cursor();
+        conversions.set_event_filter(self.conversion)?;
+
+        let mut report = AttributionReport::default();
+        let mut trail_touches: Vec<(Timestamp, Value)> = Vec::new();
+        for trail_id in db.scan_trails(Some(self.conversion)) {
+            conversions.get_trail(trail_id)?;
+            let mut converted = conversions.by_ref().map(|event| event.timestamp).peekable();
+            if converted.peek().is_none() {
+                continue;
+            }
+            trail_touches.clear();
+            touches.get_trail(trail_id)?;
+            trail_touches.extend(touches.by_ref()
+                .map(|event| (event.timestamp, event.items[index].value())));
+
+            for at in converted {
+                report.conversions += 1;
+                let since = at.saturating_sub(self.lookback);
+                let mut window = trail_touches.iter().filter(|&&(t, _)| t >= since && t <= at);
+                let credited = match self.model {
+                    TouchModel::FirstTouch => window.next(),
+                    TouchModel::LastTouch => window.last(),
+                };
+                match credited {
+                    Some(&(_, value)) => *report.credited.entry(value).or_insert(0) += 1,
+                    None => report.unattributed += 1,
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_attribution {
+    use super::{Attribution, TouchModel};
+    use super::super::super::{Constructor, Db, EventFilter};
+    use std::path::Path;
+
+    #[test]
+    fn test_attribution() {
+        let db_path = Path::new("test_attribution");
+        let mut cons = Constructor::new(db_path, &["action", "campaign"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["click", "a"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 8, &["click", "b"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 10, &["buy", ""]).is_ok());
+        // Clicked too long before buying.
+        assert!(cons.add(&[2u8; 16], 1, &["click", "a"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 50, &["buy", ""]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let action = db.get_field("action").unwrap();
+        let campaign = db.get_field("campaign").unwrap();
+        let filter = |value| {
+            let mut filter = EventFilter::new();
+            filter.add_term(db.get_item(action, value).unwrap(), false).unwrap();
+            filter
+        };
+        let (click, buy) = (filter("click"), filter("buy"));
+        let value = |v| db.get_item(campaign, v).unwrap().value();
+
+        let last = Attribution::new(&click, &buy, campaign).lookback(20).run(&db).unwrap();
+        assert_eq!(last.conversions, 2);
+        assert_eq!(last.credited.get(&value("b")), Some(&1));
+        assert_eq!(last.unattributed, 1);
+
+        let first = Attribution::new(&click, &buy, campaign)
+            .model(TouchModel::FirstTouch)
+            .run(&db)
+            .unwrap();
+        assert_eq!(first.credited.get(&value("a")), Some(&2));
+        assert_eq!(first.unattributed, 0);
+    }
+}
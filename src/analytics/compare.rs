@@ -0,0 +1,172 @@
+//! Comparing two segments.
+//!
+//! A segment is the set of events matching a filter. `compare` counts the
+//! values of every field in two segments, so their distributions can be set
+//! side by side: the lift of a value is how much more common it is in
+//! segment A than in segment B.
+
+use super::super::{Db, Error, EventFilter, Field, Value};
+
+/// Value distributions of two segments, as computed by `compare`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Comparison {
+    /// The number of events in segment A.
+    pub events_a: u64,
+    /// The number of events in segment B.
+    pub events_b: u64,
+    /// `counts_a[field - 1][value]` is the number of events in segment A
+    /// holding `value` in `field`.
+    pub counts_a: Vec<Vec<u64>>,
+    /// The same for segment B.
+    pub counts_b: Vec<Vec<u64>>,
+}
+
+impl Comparison {
+    /// The share of events in segment A holding `value` in `field`.
+    pub fn share_a(&self, field: Field, value: Value) -> f64 {
+        share(&self.counts_a, self.events_a, field, value)
+    }
+
+    /// The share of events in segment B holding `value` in `field`.
+    pub fn share_b(&self, field: Field, value: Value) -> f64 {
+        share(&self.counts_b, self.events_b, field, value)
+    }
+
+    /// How many times more common `value` is in segment A than in segment
+    /// B, or `None` if it never occurs in segment B.
+    pub fn lift(&self, field: Field, value: Value) -> Option<f64> {
+        let b = self.share_b(field, value);
+        if b > 0.0 {
+            Some(self.share_a(field, value) / b)
+        } else {
+            None
+        }
+    }
+
+    /// The `n` values of `field` with the highest lift, highest first.
+    /// Values missing from segment B are left out.
+    pub fn top_lift(&self, field: Field, n: usize) -> Vec<(Value, f64)> {
+        let num_values = match field {
+            0 => 0,
+            field => self.counts_a.get(field as usize - 1).map_or(0, |counts| counts.len()),
+        };
+        let mut lifts: Vec<(Value, f64)> = (0..num_values as Value)
+            .filter_map(|value| self.lift(field, value).map(|lift| (value, lift)))
+            .collect();
+        lifts.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+        lifts.truncate(n);
+        lifts
+    }
+}
+
+fn share(counts: &[Vec<u64>], events: u64, field: Field, value: Value) -> f64 {
+    if events == 0 || field == 0 {
+        return 0.0;
+    }
+    let count = counts.get(field as usize - 1)
+        .and_then(|counts| counts.get(value as usize))
+        .cloned()
+        .unwrap_or(0);
+    count as f64 / events as f64
+}
+
+/// Count the values of every field in the events matching `a` and those
+/// matching `b`, in one pass over the trails of `db`. An event matching both
+/// filters counts in both segments.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::{Db, EventFilter};
+/// use traildb::analytics::compare::compare;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let variant = db.get_field("variant").unwrap();
+/// let page = db.get_field("page").unwrap();
+/// let segment = |value| {
+///     let mut filter = EventFilter::new();
+///     filter.add_term(db.get_item(variant, value).unwrap(), false).unwrap();
+///     filter
+/// };
+/// let comparison = compare(&db, &segment("b"), &segment("a")).unwrap();
+/// for (value, lift) in comparison.top_lift(page, 10) {
+///     println!("{:?}: {:.2}x", db.get_value(page, value), lift);
+/// }
+/// ```
+pub fn compare(db: &Db, a: &EventFilter, b: &EventFilter) -> Result<Comparison, Error> {
+    let counts: Vec<Vec<u64>> = (1..db.num_fields() as Field)
+        .map(|field| vec![0; db.lexicon_size(field) as usize])
+        .collect();
+    let mut comparison = Comparison {
+        events_a: 0,
+        events_b: 0,
+        counts_a: counts.clone(),
+        counts_b: counts,
+    };
+    let mut cursor_a = db.cursor();
+    cursor_a.set_event_filter(a)?;
+    let mut cursor_b = db.cursor();
+    cursor_b.set_event_filter(b)?;
+    for trail_id in 0..db.num_trails() {
+        cursor_a.get_trail(trail_id)?;
+        for event in &mut cursor_a {
+            comparison.events_a += 1;
+            count_items(&mut comparison.counts_a, event.items.iter().map(|item| item.value()));
+        }
+        cursor_b.get_trail(trail_id)?;
+        for event in &mut cursor_b {
+            comparison.events_b += 1;
+            count_items(&mut comparison.counts_b, event.items.iter().map(|item| item.value()));
+        }
+    }
+    Ok(comparison)
+}
+
+fn count_items<I: Iterator<Item = Value>>(counts: &mut [Vec<u64>], values: I) {
+    for (counts, value) in counts.iter_mut().zip(values) {
+        let value = value as usize;
+        if value >= counts.len() {
+            counts.resize(value + 1, 0);
+        }
+        counts[value] += 1;
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_compare {
+    use super::compare;
+    use super::super::super::{Constructor, Db, EventFilter};
+    use std::path::Path;
+
+    #[test]
+    fn test_compare() {
+        let db_path = Path::new("test_compare");
+        let mut cons = Constructor::new(db_path, &["variant", "page"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["a", "home"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["a", "home"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 3, &["a", "buy"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 4, &["a", "home"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 1, &["b", "home"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 2, &["b", "buy"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let variant = db.get_field("variant").unwrap();
+        let page = db.get_field("page").unwrap();
+        let segment = |value| {
+            let mut filter = EventFilter::new();
+            filter.add_term(db.get_item(variant, value).unwrap(), false).unwrap();
+            filter
+        };
+        let comparison = compare(&db, &segment("b"), &segment("a")).unwrap();
+        assert_eq!((comparison.events_a, comparison.events_b), (2, 4));
+        let buy = db.get_item(page, "buy").unwrap().value();
+        assert_eq!(comparison.share_a(page, buy), 0.5);
+        assert_eq!(comparison.lift(page, buy), Some(2.0));
+        assert_eq!(comparison.top_lift(page, 1)[0].0, buy);
+    }
+}
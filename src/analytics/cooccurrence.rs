@@ -0,0 +1,162 @@
+//! Value co-occurrence.
+//!
+//! Counts how often a value of one field appears together with a value of
+//! another (or the same) field, either in the same event or anywhere in the
+//! same trail. Empty values are ignored.
+
+use std::collections::{BTreeSet, HashMap};
+
+use super::super::{Db, Error, EventFilter, Field, Value};
+
+/// What counts as appearing together.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Scope {
+    /// Both values are in the same event. Counts events.
+    Event,
+    /// Both values are in some event of the same trail. Counts trails.
+    Trail,
+}
+
+/// Co-occurrence counts keyed by `(a, b)` value ids, as computed by
+/// `cooccurrence`. When both fields are the same only pairs with `a < b`
+/// are kept.
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct Cooccurrence {
+    pub pairs: HashMap<(Value, Value), u64>,
+}
+
+impl Cooccurrence {
+    /// How often `a` and `b` appeared together, in either order when both
+    /// fields are the same.
+    pub fn count(&self, a: Value, b: Value) -> u64 {
+        self.pairs
+            .get(&(a, b))
+            .or_else(|| self.pairs.get(&(b, a)))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// The `n` most frequent pairs, most frequent first.
+    pub fn top(&self, n: usize) -> Vec<((Value, Value), u64)> {
+        let mut pairs: Vec<((Value, Value), u64)> = self.pairs.iter().map(|(&p, &c)| (p, c)).collect();
+        pairs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        pairs.truncate(n);
+        pairs
+    }
+}
+
+/// Count how often values of `a` and `b` co-occur within `scope` over the
+/// events of `db` matching `filter`, or all events without one.
+///
+/// With `a == b` and `Scope::Trail` this counts pairs of distinct values of
+/// one field seen in the same trail; a single event only has one value per
+/// field, so `Scope::Event` needs two different fields to count anything.
+///
+/// Fails with `Error::UnknownField` if either field is the timestamp or
+/// doesn't exist in `db`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::analytics::cooccurrence::{cooccurrence, Scope};
+/// use std::path::Path;
+///
+/// // Which products are viewed by the same users?
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let product = db.get_field("product").unwrap();
+/// let matrix = cooccurrence(&db, product, product, Scope::Trail, None).unwrap();
+/// for ((a, b), trails) in matrix.top(10) {
+///     println!("{:?} + {:?}: {}", db.get_value(product, a), db.get_value(product, b), trails);
+/// }
+/// ```
+pub fn cooccurrence(db: &Db,
+                    a: Field,
+                    b: Field,
+                    scope: Scope,
+                    filter: Option<&EventFilter>)
+                    -> Result<Cooccurrence, Error> {
+    for &field in &[a, b] {
+        if field == 0 || field as u64 >= db.num_fields() {
+            return Err(Error::UnknownField);
+        }
+    }
+    let (index_a, index_b) = (a as usize - 1, b as usize - 1);
+    let mut matrix = Cooccurrence::default();
+    let mut cursor = db.cursor();
+    if let Some(filter) = filter {
+        cursor.set_event_filter(filter)?;
+    }
+    let mut values_a: BTreeSet<Value> = BTreeSet::new();
+    let mut values_b: BTreeSet<Value> = BTreeSet::new();
+    for trail_id in 0..db.num_trails() {
+        cursor.get_trail(trail_id)?;
+        match scope {
+            Scope::Event => {
+                if a == b {
+                    continue;
+                }
+                for event in &mut cursor {
+                    let (va, vb) = (event.items[index_a].value(), event.items[index_b].value());
+                    if va != 0 && vb != 0 {
+                        *matrix.pairs.entry((va, vb)).or_insert(0) += 1;
+                    }
+                }
+            }
+            Scope::Trail => {
+                values_a.clear();
+                values_b.clear();
+                for event in &mut cursor {
+                    values_a.insert(event.items[index_a].value());
+                    values_b.insert(event.items[index_b].value());
+                }
+                for &va in values_a.iter().filter(|&&v| v != 0) {
+                    for &vb in values_b.iter().filter(|&&v| v != 0) {
+                        if a != b || va < vb {
+                            *matrix.pairs.entry((va, vb)).or_insert(0) += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(matrix)
+}
+
+
+
+
+#[cfg(test)]
+mod test_cooccurrence {
+    use super::{cooccurrence, Scope};
+    use super::super::super::{Constructor, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_cooccurrence() {
+        let db_path = Path::new("test_cooccurrence");
+        let mut cons = Constructor::new(db_path, &["product", "color"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["shoe", "red"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["hat", "red"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 1, &["shoe", "blue"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 2, &["hat", ""]).is_ok());
+        assert!(cons.add(&[2u8; 16], 3, &["shoe", "red"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let product = db.get_field("product").unwrap();
+        let color = db.get_field("color").unwrap();
+        let p = |v| db.get_item(product, v).unwrap().value();
+        let c = |v| db.get_item(color, v).unwrap().value();
+
+        let events = cooccurrence(&db, product, color, Scope::Event, None).unwrap();
+        assert_eq!(events.count(p("shoe"), c("red")), 2);
+        assert_eq!(events.count(p("hat"), c("blue")), 0);
+        assert_eq!(events.pairs.values().sum::<u64>(), 4);
+
+        let trails = cooccurrence(&db, product, product, Scope::Trail, None).unwrap();
+        assert_eq!(trails.pairs.len(), 1);
+        assert_eq!(trails.count(p("hat"), p("shoe")), 2);
+        assert_eq!(trails.top(1)[0].1, 2);
+    }
+}
@@ -0,0 +1,174 @@
+//! Counting over whole databases without resolving values.
+
+use std::time::Duration;
+
+use super::super::time::TimeUnit;
+use super::super::{Db, Error, EventFilter, Field, Timestamp};
+
+/// Event counts per time bucket, as computed by `Db::time_histogram`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct TimeHistogram {
+    /// The start of the first bucket.
+    pub origin: Timestamp,
+    /// The width of a bucket, in the database's time unit.
+    pub bucket: Timestamp,
+    /// The number of events in each bucket.
+    pub counts: Vec<u64>,
+}
+
+impl TimeHistogram {
+    /// The start of bucket `n`.
+    pub fn bucket_start(&self, n: usize) -> Timestamp {
+        self.origin + n as Timestamp * self.bucket
+    }
+}
+
+impl<'a> Db<'a> {
+    /// Count the events holding each value of `field`, optionally only those
+    /// matching `filter`.
+    ///
+    /// The result is indexed by value id and has one entry per lexicon value;
+    /// index 0 counts events where the field is empty. Resolve ids with
+    /// `get_value`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// let action = db.get_field("action").unwrap();
+    /// for (value, count) in db.value_counts(action, None).unwrap().iter().enumerate() {
+    ///     println!("{:?}: {}", db.get_value(action, value as u64), count);
+    /// }
+    /// ```
+    pub fn value_counts(&self, field: Field, filter: Option<&EventFilter>) -> Result<Vec<u64>, Error> {
+        if field == 0 || field as u64 >= self.num_fields() {
+            return Err(Error::UnknownField);
+        }
+        let index = field as usize - 1;
+        let mut counts = vec![0u64; self.lexicon_size(field) as usize];
+        let mut cursor = self.cursor();
+        if let Some(filter) = filter {
+            cursor.set_event_filter(filter)?;
+        }
+        for trail_id in self.scan_trails(filter) {
+            cursor.get_trail(trail_id)?;
+            for event in &mut cursor {
+                let value = event.items[index].value() as usize;
+                if value >= counts.len() {
+                    counts.resize(value + 1, 0);
+                }
+                counts[value] += 1;
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Count events, optionally only those matching `filter`, per bucket of
+    /// width `bucket`, for timestamps in `unit`. Buckets start at multiples
+    /// of their width and span the database's timestamps.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket` is shorter than one `unit`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use traildb::time::TimeUnit;
+    /// use std::path::Path;
+    /// use std::time::Duration;
+    ///
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// let hourly = db.time_histogram(Duration::from_secs(3600), TimeUnit::Seconds, None).unwrap();
+    /// for (n, count) in hourly.counts.iter().enumerate() {
+    ///     println!("{}: {}", hourly.bucket_start(n), count);
+    /// }
+    /// ```
+    pub fn time_histogram(&self,
+                          bucket: Duration,
+                          unit: TimeUnit,
+                          filter: Option<&EventFilter>)
+                          -> Result<TimeHistogram, Error> {
+        let bucket = unit.from_duration(bucket);
+        assert!(bucket > 0, "bucket must be at least one time unit");
+        let first = self.min_timestamp() / bucket;
+        let num_buckets = if self.num_events() == 0 {
+            0
+        } else {
+            (self.max_timestamp() / bucket - first + 1) as usize
+        };
+        let mut counts = vec![0u64; num_buckets];
+        let mut cursor = self.cursor();
+        if let Some(filter) = filter {
+            cursor.set_event_filter(filter)?;
+        }
+        for trail_id in self.scan_trails(filter) {
+            cursor.get_trail(trail_id)?;
+            for event in &mut cursor {
+                counts[(event.timestamp / bucket - first) as usize] += 1;
+            }
+        }
+        Ok(TimeHistogram {
+            origin: first * bucket,
+            bucket: bucket,
+            counts: counts,
+        })
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_counts {
+    use super::super::super::time::TimeUnit;
+    use super::super::super::{Constructor, Db, EventFilter};
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[test]
+    fn test_value_counts() {
+        let db_path = Path::new("test_value_counts");
+        let mut cons = Constructor::new(db_path, &["user", "action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["alice", "login"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["alice", "logout"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 3, &["bob", "login"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 4, &["bob", ""]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let action = db.get_field("action").unwrap();
+        let counts = db.value_counts(action, None).unwrap();
+        let count = |value: &str| counts[db.get_item(action, value).unwrap().value() as usize];
+        assert_eq!(counts[0], 1);
+        assert_eq!(count("login"), 2);
+        assert_eq!(count("logout"), 1);
+
+        let user = db.get_field("user").unwrap();
+        let mut filter = EventFilter::new();
+        filter.add_term(db.get_item(user, "bob").unwrap(), false).unwrap();
+        let counts = db.value_counts(action, Some(&filter)).unwrap();
+        assert_eq!(counts.iter().sum::<u64>(), 2);
+        assert!(db.value_counts(0, None).is_err());
+    }
+
+    #[test]
+    fn test_time_histogram() {
+        let db_path = Path::new("test_time_histogram");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        for &ts in &[3500u64, 3600, 3700, 11000] {
+            assert!(cons.add(&[1u8; 16], ts, &["a"]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let hourly = db.time_histogram(Duration::from_secs(3600), TimeUnit::Seconds, None).unwrap();
+        assert_eq!(hourly.origin, 0);
+        assert_eq!(hourly.counts, vec![1, 2, 0, 1]);
+        assert_eq!(hourly.bucket_start(3), 10800);
+    }
+}
@@ -0,0 +1,246 @@
+//! Per-trail feature vectors.
+//!
+//! `FeatureExtractor` turns every trail into a row of numbers, ready to
+//! feed model training. Every row starts with the same columns:
+//!
+//! - `events`: the number of events
+//! - `first_timestamp` and `last_timestamp`
+//! - `recency`: the time from the last event to the reference time
+//! - `gap_mean`, `gap_min`, `gap_max` and `gap_stddev`: statistics of the
+//!   time between consecutive events, all 0 for trails with a single event
+//!
+//! followed by a `<field>=<value>` column counting the events holding each
+//! non-empty value of every field passed to `count_values`.
+
+use std::io::Write;
+#[cfg(feature = "arrow")]
+use std::sync::Arc;
+
+#[cfg(feature = "arrow")]
+use arrow::array::{ArrayRef, FixedSizeBinaryArray, Float64Array};
+#[cfg(feature = "arrow")]
+use arrow::datatypes::{DataType, Field as ArrowField, Schema};
+#[cfg(feature = "arrow")]
+use arrow::record_batch::RecordBatch;
+
+use super::super::export::ExportError;
+use super::super::{uuid_hex, Db, Error, EventFilter, Field, Timestamp, Uuid};
+
+const FIXED_COLUMNS: [&str; 8] = ["events",
+                                  "first_timestamp",
+                                  "last_timestamp",
+                                  "recency",
+                                  "gap_mean",
+                                  "gap_min",
+                                  "gap_max",
+                                  "gap_stddev"];
+
+/// Extracts a feature vector per trail.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::analytics::features::FeatureExtractor;
+/// use std::fs::File;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let action = db.get_field("action").unwrap();
+/// let features = FeatureExtractor::new()
+///     .count_values(action)
+///     .extract(&db)
+///     .unwrap();
+/// features.write_csv(File::create("features.csv").unwrap()).unwrap();
+/// ```
+#[derive(Default)]
+pub struct FeatureExtractor<'f> {
+    count_fields: Vec<Field>,
+    filter: Option<&'f EventFilter>,
+    reference_time: Option<Timestamp>,
+}
+
+/// Feature vectors, one row per trail with at least one event.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Features {
+    /// The column names.
+    pub names: Vec<String>,
+    /// The UUID of each row's trail.
+    pub uuids: Vec<Uuid>,
+    /// One vector per trail, with a value per column.
+    pub rows: Vec<Vec<f64>>,
+}
+
+impl<'f> FeatureExtractor<'f> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a column per non-empty value of `field`, counting the events
+    /// holding that value.
+    pub fn count_values(mut self, field: Field) -> Self {
+        self.count_fields.push(field);
+        self
+    }
+
+    /// Only use events matching `filter`. Trails without matching events
+    /// get no row.
+    pub fn filter(mut self, filter: &'f EventFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// The time `recency` is measured to. Defaults to the database's
+    /// latest timestamp.
+    pub fn reference_time(mut self, time: Timestamp) -> Self {
+        self.reference_time = Some(time);
+        self
+    }
+
+    /// Compute the features of every trail in `db`.
+    ///
+    /// Fails with `Error::UnknownField` if a `count_values` field is the
+    /// timestamp or doesn't exist in `db`.
+    pub fn extract(&self, db: &Db) -> Result<Features, Error> {
+        let mut names: Vec<String> = FIXED_COLUMNS.iter().map(|n| n.to_string()).collect();
+        // The column of value 1 of each count field; value v is at base + v - 1.
+        let mut bases = Vec::with_capacity(self.count_fields.len());
+        for &field in &self.count_fields {
+            let field_name = match db.get_field_name(field) {
+                Some(name) if field > 0 => name,
+                _ => return Err(Error::UnknownField),
+            };
+            bases.push(names.len());
+            for value in 1..db.lexicon_size(field) {
+                names.push(format!("{}={}", field_name, db.get_value(field, value).unwrap_or("")));
+            }
+        }
+        let reference = self.reference_time.unwrap_or_else(|| db.max_timestamp());
+
+        let mut features = Features {
+            names: names,
+            uuids: Vec::new(),
+            rows: Vec::new(),
+        };
+        let mut cursor = db.cursor();
+        if let Some(filter) = self.filter {
+            cursor.set_event_filter(filter)?;
+        }
+        let mut timestamps: Vec<Timestamp> = Vec::new();
+        for trail_id in 0..db.num_trails() {
+            cursor.get_trail(trail_id)?;
+            let mut row = vec![0.0; features.names.len()];
+            timestamps.clear();
+            for event in &mut cursor {
+                timestamps.push(event.timestamp);
+                for (&field, &base) in self.count_fields.iter().zip(&bases) {
+                    let value = event.items[field as usize - 1].value() as usize;
+                    if value > 0 && base + value - 1 < row.len() {
+                        row[base + value - 1] += 1.0;
+                    }
+                }
+            }
+            let (first, last) = match (timestamps.first(), timestamps.last()) {
+                (Some(&first), Some(&last)) => (first, last),
+                _ => continue,
+            };
+            row[0] = timestamps.len() as f64;
+            row[1] = first as f64;
+            row[2] = last as f64;
+            row[3] = reference.saturating_sub(last) as f64;
+            if timestamps.len() > 1 {
+                let gaps: Vec<f64> = timestamps.windows(2).map(|w| (w[1] - w[0]) as f64).collect();
+                let mean = gaps.iter().sum::<f64>() / gaps.len() as f64;
+                let variance = gaps.iter().map(|g| (g - mean) * (g - mean)).sum::<f64>() /
+                               gaps.len() as f64;
+                row[4] = mean;
+                row[5] = gaps.iter().cloned().fold(f64::INFINITY, f64::min);
+                row[6] = gaps.iter().cloned().fold(0.0, f64::max);
+                row[7] = variance.sqrt();
+            }
+            if let Some(uuid) = db.get_uuid(trail_id) {
+                features.uuids.push(*uuid);
+                features.rows.push(row);
+            }
+        }
+        Ok(features)
+    }
+}
+
+impl Features {
+    /// Write the features as CSV, with a header row and the trail's UUID in
+    /// hex as the first column.
+    pub fn write_csv<W: Write>(&self, mut out: W) -> Result<(), ExportError> {
+        // Names are either fixed or `field=value`; quote them in case a
+        // value holds a separator.
+        let header: Vec<String> = self.names
+            .iter()
+            .map(|n| format!("\"{}\"", n.replace('"', "\"\"")))
+            .collect();
+        writeln!(out, "uuid,{}", header.join(","))?;
+        for (uuid, row) in self.uuids.iter().zip(&self.rows) {
+            let values: Vec<String> = row.iter().map(|v| v.to_string()).collect();
+            writeln!(out, "{},{}", uuid_hex(uuid), values.join(","))?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// The features as an Arrow batch, with a `uuid` column followed by a
+    /// `Float64` column per feature.
+    #[cfg(feature = "arrow")]
+    pub fn to_record_batch(&self) -> Result<RecordBatch, ExportError> {
+        let mut columns = vec![ArrowField::new("uuid", DataType::FixedSizeBinary(16), false)];
+        columns.extend(self.names.iter().map(|n| ArrowField::new(n.as_str(), DataType::Float64, false)));
+        let mut arrays: Vec<ArrayRef> = Vec::with_capacity(columns.len());
+        arrays.push(Arc::new(FixedSizeBinaryArray::try_from_sparse_iter_with_size(
+            self.uuids.iter().map(|u| Some(&u[..])),
+            16)?));
+        for i in 0..self.names.len() {
+            arrays.push(Arc::new(Float64Array::from_iter_values(self.rows.iter().map(|row| row[i]))));
+        }
+        Ok(RecordBatch::try_new(Arc::new(Schema::new(columns)), arrays)?)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_features {
+    use super::FeatureExtractor;
+    use super::super::super::{Constructor, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_extract() {
+        let db_path = Path::new("test_features");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 10, &["view"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 20, &["view"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 40, &["buy"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 5, &[""]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let action = db.get_field("action").unwrap();
+        let features = FeatureExtractor::new()
+            .count_values(action)
+            .reference_time(100)
+            .extract(&db)
+            .unwrap();
+        let column = |name: &str| features.names.iter().position(|n| n == name).unwrap();
+        let row = &features.rows[features.uuids.iter().position(|u| *u == [1u8; 16]).unwrap()];
+        assert_eq!(row[column("events")], 3.0);
+        assert_eq!(row[column("recency")], 60.0);
+        assert_eq!(row[column("gap_mean")], 15.0);
+        assert_eq!(row[column("gap_max")], 20.0);
+        assert_eq!(row[column("action=view")], 2.0);
+        assert_eq!(row[column("action=buy")], 1.0);
+
+        let mut csv = Vec::new();
+        features.write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 3);
+        assert!(FeatureExtractor::new().count_values(0).extract(&db).is_err());
+    }
+}
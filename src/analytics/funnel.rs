@@ -0,0 +1,260 @@
+//! Funnel analysis.
+//!
+//! A funnel is an ordered list of steps, each an `EventFilter`. A trail
+//! reaches step `n` if it has events matching steps `0..=n` in order, each
+//! a later event of the trail than the previous one, though possibly at the
+//! same timestamp, and, for steps with a window, no later than the window
+//! after it.
+
+use std::mem;
+
+use super::super::{Db, Error, EventFilter, Timestamp, TrailId};
+
+/// An ordered list of steps to evaluate against a `Db`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::{Db, EventFilter};
+/// use traildb::analytics::funnel::Funnel;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let action = db.get_field("action").unwrap();
+/// let step = |value| {
+///     let mut filter = EventFilter::new();
+///     filter.add_term(db.get_item(action, value).unwrap(), false).unwrap();
+///     filter
+/// };
+/// let (visit, signup, purchase) = (step("visit"), step("signup"), step("purchase"));
+/// let report = Funnel::new()
+///     .step(&visit)
+///     .step(&signup)
+///     // A purchase counts only within a day of signing up.
+///     .step_within(&purchase, 86400)
+///     .run(&db)
+///     .unwrap();
+/// for (i, trails) in report.steps.iter().enumerate() {
+///     println!("step {}: {} trails ({:.1}%)", i, trails, 100.0 * report.conversion(i));
+/// }
+/// ```
+#[derive(Default)]
+pub struct Funnel<'f> {
+    steps: Vec<(&'f EventFilter, Option<Timestamp>)>,
+}
+
+/// The result of running a `Funnel`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct FunnelReport {
+    /// The number of trails reaching each step.
+    pub steps: Vec<u64>,
+    /// For every trail, indexed by trail id, the number of steps it reached.
+    pub furthest: Vec<usize>,
+}
+
+impl FunnelReport {
+    /// The share of trails reaching the first step that also reached `step`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `step` isn't a step of the funnel.
+    pub fn conversion(&self, step: usize) -> f64 {
+        match self.steps.first() {
+            Some(&first) if first > 0 => self.steps[step] as f64 / first as f64,
+            _ => 0.0,
+        }
+    }
+
+    /// The number of steps trail `trail_id` reached.
+    pub fn furthest_step(&self, trail_id: TrailId) -> usize {
+        self.furthest.get(trail_id as usize).cloned().unwrap_or(0)
+    }
+}
+
+impl<'f> Funnel<'f> {
+    pub fn new() -> Self {
+        Funnel { steps: Vec::new() }
+    }
+
+    /// Add a step matching `filter`.
+    pub fn step(mut self, filter: &'f EventFilter) -> Self {
+        self.steps.push((filter, None));
+        self
+    }
+
+    /// Add a step matching `filter` that must happen at most `window` after
+    /// the previous step.
+    pub fn step_within(mut self, filter: &'f EventFilter, window: Timestamp) -> Self {
+        self.steps.push((filter, Some(window)));
+        self
+    }
+
+    /// Evaluate the funnel for every trail in `db`.
+    pub fn run(&self, db: &Db) -> Result<FunnelReport, Error> {
+        let mut report = FunnelReport {
+            steps: vec![0; self.steps.len()],
+            furthest: vec![0; db.num_trails() as usize],
+        };
+        let first = match self.steps.first() {
+            Some(&(filter, _)) => filter,
+            None => return Ok(report),
+        };
+        let mut cursor = db.cursor();
+        let mut matches: Vec<Vec<(usize, Timestamp)>> = vec![Vec::new(); self.steps.len()];
+        // Trails without a match of the first step reach no step.
+        for trail_id in db.scan_trails(Some(first)) {
+            for positions in &mut matches {
+                positions.clear();
+            }
+            cursor.get_trail(trail_id)?;
+            for (position, event) in cursor.by_ref().enumerate() {
+                for (&(filter, _), positions) in self.steps.iter().zip(matches.iter_mut()) {
+                    if filter.matches(&event) {
+                        positions.push((position, event.timestamp));
+                    }
+                }
+            }
+            let reached = self.furthest(&matches);
+            for count in &mut report.steps[..reached] {
+                *count += 1;
+            }
+            report.furthest[trail_id as usize] = reached;
+        }
+        Ok(report)
+    }
+
+    /// The number of steps reached given the positions in the trail and
+    /// the timestamps of the events matching each step, in trail order.
+    ///
+    /// Goes step by step, keeping the events the trail can be at the step
+    /// at: the matches with a reachable match of the previous step at an
+    /// earlier event and within the window. An event matching two steps
+    /// only counts for one of them. The latest earlier match is always the
+    /// closest, so one pass over each step's matches does.
+    fn furthest(&self, matches: &[Vec<(usize, Timestamp)>]) -> usize {
+        let mut reachable = match matches.first() {
+            Some(starts) if !starts.is_empty() => starts.clone(),
+            _ => return 0,
+        };
+        let mut next = Vec::new();
+        for (reached, (&(_, window), positions)) in self.steps[1..].iter().zip(&matches[1..]).enumerate() {
+            next.clear();
+            let mut prev = None;
+            let mut i = 0;
+            for &(position, t) in positions {
+                while i < reachable.len() && reachable[i].0 < position {
+                    prev = Some(reachable[i].1);
+                    i += 1;
+                }
+                match (prev, window) {
+                    (Some(prev), Some(window)) if t - prev > window => {}
+                    (Some(_), _) => next.push((position, t)),
+                    (None, _) => {}
+                }
+            }
+            if next.is_empty() {
+                return reached + 1;
+            }
+            mem::swap(&mut reachable, &mut next);
+        }
+        self.steps.len()
+    }
+}
+
+
+
+#[cfg(test)]
+mod test_funnel {
+    use super::Funnel;
+    use super::super::super::{Constructor, Db, EventFilter};
+    use std::path::Path;
+
+    #[test]
+    fn test_funnel() {
+        let db_path = Path::new("test_funnel");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        // Completes the funnel, but only with the second visit.
+        assert!(cons.add(&[1u8; 16], 1, &["visit"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 50, &["visit"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 55, &["signup"]).is_ok());
+        // Signs up too late.
+        assert!(cons.add(&[2u8; 16], 1, &["visit"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 20, &["signup"]).is_ok());
+        // Signs up before visiting.
+        assert!(cons.add(&[3u8; 16], 1, &["signup"]).is_ok());
+        assert!(cons.add(&[3u8; 16], 2, &["visit"]).is_ok());
+        assert!(cons.add(&[4u8; 16], 1, &["other"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let action = db.get_field("action").unwrap();
+        let step = |value| {
+            let mut filter = EventFilter::new();
+            filter.add_term(db.get_item(action, value).unwrap(), false).unwrap();
+            filter
+        };
+        let (visit, signup) = (step("visit"), step("signup"));
+        let report = Funnel::new().step(&visit).step_within(&signup, 10).run(&db).unwrap();
+        assert_eq!(report.steps, vec![3, 1]);
+        let trail = |n: u8| db.get_trail_id(&[n; 16]).unwrap();
+        assert_eq!(report.furthest_step(trail(1)), 2);
+        assert_eq!(report.furthest_step(trail(2)), 1);
+        assert_eq!(report.furthest_step(trail(3)), 1);
+        assert_eq!(report.furthest_step(trail(4)), 0);
+        assert!((report.conversion(1) - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_funnel_later_match() {
+        let db_path = Path::new("test_funnel_later_match");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        // Only the second b is close enough to c.
+        for &(timestamp, action) in &[(0, "a"), (1, "b"), (10, "b"), (14, "c")] {
+            assert!(cons.add(&[1u8; 16], timestamp, &[action]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let action = db.get_field("action").unwrap();
+        let step = |value| {
+            let mut filter = EventFilter::new();
+            filter.add_term(db.get_item(action, value).unwrap(), false).unwrap();
+            filter
+        };
+        let (a, b, c) = (step("a"), step("b"), step("c"));
+        let report = Funnel::new().step(&a).step(&b).step_within(&c, 5).run(&db).unwrap();
+        assert_eq!(report.steps, vec![1, 1, 1]);
+        let report = Funnel::new().step(&a).step_within(&b, 5).step_within(&c, 3).run(&db).unwrap();
+        assert_eq!(report.steps, vec![1, 1, 0]);
+    }
+
+    #[test]
+    fn test_funnel_repeated_step() {
+        let db_path = Path::new("test_funnel_repeated_step");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        // One visit can't be both steps.
+        assert!(cons.add(&[1u8; 16], 1, &["visit"]).is_ok());
+        // Two visits, even at the same time, can.
+        assert!(cons.add(&[2u8; 16], 1, &["visit"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 1, &["visit"]).is_ok());
+        assert!(cons.add(&[3u8; 16], 1, &["visit"]).is_ok());
+        assert!(cons.add(&[3u8; 16], 5, &["visit"]).is_ok());
+        assert!(cons.add(&[4u8; 16], 1, &["other"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let action = db.get_field("action").unwrap();
+        let mut visit = EventFilter::new();
+        visit.add_term(db.get_item(action, "visit").unwrap(), false).unwrap();
+        let report = Funnel::new().step(&visit).step(&visit).run(&db).unwrap();
+        assert_eq!(report.steps, vec![3, 2]);
+        let trail = |n: u8| db.get_trail_id(&[n; 16]).unwrap();
+        assert_eq!(report.furthest_step(trail(1)), 1);
+        assert_eq!(report.furthest_step(trail(2)), 2);
+        assert_eq!(report.furthest_step(trail(3)), 2);
+        assert_eq!(report.furthest_step(trail(4)), 0);
+        let report = Funnel::new().step(&visit).step(&visit).step(&visit).run(&db).unwrap();
+        assert_eq!(report.steps, vec![3, 2, 0]);
+        assert_eq!(report.furthest.len(), 4);
+    }
+}
@@ -0,0 +1,260 @@
+//! Approximate distinct counting with HyperLogLog.
+//!
+//! Within one database every trail has a distinct UUID, so counting the
+//! trails matching a filter exactly is cheap. Sketches pay off across
+//! databases: build one per shard or day, ship and `merge` them, and
+//! estimate how many distinct UUIDs the union holds without collecting the
+//! UUIDs themselves.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use std::convert::TryFrom;
+
+use super::super::{Db, Error, EventFilter, Uuid};
+
+/// The precision `approx_unique_trails` uses: 2^14 registers, for a standard
+/// error of about 0.8%.
+pub const DEFAULT_PRECISION: u8 = 14;
+
+/// A HyperLogLog sketch of a set of UUIDs.
+///
+/// Sketches of the same precision can be merged; the result estimates the
+/// size of the union of their sets. Deserializing checks that the precision
+/// is within 4..=18 and that there are 2^`precision` registers.
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "RawHyperLogLog"))]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+/// A `HyperLogLog` as serialized, before it is checked.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct RawHyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawHyperLogLog> for HyperLogLog {
+    type Error = String;
+
+    fn try_from(raw: RawHyperLogLog) -> Result<Self, Self::Error> {
+        if raw.precision < 4 || raw.precision > 18 {
+            return Err(format!("precision {} is not within 4..=18", raw.precision));
+        }
+        if raw.registers.len() != 1 << raw.precision {
+            return Err(format!("{} registers for precision {}, expected {}",
+                               raw.registers.len(),
+                               raw.precision,
+                               1 << raw.precision));
+        }
+        Ok(HyperLogLog {
+            precision: raw.precision,
+            registers: raw.registers,
+        })
+    }
+}
+
+impl HyperLogLog {
+    /// Create an empty sketch with 2^`precision` registers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `precision` is not within 4..=18.
+    pub fn new(precision: u8) -> Self {
+        assert!(precision >= 4 && precision <= 18, "precision must be within 4..=18");
+        HyperLogLog {
+            precision: precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    pub fn insert(&mut self, uuid: &Uuid) {
+        self.insert_hash(hash_uuid(uuid));
+    }
+
+    /// Add an already hashed element. Hashes must be uniformly distributed
+    /// over all 64 bits.
+    pub fn insert_hash(&mut self, hash: u64) {
+        let p = self.precision as u32;
+        let index = (hash >> (64 - p)) as usize;
+        let rank = ((hash << p) | (1 << (p - 1))).leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// Fold `other` into this sketch. Fails with `Error::InvalidOptionValue`
+    /// if the precisions differ.
+    pub fn merge(&mut self, other: &HyperLogLog) -> Result<(), Error> {
+        if other.precision != self.precision {
+            return Err(Error::InvalidOptionValue);
+        }
+        for (register, &theirs) in self.registers.iter_mut().zip(&other.registers) {
+            if theirs > *register {
+                *register = theirs;
+            }
+        }
+        Ok(())
+    }
+
+    /// The estimated number of distinct elements added.
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            // Linear counting is more accurate for small sets.
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+/// A 64 bit hash of a UUID, stable across processes and platforms so that
+/// sketches built on different machines can be merged.
+pub fn hash_uuid(uuid: &Uuid) -> u64 {
+    let mut hi = [0u8; 8];
+    let mut lo = [0u8; 8];
+    hi.copy_from_slice(&uuid[..8]);
+    lo.copy_from_slice(&uuid[8..]);
+    fmix64(u64::from_le_bytes(lo) ^ fmix64(u64::from_le_bytes(hi)))
+}
+
+/// MurmurHash3's 64 bit finalizer.
+fn fmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^= k >> 33;
+    k
+}
+
+impl<'a> Db<'a> {
+    /// Sketch the UUIDs of the trails with at least one event matching
+    /// `filter`, or of all trails without a filter.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// // Distinct users across a week of daily databases.
+    /// let mut week = None;
+    /// for day in &["mon", "tue", "wed", "thu", "fri", "sat", "sun"] {
+    ///     let db = Db::open(Path::new(day)).unwrap();
+    ///     let sketch = db.trail_sketch(None, 14).unwrap();
+    ///     match week {
+    ///         None => week = Some(sketch),
+    ///         Some(ref mut week) => week.merge(&sketch).unwrap(),
+    ///     }
+    /// }
+    /// println!("~{:.0} users", week.unwrap().estimate());
+    /// ```
+    pub fn trail_sketch(&self,
+                        filter: Option<&EventFilter>,
+                        precision: u8)
+                        -> Result<HyperLogLog, Error> {
+        let mut sketch = HyperLogLog::new(precision);
+        let mut cursor = self.cursor();
+        if let Some(filter) = filter {
+            cursor.set_event_filter(filter)?;
+        }
+        for trail_id in 0..self.num_trails() {
+            if filter.is_some() {
+                cursor.get_trail(trail_id)?;
+                if cursor.next().is_none() {
+                    continue;
+                }
+            }
+            if let Some(uuid) = self.get_uuid(trail_id) {
+                sketch.insert(uuid);
+            }
+        }
+        Ok(sketch)
+    }
+
+    /// Estimate the number of trails with at least one event matching
+    /// `filter`, using a sketch of `DEFAULT_PRECISION`.
+    pub fn approx_unique_trails(&self, filter: Option<&EventFilter>) -> Result<f64, Error> {
+        Ok(self.trail_sketch(filter, DEFAULT_PRECISION)?.estimate())
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_hll {
+    use super::HyperLogLog;
+
+    fn uuid(n: u64) -> [u8; 16] {
+        let mut uuid = [0u8; 16];
+        uuid[..8].copy_from_slice(&n.to_le_bytes());
+        uuid
+    }
+
+    #[test]
+    fn test_estimate() {
+        let mut sketch = HyperLogLog::new(12);
+        for n in 0..100_000 {
+            sketch.insert(&uuid(n));
+            sketch.insert(&uuid(n));
+        }
+        let error = (sketch.estimate() - 100_000.0).abs() / 100_000.0;
+        assert!(error < 0.05, "error {}", error);
+
+        let mut small = HyperLogLog::new(12);
+        for n in 0..10 {
+            small.insert(&uuid(n));
+        }
+        assert!((small.estimate() - 10.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = HyperLogLog::new(10);
+        let mut b = HyperLogLog::new(10);
+        for n in 0..5000 {
+            a.insert(&uuid(n));
+            b.insert(&uuid(n + 2500));
+        }
+        a.merge(&b).unwrap();
+        let error = (a.estimate() - 7500.0).abs() / 7500.0;
+        assert!(error < 0.1, "error {}", error);
+        assert!(a.merge(&HyperLogLog::new(11)).is_err());
+    }
+
+    #[cfg(all(feature = "serde", feature = "json"))]
+    #[test]
+    fn test_deserialize() {
+        let mut sketch = HyperLogLog::new(4);
+        sketch.insert(&uuid(1));
+        let json = ::serde_json::to_string(&sketch).unwrap();
+        assert_eq!(::serde_json::from_str::<HyperLogLog>(&json).unwrap(), sketch);
+
+        for json in &[r#"{"precision":4,"registers":[0,0,0]}"#,
+                      r#"{"precision":30,"registers":[]}"#,
+                      r#"{"precision":2,"registers":[0,0,0,0]}"#] {
+            assert!(::serde_json::from_str::<HyperLogLog>(json).is_err(), "{}", json);
+        }
+    }
+}
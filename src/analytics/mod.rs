@@ -0,0 +1,19 @@
+//! Analyses that run over a whole `Db`.
+
+pub mod aggregate;
+pub mod attribution;
+pub mod compare;
+pub mod cooccurrence;
+mod counts;
+pub mod features;
+pub mod funnel;
+pub mod hll;
+pub mod paths;
+pub mod retention;
+mod sample;
+pub mod session;
+pub mod summary;
+pub mod transitions;
+pub mod window;
+
+pub use self::counts::TimeHistogram;
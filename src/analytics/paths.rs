@@ -0,0 +1,155 @@
+//! Common paths through the values of a field.
+//!
+//! A path is a sequence of `k` consecutive values of a field within one
+//! trail, e.g. the pages a user went through. Counting the most frequent
+//! paths gives the links of a sankey or flow diagram.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::super::{Db, Error, EventFilter, Field, Value};
+
+/// A path counting query, evaluated with `top`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::analytics::paths::Paths;
+/// use std::path::Path;
+///
+/// // The ten most common ways users start a visit.
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let page = db.get_field("page").unwrap();
+/// let top = Paths::new(page, 3).from_start().collapse_repeats().top(&db, 10).unwrap();
+/// for (path, trails) in top {
+///     let pages: Vec<_> = path.iter().map(|&v| db.get_value(page, v).unwrap_or("")).collect();
+///     println!("{}: {}", pages.join(" > "), trails);
+/// }
+/// ```
+pub struct Paths<'f> {
+    field: Field,
+    k: usize,
+    filter: Option<&'f EventFilter>,
+    from_start: bool,
+    collapse_repeats: bool,
+}
+
+impl<'f> Paths<'f> {
+    /// Count sequences of `k` consecutive values of `field`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `k` is 0.
+    pub fn new(field: Field, k: usize) -> Self {
+        assert!(k > 0, "k must be at least 1");
+        Paths {
+            field: field,
+            k: k,
+            filter: None,
+            from_start: false,
+            collapse_repeats: false,
+        }
+    }
+
+    /// Only consider events matching `filter`.
+    pub fn filter(mut self, filter: &'f EventFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Only count the first path of each trail, so every trail counts once.
+    /// By default every path in a trail is counted.
+    pub fn from_start(mut self) -> Self {
+        self.from_start = true;
+        self
+    }
+
+    /// Treat runs of the same value as a single step.
+    pub fn collapse_repeats(mut self) -> Self {
+        self.collapse_repeats = true;
+        self
+    }
+
+    /// Count the paths in `db`, returning every path with its count.
+    ///
+    /// Fails with `Error::UnknownField` if the field is the timestamp or
+    /// doesn't exist in `db`.
+    pub fn count(&self, db: &Db) -> Result<HashMap<Vec<Value>, u64>, Error> {
+        if self.field == 0 || self.field as u64 >= db.num_fields() {
+            return Err(Error::UnknownField);
+        }
+        let index = self.field as usize - 1;
+        let mut counts: HashMap<Vec<Value>, u64> = HashMap::new();
+        let mut cursor = db.cursor();
+        if let Some(filter) = self.filter {
+            cursor.set_event_filter(filter)?;
+        }
+        let mut steps: VecDeque<Value> = VecDeque::with_capacity(self.k);
+        for trail_id in 0..db.num_trails() {
+            cursor.get_trail(trail_id)?;
+            steps.clear();
+            for event in &mut cursor {
+                let value = event.items[index].value();
+                if self.collapse_repeats && steps.back() == Some(&value) {
+                    continue;
+                }
+                if steps.len() == self.k {
+                    steps.pop_front();
+                }
+                steps.push_back(value);
+                if steps.len() == self.k {
+                    *counts.entry(steps.iter().cloned().collect()).or_insert(0) += 1;
+                    if self.from_start {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(counts)
+    }
+
+    /// The `n` most frequent paths in `db` with their counts, most frequent
+    /// first.
+    pub fn top(&self, db: &Db, n: usize) -> Result<Vec<(Vec<Value>, u64)>, Error> {
+        let mut paths: Vec<(Vec<Value>, u64)> = self.count(db)?.into_iter().collect();
+        paths.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        paths.truncate(n);
+        Ok(paths)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_paths {
+    use super::Paths;
+    use super::super::super::{Constructor, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_paths() {
+        let db_path = Path::new("test_paths");
+        let mut cons = Constructor::new(db_path, &["page"]).unwrap();
+        for (ts, page) in ["/", "/a", "/a", "/b"].iter().enumerate() {
+            assert!(cons.add(&[1u8; 16], ts as u64, &[page]).is_ok());
+        }
+        for (ts, page) in ["/", "/a", "/b", "/"].iter().enumerate() {
+            assert!(cons.add(&[2u8; 16], ts as u64, &[page]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let page = db.get_field("page").unwrap();
+        let v = |p| db.get_item(page, p).unwrap().value();
+
+        let all = Paths::new(page, 2).count(&db).unwrap();
+        assert_eq!(all[&vec![v("/"), v("/a")]], 2);
+        assert_eq!(all[&vec![v("/a"), v("/a")]], 1);
+        assert_eq!(all.values().sum::<u64>(), 6);
+
+        let top = Paths::new(page, 3).from_start().collapse_repeats().top(&db, 5).unwrap();
+        assert_eq!(top, vec![(vec![v("/"), v("/a"), v("/b")], 2)]);
+        assert!(Paths::new(0, 2).count(&db).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! Retention and cohort analysis.
+//!
+//! Time is cut into buckets of a fixed width, e.g. weeks. A trail belongs
+//! to the cohort of the bucket holding its first event, and is retained in
+//! every later bucket in which it is active again.
+
+use super::super::{Db, Error, EventFilter, Timestamp};
+
+/// A cohort matrix, as computed by `retention`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Retention {
+    /// The start of the first bucket.
+    pub origin: Timestamp,
+    /// The width of a bucket.
+    pub bucket: Timestamp,
+    /// `cohorts[n][k]` is the number of trails first seen in bucket `n`
+    /// that were active in bucket `n + k`; `cohorts[n][0]` is the size of
+    /// cohort `n`.
+    pub cohorts: Vec<Vec<u64>>,
+}
+
+impl Retention {
+    /// The start of bucket `n`.
+    pub fn bucket_start(&self, n: usize) -> Timestamp {
+        self.origin + n as Timestamp * self.bucket
+    }
+
+    /// The share of cohort `n` active `k` buckets after its first one.
+    pub fn rate(&self, n: usize, k: usize) -> f64 {
+        match self.cohorts.get(n) {
+            Some(row) if row[0] > 0 => row.get(k).cloned().unwrap_or(0) as f64 / row[0] as f64,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Compute the cohort matrix of `db` for buckets of width `bucket`. Only
+/// events matching `activity` count as returns; without a filter every
+/// event does. Cohorts are always those of the trails' first events,
+/// whether they match `activity` or not.
+///
+/// # Panics
+///
+/// Panics if `bucket` is 0.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::analytics::retention::retention;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let weekly = retention(&db, 7 * 86400, None).unwrap();
+/// for (n, row) in weekly.cohorts.iter().enumerate() {
+///     let rates: Vec<String> = (0..row.len())
+///         .map(|k| format!("{:.0}%", 100.0 * weekly.rate(n, k)))
+///         .collect();
+///     println!("week of {}: {}", weekly.bucket_start(n), rates.join(" "));
+/// }
+/// ```
+pub fn retention(db: &Db,
+                 bucket: Timestamp,
+                 activity: Option<&EventFilter>)
+                 -> Result<Retention, Error> {
+    assert!(bucket > 0, "bucket must be at least 1");
+    let first = db.min_timestamp() / bucket;
+    let num_buckets = if db.num_events() == 0 {
+        0
+    } else {
+        (db.max_timestamp() / bucket - first + 1) as usize
+    };
+    let mut cohorts: Vec<Vec<u64>> = (0..num_buckets).map(|n| vec![0; num_buckets - n]).collect();
+
+    let mut cursor = db.cursor();
+    for trail_id in 0..db.num_trails() {
+        cursor.get_trail(trail_id)?;
+        let mut cohort: Option<usize> = None;
+        let mut last: Option<usize> = None;
+        for event in &mut cursor {
+            let n = (event.timestamp / bucket - first) as usize;
+            let start = match cohort {
+                Some(start) => start,
+                None => {
+                    cohorts[n][0] += 1;
+                    cohort = Some(n);
+                    last = Some(n);
+                    continue;
+                }
+            };
+            if last == Some(n) || activity.is_some_and(|filter| !filter.matches(&event)) {
+                continue;
+            }
+            cohorts[start][n - start] += 1;
+            last = Some(n);
+        }
+    }
+    Ok(Retention {
+        origin: first * bucket,
+        bucket: bucket,
+        cohorts: cohorts,
+    })
+}
+
+
+
+
+#[cfg(test)]
+mod test_retention {
+    use super::retention;
+    use super::super::super::{Constructor, Db, EventFilter};
+    use std::path::Path;
+
+    #[test]
+    fn test_retention() {
+        let db_path = Path::new("test_retention");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 10, &["a"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 12, &["a"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 25, &["a"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 15, &["a"]).is_ok());
+        assert!(cons.add(&[3u8; 16], 21, &["a"]).is_ok());
+        assert!(cons.add(&[3u8; 16], 31, &["a"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let report = retention(&db, 10, None).unwrap();
+        assert_eq!(report.origin, 10);
+        assert_eq!(report.cohorts, vec![vec![2, 1, 0], vec![1, 1], vec![0]]);
+        assert_eq!(report.rate(0, 1), 0.5);
+        assert_eq!(report.bucket_start(2), 30);
+    }
+
+    #[test]
+    fn test_retention_activity() {
+        let db_path = Path::new("test_retention_activity");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        // Signs up, then buys two buckets later.
+        assert!(cons.add(&[1u8; 16], 10, &["signup"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 15, &["buy"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 31, &["buy"]).is_ok());
+        // Signs up and only browses afterwards.
+        assert!(cons.add(&[2u8; 16], 12, &["signup"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 22, &["view"]).is_ok());
+        assert!(cons.add(&[3u8; 16], 21, &["buy"]).is_ok());
+        assert!(cons.add(&[3u8; 16], 25, &["buy"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let action = db.get_field("action").unwrap();
+        let mut buy = EventFilter::new();
+        buy.add_term(db.get_item(action, "buy").unwrap(), false).unwrap();
+        let report = retention(&db, 10, Some(&buy)).unwrap();
+        // Trails 1 and 2 stay in the cohort of their signups, though those
+        // aren't purchases; the purchase in their first bucket isn't a
+        // return.
+        assert_eq!(report.cohorts, vec![vec![2, 0, 1], vec![1, 0], vec![0]]);
+        assert_eq!(report.rate(0, 2), 0.5);
+    }
+}
@@ -0,0 +1,142 @@
+//! Reproducible trail sampling.
+
+use super::super::{Db, Error, TrailId};
+
+/// SplitMix64, a small generator with a fixed algorithm so that a seed
+/// picks the same sample on every platform and release.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        // The modulo bias is below 2^-32 for any realistic trail count.
+        self.next() % n
+    }
+}
+
+/// Reservoir sampling of `n` items out of `items`.
+fn reservoir<I: Iterator<Item = TrailId>>(items: I, n: usize, rng: &mut SplitMix64) -> Vec<TrailId> {
+    // `n` can be anything, so reserve no more than there are items.
+    let mut sample = Vec::with_capacity(n.min(items.size_hint().0));
+    for (seen, item) in items.enumerate() {
+        if sample.len() < n {
+            sample.push(item);
+        } else {
+            let slot = rng.below(seen as u64 + 1) as usize;
+            if slot < n {
+                sample[slot] = item;
+            }
+        }
+    }
+    sample
+}
+
+impl<'a> Db<'a> {
+    /// A uniform random sample of `n` trail ids, or all of them if the
+    /// database has fewer trails. The same seed always gives the same
+    /// sample of the same database. Ids are returned in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// for trail_id in db.sample_trails(1000, 42) {
+    ///     let uuid = db.get_uuid(trail_id).unwrap();
+    ///     // ...
+    /// }
+    /// ```
+    pub fn sample_trails(&self, n: usize, seed: u64) -> Vec<TrailId> {
+        let mut sample = reservoir(0..self.num_trails(), n, &mut SplitMix64(seed));
+        sample.sort();
+        sample
+    }
+
+    /// A random sample of about `n` trail ids stratified by the time of
+    /// each trail's first event: the database's time range is cut into
+    /// `strata` equal parts and each part contributes in proportion to the
+    /// number of trails starting in it, so new and old trails are both
+    /// represented. Ids are returned in ascending order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `strata` is 0.
+    pub fn sample_trails_stratified(&self,
+                                    n: usize,
+                                    seed: u64,
+                                    strata: usize)
+                                    -> Result<Vec<TrailId>, Error> {
+        assert!(strata > 0, "strata must be at least 1");
+        let min = self.min_timestamp();
+        let span = self.max_timestamp() - min + 1;
+        let mut members: Vec<Vec<TrailId>> = vec![Vec::new(); strata];
+        let mut cursor = self.cursor();
+        for trail_id in 0..self.num_trails() {
+            cursor.get_trail(trail_id)?;
+            if let Some(event) = cursor.next() {
+                let stratum = ((event.timestamp - min) as u128 * strata as u128 / span as u128) as usize;
+                members[stratum.min(strata - 1)].push(trail_id);
+            }
+        }
+
+        let total: usize = members.iter().map(|m| m.len()).sum();
+        let mut rng = SplitMix64(seed);
+        let mut sample = Vec::with_capacity(n.min(total));
+        for stratum in &members {
+            let share = if total == 0 {
+                0
+            } else {
+                ((stratum.len() as u128 * n as u128 + total as u128 / 2) / total as u128) as usize
+            };
+            sample.extend(reservoir(stratum.iter().cloned(), share, &mut rng));
+        }
+        sample.sort();
+        Ok(sample)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_sample {
+    use super::super::super::{Constructor, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_sample_trails() {
+        let db_path = Path::new("test_sample_trails");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        for n in 0..100u8 {
+            assert!(cons.add(&[n; 16], n as u64, &["a"]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let sample = db.sample_trails(10, 7);
+        assert_eq!(sample.len(), 10);
+        assert_eq!(sample, db.sample_trails(10, 7));
+        assert_ne!(sample, db.sample_trails(10, 8));
+        assert!(sample.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(db.sample_trails(1000, 7).len(), 100);
+        assert_eq!(db.sample_trails(usize::MAX, 7).len(), 100);
+        assert_eq!(db.sample_trails_stratified(usize::MAX, 7, 2).unwrap().len(), 100);
+
+        let stratified = db.sample_trails_stratified(10, 7, 2).unwrap();
+        assert_eq!(stratified.len(), 10);
+        let early = stratified.iter()
+            .filter(|&&id| db.get_uuid(id).unwrap()[0] < 50)
+            .count();
+        assert_eq!(early, 5);
+    }
+}
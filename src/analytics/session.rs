@@ -0,0 +1,193 @@
+//! Sessionization.
+//!
+//! A session is a run of events of one trail where no two consecutive
+//! events are more than the session gap apart.
+
+use std::path::Path;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::super::{ConstructorBuilder, Db, Error, Field, Timestamp, TrailId, Uuid};
+
+/// One session of a trail.
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Session {
+    pub trail_id: TrailId,
+    pub uuid: Uuid,
+    /// The timestamp of the first event.
+    pub start: Timestamp,
+    /// The timestamp of the last event.
+    pub end: Timestamp,
+    /// The number of events in the session.
+    pub events: u64,
+    /// The value of the `Sessionizer`'s field in the first event, if it has
+    /// one.
+    pub entry: Option<String>,
+    /// The value of the `Sessionizer`'s field in the last event, if it has
+    /// one.
+    pub exit: Option<String>,
+}
+
+/// Splits every trail of a database into sessions.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::analytics::session::Sessionizer;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let page = db.get_field("page").unwrap();
+/// // Sessions end after 30 minutes of inactivity.
+/// let sessionizer = Sessionizer::new(1800).entry_exit(page);
+/// sessionizer.for_each(&db, |session| {
+///         println!("{} events, landed on {:?}", session.events, session.entry);
+///     })
+///     .unwrap();
+/// ```
+#[derive(Debug,Clone)]
+pub struct Sessionizer {
+    gap: Timestamp,
+    field: Option<Field>,
+}
+
+impl Sessionizer {
+    /// Start a new session whenever more than `gap` passes between two
+    /// events.
+    pub fn new(gap: Timestamp) -> Self {
+        Sessionizer {
+            gap: gap,
+            field: None,
+        }
+    }
+
+    /// Record the values of `field` in the first and last event of every
+    /// session.
+    pub fn entry_exit(mut self, field: Field) -> Self {
+        self.field = Some(field);
+        self
+    }
+
+    /// Call `f` with every session in `db`, in trail order.
+    pub fn for_each<F>(&self, db: &Db, mut f: F) -> Result<(), Error>
+        where F: FnMut(&Session)
+    {
+        let value = |items: &[_]| -> Option<String> {
+            let field = self.field?;
+            if field == 0 {
+                return None;
+            }
+            items.get(field as usize - 1).map(|item| db.get_item_value(*item).to_string())
+        };
+        let mut cursor = db.cursor();
+        for trail_id in 0..db.num_trails() {
+            let uuid = *db.get_uuid(trail_id).ok_or(Error::InvalidTrailId)?;
+            cursor.get_trail(trail_id)?;
+            let mut current: Option<Session> = None;
+            for event in &mut cursor {
+                if let Some(ref mut session) = current {
+                    if event.timestamp - session.end <= self.gap {
+                        session.end = event.timestamp;
+                        session.events += 1;
+                        session.exit = value(event.items);
+                        continue;
+                    }
+                    f(session);
+                }
+                current = Some(Session {
+                    trail_id: trail_id,
+                    uuid: uuid,
+                    start: event.timestamp,
+                    end: event.timestamp,
+                    events: 1,
+                    entry: value(event.items),
+                    exit: value(event.items),
+                });
+            }
+            if let Some(ref session) = current {
+                f(session);
+            }
+        }
+        Ok(())
+    }
+
+    /// Collect every session in `db`.
+    pub fn sessions(&self, db: &Db) -> Result<Vec<Session>, Error> {
+        let mut sessions = Vec::new();
+        self.for_each(db, |session| sessions.push(session.clone()))?;
+        Ok(sessions)
+    }
+
+    /// Write the sessions in `db` to a new database at `dst_path`, returning
+    /// the number of sessions written.
+    ///
+    /// Every session becomes an event of its trail, timestamped with its
+    /// start, with fields `end` and `events` and, if an entry/exit field is
+    /// set, `entry` and `exit`. The result can be queried, or exported with
+    /// any exporter in `export`, like any other database.
+    pub fn write_db(&self, db: &Db, dst_path: &Path) -> Result<u64, Error> {
+        let fields: &[&str] = match self.field {
+            Some(_) => &["end", "events", "entry", "exit"],
+            None => &["end", "events"],
+        };
+        let mut cons = ConstructorBuilder::new(dst_path, fields)
+            .expected_trails(db.num_trails() as usize)
+            .build()?;
+        let mut count = 0;
+        let mut result = Ok(());
+        self.for_each(db, |session| {
+            if result.is_err() {
+                return;
+            }
+            let end = session.end.to_string();
+            let events = session.events.to_string();
+            let entry = session.entry.as_ref().map_or("", |v| v.as_str());
+            let exit = session.exit.as_ref().map_or("", |v| v.as_str());
+            let values = [end.as_str(), events.as_str(), entry, exit];
+            result = cons.add(&session.uuid, session.start, &values[..fields.len()]);
+            count += 1;
+        })?;
+        result?;
+        cons.finalize()?;
+        Ok(count)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_session {
+    use super::Sessionizer;
+    use super::super::super::{Constructor, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_sessionizer() {
+        let db_path = Path::new("test_sessionizer");
+        let mut cons = Constructor::new(db_path, &["page"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 0, &["home"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 10, &["search"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 100, &["cart"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 5, &["home"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let page = db.get_field("page").unwrap();
+        let sessions = Sessionizer::new(30).entry_exit(page).sessions(&db).unwrap();
+        assert_eq!(sessions.len(), 3);
+        let first = sessions.iter().find(|s| s.uuid == [1u8; 16] && s.start == 0).unwrap();
+        assert_eq!((first.end, first.events), (10, 2));
+        assert_eq!(first.entry, Some("home".to_string()));
+        assert_eq!(first.exit, Some("search".to_string()));
+
+        let dst_path = Path::new("test_sessionizer_dst");
+        assert_eq!(Sessionizer::new(30).write_db(&db, dst_path).unwrap(), 3);
+        let dst = Db::open(dst_path).unwrap();
+        assert_eq!(dst.field_names(), vec!["end", "events"]);
+        assert_eq!(dst.num_events(), 3);
+    }
+}
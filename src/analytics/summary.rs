@@ -0,0 +1,164 @@
+//! Per-trail summaries.
+
+use std::collections::BTreeSet;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::super::{Cursor, Db, Error, Field, Timestamp, TrailId, Uuid, Value};
+
+/// A summary of one trail, as yielded by `Db::trail_summaries`.
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TrailSummary {
+    pub trail_id: TrailId,
+    pub uuid: Uuid,
+    /// The number of events.
+    pub events: u64,
+    /// The timestamp of the first event.
+    pub first_timestamp: Timestamp,
+    /// The timestamp of the last event.
+    pub last_timestamp: Timestamp,
+    /// For every field passed to `trail_summaries`, in order, the distinct
+    /// non-empty values the trail holds, ordered by value id.
+    pub distinct: Vec<Vec<String>>,
+}
+
+impl TrailSummary {
+    /// The time between the first and last event.
+    pub fn duration(&self) -> Timestamp {
+        self.last_timestamp - self.first_timestamp
+    }
+}
+
+/// An iterator over the summaries of every trail in a `Db`, created by
+/// `Db::trail_summaries`.
+pub struct TrailSummaries<'a> {
+    db: &'a Db<'a>,
+    cursor: Cursor<'a>,
+    fields: Vec<Field>,
+    next: TrailId,
+    seen: Vec<BTreeSet<Value>>,
+}
+
+impl<'a> Db<'a> {
+    /// Summarize every trail in one pass, collecting the distinct values of
+    /// `fields` along the way.
+    ///
+    /// Fails with `Error::UnknownField` if a field is the timestamp or
+    /// doesn't exist in the database.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::{uuid_hex, Db};
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// let country = db.get_field("country").unwrap();
+    /// for summary in db.trail_summaries(&[country]).unwrap() {
+    ///     println!("{} {} events over {}s, from {:?}",
+    ///              uuid_hex(&summary.uuid),
+    ///              summary.events,
+    ///              summary.duration(),
+    ///              summary.distinct[0]);
+    /// }
+    /// ```
+    pub fn trail_summaries(&'a self, fields: &[Field]) -> Result<TrailSummaries<'a>, Error> {
+        if fields.iter().any(|&f| f == 0 || f as u64 >= self.num_fields()) {
+            return Err(Error::UnknownField);
+        }
+        Ok(TrailSummaries {
+            db: self,
+            cursor: self.cursor(),
+            fields: fields.to_vec(),
+            next: 0,
+            seen: vec![BTreeSet::new(); fields.len()],
+        })
+    }
+}
+
+impl<'a> Iterator for TrailSummaries<'a> {
+    type Item = TrailSummary;
+
+    fn next(&mut self) -> Option<TrailSummary> {
+        let trail_id = self.next;
+        if trail_id >= self.db.num_trails() {
+            return None;
+        }
+        let uuid = *self.db.get_uuid(trail_id)?;
+        self.cursor.get_trail(trail_id).ok()?;
+        self.next += 1;
+
+        for seen in &mut self.seen {
+            seen.clear();
+        }
+        let mut summary = TrailSummary {
+            trail_id: trail_id,
+            uuid: uuid,
+            events: 0,
+            first_timestamp: 0,
+            last_timestamp: 0,
+            distinct: Vec::with_capacity(self.fields.len()),
+        };
+        for event in &mut self.cursor {
+            if summary.events == 0 {
+                summary.first_timestamp = event.timestamp;
+            }
+            summary.last_timestamp = event.timestamp;
+            summary.events += 1;
+            for (&field, seen) in self.fields.iter().zip(&mut self.seen) {
+                let value = event.items[field as usize - 1].value();
+                if value != 0 {
+                    seen.insert(value);
+                }
+            }
+        }
+        for (&field, seen) in self.fields.iter().zip(&self.seen) {
+            summary.distinct.push(seen.iter()
+                .filter_map(|&value| self.db.get_value(field, value))
+                .map(|value| value.to_string())
+                .collect());
+        }
+        Some(summary)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.db.num_trails().saturating_sub(self.next) as usize;
+        (left, Some(left))
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_summary {
+    use super::super::super::{Constructor, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_trail_summaries() {
+        let db_path = Path::new("test_trail_summaries");
+        let mut cons = Constructor::new(db_path, &["country", "action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 10, &["de", "a"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 25, &["fr", "b"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 30, &["de", ""]).is_ok());
+        assert!(cons.add(&[2u8; 16], 5, &["", "a"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let country = db.get_field("country").unwrap();
+        let summaries: Vec<_> = db.trail_summaries(&[country]).unwrap().collect();
+        assert_eq!(summaries.len(), 2);
+        let first = summaries.iter().find(|s| s.uuid == [1u8; 16]).unwrap();
+        assert_eq!(first.events, 3);
+        assert_eq!(first.duration(), 20);
+        let mut countries = first.distinct[0].clone();
+        countries.sort();
+        assert_eq!(countries, vec!["de", "fr"]);
+        let second = summaries.iter().find(|s| s.uuid == [2u8; 16]).unwrap();
+        assert!(second.distinct[0].is_empty());
+        assert!(db.trail_summaries(&[0]).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Transitions between consecutive values of a field.
+//!
+//! Within each trail, every pair of consecutive events contributes one
+//! transition from the first event's value to the second's, including
+//! transitions from a value to itself. With a filter, only matching events
+//! are considered, so the events in between are skipped over.
+
+use std::collections::HashMap;
+
+use super::super::{Db, Error, EventFilter, Field, Value};
+
+/// A sparse transition matrix over value ids, as computed by `transitions`.
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct Transitions {
+    /// The number of transitions for every `(from, to)` pair seen.
+    pub counts: HashMap<(Value, Value), u64>,
+    /// The number of transitions leaving each value.
+    pub totals: HashMap<Value, u64>,
+}
+
+impl Transitions {
+    /// The number of transitions from `from` to `to`.
+    pub fn count(&self, from: Value, to: Value) -> u64 {
+        self.counts.get(&(from, to)).cloned().unwrap_or(0)
+    }
+
+    /// The share of transitions leaving `from` that go to `to`.
+    pub fn probability(&self, from: Value, to: Value) -> f64 {
+        match self.totals.get(&from) {
+            Some(&total) if total > 0 => self.count(from, to) as f64 / total as f64,
+            _ => 0.0,
+        }
+    }
+
+    /// The values following `from` with their counts, most frequent first.
+    pub fn successors(&self, from: Value) -> Vec<(Value, u64)> {
+        let mut successors: Vec<(Value, u64)> = self.counts
+            .iter()
+            .filter(|&(&(f, _), _)| f == from)
+            .map(|(&(_, to), &count)| (to, count))
+            .collect();
+        successors.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        successors
+    }
+}
+
+/// Count the transitions between consecutive values of `field` over every
+/// trail of `db`, optionally only between events matching `filter`.
+///
+/// Fails with `Error::UnknownField` if `field` is the timestamp or doesn't
+/// exist in `db`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::analytics::transitions::transitions;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let page = db.get_field("page").unwrap();
+/// let matrix = transitions(&db, page, None).unwrap();
+/// let home = db.get_item(page, "/").unwrap().value();
+/// for (to, count) in matrix.successors(home).into_iter().take(5) {
+///     println!("/ -> {:?}: {} ({:.1}%)",
+///              db.get_value(page, to),
+///              count,
+///              100.0 * matrix.probability(home, to));
+/// }
+/// ```
+pub fn transitions(db: &Db, field: Field, filter: Option<&EventFilter>) -> Result<Transitions, Error> {
+    if field == 0 || field as u64 >= db.num_fields() {
+        return Err(Error::UnknownField);
+    }
+    let index = field as usize - 1;
+    let mut matrix = Transitions::default();
+    let mut cursor = db.cursor();
+    if let Some(filter) = filter {
+        cursor.set_event_filter(filter)?;
+    }
+    for trail_id in 0..db.num_trails() {
+        cursor.get_trail(trail_id)?;
+        let mut prev: Option<Value> = None;
+        for event in &mut cursor {
+            let value = event.items[index].value();
+            if let Some(prev) = prev {
+                *matrix.counts.entry((prev, value)).or_insert(0) += 1;
+                *matrix.totals.entry(prev).or_insert(0) += 1;
+            }
+            prev = Some(value);
+        }
+    }
+    Ok(matrix)
+}
+
+
+
+
+#[cfg(test)]
+mod test_transitions {
+    use super::transitions;
+    use super::super::super::{Constructor, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_transitions() {
+        let db_path = Path::new("test_transitions");
+        let mut cons = Constructor::new(db_path, &["page"]).unwrap();
+        for (ts, page) in ["/", "/a", "/", "/b"].iter().enumerate() {
+            assert!(cons.add(&[1u8; 16], ts as u64, &[page]).is_ok());
+        }
+        for (ts, page) in ["/", "/a", "/a"].iter().enumerate() {
+            assert!(cons.add(&[2u8; 16], ts as u64, &[page]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let page = db.get_field("page").unwrap();
+        let value = |v| db.get_item(page, v).unwrap().value();
+        let matrix = transitions(&db, page, None).unwrap();
+        assert_eq!(matrix.count(value("/"), value("/a")), 2);
+        assert_eq!(matrix.count(value("/a"), value("/a")), 1);
+        assert_eq!(matrix.count(value("/b"), value("/")), 0);
+        assert!((matrix.probability(value("/"), value("/b")) - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(matrix.successors(value("/"))[0], (value("/a"), 2));
+        assert!(transitions(&db, 0, None).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! Time windows over a single trail.
+
+use std::collections::VecDeque;
+
+use super::super::{Error, EventBuf, EventFilter, Timestamp, Trail};
+
+/// The events of one tumbling window, as yielded by `Trail::windows`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Window {
+    /// The start of the window, a multiple of its width.
+    pub start: Timestamp,
+    pub events: Vec<EventBuf>,
+}
+
+/// An iterator over the non-empty tumbling windows of a trail, created by
+/// `Trail::windows`.
+pub struct TrailWindows<'a> {
+    trail: Trail<'a>,
+    width: Timestamp,
+    pending: Option<EventBuf>,
+}
+
+impl<'a> Trail<'a> {
+    /// Group the remaining events of the trail into windows of `width`,
+    /// starting at multiples of `width`. Windows without events are
+    /// skipped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `width` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// // Events per hour, for one user.
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// let trail = db.get_trail(0).unwrap();
+    /// for window in trail.windows(3600) {
+    ///     println!("{}: {}", window.start, window.events.len());
+    /// }
+    /// ```
+    pub fn windows(mut self, width: Timestamp) -> TrailWindows<'a> {
+        assert!(width > 0, "width must be at least 1");
+        let pending = self.next().map(|event| event.to_event_buf());
+        TrailWindows {
+            trail: self,
+            width: width,
+            pending: pending,
+        }
+    }
+
+    /// For every event of the trail matching `filter`, or every event
+    /// without one, its timestamp and the number of matching events in the
+    /// `window` ending at it: those at most `window - 1` earlier, up to and
+    /// including the event itself.
+    ///
+    /// The whole trail is scanned, whatever events were already consumed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::{Db, EventFilter};
+    /// use std::path::Path;
+    ///
+    /// // Did the user ever log in more than 5 times in one hour?
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// let action = db.get_field("action").unwrap();
+    /// let mut login = EventFilter::new();
+    /// login.add_term(db.get_item(action, "login").unwrap(), false).unwrap();
+    /// let trail = db.get_trail(0).unwrap();
+    /// let counts = trail.rolling_count(Some(&login), 3600).unwrap();
+    /// println!("{}", counts.iter().any(|&(_, n)| n > 5));
+    /// ```
+    pub fn rolling_count(mut self,
+                         filter: Option<&'a EventFilter>,
+                         window: Timestamp)
+                         -> Result<Vec<(Timestamp, u64)>, Error> {
+        match filter {
+            Some(filter) => self.cursor.set_event_filter(filter)?,
+            None => self.cursor.unset_event_filter(),
+        }
+        self.cursor.get_trail(self.id)?;
+        let mut open: VecDeque<Timestamp> = VecDeque::new();
+        let mut counts = Vec::new();
+        for event in self {
+            open.push_back(event.timestamp);
+            while open.front().map_or(false, |&t| t.saturating_add(window) <= event.timestamp) {
+                open.pop_front();
+            }
+            counts.push((event.timestamp, open.len() as u64));
+        }
+        Ok(counts)
+    }
+}
+
+impl<'a> Iterator for TrailWindows<'a> {
+    type Item = Window;
+
+    fn next(&mut self) -> Option<Window> {
+        let first = self.pending.take()?;
+        let start = first.timestamp - first.timestamp % self.width;
+        let mut window = Window {
+            start: start,
+            events: vec![first],
+        };
+        for event in &mut self.trail {
+            if event.timestamp - start >= self.width {
+                self.pending = Some(event.to_event_buf());
+                break;
+            }
+            window.events.push(event.to_event_buf());
+        }
+        Some(window)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_window {
+    use super::super::super::{Constructor, Db, EventFilter};
+    use std::path::Path;
+
+    #[test]
+    fn test_windows() {
+        let db_path = Path::new("test_windows");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        for &(ts, action) in &[(5u64, "a"), (8, "b"), (12, "a"), (31, "a"), (33, "a")] {
+            assert!(cons.add(&[1u8; 16], ts, &[action]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let windows: Vec<_> = db.get_trail(0).unwrap().windows(10).collect();
+        let sizes: Vec<_> = windows.iter().map(|w| (w.start, w.events.len())).collect();
+        assert_eq!(sizes, vec![(0, 2), (10, 1), (30, 2)]);
+
+        let counts = db.get_trail(0).unwrap().rolling_count(None, 10).unwrap();
+        let counts: Vec<_> = counts.iter().map(|&(_, n)| n).collect();
+        assert_eq!(counts, vec![1, 2, 2, 1, 2]);
+
+        let action = db.get_field("action").unwrap();
+        let mut filter = EventFilter::new();
+        filter.add_term(db.get_item(action, "a").unwrap(), false).unwrap();
+        let counts = db.get_trail(0).unwrap().rolling_count(Some(&filter), 10).unwrap();
+        assert_eq!(counts, vec![(5, 1), (12, 2), (31, 1), (33, 2)]);
+    }
+}
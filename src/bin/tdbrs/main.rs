@@ -0,0 +1,726 @@
+//! `tdbrs`, a command line tool for inspecting TrailDB databases.
+
+extern crate clap;
+extern crate indicatif;
+extern crate rustyline;
+extern crate signal_hook;
+extern crate traildb;
+
+mod shell;
+
+use std::error::Error;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use indicatif::{ProgressBar, ProgressStyle};
+use traildb::diff::diff_dbs;
+use traildb::export::csv::CsvExporter;
+use traildb::export::jsonl::JsonlEncoder;
+use traildb::export::{export_to, ExportOptions};
+use traildb::import::ColumnMapping;
+use traildb::query::parse_filter;
+use traildb::spool::SpoolIngest;
+use traildb::time::{format_date, format_rfc3339, parse_rfc3339, TimeUnit};
+use traildb::{merge_with_progress, migrate, prune, repair, uuid_hex, uuid_raw, Db, Field,
+              Timestamp, TrailId, Uuid, ValidationLevel};
+
+#[derive(Parser)]
+#[command(name = "tdbrs", version, about = "Inspect TrailDB databases")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print a database's metadata
+    Info {
+        path: PathBuf,
+    },
+    /// Write a database's events to stdout
+    Dump {
+        path: PathBuf,
+        /// Only dump the trail with this UUID, in hex; may be repeated
+        #[arg(long)]
+        uuid: Vec<String>,
+        #[arg(long, value_enum, default_value = "jsonl")]
+        format: DumpFormat,
+    },
+    /// Merge databases with the same fields into a new one
+    Merge {
+        /// The database to create
+        dst: PathBuf,
+        /// The databases to merge
+        #[arg(required = true)]
+        srcs: Vec<PathBuf>,
+    },
+    /// Copy the events matching a query and time range into a new database
+    Extract {
+        src: PathBuf,
+        /// The database to create
+        dst: PathBuf,
+        /// Only copy events matching this filter, e.g. 'action="purchase"'
+        #[arg(long)]
+        query: Option<String>,
+        /// Only copy events at or after this time, given as an RFC 3339 date
+        /// or timestamp, or as a raw timestamp
+        #[arg(long)]
+        from: Option<String>,
+        /// Only copy events before this time
+        #[arg(long)]
+        to: Option<String>,
+        /// The unit of the database's timestamps
+        #[arg(long, value_enum, default_value = "seconds")]
+        unit: Unit,
+    },
+    /// Explore a database interactively
+    Shell {
+        path: PathBuf,
+    },
+    /// Check a database's structure, exiting with an error if problems are
+    /// found
+    Check {
+        path: PathBuf,
+        #[arg(long, value_enum, default_value = "full")]
+        level: CheckLevel,
+    },
+    /// Copy the trails of a damaged database that decode cleanly into a new
+    /// one
+    Repair {
+        src: PathBuf,
+        /// The database to create
+        dst: PathBuf,
+    },
+    /// Compare the fields, trails and events of two databases, exiting with
+    /// an error if they differ
+    Diff {
+        a: PathBuf,
+        b: PathBuf,
+        /// List at most this many UUIDs per kind of difference
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Print field cardinalities, top values and event histograms
+    Stats {
+        path: PathBuf,
+        /// The number of top values to print per field
+        #[arg(long, default_value_t = 5)]
+        top: usize,
+        /// The most buckets to split the time range into
+        #[arg(long, default_value_t = 24)]
+        buckets: u64,
+        /// The unit of the database's timestamps
+        #[arg(long, value_enum, default_value = "seconds")]
+        unit: Unit,
+    },
+    /// Rewrite a database written in an older format into the current one
+    Migrate {
+        src: PathBuf,
+        /// The database to create
+        dst: PathBuf,
+    },
+    /// Copy a database without the events older than a cutoff into a new one
+    Prune {
+        src: PathBuf,
+        /// The database to create
+        dst: PathBuf,
+        /// Drop events before this time, given as an RFC 3339 date or
+        /// timestamp, or as a raw timestamp
+        #[arg(long)]
+        older_than: String,
+        /// The unit of the database's timestamps
+        #[arg(long, value_enum, default_value = "seconds")]
+        unit: Unit,
+    },
+    /// Build a database from the files appearing in a spool directory every
+    /// roll interval, until interrupted
+    Ingest {
+        /// The directory to write databases to
+        dst: PathBuf,
+        /// The spool directory to read .jsonl and .csv files from
+        #[arg(long)]
+        watch: PathBuf,
+        /// How long each database collects events, e.g. 30m or 1h
+        #[arg(long, default_value = "1h")]
+        roll: String,
+        /// The fields of the databases, separated by commas
+        #[arg(long, required = true, value_delimiter = ',')]
+        fields: Vec<String>,
+        /// The column holding the UUID
+        #[arg(long, default_value = "uuid")]
+        uuid: String,
+        /// The column holding the timestamp
+        #[arg(long, default_value = "timestamp")]
+        timestamp: String,
+        /// A manifest file to list every published database in
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone,Copy,ValueEnum)]
+enum DumpFormat {
+    Jsonl,
+    Csv,
+}
+
+#[derive(Clone,Copy,ValueEnum)]
+enum Unit {
+    Seconds,
+    Millis,
+    Micros,
+}
+
+impl Unit {
+    fn time_unit(self) -> TimeUnit {
+        match self {
+            Unit::Seconds => TimeUnit::Seconds,
+            Unit::Millis => TimeUnit::Milliseconds,
+            Unit::Micros => TimeUnit::Microseconds,
+        }
+    }
+}
+
+#[derive(Clone,Copy,ValueEnum)]
+enum CheckLevel {
+    Metadata,
+    Lexicons,
+    Full,
+}
+
+impl CheckLevel {
+    fn validation_level(self) -> ValidationLevel {
+        match self {
+            CheckLevel::Metadata => ValidationLevel::Metadata,
+            CheckLevel::Lexicons => ValidationLevel::Lexicons,
+            CheckLevel::Full => ValidationLevel::Full,
+        }
+    }
+}
+
+/// Bucket widths for time histograms, in seconds, from finest to coarsest.
+const TIME_BUCKETS: &[u64] = &[1, 60, 3600, 86_400, 7 * 86_400, 30 * 86_400, 365 * 86_400];
+
+/// The width of a full histogram bar.
+const BAR_WIDTH: u64 = 40;
+
+type CliResult = Result<(), Box<dyn Error>>;
+
+fn main() {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Info { path } => info(&path),
+        Command::Dump { path, uuid, format } => dump(&path, &uuid, format),
+        Command::Merge { dst, srcs } => merge(&dst, &srcs),
+        Command::Extract { src, dst, query, from, to, unit } => {
+            extract(&src, &dst, query.as_deref(), from.as_deref(), to.as_deref(), unit)
+        }
+        Command::Shell { path } => open(&path).and_then(|db| shell::run(&db)),
+        Command::Check { path, level } => check(&path, level),
+        Command::Repair { src, dst } => repair_db(&src, &dst),
+        Command::Diff { a, b, limit } => diff(&a, &b, limit),
+        Command::Stats { path, top, buckets, unit } => stats(&path, top, buckets, unit),
+        Command::Migrate { src, dst } => migrate_db(&src, &dst),
+        Command::Prune { src, dst, older_than, unit } => prune_db(&src, &dst, &older_than, unit),
+        Command::Ingest { dst, watch, roll, fields, uuid, timestamp, manifest } => {
+            ingest(&dst, &watch, &roll, &fields, &uuid, &timestamp, manifest.as_deref())
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("tdbrs: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Open the database at `path`, naming it in the error.
+fn open(path: &Path) -> Result<Db<'static>, Box<dyn Error>> {
+    Db::open(path).map_err(|e| match e {
+        traildb::Error::IncompatibleVersion => {
+            format!("{}: {} (written by a newer TrailDB than this build reads)", path.display(), e)
+                .into()
+        }
+        e => format!("{}: {}", path.display(), e).into(),
+    })
+}
+
+/// Parse a UUID given as 32 hex characters, with or without hyphens.
+fn parse_uuid(s: &str) -> Result<Uuid, Box<dyn Error>> {
+    let hex: String = s.chars().filter(|&c| c != '-').collect();
+    uuid_raw(&hex.to_lowercase()).ok_or_else(|| format!("invalid UUID {:?}", s).into())
+}
+
+/// Parse a time given as a raw timestamp or in RFC 3339 into a timestamp in
+/// `unit`.
+fn parse_time(s: &str, unit: Unit) -> Result<Timestamp, Box<dyn Error>> {
+    if let Ok(timestamp) = s.parse() {
+        return Ok(timestamp);
+    }
+    let secs = parse_rfc3339(s).ok_or_else(|| format!("invalid time {:?}", s))?;
+    Ok(unit.time_unit().from_duration(Duration::from_secs(secs)))
+}
+
+/// Parse a duration given as a count of seconds, minutes, hours or days,
+/// e.g. `90s`, `15m`, `1h` or `1d`.
+fn parse_duration(s: &str) -> Result<Duration, Box<dyn Error>> {
+    let invalid = || format!("invalid duration {:?}", s);
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let n: u64 = s[..split].parse().map_err(|_| invalid())?;
+    let scale = match &s[split..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        _ => return Err(invalid().into()),
+    };
+    match n.checked_mul(scale) {
+        Some(secs) if secs > 0 => Ok(Duration::from_secs(secs)),
+        _ => Err(invalid().into()),
+    }
+}
+
+fn info(path: &Path) -> CliResult {
+    let db = open(path)?;
+    let info = db.info();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "path:       {}", path.display())?;
+    writeln!(out, "version:    {}", info.version)?;
+    writeln!(out, "trails:     {}", info.num_trails)?;
+    writeln!(out, "events:     {}", info.num_events)?;
+    writeln!(out, "timestamps: {} - {}", info.min_timestamp, info.max_timestamp)?;
+    writeln!(out, "fields:     {}", info.fields.len())?;
+    for (i, name) in info.fields.iter().enumerate() {
+        writeln!(out, "  {:>3} {} ({} values)", i + 1, name, db.lexicon_size(i as u32 + 1) - 1)?;
+    }
+    Ok(())
+}
+
+fn dump(path: &Path, uuids: &[String], format: DumpFormat) -> CliResult {
+    let db = open(path)?;
+    let trails: Option<Vec<TrailId>> = if uuids.is_empty() {
+        None
+    } else {
+        let mut trails = Vec::with_capacity(uuids.len());
+        for uuid in uuids {
+            let trail_id = db.get_trail_id(&parse_uuid(uuid)?)
+                .ok_or_else(|| format!("no trail with UUID {}", uuid))?;
+            trails.push(trail_id);
+        }
+        Some(trails)
+    };
+    let stdout = io::stdout();
+    let out = BufWriter::new(stdout.lock());
+    match format {
+        DumpFormat::Jsonl => {
+            let mut options = ExportOptions::new();
+            if let Some(ref trails) = trails {
+                options = options.trails(trails);
+            }
+            export_to(&db, &mut JsonlEncoder::new(), out, options)?;
+        }
+        DumpFormat::Csv => {
+            let mut exporter = CsvExporter::new();
+            if let Some(ref trails) = trails {
+                exporter = exporter.trails(trails);
+            }
+            exporter.export(&db, out)?;
+        }
+    }
+    Ok(())
+}
+
+fn merge(dst: &Path, srcs: &[PathBuf]) -> CliResult {
+    // Check the schemas up front, so a mismatch says which input and fields
+    // are to blame.
+    let mut mismatched = 0;
+    let mut expected: Vec<String> = Vec::new();
+    for (i, src) in srcs.iter().enumerate() {
+        let mut db = open(src)?;
+        let fields: Vec<String> = db.field_names().iter().map(|f| f.to_string()).collect();
+        db.close();
+        if i == 0 {
+            expected = fields;
+            continue;
+        }
+        if fields == expected {
+            continue;
+        }
+        mismatched += 1;
+        eprintln!("{}: fields differ from {}", src.display(), srcs[0].display());
+        let missing: Vec<&str> = expected.iter()
+            .filter(|f| !fields.contains(f))
+            .map(|f| f.as_str())
+            .collect();
+        let extra: Vec<&str> = fields.iter()
+            .filter(|f| !expected.contains(f))
+            .map(|f| f.as_str())
+            .collect();
+        if !missing.is_empty() {
+            eprintln!("  missing: {}", missing.join(", "));
+        }
+        if !extra.is_empty() {
+            eprintln!("  extra:   {}", extra.join(", "));
+        }
+        if missing.is_empty() && extra.is_empty() {
+            eprintln!("  order:   {} (expected {})", fields.join(", "), expected.join(", "));
+        }
+    }
+    if mismatched > 0 {
+        return Err(format!("{} of {} inputs have mismatched fields", mismatched, srcs.len()).into());
+    }
+
+    let bar = ProgressBar::new(srcs.len() as u64);
+    bar.set_style(ProgressStyle::with_template("{elapsed_precise} [{bar:40}] {pos}/{len} {msg}")?
+        .progress_chars("=> "));
+    bar.set_message("appending");
+    let srcs: Vec<&Path> = srcs.iter().map(|src| src.as_path()).collect();
+    let report = merge_with_progress(dst, &srcs, |appended| {
+            bar.set_position(appended as u64);
+            if appended == srcs.len() {
+                bar.set_message("finalizing");
+            }
+        })
+        .map_err(|e| format!("{}: {}", dst.display(), e))?;
+    bar.finish_and_clear();
+    eprintln!("merged {} inputs into {}: {} trails ({} before merging), {} events",
+              report.inputs,
+              dst.display(),
+              report.num_trails,
+              report.input_trails,
+              report.num_events);
+    Ok(())
+}
+
+fn extract(src: &Path,
+           dst: &Path,
+           query: Option<&str>,
+           from: Option<&str>,
+           to: Option<&str>,
+           unit: Unit)
+           -> CliResult {
+    let db = open(src)?;
+    let filter = match query {
+        Some(query) => Some(parse_filter(&db, query)?),
+        None => None,
+    };
+    let start = match from {
+        Some(from) => parse_time(from, unit)?,
+        None => 0,
+    };
+    let end = match to {
+        Some(to) => parse_time(to, unit)?,
+        None => Timestamp::MAX,
+    };
+    let events = db.extract(dst, filter.as_ref(), start, end)
+        .map_err(|e| format!("{}: {}", dst.display(), e))?;
+    eprintln!("extracted {} of {} events into {}", events, db.num_events(), dst.display());
+    Ok(())
+}
+
+fn check(path: &Path, level: CheckLevel) -> CliResult {
+    let db = open(path)?;
+    let report = db.validate(level.validation_level());
+    for finding in &report.findings {
+        println!("{}: {}", path.display(), finding);
+    }
+    eprintln!("checked {} trails, {} events: {} problems",
+              report.trails_checked,
+              report.events_checked,
+              report.findings.len());
+    if !report.is_ok() {
+        return Err(format!("{} failed validation", path.display()).into());
+    }
+    Ok(())
+}
+
+fn repair_db(src: &Path, dst: &Path) -> CliResult {
+    let report = repair(src, dst).map_err(|e| format!("{}: {}", src.display(), e))?;
+    for skipped in &report.skipped {
+        let uuid = skipped.uuid.as_ref().map_or("?".to_string(), uuid_hex);
+        println!("skipped trail {} ({}), {} events: {}",
+                 skipped.trail_id,
+                 uuid,
+                 skipped.events,
+                 skipped.reason);
+    }
+    eprintln!("copied {} trails, {} events into {}; skipped {} trails",
+              report.trails_copied,
+              report.events_copied,
+              dst.display(),
+              report.skipped.len());
+    Ok(())
+}
+
+fn migrate_db(src: &Path, dst: &Path) -> CliResult {
+    let report = migrate(src, dst).map_err(|e| format!("{}: {}", src.display(), e))?;
+    if report.from_version == report.to_version {
+        eprintln!("{} is already at version {}; rewrote it anyway", src.display(), report.to_version);
+    }
+    eprintln!("migrated {} trails, {} events from version {} to {} into {}",
+              report.num_trails,
+              report.num_events,
+              report.from_version,
+              report.to_version,
+              dst.display());
+    Ok(())
+}
+
+fn prune_db(src: &Path, dst: &Path, older_than: &str, unit: Unit) -> CliResult {
+    let cutoff = parse_time(older_than, unit)?;
+    let report = prune(src, dst, cutoff).map_err(|e| format!("{}: {}", src.display(), e))?;
+    eprintln!("kept {} trails, {} events in {}; dropped {} trails, {} events",
+              report.num_trails,
+              report.num_events,
+              dst.display(),
+              report.dropped_trails,
+              report.dropped_events);
+    Ok(())
+}
+
+fn diff(a: &Path, b: &Path, limit: usize) -> CliResult {
+    let db_a = open(a)?;
+    let db_b = open(b)?;
+    let diff = diff_dbs(&db_a, &db_b)?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if !diff.fields_only_a.is_empty() {
+        writeln!(out, "fields only in {}: {}", a.display(), diff.fields_only_a.join(", "))?;
+    }
+    if !diff.fields_only_b.is_empty() {
+        writeln!(out, "fields only in {}: {}", b.display(), diff.fields_only_b.join(", "))?;
+    }
+    if diff.field_order_differs {
+        writeln!(out, "fields are in a different order")?;
+    }
+    writeln!(out, "{} shared trails", diff.shared_trails)?;
+    list_uuids(&mut out, &format!("trails only in {}", a.display()), &diff.trails_only_a, limit)?;
+    list_uuids(&mut out, &format!("trails only in {}", b.display()), &diff.trails_only_b, limit)?;
+    if !diff.event_counts.is_empty() {
+        writeln!(out, "{} trails with different event counts", diff.event_counts.len())?;
+        for &(ref uuid, events_a, events_b) in diff.event_counts.iter().take(limit) {
+            writeln!(out, "  {} {} vs {}", uuid_hex(uuid), events_a, events_b)?;
+        }
+    }
+    list_uuids(&mut out, "trails with different events", &diff.changed_trails, limit)?;
+    if !diff.is_empty() {
+        return Err(format!("{} and {} differ", a.display(), b.display()).into());
+    }
+    Ok(())
+}
+
+fn list_uuids<W: Write>(out: &mut W, what: &str, uuids: &[Uuid], limit: usize) -> io::Result<()> {
+    if uuids.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "{} {}", uuids.len(), what)?;
+    for uuid in uuids.iter().take(limit) {
+        writeln!(out, "  {}", uuid_hex(uuid))?;
+    }
+    if uuids.len() > limit {
+        writeln!(out, "  ...")?;
+    }
+    Ok(())
+}
+
+fn stats(path: &Path, top: usize, buckets: u64, unit: Unit) -> CliResult {
+    let db = open(path)?;
+    let unit = unit.time_unit();
+    let format_time = |timestamp| {
+        let (secs, millis) = unit.split(timestamp);
+        format_rfc3339(secs, if unit == TimeUnit::Seconds { None } else { Some(millis) })
+    };
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "trails: {}", db.num_trails())?;
+    writeln!(out, "events: {}", db.num_events())?;
+    if db.num_events() > 0 {
+        writeln!(out,
+                 "time:   {} - {}",
+                 format_time(db.min_timestamp()),
+                 format_time(db.max_timestamp()))?;
+    }
+
+    // The value counts of every field and the trail lengths, in power-of-two
+    // buckets 1, 2-3, 4-7, ..., in a single pass.
+    let mut counts: Vec<Vec<u64>> = (1..db.num_fields())
+        .map(|field| vec![0u64; db.lexicon_size(field as Field) as usize])
+        .collect();
+    let mut lengths = vec![0u64; 65];
+    let mut cursor = db.cursor();
+    for trail_id in 0..db.num_trails() {
+        cursor.get_trail(trail_id)?;
+        let mut len = 0u64;
+        for event in &mut cursor {
+            for (field, item) in counts.iter_mut().zip(event.items) {
+                let value = item.value() as usize;
+                if value >= field.len() {
+                    field.resize(value + 1, 0);
+                }
+                field[value] += 1;
+            }
+            len += 1;
+        }
+        lengths[64 - len.leading_zeros() as usize] += 1;
+    }
+
+    writeln!(out, "\nfields:")?;
+    for (i, (name, counts)) in db.field_names().iter().zip(&counts).enumerate() {
+        let field = i as Field + 1;
+        writeln!(out,
+                 "  {} ({} values, {} events empty)",
+                 name,
+                 db.lexicon_size(field) - 1,
+                 counts[0])?;
+        let mut values: Vec<(usize, u64)> = counts.iter()
+            .cloned()
+            .enumerate()
+            .skip(1)
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        values.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for &(value, count) in values.iter().take(top) {
+            writeln!(out,
+                     "    {:>10} {:>5.1}% {:?}",
+                     count,
+                     100.0 * count as f64 / db.num_events() as f64,
+                     db.get_value(field, value as u64).unwrap_or(""))?;
+        }
+    }
+
+    writeln!(out, "\nevents per trail:")?;
+    let max = lengths.iter().cloned().max().unwrap_or(0);
+    for (bits, &count) in lengths.iter().enumerate().filter(|&(_, &count)| count > 0) {
+        let range = match bits {
+            0 => "0".to_string(),
+            1 => "1".to_string(),
+            bits => format!("{}-{}", 1u64 << (bits - 1), (1u64 << (bits - 1)) * 2 - 1),
+        };
+        writeln!(out, "  {:>24} {:>10} {}", range, count, bar(count, max))?;
+    }
+
+    if db.num_events() > 0 {
+        let span = db.max_timestamp() - db.min_timestamp();
+        let width = TIME_BUCKETS.iter()
+            .cloned()
+            .find(|&secs| span / unit.from_duration(Duration::from_secs(secs)).max(1) < buckets)
+            .unwrap_or(TIME_BUCKETS[TIME_BUCKETS.len() - 1]);
+        let histogram = db.time_histogram(Duration::from_secs(width), unit, None)?;
+        writeln!(out, "\nevents over time:")?;
+        let max = histogram.counts.iter().cloned().max().unwrap_or(0);
+        for (n, &count) in histogram.counts.iter().enumerate() {
+            let start = histogram.bucket_start(n);
+            let label = if width >= 86_400 {
+                format_date(unit.split(start).0)
+            } else {
+                format_time(start)
+            };
+            writeln!(out, "  {:>24} {:>10} {}", label, count, bar(count, max))?;
+        }
+    }
+    Ok(())
+}
+
+/// A bar of `#`s as long relative to `BAR_WIDTH` as `count` is to `max`.
+fn bar(count: u64, max: u64) -> String {
+    if max == 0 {
+        return String::new();
+    }
+    "#".repeat((count * BAR_WIDTH).div_ceil(max) as usize)
+}
+
+fn ingest(dst: &Path,
+          spool: &Path,
+          roll: &str,
+          fields: &[String],
+          uuid: &str,
+          timestamp: &str,
+          manifest: Option<&Path>)
+          -> CliResult {
+    let fields: Vec<&str> = fields.iter().map(|f| f.as_str()).collect();
+    // SIGINT and SIGTERM stop the ingest once the current file is read,
+    // publishing the shard being built. A second one exits right away; the
+    // events of the unpublished shard are read again on the next start.
+    let stop = Arc::new(AtomicBool::new(false));
+    for &signal in &[signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(signal, 1, stop.clone())?;
+        signal_hook::flag::register(signal, stop.clone())?;
+    }
+    let mut watcher = SpoolIngest::new(spool, dst, &fields)
+        .mapping(ColumnMapping::new(uuid, timestamp))
+        .roll_every(parse_duration(roll)?);
+    if let Some(manifest) = manifest {
+        watcher = watcher.manifest(manifest);
+    }
+    watcher.run(&stop, |path, report| {
+        for file in &report.files {
+            match file.result {
+                Ok(ref r) if !r.errors.is_empty() => {
+                    eprintln!("{}: skipped {} of {} rows", file.name, r.errors.len(), r.rows)
+                }
+                Ok(_) => {}
+                Err(ref e) => eprintln!("{}: {}", file.name, e),
+            }
+        }
+        eprintln!("wrote {} events from {} files into {}",
+                  report.imported(),
+                  report.files.len(),
+                  path.display());
+    })?;
+    Ok(())
+}
+
+
+
+
+#[cfg(test)]
+mod test_tdbrs {
+    use super::{bar, parse_duration, parse_time, parse_uuid, Cli, Command, Unit, BAR_WIDTH};
+    use clap::Parser;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("15m").unwrap(), Duration::from_secs(900));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_duration("2d").unwrap(), Duration::from_secs(172_800));
+        for s in &["", "0s", "1", "1w", "h", "-1h", "18446744073709551615d", "99999999999999999999s"] {
+            assert!(parse_duration(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_parse_uuid_and_time() {
+        let uuid = parse_uuid("000102030405060708090A0B0C0D0E0F").unwrap();
+        assert_eq!(uuid, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+        assert_eq!(parse_uuid("00010203-0405-0607-0809-0a0b0c0d0e0f").unwrap(), uuid);
+        assert!(parse_uuid("0001").is_err());
+
+        assert_eq!(parse_time("1234", Unit::Millis).unwrap(), 1234);
+        assert_eq!(parse_time("1970-01-01T00:01:00Z", Unit::Seconds).unwrap(), 60);
+        assert_eq!(parse_time("1970-01-01T00:01:00Z", Unit::Millis).unwrap(), 60_000);
+        assert!(parse_time("yesterday", Unit::Seconds).is_err());
+    }
+
+    #[test]
+    fn test_bar() {
+        assert_eq!(bar(0, 0), "");
+        assert_eq!(bar(5, 5).len() as u64, BAR_WIDTH);
+        assert_eq!(bar(1, 1000).len(), 1);
+    }
+
+    #[test]
+    fn test_cli() {
+        match Cli::try_parse_from(["tdbrs", "stats", "events", "--top", "3"]).unwrap().command {
+            Command::Stats { path, top, .. } => assert_eq!((path.to_str().unwrap(), top), ("events", 3)),
+            _ => panic!("expected stats"),
+        }
+        assert!(Cli::try_parse_from(["tdbrs", "merge", "dst"]).is_err());
+        assert!(Cli::try_parse_from(["tdbrs", "frobnicate"]).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//! `tdbrs shell`, an interactive prompt over one database.
+
+use std::error::Error;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use traildb::query::parse_filter;
+use traildb::{uuid_hex, Db, Field, TrailId};
+
+use super::parse_uuid;
+
+const COMMANDS: &[&str] = &["fields", "uuid", "trail", "filter", "stats", "help", "quit"];
+
+const HELP: &str = "\
+fields               list the fields
+uuid <uuid>          look up the trail id of a UUID
+trail <uuid|id>      print the events of a trail
+filter <query>       count the events and trails matching a query,
+                     e.g. filter action=\"purchase\" AND country!=\"us\"
+stats <field> [n]    print the n most common values of a field (default 10)
+help                 print this help
+quit                 leave the shell";
+
+/// The number of matching trails `filter` lists.
+const LISTED_TRAILS: usize = 10;
+
+/// Completes commands in the first word and field names after it.
+struct ShellHelper {
+    fields: Vec<String>,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(|c: char| c.is_whitespace() || "()=!\"".contains(c))
+            .map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        let candidates = if line[..start].trim().is_empty() {
+            COMMANDS.iter().filter(|c| c.starts_with(word)).map(|c| c.to_string()).collect()
+        } else {
+            self.fields.iter().filter(|f| f.starts_with(word)).cloned().collect()
+        };
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+/// Read commands from the terminal and run them against `db` until `quit`
+/// or end of input. A failing command prints its error and the shell goes
+/// on.
+pub fn run(db: &Db) -> Result<(), Box<dyn Error>> {
+    let mut editor: Editor<ShellHelper, _> = Editor::new()?;
+    editor.set_helper(Some(ShellHelper {
+        fields: db.field_names().iter().map(|f| f.to_string()).collect(),
+    }));
+    println!("{} trails, {} events; type `help` for commands", db.num_trails(), db.num_events());
+    loop {
+        let line = match editor.readline("tdbrs> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line)?;
+        let (command, args) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        let result = match command {
+            "fields" => fields(db),
+            "uuid" => uuid(db, args),
+            "trail" => trail(db, args),
+            "filter" => filter(db, args),
+            "stats" => stats(db, args),
+            "help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            "quit" | "exit" => return Ok(()),
+            _ => Err(format!("unknown command {:?}; type `help` for commands", command).into()),
+        };
+        if let Err(e) = result {
+            println!("error: {}", e);
+        }
+    }
+}
+
+fn fields(db: &Db) -> Result<(), Box<dyn Error>> {
+    for (i, name) in db.field_names().iter().enumerate() {
+        println!("{:>3} {} ({} values)", i + 1, name, db.lexicon_size(i as Field + 1) - 1);
+    }
+    Ok(())
+}
+
+fn uuid(db: &Db, args: &str) -> Result<(), Box<dyn Error>> {
+    match db.get_trail_id(&parse_uuid(args)?) {
+        Some(trail_id) => println!("{}", trail_id),
+        None => println!("no trail with UUID {}", args),
+    }
+    Ok(())
+}
+
+/// Resolve a trail given by UUID or, if it's short enough, by trail id.
+fn trail_id(db: &Db, arg: &str) -> Result<TrailId, Box<dyn Error>> {
+    if arg.len() < 32 {
+        if let Ok(trail_id) = arg.parse::<TrailId>() {
+            if trail_id < db.num_trails() {
+                return Ok(trail_id);
+            }
+            return Err(format!("no trail with id {}", trail_id).into());
+        }
+    }
+    db.get_trail_id(&parse_uuid(arg)?).ok_or_else(|| format!("no trail with UUID {}", arg).into())
+}
+
+fn trail(db: &Db, args: &str) -> Result<(), Box<dyn Error>> {
+    let trail_id = trail_id(db, args)?;
+    let batch = db.trail_batch(trail_id)?;
+    let fields = db.field_names();
+    println!("trail {} ({}), {} events", trail_id, uuid_hex(&batch.uuid), batch.events.len());
+    for event in &batch.events {
+        let items: Vec<String> = fields.iter()
+            .zip(&event.values)
+            .filter(|&(_, value)| !value.is_empty())
+            .map(|(field, value)| format!("{}={:?}", field, value))
+            .collect();
+        println!("  {} {}", event.timestamp, items.join(" "));
+    }
+    Ok(())
+}
+
+fn filter(db: &Db, args: &str) -> Result<(), Box<dyn Error>> {
+    let filter = parse_filter(db, args)?;
+    let mut cursor = db.cursor();
+    cursor.set_event_filter(&filter)?;
+    let mut events = 0;
+    let mut trails = Vec::new();
+    for trail_id in 0..db.num_trails() {
+        cursor.get_trail(trail_id)?;
+        let n = (&mut cursor).count();
+        if n > 0 {
+            events += n;
+            trails.push(trail_id);
+        }
+    }
+    println!("{} events in {} trails", events, trails.len());
+    for &trail_id in trails.iter().take(LISTED_TRAILS) {
+        println!("  {} {}", trail_id, uuid_hex(db.get_uuid(trail_id).unwrap()));
+    }
+    if trails.len() > LISTED_TRAILS {
+        println!("  ...");
+    }
+    Ok(())
+}
+
+fn stats(db: &Db, args: &str) -> Result<(), Box<dyn Error>> {
+    let mut args = args.split_whitespace();
+    let name = args.next().ok_or("usage: stats <field> [n]")?;
+    let n: usize = match args.next() {
+        Some(n) => n.parse().map_err(|_| format!("invalid count {:?}", n))?,
+        None => 10,
+    };
+    let field = db.get_field(name).ok_or_else(|| format!("no field {:?}", name))?;
+    let counts = db.value_counts(field, None)?;
+    let total: u64 = counts.iter().sum();
+    let mut values: Vec<(usize, u64)> = counts.into_iter().enumerate().filter(|&(_, c)| c > 0).collect();
+    values.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    println!("{}: {} distinct values in {} events", name, values.len(), total);
+    for &(value, count) in values.iter().take(n) {
+        let share = 100.0 * count as f64 / total as f64;
+        println!("  {:>10} {:>5.1}% {:?}", count, share, db.get_value(field, value as u64).unwrap_or(""));
+    }
+    Ok(())
+}
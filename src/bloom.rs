@@ -0,0 +1,396 @@
+//! Bloom filters of the UUIDs of a database, to tell without opening a
+//! shard, or without touching the UUID index of a cold one, that it
+//! doesn't hold a trail.
+//!
+//! A filter is written with `Db::build_uuid_bloom`, by default to the
+//! sidecar `BLOOM_EXT` of the database (see `sidecar_path`). `Db` loads
+//! that sidecar the first time `get_trail_id` is called and answers for
+//! UUIDs the filter rules out without looking them up. Services holding
+//! many shards can open the filters alone with `UuidBloom::open` and only
+//! open the shards that may hold a UUID.
+//!
+//! The file holds little-endian `u64`s:
+//!
+//! ```text
+//! "TDBBLOOM" version num_trails fingerprint num_hashes num_bits bits...
+//! ```
+//!
+//! `fingerprint` identifies the database the filter was built for by its
+//! counts, first and last UUIDs and the sizes and modification times of
+//! its files, so that a filter left next to a database rebuilt at the same
+//! path is ignored rather than trusted. `num_hashes` is at most 64.
+
+use std::f64::consts::LN_2;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::durable::sync_dir;
+use super::{stored_path, Db, Field, Uuid};
+
+const MAGIC: &[u8; 8] = b"TDBBLOOM";
+const VERSION: u64 = 3;
+/// The most hash functions a filter may use. A false positive rate low
+/// enough to need more isn't worth the lookups, and a corrupt count would
+/// make every lookup loop for ages.
+const MAX_HASHES: u64 = 64;
+/// The extension of bloom filter sidecars.
+pub const BLOOM_EXT: &str = "bloom";
+
+/// A bloom filter of the UUIDs of a database.
+#[derive(Debug,Clone,PartialEq)]
+pub struct UuidBloom {
+    num_trails: u64,
+    /// The `Db::fingerprint` of the database the filter was built for, 0
+    /// for filters built by hand.
+    fingerprint: u64,
+    num_hashes: u64,
+    num_bits: u64,
+    bits: Vec<u64>,
+}
+
+impl UuidBloom {
+    /// An empty filter sized for `n` UUIDs with a false positive rate of
+    /// `fpp`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fpp` isn't between 0 and 1, exclusive.
+    pub fn new(n: u64, fpp: f64) -> Self {
+        assert!(fpp > 0.0 && fpp < 1.0, "fpp must be between 0 and 1");
+        let n = n.max(1) as f64;
+        let num_bits = (-n * fpp.ln() / (LN_2 * LN_2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * LN_2).round().clamp(1.0, MAX_HASHES as f64) as u64;
+        UuidBloom {
+            num_trails: 0,
+            fingerprint: 0,
+            num_hashes: num_hashes,
+            num_bits: num_bits,
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+        }
+    }
+
+    /// Read the filter at `path`.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let corrupt = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+        if bytes.len() < 48 || &bytes[..8] != MAGIC {
+            return Err(corrupt("not a UUID bloom filter"));
+        }
+        let mut words = bytes[8..].chunks_exact(8).map(|chunk| {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            u64::from_le_bytes(word)
+        });
+        if words.next() != Some(VERSION) {
+            return Err(corrupt("unknown UUID bloom filter version"));
+        }
+        let num_trails = words.next().unwrap();
+        let fingerprint = words.next().unwrap();
+        let num_hashes = words.next().unwrap();
+        let num_bits = words.next().unwrap();
+        let bits: Vec<u64> = words.collect();
+        if num_hashes == 0 || num_hashes > MAX_HASHES {
+            return Err(corrupt("bad number of UUID bloom filter hashes"));
+        }
+        if num_bits == 0 || (bits.len() as u64) < num_bits.div_ceil(64) {
+            return Err(corrupt("truncated UUID bloom filter"));
+        }
+        Ok(UuidBloom {
+            num_trails: num_trails,
+            fingerprint: fingerprint,
+            num_hashes: num_hashes,
+            num_bits: num_bits,
+            bits: bits,
+        })
+    }
+
+    /// The number of UUIDs added.
+    pub fn num_trails(&self) -> u64 {
+        self.num_trails
+    }
+
+    pub fn insert(&mut self, uuid: &Uuid) {
+        for bit in positions(uuid, self.num_hashes, self.num_bits) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.num_trails += 1;
+    }
+
+    /// False if `uuid` was definitely not added; true if it probably was.
+    pub fn may_contain(&self, uuid: &Uuid) -> bool {
+        positions(uuid, self.num_hashes, self.num_bits)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Write the filter to `path`, under a hidden name first and renamed
+    /// into place, so readers never see a partial one.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let tmp = dir.join(format!(".{}.tmp", name));
+        let mut bytes = Vec::with_capacity(48 + 8 * self.bits.len());
+        bytes.extend_from_slice(MAGIC);
+        let header = [VERSION, self.num_trails, self.fingerprint, self.num_hashes, self.num_bits];
+        for word in header.iter().chain(&self.bits) {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        let mut file = File::create(&tmp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        sync_dir(dir)
+    }
+}
+
+/// The bits of `uuid` in a filter of `num_bits` bits, by double hashing.
+/// UUIDs aren't always random, so both halves are mixed first.
+fn positions(uuid: &Uuid, num_hashes: u64, num_bits: u64) -> impl Iterator<Item = u64> {
+    let (lo, hi) = uuid_words(uuid);
+    let h1 = mix(lo ^ mix(hi));
+    let h2 = mix(h1 ^ hi) | 1;
+    (0..num_hashes).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+}
+
+/// The two little-endian halves of `uuid`.
+fn uuid_words(uuid: &Uuid) -> (u64, u64) {
+    let mut lo = [0u8; 8];
+    let mut hi = [0u8; 8];
+    lo.copy_from_slice(&uuid[..8]);
+    hi.copy_from_slice(&uuid[8..]);
+    (u64::from_le_bytes(lo), u64::from_le_bytes(hi))
+}
+
+/// `hash` with `word` folded in. Not cryptographic: it tells sidecars of
+/// another database apart, it doesn't resist forged ones.
+pub(crate) fn fold(hash: u64, word: u64) -> u64 {
+    mix(hash ^ word).wrapping_add(0x9e37_79b9_7f4a_7c15)
+}
+
+/// The size and modification time, in nanoseconds since the epoch, of the
+/// file at `path`, or of every file in it, by name, if it is a directory.
+/// Files that can't be read count as empty and never modified.
+fn file_stamps(path: &Path) -> Vec<(u64, u64)> {
+    let stamp = |path: &Path| {
+        fs::metadata(path).map_or((0, 0), |meta| {
+            let modified = meta.modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_nanos() as u64);
+            (meta.len(), modified)
+        })
+    };
+    if !path.is_dir() {
+        return vec![stamp(path)];
+    }
+    let mut files: Vec<PathBuf> = match fs::read_dir(path) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    files.iter().map(|file| stamp(file)).collect()
+}
+
+/// The splitmix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 30;
+    x = x.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+impl<'a> Db<'a> {
+    /// A hash of the database's counts, timestamps and lexicon sizes, of
+    /// its first and last UUIDs, and of the sizes and modification times
+    /// of its files, stored in sidecars to tell whether they were built
+    /// for it. It only reads the header of the database and the metadata
+    /// of its files, so it is cheap enough to check whenever a sidecar is
+    /// loaded.
+    pub(crate) fn fingerprint(&self) -> u64 {
+        let counts = [self.num_trails(),
+                      self.num_events(),
+                      self.num_fields(),
+                      self.min_timestamp(),
+                      self.max_timestamp()];
+        let mut hash = counts.iter().fold(0, |hash, &word| fold(hash, word));
+        for field in 1..self.num_fields() as Field {
+            hash = fold(hash, self.lexicon_size(field));
+        }
+        if self.num_trails() > 0 {
+            for &trail_id in &[0, self.num_trails() - 1] {
+                if let Some(uuid) = self.get_uuid(trail_id) {
+                    let (lo, hi) = uuid_words(uuid);
+                    hash = fold(fold(hash, lo), hi);
+                }
+            }
+        }
+        file_stamps(&stored_path(self.path()))
+            .into_iter()
+            .fold(hash, |hash, (len, modified)| fold(fold(hash, len), modified))
+    }
+
+    /// Build a bloom filter of the UUIDs of the database with a false
+    /// positive rate of `fpp` and write it to `path`, usually
+    /// `self.sidecar_path(BLOOM_EXT)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fpp` isn't between 0 and 1, exclusive.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::{sidecar_path, Db, UuidBloom, BLOOM_EXT};
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("2024-01-01")).unwrap();
+    /// db.build_uuid_bloom(&db.sidecar_path(BLOOM_EXT), 0.01).unwrap();
+    ///
+    /// // Elsewhere, without opening the shard:
+    /// let bloom = UuidBloom::open(&sidecar_path(Path::new("2024-01-01"), BLOOM_EXT)).unwrap();
+    /// if bloom.may_contain(&[7u8; 16]) {
+    ///     println!("worth opening");
+    /// }
+    /// ```
+    pub fn build_uuid_bloom(&self, path: &Path, fpp: f64) -> io::Result<UuidBloom> {
+        let mut bloom = UuidBloom::new(self.num_trails(), fpp);
+        bloom.fingerprint = self.fingerprint();
+        for trail_id in 0..self.num_trails() {
+            if let Some(uuid) = self.get_uuid(trail_id) {
+                bloom.insert(uuid);
+            }
+        }
+        bloom.write(path)?;
+        Ok(bloom)
+    }
+
+    /// The bloom filter in the sidecar `BLOOM_EXT` of the database, read the
+    /// first time it is asked for. A filter built for another database, or
+    /// for this one before it was rebuilt, is ignored.
+    pub fn uuid_bloom(&self) -> Option<&UuidBloom> {
+        self.bloom
+            .get_or_init(|| {
+                let path = self.sidecar_path(BLOOM_EXT);
+                if !path.exists() {
+                    return None;
+                }
+                match UuidBloom::open(&path) {
+                    Ok(ref bloom) if bloom.num_trails != self.num_trails() ||
+                                     bloom.fingerprint != self.fingerprint() => {
+                        warn!("traildb: ignoring stale UUID bloom filter {}", path.display());
+                        None
+                    }
+                    Ok(bloom) => Some(bloom),
+                    Err(e) => {
+                        warn!("traildb: ignoring UUID bloom filter {}: {}", path.display(), e);
+                        None
+                    }
+                }
+            })
+            .as_ref()
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_bloom {
+    use super::{UuidBloom, BLOOM_EXT};
+    use super::super::{Constructor, Db};
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    #[test]
+    fn test_uuid_bloom() {
+        let db_path = Path::new("test_uuid_bloom");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        for i in 0..100u8 {
+            assert!(cons.add(&[i; 16], 1, &["view"]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let path = db.sidecar_path(BLOOM_EXT);
+        let _ = fs::remove_file(&path);
+        let bloom = db.build_uuid_bloom(&path, 0.01).unwrap();
+        assert_eq!(bloom.num_trails(), 100);
+        assert!((0..100u8).all(|i| bloom.may_contain(&[i; 16])));
+        let false_positives = (100..=255u8).filter(|&i| bloom.may_contain(&[i; 16])).count();
+        assert!(false_positives < 10);
+        assert_eq!(UuidBloom::open(&path).unwrap(), bloom);
+
+        let reopened = Db::open(db_path).unwrap();
+        assert_eq!(reopened.get_trail_id(&[200u8; 16]), None);
+        assert_eq!(reopened.uuid_bloom(), Some(&bloom));
+        assert!(reopened.get_trail_id(&[7u8; 16]).is_some());
+
+        // num_hashes, after the magic, version, num_trails and fingerprint.
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(UuidBloom::open(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(Db::open(db_path).unwrap().uuid_bloom().is_none());
+    }
+
+    #[test]
+    fn test_uuid_bloom_rebuilt() {
+        let db_path = Path::new("test_uuid_bloom_rebuilt");
+        let build = |first: u8| {
+            let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+            for i in first..first + 50 {
+                assert!(cons.add(&[i; 16], 1, &["view"]).is_ok());
+            }
+            assert!(cons.finalize().is_ok());
+        };
+        build(0);
+        let db = Db::open(db_path).unwrap();
+        db.build_uuid_bloom(&db.sidecar_path(BLOOM_EXT), 0.01).unwrap();
+        assert!(Db::open(db_path).unwrap().uuid_bloom().is_some());
+
+        // The same number of trails, with other UUIDs.
+        build(100);
+        let rebuilt = Db::open(db_path).unwrap();
+        assert_eq!(rebuilt.num_trails(), db.num_trails());
+        assert!(rebuilt.uuid_bloom().is_none());
+        assert!(rebuilt.get_trail_id(&[120u8; 16]).is_some());
+        assert_eq!(rebuilt.get_trail_id(&[20u8; 16]), None);
+    }
+
+    #[test]
+    fn test_uuid_bloom_fixture() {
+        // Byte by byte, so that the format is checked on hosts of either
+        // byte order.
+        let fixture: Vec<u8> = [&b"TDBBLOOM"[..],
+                                b"\x03\0\0\0\0\0\0\0", // version
+                                b"\x02\0\0\0\0\0\0\0", // num_trails
+                                b"\x08\x07\x06\x05\x04\x03\x02\x01", // fingerprint
+                                b"\x01\0\0\0\0\0\0\0", // num_hashes
+                                b"\x40\0\0\0\0\0\0\0", // num_bits
+                                b"\x01\0\0\0\0\0\0\x08"] // bits 0 and 59
+            .concat();
+        let path = Path::new("test_uuid_bloom_fixture.bloom");
+        fs::write(path, &fixture).unwrap();
+        let bloom = UuidBloom::open(path).unwrap();
+        assert_eq!(bloom.num_trails(), 2);
+        assert_eq!(bloom.fingerprint, 0x0102_0304_0506_0708);
+        // [0; 16] hashes to bit 0, [1; 16] to bit 59 and [2; 16] to bit 38.
+        assert!(bloom.may_contain(&[0u8; 16]));
+        assert!(bloom.may_contain(&[1u8; 16]));
+        assert!(!bloom.may_contain(&[2u8; 16]));
+
+        let mut built = UuidBloom::new(2, 0.5);
+        built.num_hashes = 1;
+        built.num_bits = 64;
+        built.bits = vec![0];
+        built.fingerprint = 0x0102_0304_0506_0708;
+        built.insert(&[0u8; 16]);
+        built.insert(&[1u8; 16]);
+        assert_eq!(built, bloom);
+        built.write(path).unwrap();
+        assert_eq!(fs::read(path).unwrap(), fixture);
+    }
+}
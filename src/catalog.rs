@@ -0,0 +1,410 @@
+//! Many named databases under one root directory, such as one per tenant or
+//! per app.
+//!
+//! A name is one or more `/`-separated segments, `acme` or `acme/clicks`,
+//! and the database named `acme/clicks` lives at `<root>/acme/clicks`: the
+//! package `clicks.tdb` or the directory `clicks` in the directory `acme`.
+//! Segments can't start with `.`, which keeps names from escaping the root
+//! and leaves hidden names to databases being written.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::durable::{finalized_path, sync_dir};
+use super::{prune, sidecar_path, Constructor, Db, Error, SharedDb, Timestamp, METADATA_EXT, SIDECAR_EXTS};
+
+/// Why a catalog operation failed.
+#[derive(Debug)]
+pub enum CatalogError {
+    Io(io::Error),
+    /// The name isn't a valid database name.
+    InvalidName(String),
+    /// There is no database with the name.
+    NotFound(String),
+    Db(Error),
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CatalogError::Io(ref e) => write!(f, "CatalogError::Io({})", e),
+            CatalogError::InvalidName(ref name) => write!(f, "CatalogError::InvalidName({})", name),
+            CatalogError::NotFound(ref name) => write!(f, "CatalogError::NotFound({})", name),
+            CatalogError::Db(ref e) => write!(f, "CatalogError::Db({})", e),
+        }
+    }
+}
+
+impl error::Error for CatalogError {}
+
+impl From<io::Error> for CatalogError {
+    fn from(e: io::Error) -> Self {
+        CatalogError::Io(e)
+    }
+}
+
+impl From<Error> for CatalogError {
+    fn from(e: Error) -> Self {
+        CatalogError::Db(e)
+    }
+}
+
+/// The outcome of `Catalog::retain`.
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct RetentionReport {
+    /// The shards deleted as a whole, every event being older than the
+    /// cutoff.
+    pub removed: Vec<String>,
+    /// The shards rewritten without their older events.
+    pub pruned: Vec<String>,
+    /// The events deleted from pruned and removed shards.
+    pub dropped_events: u64,
+}
+
+/// A registry of the databases under a root directory, opened by name the
+/// first time they are asked for and kept open until `evict`ed or the
+/// catalog is dropped. A catalog can be shared between threads.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Catalog;
+/// use std::path::Path;
+///
+/// let catalog = Catalog::new(Path::new("/var/lib/events")).unwrap();
+/// let mut cons = catalog.constructor("acme/clicks", &["page"]).unwrap();
+/// cons.add(&[1u8; 16], 1, &["/"]).unwrap();
+/// cons.finalize().unwrap();
+///
+/// for name in catalog.names().unwrap() {
+///     let db = catalog.open(&name).unwrap();
+///     println!("{}: {} events", name, db.num_events());
+/// }
+/// ```
+pub struct Catalog {
+    root: PathBuf,
+    opened: Mutex<HashMap<String, SharedDb>>,
+}
+
+impl Catalog {
+    /// The catalog of the databases under `root`, which is created if it
+    /// doesn't exist.
+    pub fn new(root: &Path) -> io::Result<Self> {
+        fs::create_dir_all(root)?;
+        Ok(Catalog {
+            root: root.to_path_buf(),
+            opened: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The path to open or build the database `name` with.
+    pub fn path(&self, name: &str) -> Result<PathBuf, CatalogError> {
+        let valid = !name.is_empty() &&
+                    name.split('/').all(|segment| !segment.is_empty() && !segment.starts_with('.'));
+        if valid {
+            Ok(self.root.join(name))
+        } else {
+            Err(CatalogError::InvalidName(name.to_string()))
+        }
+    }
+
+    /// Whether there is a database named `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        match self.path(name) {
+            Ok(path) => find(&path).is_some(),
+            Err(_) => false,
+        }
+    }
+
+    /// The names of the databases in the catalog, sorted.
+    pub fn names(&self) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        list(&self.root, "", &mut names)?;
+        names.sort();
+        Ok(names)
+    }
+
+    /// The database `name`, opened by the first call and shared by the
+    /// following ones.
+    pub fn open(&self, name: &str) -> Result<SharedDb, CatalogError> {
+        let path = self.path(name)?;
+        let mut opened = self.opened.lock().unwrap();
+        if let Some(db) = opened.get(name) {
+            return Ok(db.clone());
+        }
+        if find(&path).is_none() {
+            return Err(CatalogError::NotFound(name.to_string()));
+        }
+        let db = SharedDb::open(&path)?;
+        opened.insert(name.to_string(), db.clone());
+        Ok(db)
+    }
+
+    /// Whether the database `name` is held open by the catalog.
+    pub fn is_open(&self, name: &str) -> bool {
+        self.opened.lock().unwrap().contains_key(name)
+    }
+
+    /// Stop holding the database `name` open, so that the next `open` sees
+    /// it as it is on disk then. It is closed once the handles already
+    /// given out are dropped. Returns false if it wasn't open.
+    pub fn evict(&self, name: &str) -> bool {
+        self.opened.lock().unwrap().remove(name).is_some()
+    }
+
+    /// A constructor for the database `name`, creating the directories of
+    /// its name. A database already open under the name is only seen again
+    /// once evicted.
+    pub fn constructor(&self, name: &str, fields: &[&str]) -> Result<Constructor, CatalogError> {
+        let path = self.path(name)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(Constructor::new(&path, fields)?)
+    }
+
+    /// Evict the database `name` and delete it from disk, along with its
+    /// sidecars, so that a database created under the name later doesn't
+    /// pick them up.
+    pub fn remove(&self, name: &str) -> Result<(), CatalogError> {
+        let path = self.path(name)?;
+        self.evict(name);
+        let found = find(&path).ok_or_else(|| CatalogError::NotFound(name.to_string()))?;
+        remove_sidecars(&found, SIDECAR_EXTS)?;
+        if found.is_dir() {
+            fs::remove_dir_all(&found)?;
+        } else {
+            fs::remove_file(&found)?;
+        }
+        Ok(())
+    }
+
+    /// Apply a retention policy to the shards named `prefix` or under
+    /// `prefix/`, deleting the events with timestamps before `older_than`.
+    /// Shards with no newer events are deleted; shards with some are
+    /// rewritten with `prune` and replace the old ones, keeping their
+    /// metadata. Their other sidecars describe the old events, so they are
+    /// deleted, to be built again.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Catalog;
+    /// use std::path::Path;
+    /// use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    ///
+    /// let catalog = Catalog::new(Path::new("/var/lib/events")).unwrap();
+    /// let ninety_days = SystemTime::now() - Duration::from_secs(90 * 86_400);
+    /// let cutoff = ninety_days.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    /// let report = catalog.retain("acme/clicks", cutoff).unwrap();
+    /// println!("removed {} shards", report.removed.len());
+    /// ```
+    pub fn retain(&self, prefix: &str, older_than: Timestamp) -> Result<RetentionReport, CatalogError> {
+        self.path(prefix)?;
+        let nested = format!("{}/", prefix);
+        let mut report = RetentionReport::default();
+        for name in self.names()? {
+            if name != prefix && !name.starts_with(&nested) {
+                continue;
+            }
+            let path = match find(&self.root.join(&name)) {
+                Some(path) => path,
+                None => continue,
+            };
+            let mut db = Db::open(&path)?;
+            let (num_events, min, max) = (db.num_events(), db.min_timestamp(), db.max_timestamp());
+            db.close();
+            if num_events == 0 || max < older_than {
+                self.remove(&name)?;
+                report.dropped_events += num_events;
+                report.removed.push(name);
+            } else if min < older_than {
+                report.dropped_events += self.replace_pruned(&name, &path, older_than)?;
+                report.pruned.push(name);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Prune the shard `name` at `path` under a hidden name and move the
+    /// result over it. Returns the number of events dropped.
+    ///
+    /// The original is only deleted once the pruned copy is in place: a
+    /// package is replaced by a rename, and a directory is first renamed
+    /// aside, so that a crash leaves one of them under the shard's name or,
+    /// between the two renames, both under hidden ones.
+    fn replace_pruned(&self, name: &str, path: &Path, older_than: Timestamp) -> Result<u64, CatalogError> {
+        let dst = self.root.join(name);
+        let dir = dst.parent().unwrap_or_else(|| Path::new(""));
+        let leaf = dst.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let hidden = dir.join(format!(".{}.pruned", leaf));
+        let dropped = prune(path, &hidden, older_than)?.dropped_events;
+        let pruned = finalized_path(&hidden);
+        let target = if pruned.is_dir() { dst.clone() } else { dir.join(format!("{}.tdb", leaf)) };
+        let stale: Vec<&str> = SIDECAR_EXTS.iter().cloned().filter(|&ext| ext != METADATA_EXT).collect();
+        remove_sidecars(path, &stale)?;
+        let metadata = sidecar_path(path, METADATA_EXT);
+        self.evict(name);
+        if path != target {
+            fs::rename(&pruned, &target)?;
+            if path.is_dir() {
+                fs::remove_dir_all(path)?;
+            } else {
+                fs::remove_file(path)?;
+            }
+        } else if path.is_dir() {
+            let aside = dir.join(format!(".{}.old", leaf));
+            if aside.exists() {
+                fs::remove_dir_all(&aside)?;
+            }
+            fs::rename(path, &aside)?;
+            fs::rename(&pruned, &target)?;
+            fs::remove_dir_all(&aside)?;
+        } else {
+            fs::rename(&pruned, &target)?;
+        }
+        let moved = sidecar_path(&target, METADATA_EXT);
+        if moved != metadata && metadata.exists() {
+            fs::rename(&metadata, &moved)?;
+        }
+        sync_dir(dir)?;
+        Ok(dropped)
+    }
+}
+
+/// Delete the sidecars `exts` of the database at `path`, if it has them.
+fn remove_sidecars(path: &Path, exts: &[&str]) -> io::Result<()> {
+    for ext in exts {
+        match fs::remove_file(sidecar_path(path, ext)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+/// The database at `path`: a directory, or a package with or without its
+/// `.tdb` extension.
+fn find(path: &Path) -> Option<PathBuf> {
+    if path.join("info").exists() {
+        return Some(path.to_path_buf());
+    }
+    let mut packaged = path.as_os_str().to_owned();
+    packaged.push(".tdb");
+    let packaged = PathBuf::from(packaged);
+    if packaged.is_file() {
+        Some(packaged)
+    } else {
+        None
+    }
+}
+
+/// Add the names of the databases in `dir`, named under `prefix`, to
+/// `names`, descending into directories that aren't databases.
+fn list(dir: &Path, prefix: &str, names: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if file_name.starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            let name = format!("{}{}", prefix, file_name);
+            if path.join("info").exists() {
+                names.push(name);
+            } else {
+                list(&path, &format!("{}/", name), names)?;
+            }
+        } else if file_name.ends_with(".tdb") {
+            names.push(format!("{}{}", prefix, &file_name[..file_name.len() - 4]));
+        }
+    }
+    Ok(())
+}
+
+
+
+
+#[cfg(test)]
+mod test_catalog {
+    use super::{Catalog, CatalogError};
+    use super::super::sidecar_path;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_catalog() {
+        let root = Path::new("test_catalog");
+        let _ = fs::remove_dir_all(root);
+        let catalog = Catalog::new(root).unwrap();
+        for &(name, n) in &[("acme/clicks", 3u64), ("acme/views", 1), ("globex", 2)] {
+            let mut cons = catalog.constructor(name, &["action"]).unwrap();
+            for t in 0..n {
+                assert!(cons.add(&[1u8; 16], t, &["view"]).is_ok());
+            }
+            assert!(cons.finalize().is_ok());
+        }
+
+        assert_eq!(catalog.names().unwrap(), vec!["acme/clicks", "acme/views", "globex"]);
+        assert!(catalog.contains("acme/views"));
+        assert!(!catalog.contains("acme"));
+        assert!(!catalog.is_open("acme/clicks"));
+        assert_eq!(catalog.open("acme/clicks").unwrap().num_events(), 3);
+        assert!(catalog.is_open("acme/clicks"));
+        assert!(catalog.evict("acme/clicks"));
+
+        match catalog.open("../acme") {
+            Err(CatalogError::InvalidName(_)) => {}
+            other => panic!("unexpected {:?}", other.map(|db| db.num_events())),
+        }
+        match catalog.open("initech") {
+            Err(CatalogError::NotFound(_)) => {}
+            other => panic!("unexpected {:?}", other.map(|db| db.num_events())),
+        }
+
+        let sidecar = sidecar_path(&root.join("globex"), "index");
+        fs::write(&sidecar, b"stale").unwrap();
+        catalog.remove("globex").unwrap();
+        assert_eq!(catalog.names().unwrap(), vec!["acme/clicks", "acme/views"]);
+        assert!(!sidecar.exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_retain() {
+        let root = Path::new("test_retain");
+        let _ = fs::remove_dir_all(root);
+        let catalog = Catalog::new(root).unwrap();
+        for &(name, start) in &[("clicks/2024-01", 100u64), ("clicks/2024-02", 200), ("views", 100)] {
+            let mut cons = catalog.constructor(name, &["action"]).unwrap();
+            cons.set_metadata("owner", "growth");
+            for t in start..start + 100 {
+                assert!(cons.add(&[(t % 7) as u8; 16], t, &["view"]).is_ok());
+            }
+            assert!(cons.finalize().is_ok());
+        }
+        assert_eq!(catalog.open("clicks/2024-02").unwrap().num_events(), 100);
+
+        let report = catalog.retain("clicks", 250).unwrap();
+        assert_eq!(report.removed, vec!["clicks/2024-01"]);
+        assert_eq!(report.pruned, vec!["clicks/2024-02"]);
+        assert_eq!(report.dropped_events, 150);
+        assert_eq!(catalog.names().unwrap(), vec!["clicks/2024-02", "views"]);
+        assert!(!catalog.is_open("clicks/2024-02"));
+        let db = catalog.open("clicks/2024-02").unwrap();
+        assert_eq!((db.num_events(), db.db().min_timestamp()), (50, 250));
+        assert_eq!(db.db().metadata().unwrap()["owner"], "growth");
+        assert_eq!(catalog.open("views").unwrap().num_events(), 100);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+//! SHA-256 checksums of the files of a database, to catch bit rot in
+//! archives before it turns into wrong numbers.
+//!
+//! Checksums are written to the sidecar `CHECKSUM_EXT` of the database (see
+//! `sidecar_path`) by `write_checksums`, or by a constructor built with
+//! `ConstructorBuilder::checksums` when it finalizes. Each line holds the
+//! hex digest of a file and its path within the database, `.` for a
+//! package, so the sidecar stays valid when the database is renamed.
+
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use ::sha2::{Digest, Sha256};
+
+use super::durable::sync_dir;
+use super::{sidecar_path, Db};
+
+/// The extension of checksum sidecars.
+pub const CHECKSUM_EXT: &str = "sha256";
+
+/// Why a database failed verification.
+#[derive(Debug)]
+pub enum ChecksumError {
+    Io(io::Error),
+    /// The database has no checksum sidecar.
+    Missing(PathBuf),
+    /// The sidecar can't be parsed.
+    Malformed(String),
+    /// Files whose contents don't match their checksum, or that are gone.
+    Mismatch(Vec<PathBuf>),
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChecksumError::Io(ref e) => write!(f, "ChecksumError::Io({})", e),
+            ChecksumError::Missing(ref path) => write!(f, "ChecksumError::Missing({})", path.display()),
+            ChecksumError::Malformed(ref e) => write!(f, "ChecksumError::Malformed({})", e),
+            ChecksumError::Mismatch(ref paths) => {
+                let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "ChecksumError::Mismatch({})", paths.join(", "))
+            }
+        }
+    }
+}
+
+impl error::Error for ChecksumError {}
+
+impl From<io::Error> for ChecksumError {
+    fn from(e: io::Error) -> Self {
+        ChecksumError::Io(e)
+    }
+}
+
+/// Write the checksums of the database at `db_path` to its sidecar and
+/// return the sidecar's path.
+pub fn write_checksums(db_path: &Path) -> io::Result<PathBuf> {
+    let sidecar = sidecar_path(db_path, CHECKSUM_EXT);
+    let db = located(&sidecar);
+    let mut lines = String::new();
+    if db.is_dir() {
+        let mut names: Vec<_> = fs::read_dir(&db)?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<io::Result<_>>()?;
+        names.sort();
+        for name in names {
+            let path = db.join(&name);
+            if path.is_file() {
+                lines.push_str(&format!("{}  {}\n", digest(&path)?, name.to_string_lossy()));
+            }
+        }
+    } else {
+        lines.push_str(&format!("{}  .\n", digest(&db)?));
+    }
+
+    let dir = sidecar.parent().unwrap_or_else(|| Path::new(""));
+    let name = sidecar.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let tmp = dir.join(format!(".{}.tmp", name));
+    let mut file = File::create(&tmp)?;
+    file.write_all(lines.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, &sidecar)?;
+    sync_dir(dir)?;
+    Ok(sidecar)
+}
+
+impl<'a> Db<'a> {
+    /// Check the files of the database against the checksums in its
+    /// sidecar. This reads every byte of the database.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("archive/2019-03")).unwrap();
+    /// if let Err(e) = db.verify_checksums() {
+    ///     eprintln!("restore 2019-03 from a replica: {}", e);
+    /// }
+    /// ```
+    pub fn verify_checksums(&self) -> Result<(), ChecksumError> {
+        let sidecar = self.sidecar_path(CHECKSUM_EXT);
+        let contents = match fs::read_to_string(&sidecar) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(ChecksumError::Missing(sidecar));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let db = located(&sidecar);
+        let mut mismatched = Vec::new();
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            let (expected, name) = match line.find("  ") {
+                Some(at) => (&line[..at], &line[at + 2..]),
+                None => return Err(ChecksumError::Malformed(line.to_string())),
+            };
+            let path = if name == "." { db.clone() } else { db.join(name) };
+            match digest(&path) {
+                Ok(ref actual) if actual == expected => {}
+                Ok(_) => mismatched.push(path),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => mismatched.push(path),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if mismatched.is_empty() {
+            Ok(())
+        } else {
+            Err(ChecksumError::Mismatch(mismatched))
+        }
+    }
+}
+
+/// The database a sidecar belongs to: its path without the extension.
+fn located(sidecar: &Path) -> PathBuf {
+    sidecar.with_extension("")
+}
+
+/// The hex SHA-256 digest of the file at `path`.
+fn digest(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+
+
+
+#[cfg(test)]
+mod test_checksum {
+    use super::{write_checksums, ChecksumError, CHECKSUM_EXT};
+    use super::super::{ConstructorBuilder, Db};
+    use std::fs::{self, OpenOptions};
+    use std::io::{Seek, SeekFrom, Write};
+    use std::path::Path;
+
+    #[test]
+    fn test_checksums() {
+        let db_path = Path::new("test_checksums");
+        let mut cons = ConstructorBuilder::new(db_path, &["action"]).checksums(true).build().unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["view"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        assert!(db.sidecar_path(CHECKSUM_EXT).exists());
+        assert!(db.verify_checksums().is_ok());
+
+        let package = Path::new("test_checksums.tdb");
+        let mut file = OpenOptions::new().write(true).open(package).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(b"rot").unwrap();
+        match db.verify_checksums() {
+            Err(ChecksumError::Mismatch(ref paths)) => assert_eq!(paths, &[package.to_path_buf()]),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        assert_eq!(write_checksums(db_path).unwrap(), Path::new("test_checksums.tdb.sha256"));
+        assert!(db.verify_checksums().is_ok());
+        fs::remove_file(db.sidecar_path(CHECKSUM_EXT)).unwrap();
+        match db.verify_checksums() {
+            Err(ChecksumError::Missing(_)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! zstd-compressed packages, `events.tdb.zst`, for cold archives.
+//!
+//! libtraildb can only map a package from a file, so `Db::open` decompresses
+//! a compressed package to a temporary file, removed once the `Db` is
+//! dropped, and opens that. A compressed package is opened with its path,
+//! `events.tdb.zst`, or with `events` when neither `events` nor
+//! `events.tdb` exists.
+//!
+//! Packages are compressed with `compress_package`, or by a constructor
+//! built with `ConstructorBuilder::zstd` when it finalizes. Sidecars belong
+//! to the file on disk, so those of a compressed package are
+//! `events.tdb.zst.<ext>` and are built again after compressing.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::durable::{finalized_path, sync_dir};
+
+/// The extension of compressed packages, after `.tdb`.
+pub const ZSTD_EXT: &str = "zst";
+
+/// Distinguishes the decompressed packages of one process.
+static UNPACKED_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// Compress the package a constructor opened with `path` finalized, or the
+/// package at `path`, to `<package>.zst` and remove the uncompressed one.
+/// Returns the path of the compressed package. `level` is a zstd level; 0
+/// picks zstd's default.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::compress_package;
+/// use std::path::Path;
+///
+/// let packed = compress_package(Path::new("archive/2019-03"), 19).unwrap();
+/// assert_eq!(packed, Path::new("archive/2019-03.tdb.zst"));
+/// ```
+pub fn compress_package(path: &Path, level: i32) -> io::Result<PathBuf> {
+    let package = finalized_path(path);
+    if package.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("{} is a directory, not a package", package.display())));
+    }
+    let mut packed = package.as_os_str().to_owned();
+    packed.push(".");
+    packed.push(ZSTD_EXT);
+    let packed = PathBuf::from(packed);
+
+    let dir = packed.parent().unwrap_or_else(|| Path::new(""));
+    let name = packed.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let tmp = dir.join(format!(".{}.tmp", name));
+    let mut src = BufReader::new(File::open(&package)?);
+    let mut dst = BufWriter::new(File::create(&tmp)?);
+    ::zstd::stream::copy_encode(&mut src, &mut dst, level)?;
+    dst.flush()?;
+    dst.get_ref().sync_all()?;
+    fs::rename(&tmp, &packed)?;
+    fs::remove_file(&package)?;
+    sync_dir(dir)?;
+    Ok(packed)
+}
+
+/// The compressed package `Db::open` should read for `path`, if any.
+pub(crate) fn compressed_package(path: &Path) -> Option<PathBuf> {
+    if path.extension().is_some_and(|ext| ext == ZSTD_EXT) {
+        return Some(path.to_path_buf());
+    }
+    let mut packaged = path.as_os_str().to_owned();
+    packaged.push(".tdb");
+    if path.exists() || Path::new(&packaged).exists() {
+        return None;
+    }
+    packaged.push(".");
+    packaged.push(ZSTD_EXT);
+    let packed = PathBuf::from(packaged);
+    if packed.exists() {
+        Some(packed)
+    } else {
+        None
+    }
+}
+
+/// A package decompressed to a temporary file, removed on drop.
+#[derive(Debug)]
+pub(crate) struct Unpacked {
+    path: PathBuf,
+}
+
+impl Unpacked {
+    /// Decompress the package at `packed`.
+    pub(crate) fn new(packed: &Path) -> io::Result<Self> {
+        let path = env::temp_dir().join(format!("traildb-{}-{}.tdb",
+                                                process::id(),
+                                                UNPACKED_SEQ.fetch_add(1, Ordering::Relaxed)));
+        let unpacked = Unpacked { path: path };
+        let mut src = BufReader::new(File::open(packed)?);
+        let mut dst = BufWriter::new(File::create(&unpacked.path)?);
+        ::zstd::stream::copy_decode(&mut src, &mut dst)?;
+        dst.flush()?;
+        Ok(unpacked)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Unpacked {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_compress {
+    use super::compress_package;
+    use super::super::{ConstructorBuilder, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_compressed_package() {
+        let db_path = Path::new("test_compressed_package");
+        let mut cons = ConstructorBuilder::new(db_path, &["action"]).zstd(3).build().unwrap();
+        for t in 0..10 {
+            assert!(cons.add(&[1u8; 16], t, &["view"]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+        assert!(!Path::new("test_compressed_package.tdb").exists());
+        assert!(Path::new("test_compressed_package.tdb.zst").exists());
+
+        let db = Db::open(db_path).unwrap();
+        assert_eq!(db.num_events(), 10);
+        let db = Db::open(Path::new("test_compressed_package.tdb.zst")).unwrap();
+        assert_eq!(db.num_trails(), 1);
+        assert_eq!(db.sidecar_path("index"), Path::new("test_compressed_package.tdb.zst.index"));
+
+        assert!(compress_package(db_path, 0).is_err());
+    }
+}
@@ -0,0 +1,1017 @@
+use std::collections::HashSet;
+use std::fs;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{Constructor, ConstructorBuilder, Db, Error, Event, EventFilter, Field, ResolvedEvent,
+            Timestamp, TrailId, Uuid, Version, VERSION_LATEST};
+
+impl<'a> Db<'a> {
+    /// Write the events matching `filter` into a new database at `dst_path`,
+    /// returning the number of events written.
+    ///
+    /// The new database has the same fields as this one. Trails without a
+    /// matching event are left out.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::{Db, EventFilter};
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// let customer = db.get_field("customer").unwrap();
+    /// let mut filter = EventFilter::new();
+    /// filter.add_term(db.get_item(customer, "acme").unwrap(), false).unwrap();
+    /// db.copy_filtered(Path::new("acme_traildb"), &filter).unwrap();
+    /// ```
+    pub fn copy_filtered(&self, dst_path: &Path, filter: &EventFilter) -> Result<u64, Error> {
+        let fields = self.field_names();
+        let sources: Vec<Field> = (1..self.num_fields() as Field).collect();
+        let trails = self.scan_trails(Some(filter));
+        rewrite(self, dst_path, &fields, &sources, Some(filter), trails, |_| true)
+    }
+
+    /// Write the trails with the given UUIDs into a new database at
+    /// `dst_path`, returning the number of events written.
+    ///
+    /// UUIDs missing from this database are ignored. Small lists are looked
+    /// up trail by trail; lists covering a large part of the database are
+    /// matched in a single pass over the UUIDs instead.
+    pub fn extract_uuids(&self, dst_path: &Path, uuids: &[Uuid]) -> Result<u64, Error> {
+        let num_trails = self.num_trails();
+        let mut trails: Vec<TrailId> = if (uuids.len() as u64) < num_trails / SCAN_RATIO {
+            uuids.iter().filter_map(|uuid| self.get_trail_id(uuid)).collect()
+        } else {
+            let wanted: HashSet<&Uuid> = uuids.iter().collect();
+            (0..num_trails)
+                .filter(|&id| self.get_uuid(id).map_or(false, |uuid| wanted.contains(uuid)))
+                .collect()
+        };
+        trails.sort();
+        trails.dedup();
+
+        let fields = self.field_names();
+        let sources: Vec<Field> = (1..self.num_fields() as Field).collect();
+        rewrite(self, dst_path, &fields, &sources, None, trails, |_| true)
+    }
+
+    /// Write the events with timestamps in `start..end` into a new database
+    /// at `dst_path`, returning the number of events written.
+    ///
+    /// All fields are kept. Trails without an event in the window are left
+    /// out.
+    pub fn extract_time_range(&self,
+                              dst_path: &Path,
+                              start: Timestamp,
+                              end: Timestamp)
+                              -> Result<u64, Error> {
+        let fields = self.field_names();
+        let sources: Vec<Field> = (1..self.num_fields() as Field).collect();
+        rewrite(self,
+                dst_path,
+                &fields,
+                &sources,
+                None,
+                0..self.num_trails(),
+                |event| event.timestamp >= start && event.timestamp < end)
+    }
+
+    /// Write the events matching `filter`, if given, with timestamps in
+    /// `start..end` into a new database at `dst_path`, returning the number
+    /// of events written. Combines `copy_filtered` and
+    /// `extract_time_range` in a single pass.
+    pub fn extract(&self,
+                   dst_path: &Path,
+                   filter: Option<&EventFilter>,
+                   start: Timestamp,
+                   end: Timestamp)
+                   -> Result<u64, Error> {
+        self.extract_cancellable(dst_path, filter, start, end, &AtomicBool::new(false))
+    }
+
+    /// Like `extract`, giving up with `Error::Cancelled` once `cancel` is
+    /// set. It is checked between trails; a cancelled extract is never
+    /// finalized, so nothing is left at `dst_path`.
+    pub fn extract_cancellable(&self,
+                               dst_path: &Path,
+                               filter: Option<&EventFilter>,
+                               start: Timestamp,
+                               end: Timestamp,
+                               cancel: &AtomicBool)
+                               -> Result<u64, Error> {
+        let fields = self.field_names();
+        let sources: Vec<Field> = (1..self.num_fields() as Field).collect();
+        let keep = |event: &Event| event.timestamp >= start && event.timestamp < end;
+        rewrite_cancellable(self,
+                            dst_path,
+                            &fields,
+                            &sources,
+                            filter,
+                            self.scan_trails(filter),
+                            keep,
+                            |uuid| Some(*uuid),
+                            Some(cancel))
+    }
+
+    /// Write all events into a new database at `dst_path` with only the
+    /// listed fields, returning the number of events written.
+    ///
+    /// Fields not listed are dropped, e.g. to strip personal data before
+    /// sharing a database. Naming a field this database doesn't have fails
+    /// with `Error::UnknownField`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// db.copy_projected(Path::new("shared_traildb"), &["user", "action"]).unwrap();
+    /// ```
+    pub fn copy_projected(&self, dst_path: &Path, fields: &[&str]) -> Result<u64, Error> {
+        let sources = self.source_fields(fields)?;
+        rewrite(self, dst_path, fields, &sources, None, 0..self.num_trails(), |_| true)
+    }
+
+    /// Write all events into a new database at `dst_path` with fields
+    /// renamed and reordered, returning the number of events written.
+    ///
+    /// Each `(name, source)` pair adds an output field `name` holding the
+    /// values of this database's field `source`, in the order given. Fields
+    /// not mentioned are dropped, and a source may be used more than once.
+    /// This conforms databases from different producers to one schema so
+    /// they can be appended together.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// db.copy_mapped(Path::new("conformed_traildb"),
+    ///                &[("action", "event_type"), ("user", "username")])
+    ///     .unwrap();
+    /// ```
+    pub fn copy_mapped(&self, dst_path: &Path, mapping: &[(&str, &str)]) -> Result<u64, Error> {
+        let fields: Vec<&str> = mapping.iter().map(|&(name, _)| name).collect();
+        let sources: Vec<&str> = mapping.iter().map(|&(_, source)| source).collect();
+        let sources = self.source_fields(&sources)?;
+        rewrite(self, dst_path, &fields, &sources, None, 0..self.num_trails(), |_| true)
+    }
+
+    /// Rewrite this database into `n_shards` databases under `dst_dir`,
+    /// named `shard-00000`, `shard-00001` and so on. Returns the number of
+    /// events written to each shard.
+    ///
+    /// A trail goes to shard `hash(uuid) % n_shards`, so all events of a
+    /// UUID stay together. Use a hash that is stable across processes when
+    /// shards are produced on different machines.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n_shards` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// // UUIDs are usually random, so their leading bytes hash well enough.
+    /// let counts = db.split(Path::new("shards"), 8, |uuid| {
+    ///         let mut head = [0u8; 8];
+    ///         head.copy_from_slice(&uuid[..8]);
+    ///         u64::from_le_bytes(head)
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn split<F>(&self, dst_dir: &Path, n_shards: usize, hash: F) -> Result<Vec<u64>, Error>
+        where F: Fn(&Uuid) -> u64
+    {
+        assert!(n_shards > 0, "n_shards must be at least 1");
+        fs::create_dir_all(dst_dir).map_err(|_| Error::IoOpen)?;
+        let fields = self.field_names();
+        let mut shards = Vec::with_capacity(n_shards);
+        for i in 0..n_shards {
+            let path = dst_dir.join(format!("shard-{:05}", i));
+            shards.push(ConstructorBuilder::new(&path, &fields)
+                .expected_trails(self.num_trails() as usize / n_shards)
+                .expected_events(self.num_events() as usize / n_shards)
+                .build()?);
+        }
+
+        let mut counts = vec![0; n_shards];
+        let mut values: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut cursor = self.cursor();
+        for trail_id in 0..self.num_trails() {
+            let uuid = *self.get_uuid(trail_id).ok_or(Error::InvalidTrailId)?;
+            let shard = (hash(&uuid) % n_shards as u64) as usize;
+            cursor.get_trail(trail_id)?;
+            for event in &mut cursor {
+                values.clear();
+                values.extend(event.items.iter().map(|&item| self.get_item_value(item)));
+                shards[shard].add(&uuid, event.timestamp, &values)?;
+                counts[shard] += 1;
+            }
+        }
+        for cons in &mut shards {
+            cons.finalize()?;
+        }
+        Ok(counts)
+    }
+
+    /// Write every event `f` keeps into a new database at `dst_path` with
+    /// the same fields, returning the number of events written.
+    ///
+    /// `f` is given the UUID and resolved values of every event and returns
+    /// the values to write instead, or `None` to drop the event. Trails left
+    /// without events are dropped. This is the primitive behind redaction
+    /// and cleanup jobs that `copy_filtered` and `copy_mapped` can't express.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` returns a number of values other than the number of
+    /// fields.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// // Mask email addresses in the second field, drop test traffic.
+    /// db.rewrite(Path::new("redacted_traildb"), |_uuid, event| {
+    ///         if event.values[0] == "test" {
+    ///             return None;
+    ///         }
+    ///         let mut values = event.values.clone();
+    ///         if values[1].contains('@') {
+    ///             values[1] = "<redacted>".to_string();
+    ///         }
+    ///         Some(values)
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn rewrite<F>(&self, dst_path: &Path, f: F) -> Result<u64, Error>
+        where F: FnMut(&Uuid, &ResolvedEvent) -> Option<Vec<String>>
+    {
+        self.rewrite_fields(dst_path, &self.field_names(), f)
+    }
+
+    /// Like `rewrite`, writing a database with the fields `fields`, for
+    /// which `f` returns values, instead of this database's fields.
+    pub fn rewrite_fields<F>(&self, dst_path: &Path, fields: &[&str], mut f: F) -> Result<u64, Error>
+        where F: FnMut(&Uuid, &ResolvedEvent) -> Option<Vec<String>>
+    {
+        let mut cons = ConstructorBuilder::new(dst_path, fields)
+            .expected_trails(self.num_trails() as usize)
+            .expected_events(self.num_events() as usize)
+            .build()?;
+        let mut cursor = self.cursor();
+        let mut count = 0;
+        for trail_id in 0..self.num_trails() {
+            let uuid = *self.get_uuid(trail_id).ok_or(Error::InvalidTrailId)?;
+            cursor.get_trail(trail_id)?;
+            for event in &mut cursor {
+                let values = match f(&uuid, &self.resolve_event(&event)) {
+                    Some(values) => values,
+                    None => continue,
+                };
+                if values.len() != fields.len() {
+                    cons.close();
+                    panic!("rewrite returned {} values for {} fields", values.len(), fields.len());
+                }
+                let values: Vec<&str> = values.iter().map(|v| v.as_str()).collect();
+                cons.add(&uuid, event.timestamp, &values)?;
+                count += 1;
+            }
+        }
+        cons.finalize()?;
+        Ok(count)
+    }
+
+    /// Write a smaller database with the same fields into `dst_path`, for
+    /// development environments and test fixtures, returning the number of
+    /// events written. The same database is always sampled the same way.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `n` of `how` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::{Db, Downsample};
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("production")).unwrap();
+    /// db.downsample(Path::new("fixture"), Downsample::EveryNthTrail(1000)).unwrap();
+    /// ```
+    pub fn downsample(&self, dst_path: &Path, how: Downsample) -> Result<u64, Error> {
+        let fields = self.field_names();
+        let sources: Vec<Field> = (1..self.num_fields() as Field).collect();
+        match how {
+            Downsample::EveryNthTrail(n) => {
+                assert!(n > 0, "n must be at least 1");
+                let trails = (0..self.num_trails()).step_by(n as usize);
+                rewrite(self, dst_path, &fields, &sources, None, trails, |_| true)
+            }
+            Downsample::PerTrailCap(n) => {
+                assert!(n > 0, "n must be at least 1");
+                let mut cons = ConstructorBuilder::new(dst_path, &fields)
+                    .expected_trails(self.num_trails() as usize)
+                    .build()?;
+                let mut values: Vec<&str> = Vec::with_capacity(fields.len());
+                let mut cursor = self.cursor();
+                let mut count = 0;
+                for trail_id in 0..self.num_trails() {
+                    let uuid = *self.get_uuid(trail_id).ok_or(Error::InvalidTrailId)?;
+                    cursor.get_trail(trail_id)?;
+                    for event in (&mut cursor).take(n as usize) {
+                        values.clear();
+                        values.extend(event.items.iter().map(|&item| self.get_item_value(item)));
+                        cons.add(&uuid, event.timestamp, &values)?;
+                        count += 1;
+                    }
+                }
+                cons.finalize()?;
+                Ok(count)
+            }
+        }
+    }
+
+    fn source_fields(&self, names: &[&str]) -> Result<Vec<Field>, Error> {
+        names.iter()
+            .map(|name| match self.get_field(name) {
+                Some(0) | None => Err(Error::UnknownField),
+                Some(field) => Ok(field),
+            })
+            .collect()
+    }
+}
+
+impl Constructor {
+    /// Add every event of `db`, whose fields must all be fields of this
+    /// constructor, filling the fields `db` doesn't have with `default`.
+    /// Returns the number of events added.
+    ///
+    /// `append` requires the same fields in the same order and fails with
+    /// `Error::AppendFieldsMismatch` otherwise; this brings databases
+    /// written before a field was added up to the current schema. A field
+    /// of `db` this constructor doesn't have still fails with
+    /// `Error::AppendFieldsMismatch`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::{Constructor, Db};
+    /// use std::path::Path;
+    ///
+    /// // Last month's shard predates the "country" field.
+    /// let old = Db::open(Path::new("2024-01")).unwrap();
+    /// let mut cons = Constructor::new(Path::new("2024-01-v2"), &["action", "country"]).unwrap();
+    /// cons.append_with_default(&old, "unknown").unwrap();
+    /// cons.finalize().unwrap();
+    /// ```
+    pub fn append_with_default(&mut self, db: &Db, default: &str) -> Result<u64, Error> {
+        self.append_with(db, |_, _, _| default.to_string())
+    }
+
+    /// Like `append_with_default`, computing the value of every missing
+    /// field with `fill`, given the field's name and the UUID and resolved
+    /// values of the event in `db`.
+    pub fn append_with<F>(&mut self, db: &Db, mut fill: F) -> Result<u64, Error>
+        where F: FnMut(&str, &Uuid, &ResolvedEvent) -> String
+    {
+        let fields = self.field_names().to_vec();
+        let names = db.field_names();
+        if names.iter().any(|name| !fields.iter().any(|field| field == name)) {
+            return Err(Error::AppendFieldsMismatch);
+        }
+        // The index of each field of this constructor among the values of
+        // `db`, if it has the field.
+        let sources: Vec<Option<usize>> = fields.iter()
+            .map(|field| names.iter().position(|name| name == field))
+            .collect();
+
+        let mut cursor = db.cursor();
+        let mut count = 0;
+        for trail_id in 0..db.num_trails() {
+            let uuid = *db.get_uuid(trail_id).ok_or(Error::InvalidTrailId)?;
+            cursor.get_trail(trail_id)?;
+            for event in &mut cursor {
+                let event = db.resolve_event(&event);
+                let values: Vec<String> = sources.iter()
+                    .zip(&fields)
+                    .map(|(source, field)| match *source {
+                        Some(i) => event.values[i].clone(),
+                        None => fill(field, &uuid, &event),
+                    })
+                    .collect();
+                let values: Vec<&str> = values.iter().map(|v| v.as_str()).collect();
+                self.add(&uuid, event.timestamp, &values)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// How `Db::downsample` samples a database.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Downsample {
+    /// Keep every `n`th trail, with all its events.
+    EveryNthTrail(u64),
+    /// Keep every trail, with at most its first `n` events.
+    PerTrailCap(u64),
+}
+
+/// Statistics of a `merge`.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct MergeReport {
+    /// The number of databases merged.
+    pub inputs: usize,
+    /// The total number of trails across the inputs. Trails sharing a UUID
+    /// are combined, so this can exceed `num_trails`.
+    pub input_trails: u64,
+    /// The number of trails in the merged database.
+    pub num_trails: u64,
+    /// The number of events in the merged database.
+    pub num_events: u64,
+    pub min_timestamp: Timestamp,
+    pub max_timestamp: Timestamp,
+}
+
+/// Concatenate the finalized databases at `srcs` into a new database at
+/// `dst_path`.
+///
+/// All inputs must have the same fields in the same order, otherwise
+/// `Error::AppendFieldsMismatch` is returned before anything is written.
+/// Events of a UUID found in several inputs end up in a single trail.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::merge;
+/// use std::path::Path;
+///
+/// let report = merge(Path::new("week"),
+///                    &[Path::new("monday"), Path::new("tuesday")])
+///     .unwrap();
+/// println!("{} events in {} trails", report.num_events, report.num_trails);
+/// ```
+pub fn merge(dst_path: &Path, srcs: &[&Path]) -> Result<MergeReport, Error> {
+    merge_with_progress(dst_path, srcs, |_| ())
+}
+
+/// Like `merge`, calling `progress` with the number of inputs appended so
+/// far after each one. Finalizing the merged database, which can take as
+/// long as the appends, follows the last call.
+pub fn merge_with_progress<F>(dst_path: &Path, srcs: &[&Path], progress: F) -> Result<MergeReport, Error>
+    where F: FnMut(usize)
+{
+    merge_cancellable(dst_path, srcs, progress, &AtomicBool::new(false))
+}
+
+/// Like `merge_with_progress`, giving up with `Error::Cancelled` once
+/// `cancel` is set. It is checked between inputs; a cancelled merge is
+/// never finalized, so nothing is left at `dst_path`.
+#[cfg_attr(feature = "tracing",
+           tracing::instrument(level = "debug",
+                               skip_all,
+                               fields(dst = %dst_path.display(),
+                                      inputs = srcs.len(),
+                                      events = tracing::field::Empty)))]
+pub fn merge_cancellable<F>(dst_path: &Path,
+                            srcs: &[&Path],
+                            mut progress: F,
+                            cancel: &AtomicBool)
+                            -> Result<MergeReport, Error>
+    where F: FnMut(usize)
+{
+    let mut dbs = Vec::with_capacity(srcs.len());
+    for src in srcs {
+        dbs.push(Opened::open(src)?);
+    }
+    let fields: Vec<&str> = match dbs.first() {
+        Some(db) => db.field_names(),
+        None => Vec::new(),
+    };
+    if dbs.iter().any(|db| db.field_names() != fields) {
+        return Err(Error::AppendFieldsMismatch);
+    }
+
+    let mut cons = ConstructorBuilder::new(dst_path, &fields)
+        .expected_trails(dbs.iter().map(|db| db.num_trails() as usize).sum())
+        .expected_events(dbs.iter().map(|db| db.num_events() as usize).sum())
+        .build()?;
+    for (i, db) in dbs.iter().enumerate() {
+        if cancel.load(Ordering::Relaxed) {
+            cons.close();
+            return Err(Error::Cancelled);
+        }
+        cons.append(db)?;
+        progress(i + 1);
+    }
+    let report = merge_report(&cons, &dbs);
+    cons.finalize()?;
+    record_span!("events", report.num_events);
+    Ok(report)
+}
+
+fn merge_report(cons: &Constructor, dbs: &[Opened]) -> MergeReport {
+    let non_empty = || dbs.iter().filter(|db| db.num_events() > 0);
+    MergeReport {
+        inputs: dbs.len(),
+        input_trails: dbs.iter().map(|db| db.num_trails()).sum(),
+        num_trails: dbs.iter()
+            .flat_map(|db| (0..db.num_trails()).filter_map(move |trail_id| db.get_uuid(trail_id)))
+            .collect::<HashSet<_>>()
+            .len() as u64,
+        num_events: cons.num_events(),
+        min_timestamp: non_empty().map(|db| db.min_timestamp()).min().unwrap_or(0),
+        max_timestamp: non_empty().map(|db| db.max_timestamp()).max().unwrap_or(0),
+    }
+}
+
+/// The outcome of `migrate`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct MigrateReport {
+    /// The format version of the source database.
+    pub from_version: Version,
+    /// The format version of the new database, read back from it once
+    /// written.
+    pub to_version: Version,
+    pub num_trails: u64,
+    pub num_events: u64,
+}
+
+impl MigrateReport {
+    /// Whether the source was in an older format than the new database,
+    /// rather than rewritten as it was.
+    pub fn upgraded(&self) -> bool {
+        self.from_version < self.to_version
+    }
+}
+
+/// Rewrite the database at `src` into a new database at `dst` in the
+/// current format.
+///
+/// Older formats can still be read, but only in the way the C library
+/// supports them. Rewriting decodes every event and adds it anew, so the
+/// result has the same fields, trails and events. Databases written in a
+/// newer format than this build supports fail to open with
+/// `Error::IncompatibleVersion`, and so does the new database if it isn't
+/// in `VERSION_LATEST` once written.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::{migrate, VERSION_LATEST};
+/// use std::path::Path;
+///
+/// let report = migrate(Path::new("legacy"), Path::new("current")).unwrap();
+/// assert_eq!(report.to_version, VERSION_LATEST);
+/// if report.upgraded() {
+///     println!("upgraded from version {}", report.from_version);
+/// }
+/// ```
+pub fn migrate(src: &Path, dst: &Path) -> Result<MigrateReport, Error> {
+    let db = Opened::open(src)?;
+    let fields = db.field_names();
+    let sources: Vec<Field> = (1..db.num_fields() as Field).collect();
+    let num_events = rewrite(&db, dst, &fields, &sources, None, 0..db.num_trails(), |_| true)?;
+    let migrated = Opened::open(dst)?;
+    if migrated.version() != VERSION_LATEST {
+        return Err(Error::IncompatibleVersion);
+    }
+    Ok(MigrateReport {
+        from_version: db.version(),
+        to_version: migrated.version(),
+        num_trails: migrated.num_trails(),
+        num_events: num_events,
+    })
+}
+
+/// The outcome of `prune`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct PruneReport {
+    /// The trails and events kept.
+    pub num_trails: u64,
+    pub num_events: u64,
+    /// The trails whose events were all older than the cutoff.
+    pub dropped_trails: u64,
+    pub dropped_events: u64,
+}
+
+/// Rewrite the database at `src` into a new database at `dst` without the
+/// events with timestamps before `older_than`, e.g. to enforce a retention
+/// period. Trails left without events are dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::prune;
+/// use std::path::Path;
+///
+/// // Keep 2024 onwards.
+/// let report = prune(Path::new("events"), Path::new("events_pruned"), 1_704_067_200).unwrap();
+/// println!("dropped {} events", report.dropped_events);
+/// ```
+pub fn prune(src: &Path, dst: &Path, older_than: Timestamp) -> Result<PruneReport, Error> {
+    let db = Opened::open(src)?;
+    let fields = db.field_names();
+    let sources: Vec<Field> = (1..db.num_fields() as Field).collect();
+    let num_events = rewrite(&db,
+                             dst,
+                             &fields,
+                             &sources,
+                             None,
+                             0..db.num_trails(),
+                             |event| event.timestamp >= older_than)?;
+    let pruned = Opened::open(dst)?;
+    Ok(PruneReport {
+        num_trails: pruned.num_trails(),
+        num_events: num_events,
+        dropped_trails: db.num_trails() - pruned.num_trails(),
+        dropped_events: db.num_events() - num_events,
+    })
+}
+
+/// A database opened for the length of a call, closed when dropped, so
+/// that it is closed on errors too.
+struct Opened(Db<'static>);
+
+impl Opened {
+    fn open(path: &Path) -> Result<Self, Error> {
+        Db::open(path).map(Opened)
+    }
+}
+
+impl Deref for Opened {
+    type Target = Db<'static>;
+
+    fn deref(&self) -> &Db<'static> {
+        &self.0
+    }
+}
+
+impl Drop for Opened {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// `extract_uuids` scans every trail once the UUID list is at least
+/// 1/`SCAN_RATIO` of the database.
+const SCAN_RATIO: u64 = 16;
+
+/// Copy the events of `trails` that pass `filter` and `keep` into a new
+/// database at `dst`. Output field `i` takes its values from source field
+/// `sources[i]`.
+fn rewrite<'a, I, F>(db: &'a Db<'a>,
+                     dst: &Path,
+                     fields: &[&str],
+                     sources: &[Field],
+                     filter: Option<&'a EventFilter>,
+                     trails: I,
+                     keep: F)
+                     -> Result<u64, Error>
+    where I: IntoIterator<Item = TrailId>,
+          F: FnMut(&Event) -> bool
+{
+    rewrite_cancellable(db, dst, fields, sources, filter, trails, keep, |uuid| Some(*uuid), None)
+}
+
+/// `rewrite`, writing every trail under the UUID `uuids` maps it to, or
+/// leaving it out for `None`, and checking `cancel` before every trail. The
+/// constructor is closed without finalizing when cancelled.
+#[allow(clippy::too_many_arguments)]
+#[cfg_attr(feature = "tracing",
+           tracing::instrument(level = "debug",
+                               skip_all,
+                               fields(dst = %dst.display(), events = tracing::field::Empty)))]
+pub(crate) fn rewrite_cancellable<'a, I, F, U>(db: &'a Db<'a>,
+                                               dst: &Path,
+                                               fields: &[&str],
+                                               sources: &[Field],
+                                               filter: Option<&'a EventFilter>,
+                                               trails: I,
+                                               mut keep: F,
+                                               mut uuids: U,
+                                               cancel: Option<&AtomicBool>)
+                                               -> Result<u64, Error>
+    where I: IntoIterator<Item = TrailId>,
+          F: FnMut(&Event) -> bool,
+          U: FnMut(&Uuid) -> Option<Uuid>
+{
+    let mut cons = ConstructorBuilder::new(dst, fields)
+        .expected_trails(db.num_trails() as usize)
+        .expected_events(db.num_events() as usize)
+        .build()?;
+    let mut cursor = db.cursor();
+    if let Some(filter) = filter {
+        cursor.set_event_filter(filter)?;
+    }
+    let mut values: Vec<&str> = Vec::with_capacity(sources.len());
+    let mut count = 0;
+    for trail_id in trails {
+        if cancel.map_or(false, |cancel| cancel.load(Ordering::Relaxed)) {
+            cons.close();
+            return Err(Error::Cancelled);
+        }
+        let uuid = match uuids(db.get_uuid(trail_id).ok_or(Error::InvalidTrailId)?) {
+            Some(uuid) => uuid,
+            None => continue,
+        };
+        cursor.get_trail(trail_id)?;
+        for event in &mut cursor {
+            if !keep(&event) {
+                continue;
+            }
+            // Item i of an event belongs to field i + 1; field 0 is the timestamp.
+            values.clear();
+            values.extend(sources.iter().map(|&field| db.get_item_value(event.items[field as usize - 1])));
+            cons.add(&uuid, event.timestamp, &values)?;
+            count += 1;
+        }
+    }
+    cons.finalize()?;
+    record_span!("events", count);
+    Ok(count)
+}
+
+
+
+
+#[cfg(test)]
+pub(crate) mod test_copy {
+    use super::{merge, merge_cancellable, migrate, prune, Downsample, MigrateReport};
+    use super::super::{Constructor, Db, Error, EventFilter, VERSION_LATEST, VERSION_V0, VERSION_V0_1};
+    use std::path::Path;
+    use std::sync::atomic::AtomicBool;
+
+    /// Three trails: alice with two events, bob and carol with one each.
+    pub(crate) fn source(path: &Path) -> Db<'static> {
+        let mut cons = Constructor::new(path, &["user", "action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["alice", "login"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["alice", "logout"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 3, &["bob", "login"]).is_ok());
+        assert!(cons.add(&[3u8; 16], 4, &["carol", "logout"]).is_ok());
+        assert!(cons.finalize().is_ok());
+        Db::open(path).unwrap()
+    }
+
+    #[test]
+    fn test_copy_filtered() {
+        let db = source(Path::new("test_copy_filtered_src"));
+        let action = db.get_field("action").unwrap();
+        let mut filter = EventFilter::new();
+        filter.add_term(db.get_item(action, "login").unwrap(), false).unwrap();
+        let dst_path = Path::new("test_copy_filtered_dst");
+        assert_eq!(db.copy_filtered(dst_path, &filter).unwrap(), 2);
+
+        let dst = Db::open(dst_path).unwrap();
+        assert_eq!(dst.field_names(), vec!["user", "action"]);
+        assert_eq!(dst.num_trails(), 2);
+        assert_eq!(dst.num_events(), 2);
+        let trail_id = dst.get_trail_id(&[2u8; 16]).unwrap();
+        let batch = dst.trail_batch(trail_id).unwrap();
+        assert_eq!(batch.events[0].values, vec!["bob", "login"]);
+    }
+
+    #[test]
+    fn test_extract_uuids() {
+        let db = source(Path::new("test_extract_uuids_src"));
+        let dst_path = Path::new("test_extract_uuids_dst");
+        assert_eq!(db.extract_uuids(dst_path, &[[3u8; 16], [1u8; 16], [9u8; 16]]).unwrap(), 3);
+
+        let dst = Db::open(dst_path).unwrap();
+        assert_eq!(dst.num_trails(), 2);
+        assert!(dst.get_trail_id(&[2u8; 16]).is_none());
+        let trail_id = dst.get_trail_id(&[1u8; 16]).unwrap();
+        assert_eq!(dst.trail_batch(trail_id).unwrap().events.len(), 2);
+    }
+
+    #[test]
+    fn test_extract_time_range() {
+        let db = source(Path::new("test_extract_time_range_src"));
+        let dst_path = Path::new("test_extract_time_range_dst");
+        assert_eq!(db.extract_time_range(dst_path, 2, 4).unwrap(), 2);
+
+        let dst = Db::open(dst_path).unwrap();
+        assert_eq!(dst.num_trails(), 2);
+        assert_eq!(dst.min_timestamp(), 2);
+        assert_eq!(dst.max_timestamp(), 3);
+    }
+
+    #[test]
+    fn test_copy_projected() {
+        let db = source(Path::new("test_copy_projected_src"));
+        let dst_path = Path::new("test_copy_projected_dst");
+        assert_eq!(db.copy_projected(dst_path, &["action"]).unwrap(), 4);
+        assert_eq!(db.copy_projected(Path::new("test_copy_projected_bad"), &["email"]),
+                   Err(Error::UnknownField));
+
+        let dst = Db::open(dst_path).unwrap();
+        assert_eq!(dst.field_names(), vec!["action"]);
+        assert_eq!(dst.num_events(), 4);
+        let trail_id = dst.get_trail_id(&[3u8; 16]).unwrap();
+        assert_eq!(dst.trail_batch(trail_id).unwrap().events[0].values, vec!["logout"]);
+    }
+
+    #[test]
+    fn test_copy_mapped() {
+        let db = source(Path::new("test_copy_mapped_src"));
+        let dst_path = Path::new("test_copy_mapped_dst");
+        let mapping = [("event", "action"), ("username", "user")];
+        assert_eq!(db.copy_mapped(dst_path, &mapping).unwrap(), 4);
+
+        let dst = Db::open(dst_path).unwrap();
+        assert_eq!(dst.field_names(), vec!["event", "username"]);
+        let trail_id = dst.get_trail_id(&[2u8; 16]).unwrap();
+        assert_eq!(dst.trail_batch(trail_id).unwrap().events[0].values, vec!["login", "bob"]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let first = Path::new("test_merge_first");
+        let second = Path::new("test_merge_second");
+        source(first);
+        let mut cons = Constructor::new(second, &["user", "action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 5, &["alice", "login"]).is_ok());
+        assert!(cons.add(&[4u8; 16], 6, &["dave", "login"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let dst_path = Path::new("test_merge_dst");
+        let report = merge(dst_path, &[first, second]).unwrap();
+        assert_eq!(report.inputs, 2);
+        assert_eq!(report.input_trails, 5);
+        assert_eq!(report.num_trails, 4);
+        assert_eq!(report.num_events, 6);
+        assert_eq!((report.min_timestamp, report.max_timestamp), (1, 6));
+
+        let dst = Db::open(dst_path).unwrap();
+        assert_eq!(dst.num_trails(), 4);
+        let trail_id = dst.get_trail_id(&[1u8; 16]).unwrap();
+        assert_eq!(dst.trail_batch(trail_id).unwrap().events.len(), 3);
+
+        let other = Path::new("test_merge_other");
+        let mut cons = Constructor::new(other, &["user"]).unwrap();
+        assert!(cons.finalize().is_ok());
+        assert_eq!(merge(Path::new("test_merge_bad"), &[first, other]),
+                   Err(Error::AppendFieldsMismatch));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let src = Path::new("test_cancel_src");
+        let db = source(src);
+        let cancel = AtomicBool::new(true);
+        let dst_path = Path::new("test_cancel_extract");
+        assert_eq!(db.extract_cancellable(dst_path, None, 0, u64::MAX, &cancel),
+                   Err(Error::Cancelled));
+        assert!(Db::open(dst_path).is_err());
+
+        let dst_path = Path::new("test_cancel_merge");
+        assert_eq!(merge_cancellable(dst_path, &[src], |_| {}, &cancel).map(|_| ()),
+                   Err(Error::Cancelled));
+        assert!(Db::open(dst_path).is_err());
+    }
+
+    #[test]
+    fn test_split() {
+        let db = source(Path::new("test_split_src"));
+        let dst_dir = Path::new("test_split_dst");
+        let counts = db.split(dst_dir, 2, |uuid| uuid[0] as u64).unwrap();
+        assert_eq!(counts, vec![1, 3]);
+
+        let even = Db::open(&dst_dir.join("shard-00000")).unwrap();
+        assert_eq!(even.num_trails(), 1);
+        assert!(even.get_trail_id(&[2u8; 16]).is_some());
+        let odd = Db::open(&dst_dir.join("shard-00001")).unwrap();
+        assert_eq!(odd.num_trails(), 2);
+        assert_eq!(odd.field_names(), vec!["user", "action"]);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let src = source(Path::new("test_migrate_src"));
+        let dst_path = Path::new("test_migrate_dst");
+        let report = migrate(Path::new("test_migrate_src"), dst_path).unwrap();
+        // The constructor writes the latest format, so there is nothing to
+        // upgrade, but the versions are still read from both databases.
+        assert_eq!(src.version(), VERSION_V0_1);
+        assert_eq!(report.from_version, VERSION_V0_1);
+        assert_eq!(report.to_version, VERSION_LATEST);
+        assert!(!report.upgraded());
+        assert_eq!((report.num_trails, report.num_events), (3, 4));
+
+        let dst = Db::open(dst_path).unwrap();
+        assert_eq!(dst.version(), VERSION_LATEST);
+        assert_eq!(dst.field_names(), src.field_names());
+        for trail_id in 0..src.num_trails() {
+            let before = src.trail_batch(trail_id).unwrap();
+            let after = dst.trail_batch(dst.get_trail_id(&before.uuid).unwrap()).unwrap();
+            assert_eq!(after, before);
+        }
+
+        let legacy = MigrateReport {
+            from_version: VERSION_V0,
+            to_version: VERSION_LATEST,
+            num_trails: 0,
+            num_events: 0,
+        };
+        assert!(legacy.upgraded());
+    }
+
+    #[test]
+    fn test_prune() {
+        source(Path::new("test_prune_src"));
+        let dst_path = Path::new("test_prune_dst");
+        let report = prune(Path::new("test_prune_src"), dst_path, 3).unwrap();
+        assert_eq!((report.num_trails, report.num_events), (2, 2));
+        assert_eq!((report.dropped_trails, report.dropped_events), (1, 2));
+
+        let dst = Db::open(dst_path).unwrap();
+        assert_eq!(dst.get_trail_id(&[1u8; 16]), None);
+        assert_eq!(dst.min_timestamp(), 3);
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let src = source(Path::new("test_rewrite_src"));
+        let dst_path = Path::new("test_rewrite_dst");
+        let written = src.rewrite(dst_path, |uuid, event| {
+                if uuid == &[2u8; 16] {
+                    return None;
+                }
+                Some(vec![event.values[0].to_uppercase(), event.values[1].clone()])
+            })
+            .unwrap();
+        assert_eq!(written, 3);
+
+        let dst = Db::open(dst_path).unwrap();
+        assert_eq!(dst.field_names(), src.field_names());
+        assert_eq!(dst.get_trail_id(&[2u8; 16]), None);
+        let trail_id = dst.get_trail_id(&[3u8; 16]).unwrap();
+        assert_eq!(dst.trail_batch(trail_id).unwrap().events[0].values, vec!["CAROL", "logout"]);
+
+        let projected = src.rewrite_fields(Path::new("test_rewrite_fields_dst"), &["who"], |_, event| {
+                Some(vec![event.values[0].clone()])
+            })
+            .unwrap();
+        assert_eq!(projected, 4);
+    }
+
+    #[test]
+    fn test_append_with_default() {
+        let src = source(Path::new("test_append_with_default_src"));
+        let dst_path = Path::new("test_append_with_default_dst");
+        let mut cons = Constructor::new(dst_path, &["action", "country", "user"]).unwrap();
+        assert_eq!(cons.append(&src), Err(Error::AppendFieldsMismatch));
+        assert_eq!(cons.append_with_default(&src, "unknown").unwrap(), 4);
+        let added = cons.append_with(&src, |field, _, event| format!("{}-{}", field, event.values[0]))
+            .unwrap();
+        assert_eq!(added, 4);
+        assert!(cons.finalize().is_ok());
+
+        let dst = Db::open(dst_path).unwrap();
+        assert_eq!(dst.num_events(), 8);
+        let trail_id = dst.get_trail_id(&[2u8; 16]).unwrap();
+        let mut countries: Vec<String> = dst.trail_batch(trail_id)
+            .unwrap()
+            .events
+            .into_iter()
+            .map(|event| event.values[1].clone())
+            .collect();
+        countries.sort();
+        assert_eq!(countries, vec!["country-bob", "unknown"]);
+
+        let mut narrow = Constructor::new(Path::new("test_append_with_default_narrow"), &["user"]).unwrap();
+        assert_eq!(narrow.append_with_default(&src, ""), Err(Error::AppendFieldsMismatch));
+        narrow.close();
+    }
+
+    #[test]
+    fn test_downsample() {
+        let src = source(Path::new("test_downsample_src"));
+        let written = src.downsample(Path::new("test_downsample_nth"), Downsample::EveryNthTrail(2)).unwrap();
+        let nth = Db::open(Path::new("test_downsample_nth")).unwrap();
+        assert_eq!(nth.num_trails(), 2);
+        assert_eq!(nth.num_events(), written);
+
+        assert_eq!(src.downsample(Path::new("test_downsample_cap"), Downsample::PerTrailCap(1)).unwrap(),
+                   3);
+        let cap = Db::open(Path::new("test_downsample_cap")).unwrap();
+        assert_eq!(cap.num_trails(), 3);
+        let trail_id = cap.get_trail_id(&[1u8; 16]).unwrap();
+        assert_eq!(cap.trail_batch(trail_id).unwrap().events[0].values, vec!["alice", "login"]);
+    }
+}
@@ -0,0 +1,118 @@
+//! Statistics kept by every `Db` about the work done on it, to attribute
+//! the cost of a scan without a profiler.
+
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::Db;
+
+/// A snapshot of the counters of a `Db`, returned by `Db::counters`.
+#[derive(Debug,Clone,Copy,Default,PartialEq)]
+pub struct Counters {
+    /// Cursors created.
+    pub cursors: u64,
+    /// Trails loaded into cursors.
+    pub trails: u64,
+    /// Events returned by cursors.
+    pub events: u64,
+    /// The size of the files mapped when the database was opened. Not
+    /// reset; how much of it a scan touches is up to the page cache.
+    pub bytes_mapped: u64,
+}
+
+/// The live counters of a `Db`, shared with its cursors. Cursors publish
+/// the events they return when they load the next trail or are dropped, so
+/// that counting doesn't add an atomic operation per event.
+#[derive(Debug,Default)]
+pub(crate) struct DbCounters {
+    pub(crate) cursors: AtomicU64,
+    pub(crate) trails: AtomicU64,
+    pub(crate) events: AtomicU64,
+    pub(crate) bytes_mapped: AtomicU64,
+}
+
+impl DbCounters {
+    /// Counters for the database opened from `path`.
+    pub(crate) fn opened(path: &Path) -> Self {
+        let counters = DbCounters::default();
+        counters.bytes_mapped.store(mapped_size(path), Ordering::Relaxed);
+        counters
+    }
+}
+
+impl<'a> Db<'a> {
+    /// The counters of the database, covering every cursor created from it
+    /// since it was opened or since the last `reset_counters`. Events still
+    /// being returned by a cursor are counted once it moves to the next
+    /// trail or is dropped.
+    pub fn counters(&self) -> Counters {
+        Counters {
+            cursors: self.counters.cursors.load(Ordering::Relaxed),
+            trails: self.counters.trails.load(Ordering::Relaxed),
+            events: self.counters.events.load(Ordering::Relaxed),
+            bytes_mapped: self.counters.bytes_mapped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Return the counters and start them over, e.g. before each scan.
+    pub fn reset_counters(&self) -> Counters {
+        Counters {
+            cursors: self.counters.cursors.swap(0, Ordering::Relaxed),
+            trails: self.counters.trails.swap(0, Ordering::Relaxed),
+            events: self.counters.events.swap(0, Ordering::Relaxed),
+            bytes_mapped: self.counters.bytes_mapped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The size of the database libtraildb opens for `path`: the files of a
+/// database directory, or a package at `path` or `path.tdb`.
+fn mapped_size(path: &Path) -> u64 {
+    let size = |path: &Path| fs::metadata(path).map(|m| m.len()).ok();
+    if path.is_dir() {
+        fs::read_dir(path)
+            .map(|entries| entries.filter_map(|e| e.ok()).filter_map(|e| size(&e.path())).sum())
+            .unwrap_or(0)
+    } else {
+        let mut packaged = path.as_os_str().to_owned();
+        packaged.push(".tdb");
+        size(path).or_else(|| size(Path::new(&packaged))).unwrap_or(0)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_counters {
+    use super::super::{Constructor, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_counters() {
+        let db_path = Path::new("test_counters");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        for t in 0..3 {
+            assert!(cons.add(&[1u8; 16], t, &["view"]).is_ok());
+            assert!(cons.add(&[2u8; 16], t, &["view"]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        assert!(db.counters().bytes_mapped > 0);
+        {
+            let mut cursor = db.cursor();
+            for trail_id in 0..db.num_trails() {
+                cursor.get_trail(trail_id).unwrap();
+                assert_eq!((&mut cursor).count(), 3);
+            }
+        }
+        let counters = db.reset_counters();
+        assert_eq!(counters.cursors, 1);
+        assert_eq!(counters.trails, 2);
+        assert_eq!(counters.events, 6);
+        assert_eq!(db.counters().events, 0);
+        assert_eq!(db.counters().bytes_mapped, counters.bytes_mapped);
+    }
+}
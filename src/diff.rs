@@ -0,0 +1,302 @@
+//! Comparing two databases, or the history of a trail across them.
+//!
+//! Events are matched by timestamp. Events identical on both sides are left
+//! out; of the rest, events sharing a timestamp are paired up as changed, in
+//! order, and the remainder are added or removed.
+//!
+//! `diff_dbs` compares whole databases: their fields, which trails they
+//! hold and the events of the trails they share, e.g. to check that a
+//! rewritten or migrated database matches the original.
+
+use std::collections::BTreeMap;
+
+use super::{Db, Error, ResolvedEvent, Uuid};
+
+/// The differences between two versions of a trail.
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct TrailDiff {
+    /// The field names the values of every event are ordered by.
+    pub fields: Vec<String>,
+    /// Events only in the first trail.
+    pub removed: Vec<ResolvedEvent>,
+    /// Events only in the second trail.
+    pub added: Vec<ResolvedEvent>,
+    /// Events with the same timestamp but different values, as
+    /// `(first, second)`.
+    pub changed: Vec<(ResolvedEvent, ResolvedEvent)>,
+}
+
+impl TrailDiff {
+    /// Whether both trails hold the same events.
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// The differences between two databases, as found by `diff_dbs`.
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct DbDiff {
+    /// Fields only the first database has.
+    pub fields_only_a: Vec<String>,
+    /// Fields only the second database has.
+    pub fields_only_b: Vec<String>,
+    /// Whether the shared fields are in a different order.
+    pub field_order_differs: bool,
+    /// The number of trails in both databases.
+    pub shared_trails: u64,
+    /// Trails only in the first database.
+    pub trails_only_a: Vec<Uuid>,
+    /// Trails only in the second database.
+    pub trails_only_b: Vec<Uuid>,
+    /// Shared trails with a different number of events, as
+    /// `(uuid, events in a, events in b)`.
+    pub event_counts: Vec<(Uuid, u64, u64)>,
+    /// Shared trails with as many events on both sides, but different ones.
+    pub changed_trails: Vec<Uuid>,
+}
+
+impl DbDiff {
+    /// Whether both databases have the same fields, in the same order, and
+    /// the same trails holding the same events.
+    pub fn is_empty(&self) -> bool {
+        self.fields_only_a.is_empty() && self.fields_only_b.is_empty() &&
+        !self.field_order_differs && self.trails_only_a.is_empty() &&
+        self.trails_only_b.is_empty() && self.event_counts.is_empty() &&
+        self.changed_trails.is_empty()
+    }
+}
+
+/// Diff two event lists whose values are in the same field order.
+pub fn diff_events(a: &[ResolvedEvent], b: &[ResolvedEvent]) -> TrailDiff {
+    let mut by_time: BTreeMap<u64, (Vec<&ResolvedEvent>, Vec<&ResolvedEvent>)> = BTreeMap::new();
+    for event in a {
+        by_time.entry(event.timestamp).or_insert_with(Default::default).0.push(event);
+    }
+    for event in b {
+        by_time.entry(event.timestamp).or_insert_with(Default::default).1.push(event);
+    }
+
+    let mut diff = TrailDiff::default();
+    for (_, (mut old, mut new)) in by_time {
+        old.retain(|event| match new.iter().position(|other| other == event) {
+            Some(i) => {
+                new.remove(i);
+                false
+            }
+            None => true,
+        });
+        let paired = old.len().min(new.len());
+        for (old, new) in old.iter().zip(&new) {
+            diff.changed.push(((*old).clone(), (*new).clone()));
+        }
+        diff.removed.extend(old[paired..].iter().map(|&e| e.clone()));
+        diff.added.extend(new[paired..].iter().map(|&e| e.clone()));
+    }
+    diff
+}
+
+/// Diff the trail of `uuid` in `a` against the one in `b`. A trail missing
+/// from one database counts as having no events.
+///
+/// Fields are matched by name: values are ordered by the fields of `a`
+/// followed by fields only `b` has, and a field missing from one database
+/// is empty in all of its events.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::diff::diff_trails;
+/// use std::path::Path;
+///
+/// let before = Db::open(Path::new("events")).unwrap();
+/// let after = Db::open(Path::new("events_migrated")).unwrap();
+/// for trail_id in 0..before.num_trails() {
+///     let uuid = before.get_uuid(trail_id).unwrap();
+///     let diff = diff_trails(&before, &after, uuid).unwrap();
+///     assert!(diff.is_empty(), "{:?}", diff);
+/// }
+/// ```
+pub fn diff_trails(a: &Db, b: &Db, uuid: &Uuid) -> Result<TrailDiff, Error> {
+    let mut fields: Vec<String> = a.field_names().iter().map(|f| f.to_string()).collect();
+    for name in b.field_names() {
+        if !fields.iter().any(|f| f == name) {
+            fields.push(name.to_string());
+        }
+    }
+    let events_a = aligned_events(a, uuid, &fields)?;
+    let events_b = aligned_events(b, uuid, &fields)?;
+    let mut diff = diff_events(&events_a, &events_b);
+    diff.fields = fields;
+    Ok(diff)
+}
+
+/// Compare the fields and trails of `a` and `b`, and the events of every
+/// trail they share.
+///
+/// Events are compared on the fields both databases have, matched by name,
+/// so adding or reordering fields alone doesn't make trails differ. UUIDs
+/// are listed in the order of the database they come from.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::diff::diff_dbs;
+/// use std::path::Path;
+///
+/// let before = Db::open(Path::new("events")).unwrap();
+/// let after = Db::open(Path::new("events_migrated")).unwrap();
+/// let diff = diff_dbs(&before, &after).unwrap();
+/// assert!(diff.trails_only_a.is_empty() && diff.changed_trails.is_empty());
+/// ```
+pub fn diff_dbs(a: &Db, b: &Db) -> Result<DbDiff, Error> {
+    let names_a = a.field_names();
+    let names_b = b.field_names();
+    let shared: Vec<String> = names_a.iter()
+        .filter(|name| names_b.contains(name))
+        .map(|name| name.to_string())
+        .collect();
+    let mut diff = DbDiff {
+        fields_only_a: names_a.iter()
+            .filter(|name| !names_b.contains(name))
+            .map(|name| name.to_string())
+            .collect(),
+        fields_only_b: names_b.iter()
+            .filter(|name| !names_a.contains(name))
+            .map(|name| name.to_string())
+            .collect(),
+        field_order_differs: !names_b.iter()
+            .filter(|name| names_a.contains(name))
+            .eq(shared.iter()),
+        ..DbDiff::default()
+    };
+
+    for trail_id in 0..a.num_trails() {
+        let uuid = *a.get_uuid(trail_id).ok_or(Error::InvalidTrailId)?;
+        if b.get_trail_id(&uuid).is_none() {
+            diff.trails_only_a.push(uuid);
+            continue;
+        }
+        diff.shared_trails += 1;
+        let events_a = aligned_events(a, &uuid, &shared)?;
+        let events_b = aligned_events(b, &uuid, &shared)?;
+        if events_a.len() != events_b.len() {
+            diff.event_counts.push((uuid, events_a.len() as u64, events_b.len() as u64));
+        } else if events_a != events_b && !diff_events(&events_a, &events_b).is_empty() {
+            // Events sharing a timestamp may come in another order.
+            diff.changed_trails.push(uuid);
+        }
+    }
+    for trail_id in 0..b.num_trails() {
+        let uuid = *b.get_uuid(trail_id).ok_or(Error::InvalidTrailId)?;
+        if a.get_trail_id(&uuid).is_none() {
+            diff.trails_only_b.push(uuid);
+        }
+    }
+    Ok(diff)
+}
+
+/// The events of `uuid` in `db`, with values ordered by `fields`.
+fn aligned_events(db: &Db, uuid: &Uuid, fields: &[String]) -> Result<Vec<ResolvedEvent>, Error> {
+    let trail_id = match db.get_trail_id(uuid) {
+        Some(trail_id) => trail_id,
+        None => return Ok(Vec::new()),
+    };
+    let positions: Vec<Option<usize>> = fields.iter()
+        .map(|name| db.get_field(name).map(|field| field as usize - 1))
+        .collect();
+    let batch = db.trail_batch(trail_id)?;
+    Ok(batch.events
+        .into_iter()
+        .map(|event| {
+            ResolvedEvent {
+                timestamp: event.timestamp,
+                values: positions.iter()
+                    .map(|p| p.and_then(|p| event.values.get(p).cloned()).unwrap_or_default())
+                    .collect(),
+            }
+        })
+        .collect())
+}
+
+
+
+
+#[cfg(test)]
+mod test_diff {
+    use super::{diff_dbs, diff_events, diff_trails};
+    use super::super::{Constructor, Db, ResolvedEvent};
+    use std::path::Path;
+
+    fn event(timestamp: u64, values: &[&str]) -> ResolvedEvent {
+        ResolvedEvent {
+            timestamp: timestamp,
+            values: values.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff_events() {
+        let a = vec![event(1, &["x"]), event(2, &["y"]), event(3, &["z"])];
+        let b = vec![event(1, &["x"]), event(2, &["Y"]), event(4, &["w"])];
+        let diff = diff_events(&a, &b);
+        assert_eq!(diff.changed, vec![(event(2, &["y"]), event(2, &["Y"]))]);
+        assert_eq!(diff.removed, vec![event(3, &["z"])]);
+        assert_eq!(diff.added, vec![event(4, &["w"])]);
+        assert!(diff_events(&a, &a).is_empty());
+    }
+
+    #[test]
+    fn test_diff_trails() {
+        let path_a = Path::new("test_diff_trails_a");
+        let mut cons = Constructor::new(path_a, &["action", "page"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["view", "/"]).is_ok());
+        assert!(cons.finalize().is_ok());
+        let path_b = Path::new("test_diff_trails_b");
+        let mut cons = Constructor::new(path_b, &["page", "action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["/", "view"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["/a", "view"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let a = Db::open(path_a).unwrap();
+        let b = Db::open(path_b).unwrap();
+        let diff = diff_trails(&a, &b, &[1u8; 16]).unwrap();
+        assert_eq!(diff.fields, vec!["action", "page"]);
+        assert_eq!(diff.added, vec![event(2, &["view", "/a"])]);
+        assert!(diff.removed.is_empty() && diff.changed.is_empty());
+        assert!(diff_trails(&a, &b, &[2u8; 16]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_diff_dbs() {
+        let path_a = Path::new("test_diff_dbs_a");
+        let mut cons = Constructor::new(path_a, &["action", "page"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["view", "/"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 1, &["view", "/"]).is_ok());
+        assert!(cons.add(&[3u8; 16], 1, &["view", "/"]).is_ok());
+        assert!(cons.add(&[4u8; 16], 1, &["view", "/"]).is_ok());
+        assert!(cons.finalize().is_ok());
+        let path_b = Path::new("test_diff_dbs_b");
+        let mut cons = Constructor::new(path_b, &["page", "action", "referrer"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["/", "view", "x"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 1, &["/", "view", ""]).is_ok());
+        assert!(cons.add(&[2u8; 16], 2, &["/", "view", ""]).is_ok());
+        assert!(cons.add(&[3u8; 16], 1, &["/a", "view", ""]).is_ok());
+        assert!(cons.add(&[5u8; 16], 1, &["/", "view", ""]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let a = Db::open(path_a).unwrap();
+        let b = Db::open(path_b).unwrap();
+        let diff = diff_dbs(&a, &b).unwrap();
+        assert!(diff.fields_only_a.is_empty());
+        assert_eq!(diff.fields_only_b, vec!["referrer"]);
+        assert!(diff.field_order_differs);
+        assert_eq!(diff.shared_trails, 3);
+        assert_eq!(diff.trails_only_a, vec![[4u8; 16]]);
+        assert_eq!(diff.trails_only_b, vec![[5u8; 16]]);
+        assert_eq!(diff.event_counts, vec![([2u8; 16], 1, 2)]);
+        assert_eq!(diff.changed_trails, vec![[3u8; 16]]);
+        assert!(diff_dbs(&a, &a).unwrap().is_empty());
+    }
+}
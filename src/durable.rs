@@ -0,0 +1,73 @@
+//! Getting finalized databases onto disk for good.
+//!
+//! libtraildb writes a database with buffered writes and doesn't flush
+//! them, so a database that was just finalized, or renamed into place, can
+//! still be lost or come back truncated if the machine goes down.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "json")]
+use super::reload::append_manifest;
+
+/// Where a constructor opened with `path` finalized its database: the
+/// package `<path>.tdb` unless it was built as a directory.
+pub(crate) fn finalized_path(path: &Path) -> PathBuf {
+    let mut packaged = path.as_os_str().to_owned();
+    packaged.push(".tdb");
+    let packaged = PathBuf::from(packaged);
+    if packaged.exists() {
+        packaged
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Publish the database a constructor opened with the hidden path
+/// `dst_dir/.<name>` finalized: flush it, rename it to `dst_dir/<name>`,
+/// keeping its `.tdb` extension if it is a package, and list it in
+/// `manifest`. Returns where it was published.
+#[cfg(feature = "json")]
+pub(crate) fn publish(dst_dir: &Path, name: &str, manifest: Option<&Path>) -> io::Result<PathBuf> {
+    let hidden = finalized_path(&dst_dir.join(format!(".{}", name)));
+    sync_db(&hidden)?;
+    let path = match hidden.extension() {
+        Some(ext) if ext == "tdb" => dst_dir.join(format!("{}.tdb", name)),
+        _ => dst_dir.join(name),
+    };
+    fs::rename(&hidden, &path)?;
+    sync_dir(dst_dir)?;
+    if let Some(manifest) = manifest {
+        append_manifest(manifest, &path)?;
+    }
+    Ok(path)
+}
+
+/// Flush the database at `path`, a package or a directory, and the entry
+/// of its parent directory pointing at it.
+pub(crate) fn sync_db(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                File::open(entry.path())?.sync_all()?;
+            }
+        }
+        sync_dir(path)?;
+    } else {
+        File::open(path)?.sync_all()?;
+    }
+    sync_dir(path.parent().unwrap_or_else(|| Path::new(".")))
+}
+
+/// Flush the entries of `dir`, so that files created or renamed in it
+/// stay put. Only Unix can open a directory to flush it; elsewhere this
+/// does nothing.
+pub(crate) fn sync_dir(dir: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
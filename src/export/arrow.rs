@@ -0,0 +1,261 @@
+//! Conversion of events into Apache Arrow `RecordBatch`es.
+//!
+//! Every batch has a `uuid` column (16 byte fixed size binary), a `timestamp`
+//! column and one dictionary-encoded string column per field. Dictionary
+//! keys are the value ids of the field's lexicon and the dictionary is the
+//! lexicon itself, built once and shared by every batch, so no value is
+//! copied per event.
+
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, DictionaryArray, FixedSizeBinaryBuilder, StringArray, UInt64Array};
+use arrow::datatypes::{DataType, Field as ArrowField, Schema, SchemaRef, UInt64Type};
+use arrow::record_batch::RecordBatch;
+
+use super::ExportError;
+use super::super::{Cursor, Db, Error, Event, EventFilter, Field, TrailId, Uuid};
+
+/// Converts events of one `Db` into `RecordBatch`es sharing a schema and
+/// lexicon dictionaries.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::export::arrow::ArrowConverter;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let converter = ArrowConverter::new(&db);
+/// for batch in converter.batches(&db, 8192) {
+///     let batch = batch.unwrap();
+///     println!("{} rows", batch.num_rows());
+/// }
+/// ```
+pub struct ArrowConverter {
+    schema: SchemaRef,
+    dictionaries: Vec<ArrayRef>,
+}
+
+impl ArrowConverter {
+    /// Derive the schema from `db` and load every field's lexicon.
+    pub fn new(db: &Db) -> Self {
+        let mut columns = vec![ArrowField::new("uuid", DataType::FixedSizeBinary(16), false),
+                               ArrowField::new("timestamp", DataType::UInt64, false)];
+        let mut dictionaries = Vec::new();
+        for (i, name) in db.field_names().iter().enumerate() {
+            let field = i as Field + 1;
+            let dict_type = DataType::Dictionary(Box::new(DataType::UInt64),
+                                                 Box::new(DataType::Utf8));
+            columns.push(ArrowField::new(*name, dict_type, false));
+            let lexicon = StringArray::from_iter_values((0..db.lexicon_size(field))
+                .map(|val| db.get_value(field, val).unwrap_or("")));
+            dictionaries.push(Arc::new(lexicon) as ArrayRef);
+        }
+        ArrowConverter {
+            schema: Arc::new(Schema::new(columns)),
+            dictionaries: dictionaries,
+        }
+    }
+
+    /// The schema of every batch produced by this converter.
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// The lexicon of every field, in field order, as used for dictionaries.
+    pub fn dictionaries(&self) -> &[ArrayRef] {
+        &self.dictionaries
+    }
+
+    /// Convert all events of one trail into a single batch.
+    pub fn trail_batch(&self, db: &Db, trail_id: TrailId) -> Result<RecordBatch, ExportError> {
+        let uuid = *db.get_uuid(trail_id).ok_or(Error::InvalidTrailId)?;
+        let mut cursor = db.cursor();
+        cursor.get_trail(trail_id)?;
+        let mut columns = Columns::new(self.dictionaries.len(), cursor.len() as usize);
+        for event in &mut cursor {
+            columns.push(&uuid, &event);
+        }
+        finish_batch(&mut columns, self.schema.clone(), &self.dictionaries)
+    }
+
+    /// Iterate over all events of `db` in batches of at most `batch_size`
+    /// events. A trail may span several batches.
+    pub fn batches<'a>(&self, db: &'a Db<'a>, batch_size: usize) -> RecordBatches<'a> {
+        RecordBatches {
+            db: db,
+            cursor: db.cursor(),
+            schema: self.schema.clone(),
+            dictionaries: self.dictionaries.clone(),
+            batch_size: batch_size,
+            next_trail: 0,
+            current: None,
+        }
+    }
+
+    /// Like `batches`, restricted to events matching `filter`.
+    pub fn batches_filtered<'a>(&self,
+                                db: &'a Db<'a>,
+                                filter: &'a EventFilter,
+                                batch_size: usize)
+                                -> Result<RecordBatches<'a>, Error> {
+        let mut batches = self.batches(db, batch_size);
+        batches.cursor.set_event_filter(filter)?;
+        Ok(batches)
+    }
+}
+
+/// Column buffers for one batch under construction.
+pub(crate) struct Columns {
+    uuids: FixedSizeBinaryBuilder,
+    timestamps: Vec<u64>,
+    keys: Vec<Vec<u64>>,
+}
+
+impl Columns {
+    pub(crate) fn new(num_fields: usize, capacity: usize) -> Self {
+        Columns {
+            uuids: FixedSizeBinaryBuilder::with_capacity(capacity, 16),
+            timestamps: Vec::with_capacity(capacity),
+            keys: (0..num_fields).map(|_| Vec::with_capacity(capacity)).collect(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    pub(crate) fn push(&mut self, uuid: &Uuid, event: &Event) {
+        // The builder only fails on values that aren't 16 bytes wide.
+        self.uuids.append_value(uuid).unwrap();
+        self.timestamps.push(event.timestamp);
+        for (i, keys) in self.keys.iter_mut().enumerate() {
+            keys.push(event.items.get(i).map_or(0, |item| item.value()));
+        }
+    }
+}
+
+/// Turn the buffered columns into a batch, leaving the buffers empty.
+pub(crate) fn finish_batch(columns: &mut Columns,
+                schema: SchemaRef,
+                dictionaries: &[ArrayRef])
+                -> Result<RecordBatch, ExportError> {
+    let mut arrays: Vec<ArrayRef> = Vec::with_capacity(2 + dictionaries.len());
+    arrays.push(Arc::new(columns.uuids.finish()));
+    arrays.push(Arc::new(UInt64Array::from(columns.timestamps.split_off(0))));
+    for (keys, dictionary) in columns.keys.iter_mut().zip(dictionaries) {
+        let keys = UInt64Array::from(keys.split_off(0));
+        let array = DictionaryArray::<UInt64Type>::try_new(keys, dictionary.clone())?;
+        arrays.push(Arc::new(array));
+    }
+    Ok(RecordBatch::try_new(schema, arrays)?)
+}
+
+/// An iterator over the events of a `Db` as `RecordBatch`es, created by
+/// `ArrowConverter::batches`.
+pub struct RecordBatches<'a> {
+    db: &'a Db<'a>,
+    cursor: Cursor<'a>,
+    schema: SchemaRef,
+    dictionaries: Vec<ArrayRef>,
+    batch_size: usize,
+    next_trail: TrailId,
+    current: Option<Uuid>,
+}
+
+impl<'a> Iterator for RecordBatches<'a> {
+    type Item = Result<RecordBatch, ExportError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut columns = Columns::new(self.dictionaries.len(), self.batch_size);
+        while columns.len() < self.batch_size {
+            let uuid = match self.current {
+                Some(uuid) => uuid,
+                None => {
+                    if self.next_trail >= self.db.num_trails() {
+                        break;
+                    }
+                    let trail_id = self.next_trail;
+                    self.next_trail += 1;
+                    let uuid = match self.db.get_uuid(trail_id) {
+                        Some(uuid) => *uuid,
+                        None => return Some(Err(ExportError::Db(Error::InvalidTrailId))),
+                    };
+                    if let Err(e) = self.cursor.get_trail(trail_id) {
+                        return Some(Err(ExportError::Db(e)));
+                    }
+                    self.current = Some(uuid);
+                    uuid
+                }
+            };
+            match self.cursor.next() {
+                Some(event) => columns.push(&uuid, &event),
+                None => self.current = None,
+            }
+        }
+        if columns.len() == 0 {
+            return None;
+        }
+        Some(finish_batch(&mut columns, self.schema.clone(), &self.dictionaries))
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_arrow {
+    use super::ArrowConverter;
+    use super::super::super::{Constructor, Db};
+    use arrow::array::{Array, ArrayAccessor, DictionaryArray, FixedSizeBinaryArray, StringArray, UInt64Array};
+    use arrow::datatypes::UInt64Type;
+    use arrow::record_batch::RecordBatch;
+    use std::path::Path;
+
+    #[test]
+    fn test_record_batches() {
+        let db_path = Path::new("test_arrow_record_batches");
+        let mut cons = Constructor::new(db_path, &["action", "page"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["view", "/"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["buy", "/cart"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 3, &["view", ""]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let converter = ArrowConverter::new(&db);
+        let schema = converter.schema();
+        let names: Vec<&str> = schema.fields().iter().map(|field| field.name().as_str()).collect();
+        assert_eq!(names, vec!["uuid", "timestamp", "action", "page"]);
+
+        let batches: Vec<RecordBatch> = converter.batches(&db, 2).collect::<Result<_, _>>().unwrap();
+        assert_eq!(batches.iter().map(|batch| batch.num_rows()).collect::<Vec<_>>(), vec![2, 1]);
+        let mut events = Vec::new();
+        for batch in &batches {
+            let uuids = batch.column(0).as_any().downcast_ref::<FixedSizeBinaryArray>().unwrap();
+            let timestamps = batch.column(1).as_any().downcast_ref::<UInt64Array>().unwrap();
+            let values: Vec<_> = batch.columns()[2..]
+                .iter()
+                .map(|column| {
+                    column.as_any()
+                        .downcast_ref::<DictionaryArray<UInt64Type>>()
+                        .unwrap()
+                        .downcast_dict::<StringArray>()
+                        .unwrap()
+                })
+                .collect();
+            for row in 0..batch.num_rows() {
+                events.push((uuids.value(row)[0],
+                             timestamps.value(row),
+                             values.iter().map(|column| column.value(row)).collect::<Vec<_>>()));
+            }
+        }
+        events.sort_by_key(|event| event.1);
+        assert_eq!(events,
+                   vec![(1, 1, vec!["view", "/"]), (1, 2, vec!["buy", "/cart"]), (2, 3, vec!["view", ""])]);
+
+        let trail = converter.trail_batch(&db, db.get_trail_id(&[1u8; 16]).unwrap()).unwrap();
+        assert_eq!(trail.num_rows(), 2);
+        assert_eq!(trail.schema(), schema);
+    }
+}
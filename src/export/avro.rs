@@ -0,0 +1,201 @@
+//! Avro export.
+//!
+//! Events are written as an Avro object container file whose schema is
+//! generated from the database's fields:
+//!
+//! ```text
+//! {"type":"record","name":"Event","namespace":"traildb","fields":[
+//!   {"name":"uuid","type":{"type":"fixed","name":"Uuid","size":16}},
+//!   {"name":"timestamp","type":"long"},
+//!   {"name":"user","type":"string"},
+//!   {"name":"action","type":"string"}]}
+//! ```
+//!
+//! Field names that aren't valid Avro names have offending characters
+//! replaced by `_`; the original name is kept as the field's `doc`.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+
+use super::jsonl::push_json_str;
+use super::{export_to, identifiers, EventEncoder, ExportError, ExportOptions};
+use super::super::{Db, Event, EventFilter, Uuid};
+
+/// The number of events per container block.
+const BLOCK_EVENTS: u64 = 4096;
+
+/// The Avro schema, as JSON, of the records `export` writes for `db`.
+pub fn schema(db: &Db) -> String {
+    fields_schema(&db.field_names())
+}
+
+/// The Avro schema of records with the fields `names`.
+fn fields_schema(names: &[&str]) -> String {
+    let mut schema = String::from("{\"type\":\"record\",\"name\":\"Event\",\"namespace\":\"traildb\",\
+                                   \"fields\":[{\"name\":\"uuid\",\"type\":{\"type\":\"fixed\",\
+                                   \"name\":\"Uuid\",\"size\":16}},\
+                                   {\"name\":\"timestamp\",\"type\":\"long\"}");
+    for (name, avro_name) in names.iter().zip(identifiers(names, &["uuid", "timestamp"])) {
+        schema.push_str(&format!("}},{{\"name\":\"{}\",\"type\":\"string\"", avro_name));
+        if avro_name != *name {
+            schema.push_str(",\"doc\":");
+            push_json_str(&mut schema, name);
+        }
+    }
+    schema.push_str("}]}");
+    schema
+}
+
+/// Write every event in `db` to `out` as an Avro container file. Returns the
+/// number of events written.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::export::avro;
+/// use std::fs::File;
+/// use std::io::BufWriter;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let out = BufWriter::new(File::create("events.avro").unwrap());
+/// avro::export(&db, out).unwrap();
+/// ```
+pub fn export<W: Write>(db: &Db, out: W) -> Result<u64, ExportError> {
+    export_to(db, &mut AvroEncoder::new(), out, ExportOptions::new().batch_size(BLOCK_EVENTS))
+}
+
+/// Write the events in `db` matching `filter` to `out` as an Avro container
+/// file. Returns the number of events written.
+pub fn export_filtered<W: Write>(db: &Db,
+                                 filter: &EventFilter,
+                                 out: W)
+                                 -> Result<u64, ExportError> {
+    export_to(db,
+              &mut AvroEncoder::new(),
+              out,
+              ExportOptions::new().filter(filter).batch_size(BLOCK_EVENTS))
+}
+
+/// The `EventEncoder` behind `export`. Every batch becomes one container
+/// block.
+#[derive(Debug)]
+pub struct AvroEncoder {
+    sync: [u8; 16],
+}
+
+impl AvroEncoder {
+    pub fn new() -> Self {
+        AvroEncoder { sync: sync_marker() }
+    }
+}
+
+impl Default for AvroEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventEncoder for AvroEncoder {
+    fn begin<W: Write>(&mut self, db: &Db, out: &mut W) -> Result<(), ExportError> {
+        let mut header = Vec::new();
+        header.extend_from_slice(b"Obj\x01");
+        write_long(&mut header, 2);
+        write_bytes(&mut header, b"avro.schema");
+        write_bytes(&mut header, schema(db).as_bytes());
+        write_bytes(&mut header, b"avro.codec");
+        write_bytes(&mut header, b"null");
+        write_long(&mut header, 0);
+        header.extend_from_slice(&self.sync);
+        out.write_all(&header)?;
+        Ok(())
+    }
+
+    fn encode(&mut self,
+              db: &Db,
+              uuid: &Uuid,
+              event: &Event,
+              buf: &mut Vec<u8>)
+              -> Result<(), ExportError> {
+        buf.extend_from_slice(uuid);
+        write_long(buf, event.timestamp as i64);
+        for item in event.items {
+            write_bytes(buf, db.get_item_value(*item).as_bytes());
+        }
+        Ok(())
+    }
+
+    fn write_batch<W: Write>(&mut self,
+                             buf: &[u8],
+                             events: u64,
+                             out: &mut W)
+                             -> Result<(), ExportError> {
+        let mut prefix = Vec::with_capacity(20);
+        write_long(&mut prefix, events as i64);
+        write_long(&mut prefix, buf.len() as i64);
+        out.write_all(&prefix)?;
+        out.write_all(buf)?;
+        out.write_all(&self.sync)?;
+        Ok(())
+    }
+}
+
+/// Append `n` as a zig-zag encoded variable length integer.
+fn write_long(buf: &mut Vec<u8>, n: i64) {
+    let mut z = ((n << 1) ^ (n >> 63)) as u64;
+    while z >= 0x80 {
+        buf.push((z as u8) | 0x80);
+        z >>= 7;
+    }
+    buf.push(z as u8);
+}
+
+/// Append a length prefixed byte string (Avro `bytes` and `string`).
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_long(buf, bytes.len() as i64);
+    buf.extend_from_slice(bytes);
+}
+
+/// A random marker separating container blocks.
+fn sync_marker() -> [u8; 16] {
+    let mut sync = [0u8; 16];
+    for chunk in sync.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(chunk.as_ptr() as usize);
+        let bits = hasher.finish();
+        for (i, byte) in chunk.iter_mut().enumerate() {
+            *byte = (bits >> (i * 8)) as u8;
+        }
+    }
+    sync
+}
+
+
+
+
+#[cfg(test)]
+mod test_avro {
+    use super::{fields_schema, write_long};
+
+    #[test]
+    fn test_write_long() {
+        let mut buf = Vec::new();
+        for n in &[0, -1, 1, -64, 64, 8192] {
+            write_long(&mut buf, *n);
+        }
+        assert_eq!(buf, vec![0x00, 0x01, 0x02, 0x7f, 0x80, 0x01, 0x80, 0x80, 0x01]);
+    }
+
+    #[test]
+    fn test_fields_schema() {
+        let schema = fields_schema(&["user", "say \"hi\"\\"]);
+        assert_eq!(schema,
+                   "{\"type\":\"record\",\"name\":\"Event\",\"namespace\":\"traildb\",\"fields\":[\
+                    {\"name\":\"uuid\",\"type\":{\"type\":\"fixed\",\"name\":\"Uuid\",\"size\":16}},\
+                    {\"name\":\"timestamp\",\"type\":\"long\"},\
+                    {\"name\":\"user\",\"type\":\"string\"},\
+                    {\"name\":\"say__hi__\",\"type\":\"string\",\"doc\":\"say \\\"hi\\\"\\\\\"}]}");
+    }
+}
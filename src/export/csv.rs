@@ -0,0 +1,248 @@
+//! CSV export.
+//!
+//! Columns are named after fields, plus the pseudo-columns `uuid` and
+//! `timestamp`. By default a header row of column names comes first, and
+//! every column is written, starting with `uuid` and `timestamp`.
+
+use std::io::{self, Write};
+
+use super::{for_each_event, ExportError};
+use super::super::{uuid_hex, Db, Error, EventFilter, Field, Timestamp, TrailId, Uuid};
+use super::super::time::{format_rfc3339, TimeUnit};
+
+/// How the `uuid` column is written.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum UuidFormat {
+    /// 32 lowercase hex characters.
+    Hex,
+    /// Hex in the canonical 8-4-4-4-12 grouping.
+    Hyphenated,
+}
+
+/// How the `timestamp` column is written.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum TimestampFormat {
+    /// The integer as stored.
+    Raw,
+    /// RFC 3339 in UTC, treating timestamps as seconds since the epoch.
+    Rfc3339Seconds,
+    /// RFC 3339 in UTC, treating timestamps as milliseconds since the epoch.
+    Rfc3339Millis,
+}
+
+/// Writes events from a `Db` as CSV.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::export::csv::{CsvExporter, TimestampFormat};
+/// use std::io;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// CsvExporter::new()
+///     .columns(&["timestamp", "user", "action"])
+///     .timestamp_format(TimestampFormat::Rfc3339Seconds)
+///     .export(&db, io::stdout())
+///     .unwrap();
+/// ```
+#[derive(Debug,Clone)]
+pub struct CsvExporter {
+    columns: Option<Vec<String>>,
+    trails: Option<Vec<TrailId>>,
+    uuid_format: UuidFormat,
+    timestamp_format: TimestampFormat,
+    header: bool,
+}
+
+#[derive(Clone,Copy)]
+enum Column {
+    Uuid,
+    Timestamp,
+    Field(Field),
+}
+
+impl CsvExporter {
+    pub fn new() -> Self {
+        CsvExporter {
+            columns: None,
+            trails: None,
+            uuid_format: UuidFormat::Hex,
+            timestamp_format: TimestampFormat::Raw,
+            header: true,
+        }
+    }
+
+    /// Write only these columns, in this order.
+    pub fn columns(mut self, columns: &[&str]) -> Self {
+        self.columns = Some(columns.iter().map(|c| c.to_string()).collect());
+        self
+    }
+
+    /// Only write the events of the trails in `trails`, in that order.
+    pub fn trails(mut self, trails: &[TrailId]) -> Self {
+        self.trails = Some(trails.to_vec());
+        self
+    }
+
+    pub fn uuid_format(mut self, format: UuidFormat) -> Self {
+        self.uuid_format = format;
+        self
+    }
+
+    pub fn timestamp_format(mut self, format: TimestampFormat) -> Self {
+        self.timestamp_format = format;
+        self
+    }
+
+    /// Whether to start with a header row of column names. Defaults to `true`.
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Write every event in `db` to `out`. Returns the number of events written.
+    pub fn export<W: Write>(&self, db: &Db, out: W) -> Result<u64, ExportError> {
+        self.write_events(db, None, out)
+    }
+
+    /// Write the events in `db` matching `filter` to `out`. Returns the number
+    /// of events written.
+    pub fn export_filtered<W: Write>(&self,
+                                     db: &Db,
+                                     filter: &EventFilter,
+                                     out: W)
+                                     -> Result<u64, ExportError> {
+        self.write_events(db, Some(filter), out)
+    }
+
+    fn write_events<W: Write>(&self,
+                              db: &Db,
+                              filter: Option<&EventFilter>,
+                              out: W)
+                              -> Result<u64, ExportError> {
+        let names: Vec<String> = match self.columns {
+            Some(ref columns) => columns.clone(),
+            None => {
+                let mut names = vec!["uuid".to_string(), "timestamp".to_string()];
+                names.extend(db.field_names().iter().map(|n| n.to_string()));
+                names
+            }
+        };
+        let columns = names.iter()
+            .map(|name| match name.as_str() {
+                "uuid" => Ok(Column::Uuid),
+                "timestamp" => Ok(Column::Timestamp),
+                // `time` is field 0, which events hold no item for.
+                name => {
+                    db.get_field(name)
+                        .filter(|&field| field != 0)
+                        .map(Column::Field)
+                        .ok_or(Error::UnknownField)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut writer = csv_crate::Writer::from_writer(out);
+        if self.header {
+            writer.write_record(&names).map_err(io::Error::from)?;
+        }
+        let mut record: Vec<String> = Vec::with_capacity(columns.len());
+        let trails = self.trails.as_ref().map(|trails| trails.as_slice());
+        let count = for_each_event(db, filter, trails, |_, uuid, event| {
+            record.clear();
+            for column in &columns {
+                record.push(match *column {
+                    Column::Uuid => self.format_uuid(uuid),
+                    Column::Timestamp => self.format_timestamp(event.timestamp),
+                    Column::Field(field) => {
+                        match event.items.get(field as usize - 1) {
+                            Some(item) => db.get_item_value(*item).to_string(),
+                            None => String::new(),
+                        }
+                    }
+                });
+            }
+            writer.write_record(&record).map_err(io::Error::from)?;
+            Ok(())
+        })?;
+        writer.flush()?;
+        Ok(count)
+    }
+
+    fn format_uuid(&self, uuid: &Uuid) -> String {
+        let hex = uuid_hex(uuid);
+        match self.uuid_format {
+            UuidFormat::Hex => hex,
+            UuidFormat::Hyphenated => {
+                format!("{}-{}-{}-{}-{}",
+                        &hex[0..8],
+                        &hex[8..12],
+                        &hex[12..16],
+                        &hex[16..20],
+                        &hex[20..32])
+            }
+        }
+    }
+
+    fn format_timestamp(&self, timestamp: Timestamp) -> String {
+        match self.timestamp_format {
+            TimestampFormat::Raw => timestamp.to_string(),
+            TimestampFormat::Rfc3339Seconds => format_rfc3339(timestamp, None),
+            TimestampFormat::Rfc3339Millis => {
+                let (secs, millis) = TimeUnit::Milliseconds.split(timestamp);
+                format_rfc3339(secs, Some(millis))
+            }
+        }
+    }
+}
+
+impl Default for CsvExporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_csv_export {
+    use super::{CsvExporter, UuidFormat};
+    use super::super::ExportError;
+    use super::super::super::{Constructor, Db, Error};
+    use std::path::Path;
+
+    #[test]
+    fn test_export_csv() {
+        let db_path = Path::new("test_export_csv");
+        let mut cons = Constructor::new(db_path, &["user", "note"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["alice", "plain"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["alice", "a, \"quoted\"\nline"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(CsvExporter::new().export(&db, &mut out).unwrap(), 2);
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   concat!("uuid,timestamp,user,note\n",
+                           "01010101010101010101010101010101,1,alice,plain\n",
+                           "01010101010101010101010101010101,2,alice,\"a, \"\"quoted\"\"\nline\"\n"));
+
+        let mut out = Vec::new();
+        CsvExporter::new()
+            .columns(&["note", "uuid"])
+            .uuid_format(UuidFormat::Hyphenated)
+            .header(false)
+            .export(&db, &mut out)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().next(),
+                   Some("plain,01010101-0101-0101-0101-010101010101"));
+
+        match CsvExporter::new().columns(&["time"]).export(&db, Vec::new()) {
+            Err(ExportError::Db(Error::UnknownField)) => {}
+            _ => panic!("expected time to be rejected"),
+        }
+    }
+}
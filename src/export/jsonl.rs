@@ -0,0 +1,169 @@
+//! Newline-delimited JSON export.
+//!
+//! Every event becomes one JSON object holding the trail's `uuid` (as hex),
+//! the event's `timestamp` and one string member per field:
+//!
+//! ```text
+//! {"uuid":"0123456789abcdef0123456789abcdef","timestamp":1,"user":"Alice","action":"login"}
+//! ```
+//!
+//! A field named `uuid` or `timestamp` would give objects with duplicate
+//! keys, so databases with one fail to export with
+//! `ExportError::Db(Error::InvalidFieldname)`.
+
+use std::io::Write;
+
+use super::{export_to, EventEncoder, ExportError, ExportOptions};
+use super::super::{uuid_hex, Db, Error, Event, EventFilter, Uuid};
+
+/// Write every event in `db` to `out`. Returns the number of events written.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::export::jsonl;
+/// use std::io;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let stdout = io::stdout();
+/// jsonl::export(&db, &mut stdout.lock()).unwrap();
+/// ```
+pub fn export<W: Write>(db: &Db, out: W) -> Result<u64, ExportError> {
+    export_to(db, &mut JsonlEncoder::new(), out, ExportOptions::new())
+}
+
+/// Write the events in `db` matching `filter` to `out`. Returns the number of
+/// events written.
+pub fn export_filtered<W: Write>(db: &Db,
+                                 filter: &EventFilter,
+                                 out: W)
+                                 -> Result<u64, ExportError> {
+    export_to(db, &mut JsonlEncoder::new(), out, ExportOptions::new().filter(filter))
+}
+
+/// The `EventEncoder` behind `export`.
+#[derive(Debug,Default)]
+pub struct JsonlEncoder {
+    names: Vec<String>,
+    line: String,
+}
+
+impl JsonlEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EventEncoder for JsonlEncoder {
+    fn begin<W: Write>(&mut self, db: &Db, _out: &mut W) -> Result<(), ExportError> {
+        self.names = db.field_names().iter().map(|n| n.to_string()).collect();
+        if self.names.iter().any(|name| name == "uuid" || name == "timestamp") {
+            return Err(ExportError::Db(Error::InvalidFieldname));
+        }
+        Ok(())
+    }
+
+    fn encode(&mut self,
+              db: &Db,
+              uuid: &Uuid,
+              event: &Event,
+              buf: &mut Vec<u8>)
+              -> Result<(), ExportError> {
+        let line = &mut self.line;
+        line.clear();
+        line.push_str("{\"uuid\":\"");
+        line.push_str(&uuid_hex(uuid));
+        line.push_str("\",\"timestamp\":");
+        line.push_str(&event.timestamp.to_string());
+        for item in event.items {
+            let name = self.names.get(item.field() as usize - 1).map_or("", |n| n.as_str());
+            line.push(',');
+            push_json_str(line, name);
+            line.push(':');
+            push_json_str(line, db.get_item_value(*item));
+        }
+        line.push_str("}\n");
+        buf.extend_from_slice(line.as_bytes());
+        Ok(())
+    }
+}
+
+/// Append `s` to `buf` as a quoted, escaped JSON string.
+pub(crate) fn push_json_str(buf: &mut String, s: &str) {
+    buf.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if (c as u32) < 0x20 => buf.push_str(&format!("\\u{:04x}", c as u32)),
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+
+
+
+#[cfg(test)]
+mod test_jsonl {
+    use super::push_json_str;
+    #[cfg(feature = "json")]
+    use super::export;
+    #[cfg(feature = "json")]
+    use super::super::ExportError;
+    #[cfg(feature = "json")]
+    use super::super::super::{Constructor, Db, Error};
+    #[cfg(feature = "json")]
+    use std::path::Path;
+
+    #[test]
+    fn test_push_json_str() {
+        let mut buf = String::new();
+        push_json_str(&mut buf, "a\"b\\c\nd\u{1}");
+        assert_eq!(buf, "\"a\\\"b\\\\c\\nd\\u0001\"");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_export_jsonl() {
+        use serde_json::{json, Value};
+
+        let db_path = Path::new("test_export_jsonl");
+        let mut cons = Constructor::new(db_path, &["user", "note"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["alice", "plain"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["alice", "a \"quoted\"\nline"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 3, &["bob", ""]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(export(&db, &mut out).unwrap(), 3);
+        let lines: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines,
+                   vec![json!({"uuid": "01010101010101010101010101010101", "timestamp": 1,
+                               "user": "alice", "note": "plain"}),
+                        json!({"uuid": "01010101010101010101010101010101", "timestamp": 2,
+                               "user": "alice", "note": "a \"quoted\"\nline"}),
+                        json!({"uuid": "02020202020202020202020202020202", "timestamp": 3,
+                               "user": "bob", "note": ""})]);
+
+        let clash_path = Path::new("test_export_jsonl_clash");
+        let mut cons = Constructor::new(clash_path, &["timestamp"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["soon"]).is_ok());
+        assert!(cons.finalize().is_ok());
+        match export(&Db::open(clash_path).unwrap(), Vec::new()) {
+            Err(ExportError::Db(Error::InvalidFieldname)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,402 @@
+//! Writing the contents of a `Db` out in other formats.
+
+use std::collections::HashSet;
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "arrow")]
+use ::arrow::error::ArrowError;
+#[cfg(feature = "parquet")]
+use ::parquet::errors::ParquetError;
+
+use super::{Db, Error, Event, EventFilter, TrailId, Uuid};
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod avro;
+#[cfg(feature = "csv")]
+pub mod csv;
+pub mod jsonl;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod pgcopy;
+pub mod protobuf;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+/// An error raised while exporting a database.
+#[derive(Debug)]
+pub enum ExportError {
+    /// Reading from the database failed.
+    Db(Error),
+    /// Writing to the output failed.
+    Io(io::Error),
+    /// Building Arrow arrays failed.
+    #[cfg(feature = "arrow")]
+    Arrow(ArrowError),
+    /// Writing Parquet failed.
+    #[cfg(feature = "parquet")]
+    Parquet(ParquetError),
+    /// Copying into PostgreSQL failed.
+    #[cfg(feature = "postgres")]
+    Postgres(postgres::Error),
+    /// Writing to SQLite failed.
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExportError::Db(ref e) => write!(f, "ExportError::Db({})", e),
+            ExportError::Io(ref e) => write!(f, "ExportError::Io({})", e),
+            #[cfg(feature = "arrow")]
+            ExportError::Arrow(ref e) => write!(f, "ExportError::Arrow({})", e),
+            #[cfg(feature = "parquet")]
+            ExportError::Parquet(ref e) => write!(f, "ExportError::Parquet({})", e),
+            #[cfg(feature = "postgres")]
+            ExportError::Postgres(ref e) => write!(f, "ExportError::Postgres({})", e),
+            #[cfg(feature = "sqlite")]
+            ExportError::Sqlite(ref e) => write!(f, "ExportError::Sqlite({})", e),
+        }
+    }
+}
+
+impl error::Error for ExportError {}
+
+impl From<Error> for ExportError {
+    fn from(e: Error) -> Self {
+        ExportError::Db(e)
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(e: io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<ArrowError> for ExportError {
+    fn from(e: ArrowError) -> Self {
+        ExportError::Arrow(e)
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<ParquetError> for ExportError {
+    fn from(e: ParquetError) -> Self {
+        ExportError::Parquet(e)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<postgres::Error> for ExportError {
+    fn from(e: postgres::Error) -> Self {
+        ExportError::Postgres(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for ExportError {
+    fn from(e: rusqlite::Error) -> Self {
+        ExportError::Sqlite(e)
+    }
+}
+
+/// An output format for `export_to`. Implement it to add a format of your
+/// own.
+///
+/// `export_to` encodes events into a buffer and hands the buffer to
+/// `write_batch` every `ExportOptions::batch_size` events.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::{Db, Event, Uuid};
+/// use traildb::export::{export_to, EventEncoder, ExportError, ExportOptions};
+/// use std::io;
+/// use std::path::Path;
+///
+/// /// One line per event with its timestamp and values.
+/// struct Plain;
+///
+/// impl EventEncoder for Plain {
+///     fn encode(&mut self,
+///               db: &Db,
+///               _uuid: &Uuid,
+///               event: &Event,
+///               buf: &mut Vec<u8>)
+///               -> Result<(), ExportError> {
+///         buf.extend_from_slice(event.timestamp.to_string().as_bytes());
+///         for item in event.items {
+///             buf.push(b' ');
+///             buf.extend_from_slice(db.get_item_value(*item).as_bytes());
+///         }
+///         buf.push(b'\n');
+///         Ok(())
+///     }
+/// }
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// export_to(&db, &mut Plain, io::stdout(), ExportOptions::new()).unwrap();
+/// ```
+pub trait EventEncoder {
+    /// Write anything preceding the first batch, such as a header.
+    fn begin<W: Write>(&mut self, _db: &Db, _out: &mut W) -> Result<(), ExportError> {
+        Ok(())
+    }
+
+    /// Append `event`, from the trail with UUID `uuid`, to `buf`.
+    fn encode(&mut self,
+              db: &Db,
+              uuid: &Uuid,
+              event: &Event,
+              buf: &mut Vec<u8>)
+              -> Result<(), ExportError>;
+
+    /// Write a batch of `events` encoded events. Writes `buf` unchanged by
+    /// default; formats with framing, like container blocks, wrap it here.
+    fn write_batch<W: Write>(&mut self,
+                             buf: &[u8],
+                             _events: u64,
+                             out: &mut W)
+                             -> Result<(), ExportError> {
+        out.write_all(buf)?;
+        Ok(())
+    }
+
+    /// Write anything following the last batch, such as a trailer.
+    fn finish<W: Write>(&mut self, _db: &Db, _out: &mut W) -> Result<(), ExportError> {
+        Ok(())
+    }
+}
+
+/// Options for `export_to`.
+pub struct ExportOptions<'f> {
+    filter: Option<&'f EventFilter>,
+    trails: Option<&'f [TrailId]>,
+    batch_size: u64,
+    progress: Option<Box<dyn FnMut(u64) + 'f>>,
+    cancel: Option<&'f AtomicBool>,
+}
+
+impl<'f> ExportOptions<'f> {
+    /// Export every event, in batches of 4096.
+    pub fn new() -> Self {
+        ExportOptions {
+            filter: None,
+            trails: None,
+            batch_size: 4096,
+            progress: None,
+            cancel: None,
+        }
+    }
+
+    /// Only export events matching `filter`.
+    pub fn filter(mut self, filter: &'f EventFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Only export the trails in `trails`, in that order.
+    pub fn trails(mut self, trails: &'f [TrailId]) -> Self {
+        self.trails = Some(trails);
+        self
+    }
+
+    /// The number of events encoded before a batch is written.
+    pub fn batch_size(mut self, events: u64) -> Self {
+        self.batch_size = events.max(1);
+        self
+    }
+
+    /// Call `progress` with the number of events written so far after every
+    /// batch.
+    pub fn progress<F: FnMut(u64) + 'f>(mut self, progress: F) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Give up with `Error::Cancelled` once `cancel` is set. It is checked
+    /// before every event; the output then holds the batches written so
+    /// far without the encoder's closing, so it can't pass for a complete
+    /// export.
+    pub fn cancel(mut self, cancel: &'f AtomicBool) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+}
+
+impl<'f> Default for ExportOptions<'f> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write the events of `db` to `out` with `encoder`. Returns the number of
+/// events written.
+#[cfg_attr(feature = "tracing",
+           tracing::instrument(level = "debug", skip_all, fields(events = tracing::field::Empty)))]
+pub fn export_to<W, E>(db: &Db,
+                       encoder: &mut E,
+                       mut out: W,
+                       options: ExportOptions)
+                       -> Result<u64, ExportError>
+    where W: Write,
+          E: EventEncoder
+{
+    let ExportOptions { filter, trails, batch_size, mut progress, cancel } = options;
+    encoder.begin(db, &mut out)?;
+    let mut buf = Vec::new();
+    let mut batch = 0;
+    let mut written = 0;
+    let count = for_each_event(db, filter, trails, |_, uuid, event| {
+        if cancel.map_or(false, |cancel| cancel.load(Ordering::Relaxed)) {
+            return Err(ExportError::Db(Error::Cancelled));
+        }
+        encoder.encode(db, uuid, event, &mut buf)?;
+        batch += 1;
+        if batch == batch_size {
+            encoder.write_batch(&buf, batch, &mut out)?;
+            buf.clear();
+            written += batch;
+            batch = 0;
+            if let Some(ref mut progress) = progress {
+                progress(written);
+            }
+        }
+        Ok(())
+    })?;
+    if batch > 0 {
+        encoder.write_batch(&buf, batch, &mut out)?;
+        if let Some(ref mut progress) = progress {
+            progress(count);
+        }
+    }
+    encoder.finish(db, &mut out)?;
+    out.flush()?;
+    record_span!("events", count);
+    Ok(count)
+}
+
+/// Call `f` with every event in `db`, along with its trail's id and UUID, in
+/// trail order, optionally restricted to events matching `filter` and to the
+/// trails in `trails`, in that order. Returns the number of events visited.
+fn for_each_event<F>(db: &Db,
+                     filter: Option<&EventFilter>,
+                     trails: Option<&[TrailId]>,
+                     mut f: F)
+                     -> Result<u64, ExportError>
+    where F: FnMut(TrailId, &Uuid, &Event) -> Result<(), ExportError>
+{
+    let mut cursor = db.cursor();
+    if let Some(filter) = filter {
+        cursor.set_event_filter(filter)?;
+    }
+    let trail_ids: Box<dyn Iterator<Item = TrailId>> = match trails {
+        Some(trails) => Box::new(trails.iter().cloned()),
+        None => Box::new(0..db.num_trails()),
+    };
+    let mut count = 0;
+    for trail_id in trail_ids {
+        let uuid = match db.get_uuid(trail_id) {
+            Some(uuid) => *uuid,
+            None => return Err(ExportError::Db(Error::InvalidTrailId)),
+        };
+        cursor.get_trail(trail_id)?;
+        for event in &mut cursor {
+            f(trail_id, &uuid, &event)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Names for `fields` that are valid Avro and protobuf identifiers,
+/// `[A-Za-z_][A-Za-z0-9_]*`, distinct from each other and from `reserved`.
+/// Offending characters are replaced by `_`.
+fn identifiers(fields: &[&str], reserved: &[&str]) -> Vec<String> {
+    let mut taken: HashSet<String> = reserved.iter().map(|s| s.to_string()).collect();
+    fields.iter()
+        .map(|name| {
+            let mut id: String = name.chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            if id.chars().next().map_or(true, |c| c.is_ascii_digit()) {
+                id.insert(0, '_');
+            }
+            while taken.contains(&id) {
+                id.push('_');
+            }
+            taken.insert(id.clone());
+            id
+        })
+        .collect()
+}
+
+
+
+
+#[cfg(test)]
+mod test_export {
+    use super::{export_to, identifiers, EventEncoder, ExportError, ExportOptions};
+    use super::super::{Constructor, Db, Event, Uuid};
+    use std::path::Path;
+
+    /// Writes each event's timestamp as a byte, and each batch as its size
+    /// followed by its events.
+    struct Batches;
+
+    impl EventEncoder for Batches {
+        fn encode(&mut self,
+                  _db: &Db,
+                  _uuid: &Uuid,
+                  event: &Event,
+                  buf: &mut Vec<u8>)
+                  -> Result<(), ExportError> {
+            buf.push(event.timestamp as u8);
+            Ok(())
+        }
+
+        fn write_batch<W: ::std::io::Write>(&mut self,
+                                            buf: &[u8],
+                                            events: u64,
+                                            out: &mut W)
+                                            -> Result<(), ExportError> {
+            out.write_all(&[events as u8])?;
+            out.write_all(buf)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_export_to() {
+        let db_path = Path::new("test_export_to");
+        let mut cons = Constructor::new(db_path, &["field1"]).unwrap();
+        for ts in 1..6u64 {
+            assert!(cons.add(&[0u8; 16], ts, &["a"]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let mut out = Vec::new();
+        let mut progress = Vec::new();
+        let count = export_to(&db,
+                              &mut Batches,
+                              &mut out,
+                              ExportOptions::new().batch_size(2).progress(|n| progress.push(n)))
+            .unwrap();
+        assert_eq!(count, 5);
+        assert_eq!(out, vec![2, 1, 2, 2, 3, 4, 1, 5]);
+        assert_eq!(progress, vec![2, 4, 5]);
+    }
+
+    #[test]
+    fn test_identifiers() {
+        assert_eq!(identifiers(&["user", "user-agent", "3d", "user_agent", "uuid"], &["uuid"]),
+                   vec!["user", "user_agent", "_3d", "user_agent_", "uuid_"]);
+    }
+}
@@ -0,0 +1,277 @@
+//! Parquet export, partitioned by time.
+//!
+//! Files are laid out the way Hive, Spark and Trino discover partitions:
+//!
+//! ```text
+//! <dir>/date=2017-03-01/part-00000.parquet
+//! <dir>/date=2017-03-01/hour=12/part-00000.parquet
+//! ```
+//!
+//! A partition may be split over several part files, `part-00001.parquet`
+//! and on. Rows use the schema of `export::arrow`.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+
+use super::arrow::{finish_batch, ArrowConverter, Columns};
+use super::{for_each_event, ExportError};
+use super::super::{Db, EventFilter};
+use super::super::time::{format_date, TimeUnit};
+
+/// The time range covered by one partition.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Partitioning {
+    Day,
+    Hour,
+}
+
+/// What an export wrote.
+#[derive(Debug,Default)]
+pub struct ParquetReport {
+    /// The files written, one per partition.
+    pub files: Vec<PathBuf>,
+    /// The number of events written.
+    pub events: u64,
+}
+
+/// Writes the events of a `Db` into time-partitioned Parquet files.
+///
+/// Events are stored by trail, not by time, so partitions get events all
+/// through an export and their writers are kept open, each buffering rows
+/// up to a row group. At most `max_open_files` are open at once: past that
+/// the least recently written one is closed, and its partition continues in
+/// a new part file if it gets more events.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::export::parquet::{ParquetExporter, Partitioning};
+/// use traildb::time::TimeUnit;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let report = ParquetExporter::new(Partitioning::Day)
+///     .time_unit(TimeUnit::Milliseconds)
+///     .export(&db, Path::new("warehouse/events"))
+///     .unwrap();
+/// println!("wrote {} events to {} files", report.events, report.files.len());
+/// ```
+pub struct ParquetExporter {
+    partitioning: Partitioning,
+    unit: TimeUnit,
+    batch_size: usize,
+    max_open_files: usize,
+    properties: Option<WriterProperties>,
+}
+
+struct Partition {
+    path: PathBuf,
+    writer: ArrowWriter<File>,
+    columns: Columns,
+    /// The number of events the export had seen when this partition last
+    /// got one.
+    last_used: u64,
+}
+
+impl ParquetExporter {
+    pub fn new(partitioning: Partitioning) -> Self {
+        ParquetExporter {
+            partitioning: partitioning,
+            unit: TimeUnit::Seconds,
+            batch_size: 8192,
+            max_open_files: 64,
+            properties: None,
+        }
+    }
+
+    /// The unit of the database's timestamps. Defaults to seconds.
+    pub fn time_unit(mut self, unit: TimeUnit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// The number of rows buffered per partition before they are handed to
+    /// its writer. Defaults to 8192.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// The number of files written to at once, at least 1. Defaults to 64.
+    pub fn max_open_files(mut self, max_open_files: usize) -> Self {
+        self.max_open_files = max_open_files.max(1);
+        self
+    }
+
+    /// Properties (compression, row group size, ...) for every file written.
+    pub fn properties(mut self, properties: WriterProperties) -> Self {
+        self.properties = Some(properties);
+        self
+    }
+
+    /// Write every event in `db` below the directory `dst`.
+    pub fn export(&self, db: &Db, dst: &Path) -> Result<ParquetReport, ExportError> {
+        self.write_events(db, None, dst)
+    }
+
+    /// Write the events in `db` matching `filter` below the directory `dst`.
+    pub fn export_filtered(&self,
+                           db: &Db,
+                           filter: &EventFilter,
+                           dst: &Path)
+                           -> Result<ParquetReport, ExportError> {
+        self.write_events(db, Some(filter), dst)
+    }
+
+    fn write_events(&self,
+                    db: &Db,
+                    filter: Option<&EventFilter>,
+                    dst: &Path)
+                    -> Result<ParquetReport, ExportError> {
+        let converter = ArrowConverter::new(db);
+        let num_fields = converter.dictionaries().len();
+        let mut partitions: HashMap<String, Partition> = HashMap::new();
+        // The number of part files started for each partition.
+        let mut parts: HashMap<String, usize> = HashMap::new();
+        let mut files = Vec::new();
+        let mut seen = 0;
+
+        let events = for_each_event(db, filter, None, |_, uuid, event| {
+            seen += 1;
+            let key = self.partition_key(event.timestamp);
+            if !partitions.contains_key(&key) {
+                if partitions.len() >= self.max_open_files {
+                    let oldest = partitions.iter()
+                        .min_by_key(|&(_, partition)| partition.last_used)
+                        .map(|(key, _)| key.clone())
+                        .unwrap();
+                    let partition = partitions.remove(&oldest).unwrap();
+                    files.push(close(partition, &converter)?);
+                }
+                let dir = dst.join(&key);
+                fs::create_dir_all(&dir)?;
+                let part = parts.entry(key.clone()).or_insert(0);
+                let path = dir.join(format!("part-{:05}.parquet", part));
+                *part += 1;
+                let writer = ArrowWriter::try_new(File::create(&path)?,
+                                                  converter.schema(),
+                                                  self.properties.clone())?;
+                partitions.insert(key.clone(),
+                                  Partition {
+                                      path: path,
+                                      writer: writer,
+                                      columns: Columns::new(num_fields, self.batch_size),
+                                      last_used: 0,
+                                  });
+            }
+            let partition = partitions.get_mut(&key).unwrap();
+            partition.last_used = seen;
+            partition.columns.push(uuid, event);
+            if partition.columns.len() >= self.batch_size {
+                let batch = finish_batch(&mut partition.columns,
+                                         converter.schema(),
+                                         converter.dictionaries())?;
+                partition.writer.write(&batch)?;
+            }
+            Ok(())
+        })?;
+
+        for (_, partition) in partitions {
+            files.push(close(partition, &converter)?);
+        }
+        files.sort();
+        Ok(ParquetReport {
+            files: files,
+            events: events,
+        })
+    }
+
+    /// The partition directory, relative to the export root, of `timestamp`.
+    fn partition_key(&self, timestamp: u64) -> String {
+        let (secs, _) = self.unit.split(timestamp);
+        match self.partitioning {
+            Partitioning::Day => format!("date={}", format_date(secs)),
+            Partitioning::Hour => {
+                format!("date={}/hour={:02}", format_date(secs), secs % 86_400 / 3600)
+            }
+        }
+    }
+}
+
+/// Write the rows `partition` buffers and close its file, returning its
+/// path.
+fn close(mut partition: Partition, converter: &ArrowConverter) -> Result<PathBuf, ExportError> {
+    if partition.columns.len() > 0 {
+        let batch = finish_batch(&mut partition.columns, converter.schema(), converter.dictionaries())?;
+        partition.writer.write(&batch)?;
+    }
+    partition.writer.close()?;
+    Ok(partition.path)
+}
+
+
+
+
+#[cfg(test)]
+mod test_parquet {
+    use super::{ParquetExporter, Partitioning};
+    use super::super::super::{Constructor, Db};
+    use arrow::array::{Array, FixedSizeBinaryArray, UInt64Array};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::fs::{self, File};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_parquet_export() {
+        let db_path = Path::new("test_parquet_export");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 10, &["view"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 86_400 + 10, &["buy"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 20, &["view"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 86_400 + 20, &["buy"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let dst = Path::new("test_parquet_export_out");
+        let _ = fs::remove_dir_all(dst);
+        // With one file open at a time, both days are reopened in second
+        // parts for the second trail.
+        let report = ParquetExporter::new(Partitioning::Day)
+            .batch_size(1)
+            .max_open_files(1)
+            .export(&db, dst)
+            .unwrap();
+        assert_eq!(report.events, 4);
+        assert_eq!(report.files,
+                   vec![dst.join("date=1970-01-01/part-00000.parquet"),
+                        dst.join("date=1970-01-01/part-00001.parquet"),
+                        dst.join("date=1970-01-02/part-00000.parquet"),
+                        dst.join("date=1970-01-02/part-00001.parquet")]);
+
+        let read = |path: &PathBuf| {
+            let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+                .unwrap()
+                .build()
+                .unwrap();
+            let mut rows = Vec::new();
+            for batch in reader {
+                let batch = batch.unwrap();
+                let uuids = batch.column(0).as_any().downcast_ref::<FixedSizeBinaryArray>().unwrap();
+                let timestamps = batch.column(1).as_any().downcast_ref::<UInt64Array>().unwrap();
+                for row in 0..batch.num_rows() {
+                    rows.push((uuids.value(row)[0], timestamps.value(row)));
+                }
+            }
+            rows
+        };
+        assert!(report.files.iter().all(|path| read(path).len() == 1));
+        let mut rows: Vec<(u8, u64)> = report.files.iter().flat_map(read).collect();
+        rows.sort();
+        assert_eq!(rows, vec![(1, 10), (1, 86_410), (2, 20), (2, 86_420)]);
+    }
+}
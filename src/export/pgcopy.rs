@@ -0,0 +1,220 @@
+//! PostgreSQL `COPY` export.
+//!
+//! Events are written in the text or binary format `COPY ... FROM STDIN`
+//! reads, one row per event, into a table like:
+//!
+//! ```sql
+//! CREATE TABLE events (uuid uuid NOT NULL, "timestamp" bigint NOT NULL,
+//!                      "user" text, "action" text);
+//! ```
+//!
+//! `create_table` and `copy_statement` produce the matching SQL. With the
+//! `postgres` feature, `copy_in` streams the rows over a connection.
+
+use std::io::Write;
+
+#[cfg(feature = "postgres")]
+use postgres::Client;
+
+use super::{export_to, EventEncoder, ExportError, ExportOptions};
+use super::super::{uuid_hex, Db, Event, EventFilter, Uuid};
+
+/// The `COPY` data format.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum CopyFormat {
+    /// Tab separated text, one line per row.
+    Text,
+    /// PostgreSQL's binary format, which is cheaper for the server to parse.
+    Binary,
+}
+
+/// The `CREATE TABLE` statement for a table holding the rows of `db`.
+pub fn create_table(db: &Db, table: &str) -> String {
+    let columns: String = db.field_names().iter().map(|f| format!(", {} text", quote(f))).collect();
+    format!("CREATE TABLE {} (uuid uuid NOT NULL, \"timestamp\" bigint NOT NULL{})",
+            quote(table),
+            columns)
+}
+
+/// The `COPY ... FROM STDIN` statement that loads rows of `db` written in
+/// `format` into `table`.
+pub fn copy_statement(db: &Db, table: &str, format: CopyFormat) -> String {
+    let columns: String = db.field_names().iter().map(|f| format!(", {}", quote(f))).collect();
+    let format = match format {
+        CopyFormat::Text => "text",
+        CopyFormat::Binary => "binary",
+    };
+    format!("COPY {} (uuid, \"timestamp\"{}) FROM STDIN WITH (FORMAT {})",
+            quote(table),
+            columns,
+            format)
+}
+
+/// Write every event in `db` to `out` as `COPY` data. Returns the number of
+/// events written.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::export::pgcopy::{self, CopyFormat};
+/// use std::io;
+/// use std::path::Path;
+///
+/// // Pipe into `psql -c "$(copy_statement)"`.
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let stdout = io::stdout();
+/// pgcopy::export(&db, CopyFormat::Text, &mut stdout.lock()).unwrap();
+/// ```
+pub fn export<W: Write>(db: &Db, format: CopyFormat, out: W) -> Result<u64, ExportError> {
+    export_to(db, &mut CopyEncoder::new(format), out, ExportOptions::new())
+}
+
+/// Write the events in `db` matching `filter` to `out` as `COPY` data.
+/// Returns the number of events written.
+pub fn export_filtered<W: Write>(db: &Db,
+                                 filter: &EventFilter,
+                                 format: CopyFormat,
+                                 out: W)
+                                 -> Result<u64, ExportError> {
+    export_to(db, &mut CopyEncoder::new(format), out, ExportOptions::new().filter(filter))
+}
+
+/// Load every event in `db` into `table`, which must already exist, over
+/// `client`. Returns the number of rows copied.
+#[cfg(feature = "postgres")]
+pub fn copy_in(db: &Db, client: &mut Client, table: &str) -> Result<u64, ExportError> {
+    copy_events(db, None, client, table)
+}
+
+/// Load the events in `db` matching `filter` into `table`, which must
+/// already exist, over `client`. Returns the number of rows copied.
+#[cfg(feature = "postgres")]
+pub fn copy_in_filtered(db: &Db,
+                        filter: &EventFilter,
+                        client: &mut Client,
+                        table: &str)
+                        -> Result<u64, ExportError> {
+    copy_events(db, Some(filter), client, table)
+}
+
+#[cfg(feature = "postgres")]
+fn copy_events(db: &Db,
+               filter: Option<&EventFilter>,
+               client: &mut Client,
+               table: &str)
+               -> Result<u64, ExportError> {
+    let mut writer = client.copy_in(copy_statement(db, table, CopyFormat::Binary).as_str())?;
+    let options = match filter {
+        Some(filter) => ExportOptions::new().filter(filter),
+        None => ExportOptions::new(),
+    };
+    export_to(db, &mut CopyEncoder::new(CopyFormat::Binary), &mut writer, options)?;
+    Ok(writer.finish()?)
+}
+
+/// The `EventEncoder` behind `export`.
+#[derive(Debug)]
+pub struct CopyEncoder {
+    format: CopyFormat,
+    num_columns: usize,
+}
+
+impl CopyEncoder {
+    pub fn new(format: CopyFormat) -> Self {
+        CopyEncoder {
+            format: format,
+            num_columns: 0,
+        }
+    }
+}
+
+impl EventEncoder for CopyEncoder {
+    fn begin<W: Write>(&mut self, db: &Db, out: &mut W) -> Result<(), ExportError> {
+        self.num_columns = db.field_names().len() + 2;
+        if self.format == CopyFormat::Binary {
+            // Signature, flags and header extension length.
+            out.write_all(b"PGCOPY\n\xff\r\n\0\0\0\0\0\0\0\0\0")?;
+        }
+        Ok(())
+    }
+
+    fn encode(&mut self,
+              db: &Db,
+              uuid: &Uuid,
+              event: &Event,
+              row: &mut Vec<u8>)
+              -> Result<(), ExportError> {
+        match self.format {
+            CopyFormat::Text => {
+                row.extend_from_slice(uuid_hex(uuid).as_bytes());
+                row.push(b'\t');
+                row.extend_from_slice(event.timestamp.to_string().as_bytes());
+                for item in event.items {
+                    row.push(b'\t');
+                    push_text(row, db.get_item_value(*item));
+                }
+                row.push(b'\n');
+            }
+            CopyFormat::Binary => {
+                row.extend_from_slice(&(self.num_columns as i16).to_be_bytes());
+                row.extend_from_slice(&16i32.to_be_bytes());
+                row.extend_from_slice(uuid);
+                row.extend_from_slice(&8i32.to_be_bytes());
+                row.extend_from_slice(&(event.timestamp as i64).to_be_bytes());
+                for item in event.items {
+                    let value = db.get_item_value(*item);
+                    row.extend_from_slice(&(value.len() as i32).to_be_bytes());
+                    row.extend_from_slice(value.as_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish<W: Write>(&mut self, _db: &Db, out: &mut W) -> Result<(), ExportError> {
+        if self.format == CopyFormat::Binary {
+            out.write_all(&(-1i16).to_be_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Append `value` escaped for the text format.
+fn push_text(row: &mut Vec<u8>, value: &str) {
+    for &b in value.as_bytes() {
+        match b {
+            b'\\' => row.extend_from_slice(b"\\\\"),
+            b'\t' => row.extend_from_slice(b"\\t"),
+            b'\n' => row.extend_from_slice(b"\\n"),
+            b'\r' => row.extend_from_slice(b"\\r"),
+            _ => row.push(b),
+        }
+    }
+}
+
+/// Quote an SQL identifier.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+
+
+
+#[cfg(test)]
+mod test_pgcopy {
+    use super::{push_text, quote};
+
+    #[test]
+    fn test_push_text() {
+        let mut row = Vec::new();
+        push_text(&mut row, "a\tb\\c\nd");
+        assert_eq!(row, b"a\\tb\\\\c\\nd".to_vec());
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("user"), "\"user\"");
+        assert_eq!(quote("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -0,0 +1,206 @@
+//! Protobuf export.
+//!
+//! Events are written as length-delimited `traildb.Event` messages, each
+//! prefixed with its size as a varint (what `writeDelimitedTo` and
+//! `parseDelimitedFrom` expect). The message type is generated from the
+//! database's fields:
+//!
+//! ```text
+//! syntax = "proto3";
+//! package traildb;
+//!
+//! message Event {
+//!   bytes uuid = 1;
+//!   uint64 timestamp = 2;
+//!   string user = 3;
+//!   string action = 4;
+//! }
+//! ```
+//!
+//! Field names that aren't valid protobuf identifiers have offending
+//! characters replaced by `_`. `proto_schema` returns the definition above
+//! and `descriptor` the equivalent serialized `FileDescriptorSet`, for
+//! decoders that load schemas at runtime.
+
+use std::io::Write;
+
+use super::{export_to, identifiers, EventEncoder, ExportError, ExportOptions};
+use super::super::{Db, Event, EventFilter, Timestamp, Uuid};
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_LEN: u64 = 2;
+
+// FieldDescriptorProto.Type values.
+const TYPE_UINT64: u64 = 4;
+const TYPE_STRING: u64 = 9;
+const TYPE_BYTES: u64 = 12;
+
+/// The `.proto` definition of the messages `export` writes for `db`.
+pub fn proto_schema(db: &Db) -> String {
+    let names = db.field_names();
+    let mut schema = String::from("syntax = \"proto3\";\npackage traildb;\n\nmessage Event {\n  \
+                                   bytes uuid = 1;\n  uint64 timestamp = 2;\n");
+    for (i, (name, proto_name)) in names.iter()
+        .zip(identifiers(&names, &["uuid", "timestamp"]))
+        .enumerate() {
+        schema.push_str(&format!("  string {} = {};", proto_name, i + 3));
+        if proto_name != *name {
+            schema.push_str(&format!(" // {}", name));
+        }
+        schema.push('\n');
+    }
+    schema.push_str("}\n");
+    schema
+}
+
+/// A serialized `google.protobuf.FileDescriptorSet` describing the messages
+/// `export` writes for `db`, as `protoc --descriptor_set_out` would produce
+/// for `proto_schema`.
+pub fn descriptor(db: &Db) -> Vec<u8> {
+    let names = db.field_names();
+    let mut message = Vec::new();
+    push_len(&mut message, 1, b"Event");
+    push_len(&mut message, 2, &field_descriptor("uuid", 1, TYPE_BYTES));
+    push_len(&mut message, 2, &field_descriptor("timestamp", 2, TYPE_UINT64));
+    for (i, proto_name) in identifiers(&names, &["uuid", "timestamp"]).iter().enumerate() {
+        push_len(&mut message, 2, &field_descriptor(proto_name, i as u64 + 3, TYPE_STRING));
+    }
+
+    let mut file = Vec::new();
+    push_len(&mut file, 1, b"traildb/event.proto");
+    push_len(&mut file, 2, b"traildb");
+    push_len(&mut file, 4, &message);
+    push_len(&mut file, 12, b"proto3");
+
+    let mut set = Vec::new();
+    push_len(&mut set, 1, &file);
+    set
+}
+
+/// Write every event in `db` to `out` as length-delimited messages. Returns
+/// the number of events written.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::export::protobuf;
+/// use std::fs::File;
+/// use std::io::BufWriter;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let out = BufWriter::new(File::create("events.pb").unwrap());
+/// protobuf::export(&db, out).unwrap();
+/// ```
+pub fn export<W: Write>(db: &Db, out: W) -> Result<u64, ExportError> {
+    export_to(db, &mut ProtobufEncoder::new(), out, ExportOptions::new())
+}
+
+/// Write the events in `db` matching `filter` to `out` as length-delimited
+/// messages. Returns the number of events written.
+pub fn export_filtered<W: Write>(db: &Db,
+                                 filter: &EventFilter,
+                                 out: W)
+                                 -> Result<u64, ExportError> {
+    export_to(db, &mut ProtobufEncoder::new(), out, ExportOptions::new().filter(filter))
+}
+
+/// The `EventEncoder` behind `export`.
+#[derive(Debug,Default)]
+pub struct ProtobufEncoder {
+    message: Vec<u8>,
+}
+
+impl ProtobufEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EventEncoder for ProtobufEncoder {
+    fn encode(&mut self,
+              db: &Db,
+              uuid: &Uuid,
+              event: &Event,
+              buf: &mut Vec<u8>)
+              -> Result<(), ExportError> {
+        self.message.clear();
+        push_event(&mut self.message,
+                   uuid,
+                   event.timestamp,
+                   event.items.iter().map(|item| db.get_item_value(*item)));
+        push_varint(buf, self.message.len() as u64);
+        buf.extend_from_slice(&self.message);
+        Ok(())
+    }
+}
+
+/// Append an `Event` message. Like any proto3 encoder, fields holding their
+/// default value are left out.
+fn push_event<'v, I>(buf: &mut Vec<u8>, uuid: &[u8], timestamp: Timestamp, values: I)
+    where I: Iterator<Item = &'v str>
+{
+    push_len(buf, 1, uuid);
+    if timestamp != 0 {
+        push_varint(buf, 2 << 3 | WIRE_VARINT);
+        push_varint(buf, timestamp);
+    }
+    for (i, value) in values.enumerate() {
+        if !value.is_empty() {
+            push_len(buf, i as u64 + 3, value.as_bytes());
+        }
+    }
+}
+
+fn field_descriptor(name: &str, number: u64, field_type: u64) -> Vec<u8> {
+    let mut field = Vec::new();
+    push_len(&mut field, 1, name.as_bytes());
+    push_varint(&mut field, 3 << 3 | WIRE_VARINT);
+    push_varint(&mut field, number);
+    // LABEL_OPTIONAL
+    push_varint(&mut field, 4 << 3 | WIRE_VARINT);
+    push_varint(&mut field, 1);
+    push_varint(&mut field, 5 << 3 | WIRE_VARINT);
+    push_varint(&mut field, field_type);
+    field
+}
+
+/// Append a length-delimited field.
+fn push_len(buf: &mut Vec<u8>, number: u64, bytes: &[u8]) {
+    push_varint(buf, number << 3 | WIRE_LEN);
+    push_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn push_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+
+
+
+#[cfg(test)]
+mod test_protobuf {
+    use super::{push_event, push_varint};
+
+    #[test]
+    fn test_push_varint() {
+        let mut buf = Vec::new();
+        for n in &[0, 1, 127, 128, 300] {
+            push_varint(&mut buf, *n);
+        }
+        assert_eq!(buf, vec![0x00, 0x01, 0x7f, 0x80, 0x01, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_push_event() {
+        let mut buf = Vec::new();
+        push_event(&mut buf, &[7u8; 2], 150, vec!["", "hi"].into_iter());
+        assert_eq!(buf, vec![0x0a, 0x02, 7, 7, 0x10, 0x96, 0x01, 0x22, 0x02, b'h', b'i']);
+    }
+}
@@ -0,0 +1,143 @@
+//! SQLite export.
+//!
+//! The database is written as two tables, with values resolved to text:
+//!
+//! ```sql
+//! CREATE TABLE trails (trail_id INTEGER PRIMARY KEY, uuid TEXT NOT NULL UNIQUE);
+//! CREATE TABLE events (trail_id INTEGER NOT NULL REFERENCES trails (trail_id),
+//!                      timestamp INTEGER NOT NULL, "user" TEXT, "action" TEXT);
+//! ```
+//!
+//! `trail_id`s are those of the source database. Field columns are named
+//! after their fields, with `_` appended to names SQLite would take for
+//! `trail_id`, `timestamp` or an earlier column, as it ignores case.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection};
+
+use super::{for_each_event, ExportError};
+use super::super::{uuid_hex, Db, EventFilter, TrailId};
+
+/// Write every event in `db` to a new SQLite database at `path`. Returns the
+/// number of events written.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::export::sqlite;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// sqlite::export(&db, Path::new("extract.sqlite")).unwrap();
+/// ```
+pub fn export(db: &Db, path: &Path) -> Result<u64, ExportError> {
+    write_events(db, None, path)
+}
+
+/// Write the events in `db` matching `filter`, and the trails they belong
+/// to, to a new SQLite database at `path`. Returns the number of events
+/// written.
+pub fn export_filtered(db: &Db, filter: &EventFilter, path: &Path) -> Result<u64, ExportError> {
+    write_events(db, Some(filter), path)
+}
+
+fn write_events(db: &Db, filter: Option<&EventFilter>, path: &Path) -> Result<u64, ExportError> {
+    let fields = db.field_names();
+    let columns: String = field_columns(&fields)
+        .iter()
+        .map(|column| format!(", \"{}\" TEXT", column.replace('"', "\"\"")))
+        .collect();
+    let mut conn = Connection::open(path)?;
+    conn.execute_batch(&format!("CREATE TABLE trails (trail_id INTEGER PRIMARY KEY, \
+                                                      uuid TEXT NOT NULL UNIQUE);
+                                 CREATE TABLE events (trail_id INTEGER NOT NULL \
+                                                      REFERENCES trails (trail_id), \
+                                                      timestamp INTEGER NOT NULL{});",
+                                columns))?;
+
+    let tx = conn.transaction()?;
+    let count = {
+        let mut insert_trail = tx.prepare("INSERT INTO trails (trail_id, uuid) VALUES (?1, ?2)")?;
+        let placeholders: String = (0..fields.len()).map(|i| format!(", ?{}", i + 3)).collect();
+        let mut insert_event = tx.prepare(&format!("INSERT INTO events VALUES (?1, ?2{})",
+                                                   placeholders))?;
+        let mut last_trail: Option<TrailId> = None;
+        let mut row: Vec<SqlValue> = Vec::with_capacity(fields.len() + 2);
+        for_each_event(db, filter, None, |trail_id, uuid, event| {
+            if last_trail != Some(trail_id) {
+                insert_trail.execute(params![trail_id as i64, uuid_hex(uuid)])?;
+                last_trail = Some(trail_id);
+            }
+            row.clear();
+            row.push(SqlValue::Integer(trail_id as i64));
+            row.push(SqlValue::Integer(event.timestamp as i64));
+            for item in event.items {
+                row.push(SqlValue::Text(db.get_item_value(*item).to_string()));
+            }
+            insert_event.execute(params_from_iter(row.iter()))?;
+            Ok(())
+        })?
+    };
+    tx.execute_batch("CREATE INDEX events_trail_id ON events (trail_id);")?;
+    tx.commit()?;
+    Ok(count)
+}
+
+/// The names of the columns of `fields` in the `events` table.
+fn field_columns(fields: &[&str]) -> Vec<String> {
+    let mut taken: HashSet<String> = ["trail_id", "timestamp"].iter().map(|s| s.to_string()).collect();
+    fields.iter()
+        .map(|name| {
+            let mut column = name.to_string();
+            while taken.contains(&column.to_lowercase()) {
+                column.push('_');
+            }
+            taken.insert(column.to_lowercase());
+            column
+        })
+        .collect()
+}
+
+
+
+
+#[cfg(test)]
+mod test_sqlite_export {
+    use rusqlite::Connection;
+    use super::export;
+    use super::super::super::{Constructor, Db};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_export_sqlite() {
+        let db_path = Path::new("test_export_sqlite");
+        let mut cons = Constructor::new(db_path, &["timestamp", "Trail_Id", "action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["noon", "a", "view"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["dusk", "b", "buy"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 3, &["dawn", "c", "view"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let out = Path::new("test_export_sqlite.sqlite");
+        let _ = fs::remove_file(out);
+        assert_eq!(export(&db, out).unwrap(), 3);
+
+        let conn = Connection::open(out).unwrap();
+        let mut columns = conn.prepare("SELECT name FROM pragma_table_info('events')").unwrap();
+        let columns: Vec<String> = columns.query_map([], |row| row.get(0)).unwrap().map(|c| c.unwrap()).collect();
+        assert_eq!(columns, vec!["trail_id", "timestamp", "timestamp_", "Trail_Id_", "action"]);
+        let (uuid, timestamp, value): (String, i64, String) =
+            conn.query_row("SELECT uuid, events.timestamp, timestamp_ \
+                            FROM events JOIN trails USING (trail_id) WHERE action = 'buy'",
+                           [],
+                           |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .unwrap();
+        assert_eq!((uuid.as_str(), timestamp, value.as_str()),
+                   ("01010101010101010101010101010101", 2, "dusk"));
+    }
+}
@@ -0,0 +1,539 @@
+//! A gRPC service for trail lookups and filtered event streams.
+//!
+//! `TrailService` implements:
+//!
+//! ```text
+//! syntax = "proto3";
+//! package traildb.v1;
+//!
+//! service TrailService {
+//!   rpc Info(InfoRequest) returns (InfoResponse);
+//!   rpc GetTrail(GetTrailRequest) returns (Trail);
+//!   rpc StreamEvents(StreamEventsRequest) returns (stream Event);
+//! }
+//!
+//! message InfoRequest { string db = 1; }
+//! message InfoResponse {
+//!   uint64 num_trails = 1;
+//!   uint64 num_events = 2;
+//!   uint64 min_timestamp = 3;
+//!   uint64 max_timestamp = 4;
+//!   uint64 version = 5;
+//!   repeated string fields = 6;
+//! }
+//!
+//! message GetTrailRequest { string db = 1; bytes uuid = 2; }
+//! message Trail { bytes uuid = 1; repeated Event events = 2; }
+//!
+//! message Term { string field = 1; string value = 2; bool negative = 3; }
+//! message Clause { repeated Term terms = 1; }
+//! message StreamEventsRequest {
+//!   string db = 1;
+//!   repeated Clause filter = 2;
+//!   repeated bytes uuids = 3;
+//! }
+//!
+//! message Event {
+//!   bytes uuid = 1;
+//!   uint64 timestamp = 2;
+//!   repeated string values = 3;
+//! }
+//! ```
+//!
+//! `Event.values` are ordered like `InfoResponse.fields`; the events of a
+//! `Trail` leave `uuid` empty. `db` names the database to use, as given to
+//! `TrailService::database`; an empty name picks the first one. A `StreamEvents` request without a filter streams
+//! every event, and one without UUIDs covers every trail.
+//!
+//! `StreamEvents` reads the database on a blocking thread which hands events
+//! to the response through a bounded buffer: when a client reads slower
+//! than the database is decoded, the reader waits, and it stops once the
+//! client goes away.
+//!
+//! The messages and the server below are what `tonic-build` would generate
+//! from the definition above, written out so that building doesn't require
+//! `protoc`.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use prost::Message;
+use tokio::sync::mpsc;
+use tokio::task;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError};
+use tonic::server::{Grpc, NamedService, ServerStreamingService, UnaryService};
+use tonic::{Code, Request, Response, Status};
+use tonic_prost::ProstCodec;
+
+use super::query::{build_filter, NamedTerm, QueryError};
+use super::{Db, Error, EventFilter, TrailId, Uuid};
+
+/// The number of events `StreamEvents` buffers by default.
+pub const DEFAULT_BUFFER: usize = 1024;
+
+#[derive(Clone,PartialEq,Message)]
+pub struct InfoRequest {
+    #[prost(string, tag = "1")]
+    pub db: String,
+}
+
+#[derive(Clone,PartialEq,Message)]
+pub struct InfoResponse {
+    #[prost(uint64, tag = "1")]
+    pub num_trails: u64,
+    #[prost(uint64, tag = "2")]
+    pub num_events: u64,
+    #[prost(uint64, tag = "3")]
+    pub min_timestamp: u64,
+    #[prost(uint64, tag = "4")]
+    pub max_timestamp: u64,
+    #[prost(uint64, tag = "5")]
+    pub version: u64,
+    #[prost(string, repeated, tag = "6")]
+    pub fields: Vec<String>,
+}
+
+#[derive(Clone,PartialEq,Message)]
+pub struct GetTrailRequest {
+    #[prost(string, tag = "1")]
+    pub db: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub uuid: Vec<u8>,
+}
+
+#[derive(Clone,PartialEq,Message)]
+pub struct Trail {
+    #[prost(bytes = "vec", tag = "1")]
+    pub uuid: Vec<u8>,
+    #[prost(message, repeated, tag = "2")]
+    pub events: Vec<Event>,
+}
+
+#[derive(Clone,PartialEq,Message)]
+pub struct Term {
+    #[prost(string, tag = "1")]
+    pub field: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+    #[prost(bool, tag = "3")]
+    pub negative: bool,
+}
+
+#[derive(Clone,PartialEq,Message)]
+pub struct Clause {
+    #[prost(message, repeated, tag = "1")]
+    pub terms: Vec<Term>,
+}
+
+#[derive(Clone,PartialEq,Message)]
+pub struct StreamEventsRequest {
+    #[prost(string, tag = "1")]
+    pub db: String,
+    #[prost(message, repeated, tag = "2")]
+    pub filter: Vec<Clause>,
+    #[prost(bytes = "vec", repeated, tag = "3")]
+    pub uuids: Vec<Vec<u8>>,
+}
+
+#[derive(Clone,PartialEq,Message)]
+pub struct Event {
+    #[prost(bytes = "vec", tag = "1")]
+    pub uuid: Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    pub timestamp: u64,
+    #[prost(string, repeated, tag = "3")]
+    pub values: Vec<String>,
+}
+
+/// The gRPC service name.
+pub const SERVICE_NAME: &str = "traildb.v1.TrailService";
+
+/// The `TrailService` implementation over a set of named databases.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::grpc::TrailService;
+/// use std::path::Path;
+///
+/// # async fn run() -> Result<(), tonic::transport::Error> {
+/// let service = TrailService::new()
+///     .database("events", Db::open(Path::new("events")).unwrap())
+///     .buffer(256);
+/// service.serve("127.0.0.1:50051".parse().unwrap()).await
+/// # }
+/// ```
+pub struct TrailService {
+    dbs: Vec<(String, Db<'static>)>,
+    buffer: usize,
+}
+
+impl Default for TrailService {
+    fn default() -> Self {
+        TrailService {
+            dbs: Vec::new(),
+            buffer: DEFAULT_BUFFER,
+        }
+    }
+}
+
+impl TrailService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `db` as `name`.
+    pub fn database(mut self, name: &str, db: Db<'static>) -> Self {
+        self.dbs.push((name.to_string(), db));
+        self
+    }
+
+    /// Buffer up to `events` events of a `StreamEvents` response before
+    /// the reader waits for the client. Defaults to `DEFAULT_BUFFER`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `events` is 0.
+    pub fn buffer(mut self, events: usize) -> Self {
+        assert!(events > 0, "the buffer must hold at least one event");
+        self.buffer = events;
+        self
+    }
+
+    /// The service, to be added to a `tonic::transport::Server`.
+    pub fn into_server(self) -> TrailServer {
+        TrailServer { service: Arc::new(self) }
+    }
+
+    /// Listen on `addr` and serve requests until an error occurs.
+    pub async fn serve(self, addr: SocketAddr) -> Result<(), tonic::transport::Error> {
+        tonic::transport::Server::builder()
+            .add_service(self.into_server())
+            .serve(addr)
+            .await
+    }
+
+    /// The database a request asked for.
+    fn select(&self, name: &str) -> Result<&Db<'static>, Status> {
+        let found = if name.is_empty() {
+            self.dbs.first()
+        } else {
+            self.dbs.iter().find(|&&(ref n, _)| n == name)
+        };
+        found.map(|&(_, ref db)| db)
+            .ok_or_else(|| Status::not_found(format!("no database {:?}", name)))
+    }
+
+    fn info(&self, request: InfoRequest) -> Result<InfoResponse, Status> {
+        let info = self.select(&request.db)?.info();
+        Ok(InfoResponse {
+            num_trails: info.num_trails,
+            num_events: info.num_events,
+            min_timestamp: info.min_timestamp,
+            max_timestamp: info.max_timestamp,
+            version: info.version,
+            fields: info.fields,
+        })
+    }
+
+    fn get_trail(&self, request: GetTrailRequest) -> Result<Trail, Status> {
+        let db = self.select(&request.db)?;
+        let uuid = to_uuid(&request.uuid)?;
+        let trail_id = db.get_trail_id(&uuid)
+            .ok_or_else(|| Status::not_found("no trail with this UUID"))?;
+        let batch = db.trail_batch(trail_id).map_err(db_status)?;
+        Ok(Trail {
+            uuid: request.uuid,
+            events: batch.events
+                .into_iter()
+                .map(|event| {
+                    Event {
+                        uuid: Vec::new(),
+                        timestamp: event.timestamp,
+                        values: event.values,
+                    }
+                })
+                .collect(),
+        })
+    }
+
+    /// Resolve the filter and trails of a `StreamEvents` request.
+    fn plan(&self,
+            request: &StreamEventsRequest)
+            -> Result<(Option<EventFilter>, Vec<TrailId>), Status> {
+        let db = self.select(&request.db)?;
+        let filter = if request.filter.is_empty() {
+            None
+        } else {
+            let clauses: Vec<Vec<NamedTerm>> = request.filter
+                .iter()
+                .map(|clause| {
+                    clause.terms
+                        .iter()
+                        .map(|term| {
+                            NamedTerm {
+                                field: term.field.clone(),
+                                value: term.value.clone(),
+                                negative: term.negative,
+                            }
+                        })
+                        .collect()
+                })
+                .collect();
+            Some(build_filter(db, &clauses).map_err(query_status)?)
+        };
+        let trails = if request.uuids.is_empty() {
+            (0..db.num_trails()).collect()
+        } else {
+            let mut trails = Vec::with_capacity(request.uuids.len());
+            for uuid in &request.uuids {
+                if let Some(trail_id) = db.get_trail_id(&to_uuid(uuid)?) {
+                    trails.push(trail_id);
+                }
+            }
+            trails
+        };
+        Ok((filter, trails))
+    }
+
+    /// Send the matching events of `trails` to `tx` until they run out or
+    /// the receiver is dropped.
+    fn stream_events(&self,
+                     db: &str,
+                     filter: Option<EventFilter>,
+                     trails: Vec<TrailId>,
+                     tx: mpsc::Sender<Result<Event, Status>>) {
+        let db = match self.select(db) {
+            Ok(db) => db,
+            Err(status) => {
+                let _ = tx.blocking_send(Err(status));
+                return;
+            }
+        };
+        let mut cursor = db.cursor();
+        if let Some(ref filter) = filter {
+            if let Err(e) = cursor.set_event_filter(filter) {
+                let _ = tx.blocking_send(Err(db_status(e)));
+                return;
+            }
+        }
+        for trail_id in trails {
+            if let Err(e) = cursor.get_trail(trail_id) {
+                let _ = tx.blocking_send(Err(db_status(e)));
+                return;
+            }
+            let uuid = db.get_uuid(trail_id).map_or(Vec::new(), |uuid| uuid.to_vec());
+            for event in &mut cursor {
+                let event = Event {
+                    uuid: uuid.clone(),
+                    timestamp: event.timestamp,
+                    values: event.items
+                        .iter()
+                        .map(|&item| db.get_item_value(item).to_string())
+                        .collect(),
+                };
+                if tx.blocking_send(Ok(event)).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn to_uuid(bytes: &[u8]) -> Result<Uuid, Status> {
+    let mut uuid = [0u8; 16];
+    if bytes.len() != uuid.len() {
+        return Err(Status::invalid_argument("UUIDs must be 16 bytes"));
+    }
+    uuid.copy_from_slice(bytes);
+    Ok(uuid)
+}
+
+fn db_status(e: Error) -> Status {
+    Status::internal(e.to_string())
+}
+
+fn query_status(e: QueryError) -> Status {
+    match e {
+        QueryError::Db(e) => db_status(e),
+        e => Status::invalid_argument(e.to_string()),
+    }
+}
+
+/// Run `f` on the blocking thread pool, as reading a database may fault in
+/// pages from disk.
+async fn blocking<T, F>(f: F) -> Result<T, Status>
+    where T: Send + 'static,
+          F: FnOnce() -> Result<T, Status> + Send + 'static
+{
+    task::spawn_blocking(f).await.map_err(|e| Status::internal(e.to_string()))?
+}
+
+/// A `TrailService` as a tower service handling gRPC requests.
+#[derive(Clone)]
+pub struct TrailServer {
+    service: Arc<TrailService>,
+}
+
+struct InfoMethod(Arc<TrailService>);
+
+impl UnaryService<InfoRequest> for InfoMethod {
+    type Response = InfoResponse;
+    type Future = BoxFuture<Response<InfoResponse>, Status>;
+
+    fn call(&mut self, request: Request<InfoRequest>) -> Self::Future {
+        let service = self.0.clone();
+        Box::pin(async move {
+            blocking(move || service.info(request.into_inner())).await.map(Response::new)
+        })
+    }
+}
+
+struct GetTrailMethod(Arc<TrailService>);
+
+impl UnaryService<GetTrailRequest> for GetTrailMethod {
+    type Response = Trail;
+    type Future = BoxFuture<Response<Trail>, Status>;
+
+    fn call(&mut self, request: Request<GetTrailRequest>) -> Self::Future {
+        let service = self.0.clone();
+        Box::pin(async move {
+            blocking(move || service.get_trail(request.into_inner())).await.map(Response::new)
+        })
+    }
+}
+
+struct StreamEventsMethod(Arc<TrailService>);
+
+impl ServerStreamingService<StreamEventsRequest> for StreamEventsMethod {
+    type Response = Event;
+    type ResponseStream = ReceiverStream<Result<Event, Status>>;
+    type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, request: Request<StreamEventsRequest>) -> Self::Future {
+        let service = self.0.clone();
+        Box::pin(async move {
+            let request = request.into_inner();
+            let planner = service.clone();
+            let (request, (filter, trails)) = blocking(move || {
+                    let plan = planner.plan(&request)?;
+                    Ok((request, plan))
+                })
+                .await?;
+            let (tx, rx) = mpsc::channel(service.buffer);
+            task::spawn_blocking(move || service.stream_events(&request.db, filter, trails, tx));
+            Ok(Response::new(ReceiverStream::new(rx)))
+        })
+    }
+}
+
+impl<B> Service<http::Request<B>> for TrailServer
+    where B: Body + Send + 'static,
+          B::Error: Into<StdError> + Send + 'static
+{
+    type Response = http::Response<tonic::body::Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let service = self.service.clone();
+        match req.uri().path() {
+            "/traildb.v1.TrailService/Info" => {
+                Box::pin(async move {
+                    Ok(Grpc::new(ProstCodec::default()).unary(InfoMethod(service), req).await)
+                })
+            }
+            "/traildb.v1.TrailService/GetTrail" => {
+                Box::pin(async move {
+                    Ok(Grpc::new(ProstCodec::default()).unary(GetTrailMethod(service), req).await)
+                })
+            }
+            "/traildb.v1.TrailService/StreamEvents" => {
+                Box::pin(async move {
+                    let method = StreamEventsMethod(service);
+                    Ok(Grpc::new(ProstCodec::default()).server_streaming(method, req).await)
+                })
+            }
+            _ => {
+                Box::pin(async move {
+                    let mut response = http::Response::new(tonic::body::Body::default());
+                    let headers = response.headers_mut();
+                    headers.insert(Status::GRPC_STATUS, (Code::Unimplemented as i32).into());
+                    headers.insert(http::header::CONTENT_TYPE,
+                                   tonic::metadata::GRPC_CONTENT_TYPE);
+                    Ok(response)
+                })
+            }
+        }
+    }
+}
+
+impl NamedService for TrailServer {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+
+
+
+#[cfg(test)]
+mod test_grpc {
+    use super::{Clause, Event, GetTrailRequest, InfoRequest, StreamEventsRequest, Term,
+                TrailService};
+    use super::super::{Constructor, Db};
+    use std::path::Path;
+    use tokio::sync::mpsc;
+    use tonic::{Code, Status};
+
+    #[test]
+    fn test_trail_service() {
+        let db_path = Path::new("test_trail_service");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["view"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["buy"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 1, &["view"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let service = TrailService::new().database("events", Db::open(db_path).unwrap());
+        let info = service.info(InfoRequest { db: String::new() }).unwrap();
+        assert_eq!((info.num_trails, info.fields), (2, vec!["action".to_string()]));
+        assert_eq!(service.info(InfoRequest { db: "other".to_string() }).unwrap_err().code(),
+                   Code::NotFound);
+
+        let request = GetTrailRequest {
+            db: "events".to_string(),
+            uuid: vec![1u8; 16],
+        };
+        let trail = service.get_trail(request).unwrap();
+        assert_eq!(trail.events[1].values, vec!["buy".to_string()]);
+
+        let request = StreamEventsRequest {
+            db: String::new(),
+            filter: vec![Clause {
+                             terms: vec![Term {
+                                             field: "action".to_string(),
+                                             value: "view".to_string(),
+                                             negative: false,
+                                         }],
+                         }],
+            uuids: Vec::new(),
+        };
+        let (filter, trails) = service.plan(&request).unwrap();
+        let (tx, mut rx) = mpsc::channel::<Result<Event, Status>>(1);
+        let reader = std::thread::spawn(move || {
+            let mut uuids = Vec::new();
+            while let Some(event) = rx.blocking_recv() {
+                uuids.push(event.unwrap().uuid[0]);
+            }
+            uuids
+        });
+        service.stream_events("", filter, trails, tx);
+        assert_eq!(reader.join().unwrap(), vec![1, 2]);
+    }
+}
@@ -0,0 +1,157 @@
+//! CSV import.
+
+use std::io::{self, Read};
+
+use super::{parse_timestamp, parse_uuid, ColumnMapping, ImportError, ImportReport, RowErrorKind,
+            RowSink};
+use super::super::Constructor;
+
+impl Constructor {
+    /// Add the rows of a CSV document with a header row as events.
+    ///
+    /// Rows that can't be added are skipped and reported in the returned
+    /// `ImportReport`; only I/O errors and missing columns abort the import.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Constructor;
+    /// use traildb::import::ColumnMapping;
+    /// use std::fs::File;
+    /// use std::path::Path;
+    ///
+    /// let mut cons = Constructor::new(Path::new("my_traildb"), &["user", "action"]).unwrap();
+    /// let mapping = ColumnMapping::new("session_id", "ts").field("action", "event_type");
+    /// let report = cons.import_csv(File::open("events.csv").unwrap(), &mapping).unwrap();
+    /// println!("imported {} of {} rows", report.imported, report.rows);
+    /// cons.finalize().unwrap();
+    /// ```
+    pub fn import_csv<R: Read>(&mut self,
+                               reader: R,
+                               mapping: &ColumnMapping)
+                               -> Result<ImportReport, ImportError> {
+        let mut reader = csv_crate::ReaderBuilder::new().flexible(true).from_reader(reader);
+        let headers: Vec<String> = reader.headers()
+            .map_err(io::Error::from)?
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+        let (uuid_col, timestamp_col, field_cols) = mapping.resolve(&headers, self.field_names())?;
+
+        let mut sink = RowSink::new(self, mapping);
+        let mut record = csv_crate::StringRecord::new();
+        loop {
+            let line = reader.position().line();
+            match reader.read_record(&mut record) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    if e.is_io_error() {
+                        return Err(ImportError::Io(io::Error::from(e)));
+                    }
+                    sink.skip(line, RowErrorKind::Malformed(e.to_string()));
+                    continue;
+                }
+            }
+
+            let cell = |col: usize| record.get(col).unwrap_or("");
+            let parsed = parse_uuid(cell(uuid_col))
+                .and_then(|uuid| parse_timestamp(cell(timestamp_col)).map(|ts| (uuid, ts)));
+            match parsed {
+                Ok((uuid, timestamp)) => {
+                    let values: Vec<Option<&str>> = field_cols.iter()
+                        .map(|col| col.map_or(Some(""), |col| record.get(col)))
+                        .collect();
+                    sink.add_partial(line, uuid, timestamp, &values);
+                }
+                Err(kind) => sink.skip(line, kind),
+            }
+        }
+        Ok(sink.finish())
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_csv_import {
+    use super::super::{ColumnMapping, RowErrorKind};
+    use super::super::super::{Constructor, ConstructorBuilder, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_import_csv() {
+        let input = "id,ts,event_type,user\n\
+                     00000000000000000000000000000001,1,login,alice\n\
+                     00000000-0000-0000-0000-000000000001,2,logout,alice\n\
+                     not-a-uuid,3,login,bob\n\
+                     00000000000000000000000000000002,soon,login,bob\n\
+                     00000000000000000000000000000002,5,login\n";
+        let db_path = Path::new("test_import_csv");
+        let mut cons = Constructor::new(db_path, &["user", "action"]).unwrap();
+        let mapping = ColumnMapping::new("id", "ts").field("action", "event_type");
+        let report = cons.import_csv(input.as_bytes(), &mapping).unwrap();
+        assert_eq!(report.rows, 5);
+        assert_eq!(report.imported, 3);
+        assert_eq!(report.errors.len(), 2);
+        assert_eq!(report.substituted, 1);
+        match report.errors[0].kind {
+            RowErrorKind::InvalidUuid(ref s) => assert_eq!(s, "not-a-uuid"),
+            ref kind => panic!("unexpected {:?}", kind),
+        }
+        match report.errors[1].kind {
+            RowErrorKind::InvalidTimestamp(ref s) => assert_eq!(s, "soon"),
+            ref kind => panic!("unexpected {:?}", kind),
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        assert_eq!(db.num_trails(), 2);
+        assert_eq!(db.num_events(), 3);
+    }
+
+    #[test]
+    fn test_import_csv_warnings() {
+        let input = concat!("id,ts,event_type\n",
+                            "00000000000000000000000000000001,2,login\n",
+                            "00000000000000000000000000000001,1,signup-from-a-long-campaign\n",
+                            "00000000000000000000000000000002,1,login\n");
+        let mut cons = Constructor::new(Path::new("test_import_csv_warnings"), &["action"]).unwrap();
+        let mapping = ColumnMapping::new("id", "ts")
+            .field("action", "event_type")
+            .max_value_len(6)
+            .sort(true);
+        let report = cons.import_csv(input.as_bytes(), &mapping).unwrap();
+        assert_eq!(report.imported, 3);
+        assert_eq!(report.truncated, 1);
+        assert_eq!(report.out_of_order, 1);
+        assert_eq!(cons.out_of_order(), 0);
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(Path::new("test_import_csv_warnings")).unwrap();
+        let mut cursor = db.cursor();
+        cursor.get_trail(0).unwrap();
+        let actions: Vec<&str> = cursor.map(|e| db.get_item_value(e.items[0])).collect();
+        assert_eq!(actions, vec!["signup", "login"]);
+    }
+
+    #[test]
+    fn test_import_csv_strict() {
+        let input = "id,ts,event_type\n\
+                     00000000000000000000000000000001,2,login\n\
+                     00000000000000000000000000000001,2,logout\n";
+        let mut cons = ConstructorBuilder::new(Path::new("test_import_csv_strict"), &["action"])
+            .strict(true)
+            .build()
+            .unwrap();
+        let mapping = ColumnMapping::new("id", "ts").field("action", "event_type");
+        let report = cons.import_csv(input.as_bytes(), &mapping).unwrap();
+        assert_eq!(report.imported, 1);
+        match report.errors[0].kind {
+            RowErrorKind::OutOfOrder(ref violation) => assert!(violation.is_duplicate()),
+            ref other => panic!("unexpected {:?}", other),
+        }
+        cons.close();
+    }
+}
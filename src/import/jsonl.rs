@@ -0,0 +1,155 @@
+//! Newline-delimited JSON import.
+//!
+//! Every line holds one JSON object. Fields missing from an object, or set
+//! to `null`, are left empty; numbers, booleans and nested values are added
+//! as their JSON text.
+
+use std::io::{BufRead, BufReader, Read};
+
+use serde_json::{self, Value as JsonValue};
+
+use super::{parse_timestamp, parse_uuid, ColumnMapping, ImportError, ImportReport, RowErrorKind,
+            RowSink};
+use super::super::{Constructor, Timestamp, Uuid};
+
+impl Constructor {
+    /// Add the objects of a newline-delimited JSON document as events.
+    ///
+    /// The mapping names the keys holding the UUID, the timestamp and the
+    /// fields. Timestamps may be numbers or strings. Blank lines are ignored;
+    /// lines that can't be added are skipped and reported in the returned
+    /// `ImportReport`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Constructor;
+    /// use traildb::import::ColumnMapping;
+    /// use std::fs::File;
+    /// use std::path::Path;
+    ///
+    /// let mut cons = Constructor::new(Path::new("my_traildb"), &["user", "action"]).unwrap();
+    /// let mapping = ColumnMapping::new("session_id", "ts").field("action", "event_type");
+    /// let report = cons.import_jsonl(File::open("events.jsonl").unwrap(), &mapping).unwrap();
+    /// println!("imported {} of {} rows", report.imported, report.rows);
+    /// cons.finalize().unwrap();
+    /// ```
+    pub fn import_jsonl<R: Read>(&mut self,
+                                 reader: R,
+                                 mapping: &ColumnMapping)
+                                 -> Result<ImportReport, ImportError> {
+        let fields = self.field_names().to_vec();
+        let mut sink = RowSink::new(self, mapping);
+        let mut reader = BufReader::new(reader);
+        let mut line = String::new();
+        let mut line_no = 0;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            line_no += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match decode(line.as_bytes(), mapping, &fields) {
+                Ok((uuid, timestamp, values)) => {
+                    let values: Vec<&str> = values.iter().map(|v| v.as_str()).collect();
+                    sink.add(line_no, uuid, timestamp, &values);
+                }
+                Err(kind) => sink.skip(line_no, kind),
+            }
+        }
+        Ok(sink.finish())
+    }
+}
+
+/// Decode one JSON object into a UUID, a timestamp and the values of
+/// `fields`.
+pub(crate) fn decode(json: &[u8],
+                     mapping: &ColumnMapping,
+                     fields: &[String])
+                     -> Result<(Uuid, Timestamp, Vec<String>), RowErrorKind> {
+    let object = match serde_json::from_slice(json) {
+        Ok(JsonValue::Object(object)) => object,
+        Ok(_) => return Err(RowErrorKind::Malformed("expected a JSON object".to_string())),
+        Err(e) => return Err(RowErrorKind::Malformed(e.to_string())),
+    };
+    let (uuid_key, timestamp_key, value_keys) = mapping.columns(fields);
+    let uuid = match object.get(uuid_key) {
+        Some(&JsonValue::String(ref s)) => parse_uuid(s)?,
+        other => return Err(RowErrorKind::InvalidUuid(json_text(other))),
+    };
+    let timestamp = match object.get(timestamp_key) {
+        Some(&JsonValue::Number(ref n)) if n.is_u64() => n.as_u64().unwrap(),
+        Some(&JsonValue::String(ref s)) => parse_timestamp(s)?,
+        other => return Err(RowErrorKind::InvalidTimestamp(json_text(other))),
+    };
+    let values = value_keys.iter().map(|key| json_text(object.get(*key))).collect();
+    Ok((uuid, timestamp, values))
+}
+
+/// The text of a JSON value as a field value: strings unquoted, missing and
+/// null values empty, anything else as JSON.
+fn json_text(value: Option<&JsonValue>) -> String {
+    match value {
+        None | Some(&JsonValue::Null) => String::new(),
+        Some(&JsonValue::String(ref s)) => s.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_jsonl_import {
+    use super::decode;
+    use super::super::{ColumnMapping, RowErrorKind};
+    use super::super::super::{Constructor, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_decode() {
+        let fields = vec!["user".to_string(), "action".to_string()];
+        let mapping = ColumnMapping::new("id", "ts").field("action", "event");
+        let (uuid, timestamp, values) =
+            decode(br#"{"id":"00000000000000000000000000000001","ts":7,"event":"login","n":1}"#,
+                   &mapping,
+                   &fields)
+                .unwrap();
+        assert_eq!(uuid[15], 1);
+        assert_eq!(timestamp, 7);
+        assert_eq!(values, vec!["", "login"]);
+
+        match decode(br#"{"id":"x","ts":7}"#, &mapping, &fields) {
+            Err(RowErrorKind::InvalidUuid(ref s)) => assert_eq!(s, "x"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match decode(b"[1]", &mapping, &fields) {
+            Err(RowErrorKind::Malformed(_)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_import_jsonl() {
+        let input = "{\"id\":\"00000000000000000000000000000001\",\"ts\":1,\"action\":\"login\"}\n\
+                     \n\
+                     {\"id\":\"00000000000000000000000000000001\",\"ts\":\"2\",\"user\":42}\n\
+                     {\"id\":\"00000000000000000000000000000002\",\"ts\":-3}\n\
+                     not json\n";
+        let db_path = Path::new("test_import_jsonl");
+        let mut cons = Constructor::new(db_path, &["user", "action"]).unwrap();
+        let report = cons.import_jsonl(input.as_bytes(), &ColumnMapping::new("id", "ts")).unwrap();
+        assert_eq!(report.rows, 4);
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.errors[0].row, 4);
+        assert_eq!(report.errors[1].row, 5);
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let batch = db.trail_batch(0).unwrap();
+        assert_eq!(batch.events[1].values, vec!["42", ""]);
+    }
+}
@@ -0,0 +1,376 @@
+//! Filling a `Constructor` from other formats.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io;
+
+#[cfg(feature = "parquet")]
+use ::arrow::error::ArrowError;
+#[cfg(feature = "parquet")]
+use ::parquet::errors::ParquetError;
+
+use super::{uuid_raw, Constructor, Error, OrderViolation, Timestamp, Uuid};
+
+#[cfg(feature = "csv")]
+pub mod csv;
+#[cfg(feature = "json")]
+pub mod jsonl;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+/// An error that aborts an import.
+#[derive(Debug)]
+pub enum ImportError {
+    /// Reading the input failed.
+    Io(io::Error),
+    /// A column named in the mapping is not present in the input.
+    MissingColumn(String),
+    /// The constructor rejected an event in a way that affects every row.
+    Db(Error),
+    /// Decoding Parquet failed.
+    #[cfg(feature = "parquet")]
+    Parquet(ParquetError),
+    /// Converting Parquet columns failed.
+    #[cfg(feature = "parquet")]
+    Arrow(ArrowError),
+    /// Running the SQLite query failed.
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImportError::Io(ref e) => write!(f, "ImportError::Io({})", e),
+            ImportError::MissingColumn(ref c) => write!(f, "ImportError::MissingColumn({})", c),
+            ImportError::Db(ref e) => write!(f, "ImportError::Db({})", e),
+            #[cfg(feature = "parquet")]
+            ImportError::Parquet(ref e) => write!(f, "ImportError::Parquet({})", e),
+            #[cfg(feature = "parquet")]
+            ImportError::Arrow(ref e) => write!(f, "ImportError::Arrow({})", e),
+            #[cfg(feature = "sqlite")]
+            ImportError::Sqlite(ref e) => write!(f, "ImportError::Sqlite({})", e),
+        }
+    }
+}
+
+impl error::Error for ImportError {}
+
+impl From<io::Error> for ImportError {
+    fn from(e: io::Error) -> Self {
+        ImportError::Io(e)
+    }
+}
+
+impl From<Error> for ImportError {
+    fn from(e: Error) -> Self {
+        ImportError::Db(e)
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<ParquetError> for ImportError {
+    fn from(e: ParquetError) -> Self {
+        ImportError::Parquet(e)
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<ArrowError> for ImportError {
+    fn from(e: ArrowError) -> Self {
+        ImportError::Arrow(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for ImportError {
+    fn from(e: rusqlite::Error) -> Self {
+        ImportError::Sqlite(e)
+    }
+}
+
+/// Why a single input row was skipped.
+#[derive(Debug)]
+pub enum RowErrorKind {
+    /// The row could not be decoded.
+    Malformed(String),
+    /// The UUID column did not hold 32 hex digits.
+    InvalidUuid(String),
+    /// The timestamp column did not hold an unsigned integer.
+    InvalidTimestamp(String),
+    /// The constructor rejected the event.
+    Db(Error),
+    /// A strict constructor rejected the event as out of order.
+    OutOfOrder(OrderViolation),
+}
+
+impl RowErrorKind {
+    /// Why `cons` rejected an event with `e`.
+    pub fn rejected(cons: &Constructor, e: Error) -> Self {
+        match (e, cons.last_violation()) {
+            (Error::OutOfOrder, Some(violation)) => RowErrorKind::OutOfOrder(violation.clone()),
+            (e, _) => RowErrorKind::Db(e),
+        }
+    }
+}
+
+/// A skipped input row.
+#[derive(Debug)]
+pub struct RowError {
+    /// Where the row is in the input: the line it starts on for text
+    /// formats, its index otherwise.
+    pub row: u64,
+    pub kind: RowErrorKind,
+}
+
+/// The outcome of an import.
+#[derive(Debug,Default)]
+pub struct ImportReport {
+    /// The number of rows read.
+    pub rows: u64,
+    /// The number of events added to the constructor.
+    pub imported: u64,
+    /// The rows that were skipped, and why.
+    pub errors: Vec<RowError>,
+    /// The number of values cut short to `ColumnMapping::max_value_len`.
+    pub truncated: u64,
+    /// The number of null values, or cells missing from short rows, added
+    /// as empty values.
+    pub substituted: u64,
+    /// The number of rows with a timestamp earlier than a previous row of
+    /// the same UUID. They are buffered and put in order, by `sort` or by
+    /// the constructor on finalize. Without `sort`, they are only counted
+    /// if the constructor keeps track of trails (see
+    /// `ConstructorBuilder::track_order`).
+    pub out_of_order: u64,
+}
+
+/// Describes which input columns hold the UUID, the timestamp and the
+/// values of each field.
+///
+/// Fields without an explicit mapping are read from the column of the same
+/// name, if there is one, and are left empty otherwise.
+#[derive(Debug,Clone)]
+pub struct ColumnMapping {
+    uuid: String,
+    timestamp: String,
+    fields: HashMap<String, String>,
+    sort: bool,
+    max_value_len: Option<usize>,
+}
+
+impl ColumnMapping {
+    /// Read UUIDs and timestamps from the named columns.
+    pub fn new(uuid_column: &str, timestamp_column: &str) -> Self {
+        ColumnMapping {
+            uuid: uuid_column.to_string(),
+            timestamp: timestamp_column.to_string(),
+            fields: HashMap::new(),
+            sort: false,
+            max_value_len: None,
+        }
+    }
+
+    /// Read the values of `field` from `column`.
+    pub fn field(mut self, field: &str, column: &str) -> Self {
+        self.fields.insert(field.to_string(), column.to_string());
+        self
+    }
+
+    /// Buffer all rows and add them ordered by UUID and timestamp, for inputs
+    /// that aren't already. Defaults to `false`.
+    pub fn sort(mut self, sort: bool) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Cut values longer than `len` bytes short, at a character boundary,
+    /// so that stray blobs don't bloat the lexicons. Unlimited by default.
+    pub fn max_value_len(mut self, len: usize) -> Self {
+        self.max_value_len = Some(len);
+        self
+    }
+
+    /// Resolve the mapping against the column names of an input: returns the
+    /// positions of the UUID and timestamp columns and, for every field, the
+    /// position of the column holding its values.
+    pub fn resolve<S: AsRef<str>>(&self,
+                              columns: &[S],
+                              fields: &[String])
+                              -> Result<(usize, usize, Vec<Option<usize>>), ImportError> {
+        let position = |name: &str| columns.iter().position(|c| c.as_ref() == name);
+        let require = |name: &str| {
+            position(name).ok_or_else(|| ImportError::MissingColumn(name.to_string()))
+        };
+        let uuid = require(&self.uuid)?;
+        let timestamp = require(&self.timestamp)?;
+        let mut values = Vec::with_capacity(fields.len());
+        for field in fields {
+            values.push(match self.fields.get(field) {
+                Some(column) => Some(require(column)?),
+                None => position(field),
+            });
+        }
+        Ok((uuid, timestamp, values))
+    }
+
+    /// The names of the UUID column, the timestamp column and, for every
+    /// field, the column holding its values. For inputs whose records are
+    /// keyed by name rather than by position.
+    pub fn columns<'m>(&'m self, fields: &'m [String]) -> (&'m str, &'m str, Vec<&'m str>) {
+        let values = fields.iter()
+            .map(|field| self.fields.get(field).unwrap_or(field).as_str())
+            .collect();
+        (&self.uuid, &self.timestamp, values)
+    }
+}
+
+/// Parse a UUID given as 32 hex digits, optionally hyphenated.
+pub fn parse_uuid(s: &str) -> Result<Uuid, RowErrorKind> {
+    let hex: String = s.trim().chars().filter(|c| *c != '-').collect();
+    uuid_raw(&hex).ok_or_else(|| RowErrorKind::InvalidUuid(s.to_string()))
+}
+
+/// Parse a timestamp given as an unsigned integer.
+pub fn parse_timestamp(s: &str) -> Result<Timestamp, RowErrorKind> {
+    s.trim().parse().map_err(|_| RowErrorKind::InvalidTimestamp(s.to_string()))
+}
+
+/// `value` cut to at most `len` bytes, at a character boundary.
+fn truncate(value: &str, len: usize) -> &str {
+    let mut end = len;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// Feeds the rows of an import into a constructor and keeps the report,
+/// honouring `ColumnMapping::sort` and `ColumnMapping::max_value_len`. The
+/// building block of every importer in this module, and of importers for
+/// other formats.
+///
+/// Recoverable conditions are counted in the report and, with the `log` or
+/// `tracing` feature, logged as warnings: the first of each kind with its
+/// row, and the totals when the import finishes.
+pub struct RowSink<'c> {
+    cons: &'c mut Constructor,
+    report: ImportReport,
+    max_value_len: Option<usize>,
+    buffer: Option<Vec<(Uuid, Timestamp, u64, Vec<String>)>>,
+    /// The latest timestamp of each UUID in `buffer`.
+    latest: HashMap<Uuid, Timestamp>,
+}
+
+impl<'c> RowSink<'c> {
+    pub fn new(cons: &'c mut Constructor, mapping: &ColumnMapping) -> Self {
+        let hints = cons.size_hints();
+        RowSink {
+            cons: cons,
+            report: ImportReport::default(),
+            max_value_len: mapping.max_value_len,
+            buffer: if mapping.sort { Some(Vec::with_capacity(hints.events)) } else { None },
+            latest: HashMap::with_capacity(if mapping.sort { hints.trail_capacity() } else { 0 }),
+        }
+    }
+
+    /// Record row `row` as skipped.
+    pub fn skip(&mut self, row: u64, kind: RowErrorKind) {
+        self.report.rows += 1;
+        self.report.errors.push(RowError {
+            row: row,
+            kind: kind,
+        });
+    }
+
+    /// Add row `row` as an event.
+    pub fn add(&mut self, row: u64, uuid: Uuid, timestamp: Timestamp, values: &[&str]) {
+        self.report.rows += 1;
+        let mut truncated = Vec::new();
+        let values = match self.max_value_len {
+            Some(len) if values.iter().any(|v| v.len() > len) => {
+                if self.report.truncated == 0 {
+                    warn!("traildb: row {}: values longer than {} bytes truncated", row, len);
+                }
+                self.report.truncated += values.iter().filter(|v| v.len() > len).count() as u64;
+                truncated.extend(values.iter().map(|v| truncate(v, len)));
+                &truncated[..]
+            }
+            _ => values,
+        };
+        match self.buffer {
+            Some(ref mut buffer) => {
+                let latest = self.latest.entry(uuid).or_insert(timestamp);
+                if timestamp < *latest {
+                    if self.report.out_of_order == 0 {
+                        warn!("traildb: row {}: timestamp {} earlier than {} of the same UUID; \
+                               buffered until sorted",
+                              row, timestamp, *latest);
+                    }
+                    self.report.out_of_order += 1;
+                } else {
+                    *latest = timestamp;
+                }
+                buffer.push((uuid, timestamp, row, values.iter().map(|v| v.to_string()).collect()))
+            }
+            None => {
+                let before = self.cons.out_of_order();
+                Self::add_to(self.cons, &mut self.report, row, &uuid, timestamp, values);
+                self.report.out_of_order += self.cons.out_of_order() - before;
+            }
+        }
+    }
+
+    /// Add row `row` as an event, with empty values in place of the missing
+    /// ones.
+    pub fn add_partial(&mut self, row: u64, uuid: Uuid, timestamp: Timestamp, values: &[Option<&str>]) {
+        let missing = values.iter().filter(|v| v.is_none()).count() as u64;
+        if missing > 0 {
+            if self.report.substituted == 0 {
+                warn!("traildb: row {}: missing values added as empty values", row);
+            }
+            self.report.substituted += missing;
+        }
+        let values: Vec<&str> = values.iter().map(|v| v.unwrap_or("")).collect();
+        self.add(row, uuid, timestamp, &values);
+    }
+
+    fn add_to(cons: &mut Constructor,
+              report: &mut ImportReport,
+              row: u64,
+              uuid: &Uuid,
+              timestamp: Timestamp,
+              values: &[&str]) {
+        match cons.add(uuid, timestamp, values) {
+            Ok(()) => report.imported += 1,
+            Err(e) => {
+                report.errors.push(RowError {
+                    row: row,
+                    kind: RowErrorKind::rejected(cons, e),
+                })
+            }
+        }
+    }
+
+    /// Add any buffered rows and return the report.
+    pub fn finish(mut self) -> ImportReport {
+        if let Some(mut buffer) = self.buffer.take() {
+            buffer.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+            for (uuid, timestamp, row, values) in buffer {
+                let values: Vec<&str> = values.iter().map(|v| v.as_str()).collect();
+                Self::add_to(self.cons, &mut self.report, row, &uuid, timestamp, &values);
+            }
+            self.report.errors.sort_by_key(|e| e.row);
+        }
+        let report = &self.report;
+        if report.truncated > 0 || report.substituted > 0 || report.out_of_order > 0 {
+            warn!("traildb: imported {} of {} rows: {} values truncated, {} empty values \
+                   substituted, {} rows out of order",
+                  report.imported, report.rows, report.truncated, report.substituted, report.out_of_order);
+        }
+        self.report
+    }
+}
@@ -0,0 +1,174 @@
+//! Parquet import.
+//!
+//! The UUID column may hold 16 byte binary values or hex strings, the
+//! timestamp column integers or Arrow timestamps (taken in their own unit).
+//! Field columns of any type are converted to strings; nulls become empty
+//! values.
+
+use arrow::array::{Array, ArrayRef, AsArray};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Int64Type};
+use arrow::util::display::array_value_to_string;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::file::reader::ChunkReader;
+
+use super::{parse_uuid, ColumnMapping, ImportError, ImportReport, RowErrorKind, RowSink};
+use super::super::{Constructor, Uuid};
+
+impl Constructor {
+    /// Add the rows of a Parquet file as events.
+    ///
+    /// Rows that can't be added are skipped and reported in the returned
+    /// `ImportReport`. Tables that aren't ordered by UUID and time should be
+    /// imported with `ColumnMapping::sort` enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Constructor;
+    /// use traildb::import::ColumnMapping;
+    /// use std::fs::File;
+    /// use std::path::Path;
+    ///
+    /// let mut cons = Constructor::new(Path::new("my_traildb"), &["user", "action"]).unwrap();
+    /// let mapping = ColumnMapping::new("session_id", "ts").sort(true);
+    /// let file = File::open("part-00000.parquet").unwrap();
+    /// let report = cons.import_parquet(file, &mapping).unwrap();
+    /// println!("imported {} of {} rows", report.imported, report.rows);
+    /// cons.finalize().unwrap();
+    /// ```
+    pub fn import_parquet<R: ChunkReader + 'static>(&mut self,
+                                                    reader: R,
+                                                    mapping: &ColumnMapping)
+                                                    -> Result<ImportReport, ImportError> {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(reader)?;
+        let columns: Vec<String> = builder.schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        let (uuid_col, timestamp_col, field_cols) = mapping.resolve(&columns, self.field_names())?;
+        let batches = builder.build()?;
+
+        let mut sink = RowSink::new(self, mapping);
+        let mut row = 0u64;
+        for batch in batches {
+            let batch = batch?;
+            let uuids = match *batch.column(uuid_col).data_type() {
+                DataType::FixedSizeBinary(_) |
+                DataType::Binary |
+                DataType::Utf8 => batch.column(uuid_col).clone(),
+                _ => cast(batch.column(uuid_col), &DataType::Utf8)?,
+            };
+            let timestamps = cast(batch.column(timestamp_col), &DataType::Int64)?;
+            let timestamps = timestamps.as_primitive::<Int64Type>();
+            let mut values = Vec::with_capacity(field_cols.len());
+            for col in &field_cols {
+                values.push(match *col {
+                    Some(col) => Some(cast(batch.column(col), &DataType::Utf8)?),
+                    None => None,
+                });
+            }
+
+            for i in 0..batch.num_rows() {
+                let uuid = match uuid_at(&uuids, i) {
+                    Ok(uuid) => uuid,
+                    Err(kind) => {
+                        sink.skip(row, kind);
+                        row += 1;
+                        continue;
+                    }
+                };
+                if timestamps.is_null(i) || timestamps.value(i) < 0 {
+                    let raw = array_value_to_string(batch.column(timestamp_col), i)
+                        .unwrap_or_default();
+                    sink.skip(row, RowErrorKind::InvalidTimestamp(raw));
+                    row += 1;
+                    continue;
+                }
+                let event_values: Vec<Option<&str>> = values.iter()
+                    .map(|v| match *v {
+                        Some(ref array) => {
+                            let array = array.as_string::<i32>();
+                            if array.is_null(i) { None } else { Some(array.value(i)) }
+                        }
+                        None => Some(""),
+                    })
+                    .collect();
+                sink.add_partial(row, uuid, timestamps.value(i) as u64, &event_values);
+                row += 1;
+            }
+        }
+        Ok(sink.finish())
+    }
+}
+
+fn uuid_at(array: &ArrayRef, i: usize) -> Result<Uuid, RowErrorKind> {
+    if array.is_null(i) {
+        return Err(RowErrorKind::InvalidUuid(String::new()));
+    }
+    let bytes = match *array.data_type() {
+        DataType::FixedSizeBinary(_) => array.as_fixed_size_binary().value(i),
+        DataType::Binary => array.as_binary::<i32>().value(i),
+        _ => return parse_uuid(array.as_string::<i32>().value(i)),
+    };
+    if bytes.len() != 16 {
+        return Err(RowErrorKind::InvalidUuid(format!("{:?}", bytes)));
+    }
+    let mut uuid: Uuid = [0u8; 16];
+    uuid.copy_from_slice(bytes);
+    Ok(uuid)
+}
+
+
+
+
+#[cfg(test)]
+mod test_parquet_import {
+    use super::super::ColumnMapping;
+    use super::super::super::{Constructor, Db};
+    use super::super::super::export::parquet::{ParquetExporter, Partitioning};
+    use std::fs::{self, File};
+    use std::path::Path;
+
+    #[test]
+    fn test_import_parquet() {
+        let src_path = Path::new("test_import_parquet_src");
+        let mut cons = Constructor::new(src_path, &["user", "action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["alice", "login"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 86_400 + 2, &["alice", ""]).is_ok());
+        assert!(cons.add(&[2u8; 16], 3, &["bob", "login"]).is_ok());
+        assert!(cons.finalize().is_ok());
+        let src = Db::open(src_path).unwrap();
+        let parquet_dir = Path::new("test_import_parquet_files");
+        let _ = fs::remove_dir_all(parquet_dir);
+        let exported = ParquetExporter::new(Partitioning::Day).export(&src, parquet_dir).unwrap();
+        assert_eq!(exported.files.len(), 2);
+
+        // The fields in another order than the files' columns.
+        let dst_path = Path::new("test_import_parquet_dst");
+        let mut cons = Constructor::new(dst_path, &["action", "user"]).unwrap();
+        let mapping = ColumnMapping::new("uuid", "timestamp");
+        for path in &exported.files {
+            let report = cons.import_parquet(File::open(path).unwrap(), &mapping).unwrap();
+            assert!(report.errors.is_empty());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let dst = Db::open(dst_path).unwrap();
+        assert_eq!(dst.num_events(), 3);
+        for trail_id in 0..src.num_trails() {
+            let expected = src.trail_batch(trail_id).unwrap();
+            let imported = dst.trail_batch(dst.get_trail_id(&expected.uuid).unwrap()).unwrap();
+            let swapped: Vec<(u64, Vec<String>)> = imported.events
+                .into_iter()
+                .map(|event| (event.timestamp, vec![event.values[1].clone(), event.values[0].clone()]))
+                .collect();
+            let expected: Vec<(u64, Vec<String>)> = expected.events
+                .into_iter()
+                .map(|event| (event.timestamp, event.values))
+                .collect();
+            assert_eq!(swapped, expected);
+        }
+    }
+}
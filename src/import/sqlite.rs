@@ -0,0 +1,147 @@
+//! SQLite import.
+//!
+//! The UUID column may hold 16 byte blobs or hex text, the timestamp column
+//! integers or text. Field values of any type are converted to text; nulls
+//! become empty values.
+
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+
+use super::{parse_timestamp, parse_uuid, ColumnMapping, ImportError, ImportReport, RowErrorKind,
+            RowSink};
+use super::super::{Constructor, Timestamp, Uuid};
+
+impl Constructor {
+    /// Add the rows returned by `query` as events, streaming them from `conn`.
+    ///
+    /// Rows that can't be added are skipped and reported in the returned
+    /// `ImportReport`. Adding `ORDER BY uuid, timestamp` to the query is
+    /// usually cheaper than enabling `ColumnMapping::sort`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// extern crate rusqlite;
+    /// extern crate traildb;
+    ///
+    /// use traildb::Constructor;
+    /// use traildb::import::ColumnMapping;
+    /// use std::path::Path;
+    ///
+    /// # fn main() {
+    /// let conn = rusqlite::Connection::open("app.sqlite").unwrap();
+    /// let mut cons = Constructor::new(Path::new("my_traildb"), &["user", "action"]).unwrap();
+    /// let report = cons.import_sqlite(&conn,
+    ///                    "SELECT session, ts, user, action FROM log ORDER BY session, ts",
+    ///                    &ColumnMapping::new("session", "ts"))
+    ///     .unwrap();
+    /// println!("imported {} of {} rows", report.imported, report.rows);
+    /// cons.finalize().unwrap();
+    /// # }
+    /// ```
+    pub fn import_sqlite(&mut self,
+                         conn: &Connection,
+                         query: &str,
+                         mapping: &ColumnMapping)
+                         -> Result<ImportReport, ImportError> {
+        let mut stmt = conn.prepare(query)?;
+        let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+        let (uuid_col, timestamp_col, field_cols) = mapping.resolve(&columns, self.field_names())?;
+
+        let mut sink = RowSink::new(self, mapping);
+        let mut rows = stmt.query([])?;
+        let mut index = 0u64;
+        while let Some(row) = rows.next()? {
+            let uuid = row.get_ref(uuid_col)?;
+            let timestamp = row.get_ref(timestamp_col)?;
+            let parsed = uuid_value(uuid).and_then(|uuid| timestamp_value(timestamp).map(|ts| (uuid, ts)));
+            match parsed {
+                Ok((uuid, timestamp)) => {
+                    let mut values = Vec::with_capacity(field_cols.len());
+                    for col in &field_cols {
+                        values.push(match *col {
+                            Some(col) => match row.get_ref(col)? {
+                                ValueRef::Null => None,
+                                value => Some(text_value(value)),
+                            },
+                            None => Some(String::new()),
+                        });
+                    }
+                    let values: Vec<Option<&str>> = values.iter().map(|v| v.as_deref()).collect();
+                    sink.add_partial(index, uuid, timestamp, &values);
+                }
+                Err(kind) => sink.skip(index, kind),
+            }
+            index += 1;
+        }
+        Ok(sink.finish())
+    }
+}
+
+fn uuid_value(value: ValueRef) -> Result<Uuid, RowErrorKind> {
+    match value {
+        ValueRef::Blob(bytes) if bytes.len() == 16 => {
+            let mut uuid: Uuid = [0u8; 16];
+            uuid.copy_from_slice(bytes);
+            Ok(uuid)
+        }
+        ValueRef::Text(text) => parse_uuid(&String::from_utf8_lossy(text)),
+        value => Err(RowErrorKind::InvalidUuid(text_value(value))),
+    }
+}
+
+fn timestamp_value(value: ValueRef) -> Result<Timestamp, RowErrorKind> {
+    match value {
+        ValueRef::Integer(n) if n >= 0 => Ok(n as Timestamp),
+        ValueRef::Text(text) => parse_timestamp(&String::from_utf8_lossy(text)),
+        value => Err(RowErrorKind::InvalidTimestamp(text_value(value))),
+    }
+}
+
+fn text_value(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => String::new(),
+        ValueRef::Integer(n) => n.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Text(text) |
+        ValueRef::Blob(text) => String::from_utf8_lossy(text).into_owned(),
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_sqlite_import {
+    use rusqlite::Connection;
+    use super::super::{ColumnMapping, RowErrorKind};
+    use super::super::super::{Constructor, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_import_sqlite() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE log (id, ts, event_type, user);
+                            INSERT INTO log VALUES
+                              (x'00000000000000000000000000000001', 1, 'login', 'alice'),
+                              ('00000000-0000-0000-0000-000000000001', 2, 'logout', NULL),
+                              ('00000000000000000000000000000002', -3, 'login', 'bob'),
+                              ('00000000000000000000000000000002', '4', 'login', 42);")
+            .unwrap();
+        let db_path = Path::new("test_import_sqlite");
+        let mut cons = Constructor::new(db_path, &["user", "action"]).unwrap();
+        let mapping = ColumnMapping::new("id", "ts").field("action", "event_type");
+        let report = cons.import_sqlite(&conn, "SELECT * FROM log", &mapping).unwrap();
+        assert_eq!(report.rows, 4);
+        assert_eq!(report.imported, 3);
+        match report.errors[0].kind {
+            RowErrorKind::InvalidTimestamp(ref s) => assert_eq!(s, "-3"),
+            ref kind => panic!("unexpected {:?}", kind),
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        assert_eq!(db.num_trails(), 2);
+        assert_eq!(db.num_events(), 3);
+    }
+}
@@ -0,0 +1,626 @@
+//! Inverted indexes: which trails hold a value of a field.
+//!
+//! `Db::build_index` scans a database once and writes, for every value of
+//! the selected fields, the sorted ids of the trails with an event holding
+//! it. The index is a sidecar file, by default next to the database (see
+//! `Db::sidecar_path`), and is memory-mapped when opened, so looking up a
+//! value reads only its entry and its trail ids.
+//!
+//! The file holds little-endian `u64`s:
+//!
+//! ```text
+//! "TDBINDEX" version num_trails num_events fingerprint
+//! num_fields field...
+//! num_entries (item first count)...
+//! trail_id...
+//! ```
+//!
+//! Entries are sorted by item; `first` is the position of the entry's
+//! first trail id in the trail id section. `fingerprint` is a hash of the
+//! lexicons of the indexed fields and of what identifies the database:
+//! its counts, first and last UUIDs and the sizes and modification times
+//! of its files. An index left next to a database rebuilt at the same
+//! path is found stale, even if the rebuild holds the same UUIDs and
+//! values, only in other trails.
+
+use std::cmp::{self, Ordering};
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use ::memmap2::Mmap;
+
+use super::bloom::fold;
+use super::durable::sync_dir;
+use super::{Db, Error, EventFilter, Field, Item, TrailId};
+
+const MAGIC: &[u8; 8] = b"TDBINDEX";
+const VERSION: u64 = 3;
+/// The extension of index sidecars.
+pub const INDEX_EXT: &str = "index";
+
+/// An error raised while building or opening an index.
+#[derive(Debug)]
+pub enum IndexError {
+    Io(io::Error),
+    Db(Error),
+    /// The file isn't an index, or is cut short.
+    Corrupt(String),
+    /// The index was built for another database, or for this one before
+    /// it was rebuilt.
+    Stale,
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IndexError::Io(ref e) => write!(f, "IndexError::Io({})", e),
+            IndexError::Db(ref e) => write!(f, "IndexError::Db({})", e),
+            IndexError::Corrupt(ref e) => write!(f, "IndexError::Corrupt({})", e),
+            IndexError::Stale => write!(f, "IndexError::Stale"),
+        }
+    }
+}
+
+impl error::Error for IndexError {}
+
+impl From<io::Error> for IndexError {
+    fn from(e: io::Error) -> Self {
+        IndexError::Io(e)
+    }
+}
+
+impl From<Error> for IndexError {
+    fn from(e: Error) -> Self {
+        IndexError::Db(e)
+    }
+}
+
+/// An opened index.
+pub struct InvertedIndex {
+    map: Mmap,
+    num_trails: u64,
+    num_events: u64,
+    fingerprint: u64,
+    fields: Vec<Field>,
+    entries: Range<usize>,
+    trail_ids: Range<usize>,
+}
+
+impl InvertedIndex {
+    /// Open and map the index at `path`.
+    pub fn open(path: &Path) -> Result<Self, IndexError> {
+        let file = File::open(path)?;
+        // The index is only ever replaced by renaming a new file over it,
+        // never written in place.
+        let map = unsafe { Mmap::map(&file)? };
+        let mut index = InvertedIndex {
+            map: map,
+            num_trails: 0,
+            num_events: 0,
+            fingerprint: 0,
+            fields: Vec::new(),
+            entries: 0..0,
+            trail_ids: 0..0,
+        };
+        if index.map.len() < 56 || &index.map[..8] != MAGIC {
+            return Err(IndexError::Corrupt("not an index".to_string()));
+        }
+        if index.word(1) != VERSION {
+            return Err(IndexError::Corrupt(format!("unknown version {}", index.word(1))));
+        }
+        index.num_trails = index.word(2);
+        index.num_events = index.word(3);
+        index.fingerprint = index.word(4);
+        let words = index.map.len() / 8;
+        let num_fields = index.word(5);
+        if num_fields > (words - 7) as u64 {
+            return Err(IndexError::Corrupt("truncated field list".to_string()));
+        }
+        let num_fields = num_fields as usize;
+        index.fields = (0..num_fields).map(|i| index.word(6 + i) as Field).collect();
+        let num_entries = index.word(6 + num_fields);
+        let start = 7 + num_fields;
+        if num_entries > ((words - start) / 3) as u64 {
+            return Err(IndexError::Corrupt("truncated entries".to_string()));
+        }
+        index.entries = start..start + 3 * num_entries as usize;
+        index.trail_ids = index.entries.end..words;
+        if num_entries > 0 {
+            let last = index.entries.end - 3;
+            if index.word(last + 1).saturating_add(index.word(last + 2)) > index.trail_ids.len() as u64 {
+                return Err(IndexError::Corrupt("truncated trail ids".to_string()));
+            }
+        }
+        Ok(index)
+    }
+
+    /// The number of trails of the database the index was built for.
+    pub fn num_trails(&self) -> u64 {
+        self.num_trails
+    }
+
+    /// The number of events of the database the index was built for.
+    pub fn num_events(&self) -> u64 {
+        self.num_events
+    }
+
+    /// The indexed fields.
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    pub fn is_indexed(&self, field: Field) -> bool {
+        self.fields.contains(&field)
+    }
+
+    /// The trails holding `item`, in order, or `None` if its field isn't
+    /// indexed.
+    pub fn trails(&self, item: Item) -> Option<TrailIds<'_>> {
+        if !self.is_indexed(item.field()) {
+            return None;
+        }
+        let (mut lo, mut hi) = (0, self.entries.len() / 3);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let entry = self.entries.start + 3 * mid;
+            match self.word(entry).cmp(&item.0) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => {
+                    // Clamped, so that a corrupt entry can't read past
+                    // the end of the file.
+                    let end = self.trail_ids.end;
+                    let first = cmp::min(self.trail_ids.start.saturating_add(self.word(entry + 1) as usize), end);
+                    let count = self.word(entry + 2) as usize;
+                    return Some(TrailIds {
+                        index: self,
+                        words: first..cmp::min(first.saturating_add(count), end),
+                    });
+                }
+            }
+        }
+        Some(TrailIds {
+            index: self,
+            words: 0..0,
+        })
+    }
+
+    /// The trails that can hold events matching `filter`, sorted, or `None`
+    /// if the index doesn't narrow it down.
+    ///
+    /// A clause narrows the trails down when all its terms are positive and
+    /// on indexed fields: only trails holding one of its items can match
+    /// it. The candidates are the trails that can match every such clause;
+    /// other clauses are left for a cursor to evaluate.
+    pub fn candidates(&self, filter: &EventFilter) -> Option<Vec<TrailId>> {
+        let mut candidates: Option<Vec<TrailId>> = None;
+        for clause in filter.clauses() {
+            if clause.iter().any(|term| term.negative || !self.is_indexed(term.item.field())) {
+                continue;
+            }
+            let mut trails: Vec<TrailId> = clause.iter()
+                .flat_map(|term| self.trails(term.item).unwrap())
+                .collect();
+            if clause.len() > 1 {
+                trails.sort();
+                trails.dedup();
+            }
+            candidates = Some(match candidates {
+                None => trails,
+                Some(previous) => intersect(&previous, &trails),
+            });
+        }
+        candidates
+    }
+
+    /// The `i`th little-endian word of the file.
+    fn word(&self, i: usize) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&self.map[8 * i..8 * i + 8]);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+/// The trail ids holding an item, read from the mapped index.
+#[derive(Clone)]
+pub struct TrailIds<'i> {
+    index: &'i InvertedIndex,
+    words: Range<usize>,
+}
+
+impl<'i> Iterator for TrailIds<'i> {
+    type Item = TrailId;
+
+    fn next(&mut self) -> Option<TrailId> {
+        self.words.next().map(|i| self.index.word(i))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.words.size_hint()
+    }
+}
+
+impl<'i> ExactSizeIterator for TrailIds<'i> {}
+
+/// The ids in both `a` and `b`, which are sorted.
+fn intersect(a: &[TrailId], b: &[TrailId]) -> Vec<TrailId> {
+    let mut both = Vec::with_capacity(cmp::min(a.len(), b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                both.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    both
+}
+
+/// The trails of `db` that can hold events matching `filter`, according to
+/// its index, if it has one that narrows the filter down. The index is
+/// opened by the first scan and kept with `db`; one that can't be opened is
+/// logged and ignored.
+pub(crate) fn candidate_trails(db: &Db, filter: &EventFilter) -> Option<Vec<TrailId>> {
+    db.index
+        .get_or_init(|| match db.index() {
+            Ok(index) => index,
+            Err(e) => {
+                warn!("traildb: ignoring the index of {}: {}", db.path().display(), e);
+                None
+            }
+        })
+        .as_ref()
+        .and_then(|index| index.candidates(filter))
+}
+
+/// The fingerprint of an index of `fields` of `db`: that of the database,
+/// with the values of the fields folded in. The postings aren't hashed;
+/// the database's file sizes and modification times stand for them.
+fn fingerprint(db: &Db, fields: &[Field]) -> u64 {
+    let mut hash = db.fingerprint();
+    for &field in fields {
+        for val in 1..db.lexicon_size(field) {
+            let value = db.get_value_bytes(field, val).unwrap_or(b"");
+            hash = fold(hash, value.len() as u64);
+            for chunk in value.chunks(8) {
+                let mut word = [0u8; 8];
+                word[..chunk.len()].copy_from_slice(chunk);
+                hash = fold(hash, u64::from_le_bytes(word));
+            }
+        }
+    }
+    hash
+}
+
+impl<'a> Db<'a> {
+    /// Index the values of `fields`, writing the index to `path`, and
+    /// return the number of distinct values indexed. The index is written
+    /// under a hidden name and renamed into place, so readers never see a
+    /// partial one.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use traildb::index::INDEX_EXT;
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("events")).unwrap();
+    /// db.build_index(&db.sidecar_path(INDEX_EXT), &["country", "plan"]).unwrap();
+    ///
+    /// let index = db.index().unwrap().unwrap();
+    /// let country = db.get_field("country").unwrap();
+    /// let de = db.get_item(country, "DE").unwrap();
+    /// println!("{} trails in DE", index.trails(de).unwrap().len());
+    /// ```
+    #[cfg_attr(feature = "tracing",
+               tracing::instrument(level = "debug", skip_all, fields(path = %path.display())))]
+    pub fn build_index(&self, path: &Path, fields: &[&str]) -> Result<u64, IndexError> {
+        let mut indexed = Vec::with_capacity(fields.len());
+        for name in fields {
+            // `time` is field 0, which has no items to index.
+            indexed.push(self.get_field(name).filter(|&field| field != 0).ok_or(Error::UnknownField)?);
+        }
+        indexed.sort();
+        indexed.dedup();
+
+        let mut postings: BTreeMap<u64, Vec<TrailId>> = BTreeMap::new();
+        let mut cursor = self.cursor();
+        for trail_id in 0..self.num_trails() {
+            cursor.get_trail(trail_id)?;
+            for event in &mut cursor {
+                for &field in &indexed {
+                    let item = event.items[field as usize - 1];
+                    let trails = postings.entry(item.0).or_default();
+                    if trails.last() != Some(&trail_id) {
+                        trails.push(trail_id);
+                    }
+                }
+            }
+        }
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let tmp = dir.join(format!(".{}.tmp", name));
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        out.write_all(MAGIC)?;
+        let mut header = vec![VERSION,
+                              self.num_trails(),
+                              self.num_events(),
+                              fingerprint(self, &indexed),
+                              indexed.len() as u64];
+        header.extend(indexed.iter().map(|&f| f as u64));
+        header.push(postings.len() as u64);
+        let mut first = 0u64;
+        for (&item, trails) in &postings {
+            header.extend_from_slice(&[item, first, trails.len() as u64]);
+            first += trails.len() as u64;
+        }
+        for word in header.into_iter().chain(postings.values().flatten().cloned()) {
+            out.write_all(&word.to_le_bytes())?;
+        }
+        let file = out.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        sync_dir(dir)?;
+        Ok(postings.len() as u64)
+    }
+
+    /// The index in the sidecar `INDEX_EXT` of the database, if there is
+    /// one. Fails with `IndexError::Stale` if it was built for another
+    /// database. Scans open the index once and keep it, so an index built
+    /// after their first scan is only used by databases opened after it.
+    pub fn index(&self) -> Result<Option<InvertedIndex>, IndexError> {
+        let path = self.sidecar_path(INDEX_EXT);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let index = InvertedIndex::open(&path)?;
+        if index.num_trails != self.num_trails() || index.num_events != self.num_events() ||
+           index.fingerprint != fingerprint(self, &index.fields) {
+            return Err(IndexError::Stale);
+        }
+        Ok(Some(index))
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_index {
+    use super::{IndexError, InvertedIndex, INDEX_EXT};
+    use super::super::{Constructor, Db, Error, EventFilter, Item};
+    use std::fs;
+    use std::path::Path;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_build_index() {
+        let db_path = Path::new("test_build_index");
+        let mut cons = Constructor::new(db_path, &["country", "action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["DE", "view"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["DE", "buy"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 1, &["FR", "view"]).is_ok());
+        assert!(cons.add(&[3u8; 16], 1, &["DE", "view"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let path = db.sidecar_path(INDEX_EXT);
+        assert_eq!(path, Path::new("test_build_index.tdb.index"));
+        let _ = fs::remove_file(&path);
+        assert!(db.index().unwrap().is_none());
+        assert_eq!(db.build_index(&path, &["country"]).unwrap(), 2);
+
+        let index = db.index().unwrap().unwrap();
+        let country = db.get_field("country").unwrap();
+        let action = db.get_field("action").unwrap();
+        let de = db.get_item(country, "DE").unwrap();
+        let de_trails: Vec<u64> = index.trails(de).unwrap().collect();
+        assert_eq!(de_trails.len(), 2);
+        assert!(!de_trails.contains(&db.get_trail_id(&[2u8; 16]).unwrap()));
+        assert_eq!(index.trails(db.get_item(country, "FR").unwrap()).unwrap().len(), 1);
+        // A value of an indexed field that doesn't occur.
+        assert_eq!(index.trails(Item(de.0 + (100 << 8))).unwrap().len(), 0);
+        assert!(index.trails(db.get_item(action, "view").unwrap()).is_none());
+
+        // country = DE AND (action = buy OR action = view), with only
+        // country indexed.
+        let mut filter = EventFilter::new();
+        filter.add_term(de, false).unwrap();
+        filter.new_clause().unwrap();
+        filter.add_term(db.get_item(action, "buy").unwrap(), false).unwrap();
+        filter.add_term(db.get_item(action, "view").unwrap(), false).unwrap();
+        assert_eq!(index.candidates(&filter), Some(de_trails.clone()));
+        filter.new_clause().unwrap();
+        filter.add_term(db.get_item(country, "FR").unwrap(), false).unwrap();
+        assert_eq!(index.candidates(&filter), Some(vec![]));
+        assert_eq!(db.count_events(&filter).unwrap(), 0);
+
+        let mut negative = EventFilter::new();
+        negative.add_term(de, true).unwrap();
+        assert_eq!(index.candidates(&negative), None);
+        assert_eq!(db.count_events(&negative).unwrap(), 1);
+
+        match db.build_index(&path, &["time"]) {
+            Err(IndexError::Db(Error::UnknownField)) => {}
+            _ => panic!("expected time to be rejected"),
+        }
+
+        fs::write(&path, b"TDBINDEX").unwrap();
+        match InvertedIndex::open(&path) {
+            Err(IndexError::Corrupt(_)) => {}
+            _ => panic!("expected a corrupt index"),
+        }
+    }
+
+    #[test]
+    fn test_index_rebuilt() {
+        let db_path = Path::new("test_index_rebuilt");
+        let build = |country: &str| {
+            let mut cons = Constructor::new(db_path, &["country"]).unwrap();
+            assert!(cons.add(&[1u8; 16], 1, &["DE"]).is_ok());
+            assert!(cons.add(&[2u8; 16], 1, &[country]).is_ok());
+            assert!(cons.finalize().is_ok());
+        };
+        build("FR");
+        let db = Db::open(db_path).unwrap();
+        db.build_index(&db.sidecar_path(INDEX_EXT), &["country"]).unwrap();
+        assert!(db.index().unwrap().is_some());
+
+        // The same number of trails and events, with other values.
+        build("IT");
+        let rebuilt = Db::open(db_path).unwrap();
+        match rebuilt.index() {
+            Err(IndexError::Stale) => {}
+            _ => panic!("expected a stale index"),
+        }
+        let country = rebuilt.get_field("country").unwrap();
+        let mut filter = EventFilter::new();
+        filter.add_term(rebuilt.get_item(country, "IT").unwrap(), false).unwrap();
+        assert_eq!(rebuilt.count_events(&filter).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_index_rebuilt_postings() {
+        let db_path = Path::new("test_index_rebuilt_postings");
+        // The same UUIDs, values and counts; only which trail holds which
+        // value changes.
+        let build = |de: u8, fr: u8| {
+            let mut cons = Constructor::new(db_path, &["country"]).unwrap();
+            assert!(cons.add(&[de; 16], 1, &["DE"]).is_ok());
+            assert!(cons.add(&[fr; 16], 1, &["FR"]).is_ok());
+            assert!(cons.finalize().is_ok());
+        };
+        build(1, 2);
+        let db = Db::open(db_path).unwrap();
+        db.build_index(&db.sidecar_path(INDEX_EXT), &["country"]).unwrap();
+        assert!(db.index().unwrap().is_some());
+
+        // Past the granularity of coarse file system timestamps.
+        thread::sleep(Duration::from_millis(50));
+        build(2, 1);
+        let rebuilt = Db::open(db_path).unwrap();
+        match rebuilt.index() {
+            Err(IndexError::Stale) => {}
+            _ => panic!("expected a stale index"),
+        }
+        let country = rebuilt.get_field("country").unwrap();
+        let mut filter = EventFilter::new();
+        filter.add_term(rebuilt.get_item(country, "DE").unwrap(), false).unwrap();
+        let trails: Vec<u64> = rebuilt.scan_trails(Some(&filter)).collect();
+        assert_eq!(trails, vec![0, 1]);
+        let mut cursor = rebuilt.cursor();
+        cursor.get_trail(rebuilt.get_trail_id(&[2u8; 16]).unwrap()).unwrap();
+        assert_eq!(cursor.next().unwrap().items[0], rebuilt.get_item(country, "DE").unwrap());
+    }
+
+    #[test]
+    fn test_index_matches_scan() {
+        let db_path = Path::new("test_index_matches_scan");
+        let mut cons = Constructor::new(db_path, &["country", "action"]).unwrap();
+        let countries = ["DE", "FR", "IT"];
+        let actions = ["view", "buy"];
+        for i in 0..30u8 {
+            for j in 0..(i % 4) {
+                let country = countries[(i % 3) as usize];
+                let action = actions[((i + j) % 2) as usize];
+                assert!(cons.add(&[i; 16], j as u64, &[country, action]).is_ok());
+            }
+        }
+        assert!(cons.finalize().is_ok());
+
+        // The number of matching events and the trails holding them.
+        let matching = |db: &Db, filter: &EventFilter| {
+            let mut cursor = db.cursor();
+            cursor.set_event_filter(filter).unwrap();
+            let mut trails = Vec::new();
+            for trail_id in db.scan_trails(Some(filter)) {
+                cursor.get_trail(trail_id).unwrap();
+                if (&mut cursor).count() > 0 {
+                    trails.push(trail_id);
+                }
+            }
+            (db.count_events(filter).unwrap(), trails)
+        };
+        let filters = |db: &Db| {
+            let country = db.get_field("country").unwrap();
+            let action = db.get_field("action").unwrap();
+            let item = |field, value| db.get_item(field, value).unwrap();
+            let mut filters = Vec::new();
+            let mut de = EventFilter::new();
+            de.add_term(item(country, "DE"), false).unwrap();
+            filters.push(de);
+            let mut de_or_it_buying = EventFilter::new();
+            de_or_it_buying.add_term(item(country, "DE"), false).unwrap();
+            de_or_it_buying.add_term(item(country, "IT"), false).unwrap();
+            de_or_it_buying.new_clause().unwrap();
+            de_or_it_buying.add_term(item(action, "buy"), false).unwrap();
+            filters.push(de_or_it_buying);
+            let mut fr_not_viewing = EventFilter::new();
+            fr_not_viewing.add_term(item(country, "FR"), false).unwrap();
+            fr_not_viewing.new_clause().unwrap();
+            fr_not_viewing.add_term(item(action, "view"), true).unwrap();
+            filters.push(fr_not_viewing);
+            filters
+        };
+
+        let db = Db::open(db_path).unwrap();
+        let path = db.sidecar_path(INDEX_EXT);
+        let _ = fs::remove_file(&path);
+        let scanned: Vec<_> = filters(&db).iter().map(|filter| matching(&db, filter)).collect();
+        db.build_index(&path, &["country"]).unwrap();
+
+        let indexed = Db::open(db_path).unwrap();
+        assert!(indexed.index().unwrap().is_some());
+        for (filter, scanned) in filters(&indexed).iter().zip(&scanned) {
+            let visited = indexed.scan_trails(Some(filter)).count() as u64;
+            assert!(visited < indexed.num_trails());
+            assert_eq!(&matching(&indexed, filter), scanned);
+        }
+    }
+
+    #[test]
+    fn test_index_fixture() {
+        // Byte by byte, so that the format is checked on hosts of either
+        // byte order.
+        let fixture: Vec<u8> = [&b"TDBINDEX"[..],
+                                b"\x03\0\0\0\0\0\0\0", // version
+                                b"\x00\x02\0\0\0\0\0\0", // num_trails
+                                b"\x09\0\0\0\0\0\0\0", // num_events
+                                b"\x08\x07\x06\x05\x04\x03\x02\x01", // fingerprint
+                                b"\x01\0\0\0\0\0\0\0", // num_fields
+                                b"\x01\0\0\0\0\0\0\0", // field 1
+                                b"\x02\0\0\0\0\0\0\0", // num_entries
+                                b"\x01\x01\0\0\0\0\0\0", // item 1 of field 1
+                                b"\x00\0\0\0\0\0\0\0", // first
+                                b"\x02\0\0\0\0\0\0\0", // count
+                                b"\x01\x02\0\0\0\0\0\0", // item 2 of field 1
+                                b"\x02\0\0\0\0\0\0\0", // first
+                                b"\x01\0\0\0\0\0\0\0", // count
+                                b"\x01\0\0\0\0\0\0\0", // trail 1
+                                b"\x00\x01\0\0\0\0\0\0", // trail 256
+                                b"\x04\0\0\0\0\0\0\0"] // trail 4
+            .concat();
+        let path = Path::new("test_index_fixture.index");
+        fs::write(path, &fixture).unwrap();
+        let index = InvertedIndex::open(path).unwrap();
+        assert_eq!((index.num_trails(), index.num_events()), (512, 9));
+        assert_eq!(index.fingerprint, 0x0102_0304_0506_0708);
+        assert_eq!(index.fields(), &[1]);
+        assert_eq!(Item(0x101).value(), 1);
+        assert_eq!(index.trails(Item(0x101)).unwrap().collect::<Vec<_>>(), vec![1, 256]);
+        assert_eq!(index.trails(Item(0x201)).unwrap().collect::<Vec<_>>(), vec![4]);
+        assert_eq!(index.trails(Item(0x301)).unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,310 @@
+//! Building TrailDBs from a Kafka topic.
+//!
+//! `KafkaIngest` consumes messages, adds them to a constructor and
+//! finalizes a new database under its output directory every
+//! `roll_every`. Offsets are committed only after a database has been
+//! finalized, so a crash replays the messages of the unfinished database.
+//! Disable `enable.auto.commit` on the consumer for that to hold.
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::Message;
+
+use super::import::{jsonl, ColumnMapping, ImportReport, RowError, RowErrorKind};
+use super::{Constructor, ConstructorBuilder, Error, Timestamp, Uuid};
+
+/// How message payloads are encoded.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum MessageFormat {
+    /// A JSON object per message, with keys named by the `ColumnMapping`.
+    /// Missing and null fields are empty; numbers and booleans are added
+    /// as text.
+    Json,
+    /// A bare Avro datum per message, in the record layout written by
+    /// `export::avro` for the ingest's fields.
+    Avro,
+}
+
+/// An error that stops an ingest.
+#[derive(Debug)]
+pub enum IngestError {
+    /// Consuming or committing failed.
+    Kafka(KafkaError),
+    /// Creating or finalizing a database failed.
+    Db(Error),
+    /// Creating the output directory failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for IngestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IngestError::Kafka(ref e) => write!(f, "IngestError::Kafka({})", e),
+            IngestError::Db(ref e) => write!(f, "IngestError::Db({})", e),
+            IngestError::Io(ref e) => write!(f, "IngestError::Io({})", e),
+        }
+    }
+}
+
+impl error::Error for IngestError {}
+
+impl From<KafkaError> for IngestError {
+    fn from(e: KafkaError) -> Self {
+        IngestError::Kafka(e)
+    }
+}
+
+impl From<Error> for IngestError {
+    fn from(e: Error) -> Self {
+        IngestError::Db(e)
+    }
+}
+
+impl From<io::Error> for IngestError {
+    fn from(e: io::Error) -> Self {
+        IngestError::Io(e)
+    }
+}
+
+/// A consumer loop writing rolling TrailDBs.
+///
+/// # Examples
+///
+/// ```no_run
+/// extern crate rdkafka;
+/// extern crate traildb;
+///
+/// use rdkafka::config::ClientConfig;
+/// use rdkafka::consumer::{BaseConsumer, Consumer};
+/// use std::path::Path;
+/// use std::sync::atomic::AtomicBool;
+/// use std::time::Duration;
+/// use traildb::import::ColumnMapping;
+/// use traildb::kafka::KafkaIngest;
+///
+/// # fn main() {
+/// let consumer: BaseConsumer = ClientConfig::new()
+///     .set("bootstrap.servers", "localhost:9092")
+///     .set("group.id", "traildb")
+///     .set("enable.auto.commit", "false")
+///     .create()
+///     .unwrap();
+/// consumer.subscribe(&["events"]).unwrap();
+///
+/// let stop = AtomicBool::new(false);
+/// KafkaIngest::new(consumer, Path::new("ingest"), &["user", "action"])
+///     .mapping(ColumnMapping::new("session", "ts"))
+///     .roll_every(Duration::from_secs(600))
+///     .run(&stop, |path, report| {
+///         println!("{}: {} events", path.display(), report.imported);
+///     })
+///     .unwrap();
+/// # }
+/// ```
+pub struct KafkaIngest {
+    consumer: BaseConsumer,
+    dst_dir: PathBuf,
+    fields: Vec<String>,
+    format: MessageFormat,
+    mapping: ColumnMapping,
+    roll_every: Duration,
+    poll_timeout: Duration,
+    rolled: u64,
+}
+
+/// The database currently being filled.
+struct Segment {
+    path: PathBuf,
+    cons: Constructor,
+    report: ImportReport,
+    opened: Instant,
+}
+
+impl KafkaIngest {
+    /// Ingest from `consumer`, which should already be subscribed, into
+    /// databases with `fields` under `dst_dir`.
+    ///
+    /// Defaults to JSON messages with `uuid` and `timestamp` keys, rolling
+    /// every hour.
+    pub fn new(consumer: BaseConsumer, dst_dir: &Path, fields: &[&str]) -> Self {
+        KafkaIngest {
+            consumer: consumer,
+            dst_dir: dst_dir.to_path_buf(),
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+            format: MessageFormat::Json,
+            mapping: ColumnMapping::new("uuid", "timestamp"),
+            roll_every: Duration::from_secs(3600),
+            poll_timeout: Duration::from_millis(100),
+            rolled: 0,
+        }
+    }
+
+    pub fn format(mut self, format: MessageFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Where JSON messages keep the UUID, the timestamp and the fields.
+    /// Not used for Avro messages.
+    pub fn mapping(mut self, mapping: ColumnMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    /// How long a database collects events before it is finalized.
+    pub fn roll_every(mut self, interval: Duration) -> Self {
+        self.roll_every = interval;
+        self
+    }
+
+    /// Consume until `stop` is set, then finalize the current database.
+    ///
+    /// `on_roll` is called with the path and report of every finalized
+    /// database. Undecodable messages are skipped and show up in the
+    /// report, with the message offset as the row.
+    pub fn run<F>(&mut self, stop: &AtomicBool, mut on_roll: F) -> Result<(), IngestError>
+        where F: FnMut(&Path, &ImportReport)
+    {
+        fs::create_dir_all(&self.dst_dir)?;
+        let mut segment: Option<Segment> = None;
+        while !stop.load(Ordering::Relaxed) {
+            if let Some(message) = self.consumer.poll(self.poll_timeout) {
+                let message = message?;
+                if segment.is_none() {
+                    segment = Some(self.open_segment(self.rolled)?);
+                    self.rolled += 1;
+                }
+                let segment = segment.as_mut().unwrap();
+                let row = message.offset() as u64;
+                segment.report.rows += 1;
+                let decoded = match self.format {
+                    MessageFormat::Json => jsonl::decode(message.payload().unwrap_or(&[]),
+                                                         &self.mapping,
+                                                         &self.fields),
+                    MessageFormat::Avro => {
+                        decode_avro(message.payload().unwrap_or(&[]), self.fields.len())
+                    }
+                };
+                let added = decoded.and_then(|(uuid, timestamp, values)| {
+                    let values: Vec<&str> = values.iter().map(|v| v.as_str()).collect();
+                    segment.cons.add(&uuid, timestamp, &values).map_err(RowErrorKind::Db)
+                });
+                match added {
+                    Ok(()) => segment.report.imported += 1,
+                    Err(kind) => {
+                        segment.report.errors.push(RowError {
+                            row: row,
+                            kind: kind,
+                        })
+                    }
+                }
+            }
+            if segment.as_ref().map_or(false, |s| s.opened.elapsed() >= self.roll_every) {
+                self.roll(segment.take().unwrap(), &mut on_roll)?;
+            }
+        }
+        if let Some(segment) = segment {
+            self.roll(segment, &mut on_roll)?;
+        }
+        Ok(())
+    }
+
+    fn open_segment(&self, seq: u64) -> Result<Segment, IngestError> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let path = self.dst_dir.join(format!("{}-{:05}", secs, seq));
+        let fields: Vec<&str> = self.fields.iter().map(|f| f.as_str()).collect();
+        Ok(Segment {
+            cons: ConstructorBuilder::new(&path, &fields).build()?,
+            path: path,
+            report: ImportReport::default(),
+            opened: Instant::now(),
+        })
+    }
+
+    fn roll<F>(&self, mut segment: Segment, on_roll: &mut F) -> Result<(), IngestError>
+        where F: FnMut(&Path, &ImportReport)
+    {
+        segment.cons.finalize()?;
+        self.consumer.commit_consumer_state(CommitMode::Sync)?;
+        on_roll(&segment.path, &segment.report);
+        Ok(())
+    }
+}
+
+fn decode_avro(payload: &[u8],
+               num_fields: usize)
+               -> Result<(Uuid, Timestamp, Vec<String>), RowErrorKind> {
+    let truncated = || RowErrorKind::Malformed("truncated Avro record".to_string());
+    if payload.len() < 16 {
+        return Err(truncated());
+    }
+    let mut uuid: Uuid = [0u8; 16];
+    uuid.copy_from_slice(&payload[..16]);
+    let mut rest = &payload[16..];
+    let timestamp = read_long(&mut rest).ok_or_else(&truncated)?;
+    if timestamp < 0 {
+        return Err(RowErrorKind::InvalidTimestamp(timestamp.to_string()));
+    }
+    let mut values = Vec::with_capacity(num_fields);
+    for _ in 0..num_fields {
+        let len = read_long(&mut rest).ok_or_else(&truncated)?;
+        if len < 0 || len as u64 > rest.len() as u64 {
+            return Err(truncated());
+        }
+        let (value, tail) = rest.split_at(len as usize);
+        values.push(String::from_utf8(value.to_vec())
+            .map_err(|e| RowErrorKind::Malformed(e.to_string()))?);
+        rest = tail;
+    }
+    if !rest.is_empty() {
+        return Err(RowErrorKind::Malformed("trailing bytes after Avro record".to_string()));
+    }
+    Ok((uuid, timestamp as Timestamp, values))
+}
+
+/// Read a zig-zag encoded variable length integer.
+fn read_long(buf: &mut &[u8]) -> Option<i64> {
+    let mut z = 0u64;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = buf.split_first()?;
+        *buf = rest;
+        if shift > 63 {
+            return None;
+        }
+        z |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some((z >> 1) as i64 ^ -((z & 1) as i64));
+        }
+        shift += 7;
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_kafka {
+    use super::decode_avro;
+
+    #[test]
+    fn test_decode_avro() {
+        let mut payload = vec![2u8; 16];
+        // timestamp 300, then "ab" and ""
+        payload.extend_from_slice(&[0xd8, 0x04, 0x04, b'a', b'b', 0x00]);
+        let (uuid, timestamp, values) = decode_avro(&payload, 2).unwrap();
+        assert_eq!(uuid, [2u8; 16]);
+        assert_eq!(timestamp, 300);
+        assert_eq!(values, vec!["ab", ""]);
+        assert!(decode_avro(&payload, 3).is_err());
+        assert!(decode_avro(&payload[..20], 2).is_err());
+    }
+}
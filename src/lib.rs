@@ -1,9 +1,168 @@
+#[cfg(feature = "arrow")]
+extern crate arrow;
+#[cfg(feature = "server")]
+extern crate axum;
+#[cfg(feature = "csv")]
+extern crate csv as csv_crate;
+#[cfg(any(feature = "async", feature = "remote", feature = "server"))]
+extern crate futures;
+#[cfg(feature = "log")]
+extern crate log;
+#[cfg(feature = "index")]
+extern crate memmap2;
+#[cfg(feature = "metrics")]
+extern crate metrics;
+#[cfg(feature = "remote")]
+extern crate object_store;
+#[cfg(feature = "parquet")]
+extern crate parquet;
+#[cfg(feature = "postgres")]
+extern crate postgres;
+#[cfg(feature = "proptest")]
+extern crate proptest;
+#[cfg(feature = "grpc")]
+extern crate prost;
+#[cfg(feature = "kafka")]
+extern crate rdkafka;
+#[cfg(feature = "msgpack")]
+extern crate rmp;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
+#[cfg(any(feature = "checksums", feature = "pseudonymize"))]
+extern crate sha2;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "grpc")]
+extern crate tokio_stream;
+#[cfg(feature = "grpc")]
+extern crate tonic;
+#[cfg(feature = "grpc")]
+extern crate tonic_prost;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+
+/// Record `value` as the field `name` of the current span, with the
+/// `tracing` feature.
+macro_rules! record_span {
+    ($name:literal, $value:expr) => {
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record($name, $value);
+    };
+}
+
+/// Log a warning through `log` and `tracing`, with whichever of the
+/// features are enabled.
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "log")]
+        log::warn!($($arg)+);
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)+);
+        #[cfg(not(any(feature = "log", feature = "tracing")))]
+        let _ = format_args!($($arg)+);
+    };
+}
+
 #[allow(non_camel_case_types,dead_code,non_snake_case,private_in_public)]
 mod ffi;
-use std::path::Path;
+mod bloom;
+mod catalog;
+#[cfg(feature = "checksums")]
+mod checksum;
+#[cfg(feature = "zstd")]
+mod compress;
+mod copy;
+mod counters;
+mod durable;
+mod memory;
+mod metadata;
+#[cfg(feature = "tokio")]
+mod nonblocking;
+mod parallel;
+mod pool;
+mod pseudonym;
+mod reload;
+mod reorder;
+mod scope;
+mod set;
+mod shared;
+mod stats;
+mod storage;
+#[cfg(feature = "async")]
+mod stream;
+pub mod time;
+mod validate;
+mod writer;
+pub use bloom::{UuidBloom, BLOOM_EXT};
+pub use catalog::{Catalog, CatalogError, RetentionReport};
+#[cfg(feature = "checksums")]
+pub use checksum::{write_checksums, ChecksumError, CHECKSUM_EXT};
+#[cfg(feature = "zstd")]
+pub use compress::{compress_package, ZSTD_EXT};
+pub use counters::Counters;
+pub use copy::{merge, merge_cancellable, merge_with_progress, migrate, prune, Downsample, MergeReport,
+               MigrateReport, PruneReport};
+pub use metadata::{read_metadata, write_metadata, METADATA_EXT};
+pub use parallel::Partition;
+pub use pool::{CursorPool, DbPool, PoolError, PooledCursor};
+#[cfg(feature = "pseudonymize")]
+pub use pseudonym::UuidKey;
+pub use reload::{append_manifest, ReloadReport, ReloadableDb, Shard};
+pub use reorder::ReorderBuffer;
+pub use scope::DbScope;
+pub use set::{DbSet, OpenError};
+pub use shared::SharedDb;
+pub use storage::{FileStorage, Storage};
+#[cfg(feature = "async")]
+pub use stream::{EventStream, TrailStream, DEFAULT_STREAM_BUFFER};
+pub use validate::{repair, Finding, RepairReport, SkippedTrail, ValidationLevel,
+                   ValidationReport};
+pub use writer::{WriterEvent, WriterHandle, WriterReport, WRITER_BUFFER};
+pub mod analytics;
+pub mod diff;
+pub mod export;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod import;
+#[cfg(feature = "index")]
+pub mod index;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+pub mod query;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "server")]
+pub mod socket;
+#[cfg(feature = "json")]
+pub mod spool;
+#[cfg(feature = "proptest")]
+pub mod testing;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::ffi::CString;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::mem::transmute;
+use std::ptr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use counters::DbCounters;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Debug)]
 #[derive(PartialEq)]
@@ -44,6 +203,12 @@ pub enum Error {
     TimestampTooLarge = -264,
     TrailTooLong = -265,
     OnlyDiffFilter = -513,
+    /// Not from libtraildb: a long-running operation was cancelled.
+    Cancelled = -1025,
+    /// Not from libtraildb: a strict constructor rejected an event not
+    /// later than the last one of its trail; see
+    /// `Constructor::last_violation`.
+    OutOfOrder = -1026,
 }
 
 impl std::fmt::Display for Error {
@@ -84,11 +249,15 @@ impl std::fmt::Display for Error {
             Error::TimestampTooLarge => "TimestampTooLarge",
             Error::TrailTooLong => "TrailTooLong",
             Error::OnlyDiffFilter => "OnlyDiffFilter",
+            Error::Cancelled => "Cancelled",
+            Error::OutOfOrder => "OutOfOrder",
         };
         write!(f, "Error::{}", s)
     }
 }
 
+impl std::error::Error for Error {}
+
 /// Convert a `tdb_error` either to either a `Ok(T)` or `Err(Error)`
 fn wrap_tdb_err<T>(err: ffi::tdb_error, val: T) -> Result<T, Error> {
     match err {
@@ -101,15 +270,100 @@ fn wrap_tdb_err<T>(err: ffi::tdb_error, val: T) -> Result<T, Error> {
 pub type Timestamp = u64;
 /// The type returned by `Db::version`.
 pub type Version = u64;
+/// The format of databases written by the first TrailDB releases.
+pub const VERSION_V0: Version = 0;
+/// The format of databases written by current TrailDB releases.
+pub const VERSION_V0_1: Version = 1;
+/// The format new databases are written in; see `migrate` for upgrading
+/// older ones.
+pub const VERSION_LATEST: Version = VERSION_V0_1;
 /// An integer type that identifies an individual traul in a `Db`.
 pub type TrailId = u64;
 /// A [UUID](https://en.wikipedia.org/wiki/Universally_unique_identifier)
 /// must be included with all added events.
 pub type Uuid = [u8; 16];
 
+/// Format a UUID as 32 lowercase hex characters.
+pub fn uuid_hex(uuid: &Uuid) -> String {
+    let mut hex = [0u8; 32];
+    unsafe { ffi::tdb_uuid_hex(uuid.as_ptr() as *mut u8, hex.as_mut_ptr()) };
+    String::from_utf8_lossy(&hex).into_owned()
+}
+
+/// Parse a UUID from 32 hex characters.
+pub fn uuid_raw(hex: &str) -> Option<Uuid> {
+    if hex.len() != 32 {
+        return None;
+    }
+    let mut uuid: Uuid = [0u8; 16];
+    let ret = unsafe { ffi::tdb_uuid_raw(hex.as_ptr() as *mut u8, uuid.as_mut_ptr()) };
+    match ret {
+        ffi::tdb_error::TDB_ERR_OK => Some(uuid),
+        _ => None,
+    }
+}
+
+/// Where the sidecar file `ext` of the database at `db_path` goes, next to
+/// it: for `ext` "index", `events.tdb.index` for the package `events.tdb`,
+/// whether `db_path` is `events` or `events.tdb`, and `events.index` for
+/// the directory `events`. If `events` is a compressed package
+/// `events.tdb.zst`, it is `events.tdb.zst.index`. Sidecars can be found
+/// without opening the database.
+pub fn sidecar_path(db_path: &Path, ext: &str) -> PathBuf {
+    let mut path = stored_path(db_path).into_os_string();
+    path.push(".");
+    path.push(ext);
+    PathBuf::from(path)
+}
+
+/// The file or directory holding the database at `db_path`: `db_path`
+/// itself, the package `db_path.tdb` or the compressed package
+/// `db_path.tdb.zst`, whichever exists first.
+pub(crate) fn stored_path(db_path: &Path) -> PathBuf {
+    let mut packaged = db_path.as_os_str().to_owned();
+    packaged.push(".tdb");
+    let mut packed = packaged.clone();
+    packed.push(".zst");
+    if db_path.exists() {
+        db_path.to_path_buf()
+    } else if Path::new(&packaged).exists() {
+        PathBuf::from(packaged)
+    } else if Path::new(&packed).exists() {
+        PathBuf::from(packed)
+    } else {
+        db_path.to_path_buf()
+    }
+}
+
+/// The extensions of the sidecars the crate writes: `BLOOM_EXT`,
+/// `CHECKSUM_EXT`, `INDEX_EXT` and `METADATA_EXT`, whether or not the
+/// features writing them are enabled.
+pub(crate) const SIDECAR_EXTS: &[&str] = &["bloom", "sha256", "index", "meta"];
+
 /// TODO: Document me
-#[derive(Debug,Clone,Copy)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Item(pub u64);
+
+impl Item {
+    /// The field this item belongs to.
+    pub fn field(&self) -> Field {
+        if self.0 & 128 == 0 {
+            (self.0 & 127) as Field
+        } else {
+            ((self.0 & 127) | (((self.0 >> 8) & 127) << 7)) as Field
+        }
+    }
+
+    /// The value id of this item within its field's lexicon.
+    pub fn value(&self) -> Value {
+        if self.0 & 128 == 0 {
+            (self.0 >> 8) & 0xffff_ffff
+        } else {
+            self.0 >> 16
+        }
+    }
+}
 /// TODO: Document me
 pub type Value = u64;
 /// TODO: Document me
@@ -151,27 +405,62 @@ pub type Field = u32;
 /// ```
 pub struct Constructor {
     obj: *mut ffi::tdb_cons,
+    path: PathBuf,
+    fields: Vec<String>,
+    hints: SizeHints,
+    /// With `ConstructorBuilder::strict`, `dedup` or `track_order`, the
+    /// latest timestamp added to each trail.
+    trails: Option<HashMap<Uuid, Timestamp>>,
+    num_events: u64,
+    out_of_order: u64,
+    /// With `ConstructorBuilder::dedup`, the event last added to each
+    /// trail.
+    last_events: Option<HashMap<Uuid, LastEvent>>,
+    duplicates: u64,
+    strict: bool,
+    last_violation: Option<OrderViolation>,
+    /// Written to the metadata sidecar on finalize.
+    metadata: BTreeMap<String, String>,
+    #[cfg(feature = "checksums")]
+    checksums: bool,
+    #[cfg(feature = "zstd")]
+    zstd: Option<i32>,
 }
 
 impl Constructor {
     /// Create a new TrailDB constructor.
     pub fn new(path: &Path, fields: &[&str]) -> Result<Self, Error> {
-        let mut field_ptrs = Vec::new();
-        for f in fields.iter() {
-            field_ptrs.push(f.as_ptr());
-        }
-        let ptr = unsafe { ffi::tdb_cons_init() };
-        let ret = unsafe {
-            ffi::tdb_cons_open(ptr,
-                               path_cstr(path).as_ptr(),
-                               field_ptrs.as_slice().as_ptr() as *mut *const i8,
-                               field_ptrs.len() as u64)
-        };
-        wrap_tdb_err(ret, Constructor { obj: ptr })
+        ConstructorBuilder::new(path, fields).build()
     }
 
-    /// Add an event to the constructor.
+    /// Add an event to the constructor. With `ConstructorBuilder::dedup`, an
+    /// event identical to the one last added to its trail is dropped. With
+    /// `ConstructorBuilder::strict`, an event not later than the last one of
+    /// its trail is rejected with `Error::OutOfOrder`.
     pub fn add(&mut self, uuid: &Uuid, timestamp: Timestamp, values: &[&str]) -> Result<(), Error> {
+        let hash = match self.last_events {
+            Some(ref last_events) => {
+                let hash = event_hash(timestamp, values);
+                if last_events.get(uuid).is_some_and(|last| last.is(hash, timestamp, values)) {
+                    self.duplicates += 1;
+                    return Ok(());
+                }
+                Some(hash)
+            }
+            None => None,
+        };
+        if self.strict {
+            if let Some(&previous) = self.trails.as_ref().and_then(|trails| trails.get(uuid)) {
+                if timestamp <= previous {
+                    self.last_violation = Some(OrderViolation {
+                        uuid: *uuid,
+                        timestamp: timestamp,
+                        previous: previous,
+                    });
+                    return Err(Error::OutOfOrder);
+                }
+            }
+        }
         let mut val_ptrs = Vec::new();
         let mut val_lens = Vec::new();
         for v in values.iter() {
@@ -185,24 +474,428 @@ impl Constructor {
                               val_ptrs.as_slice().as_ptr() as *mut *const i8,
                               val_lens.as_slice().as_ptr() as *const u64)
         };
-        wrap_tdb_err(ret, ())
+        wrap_tdb_err(ret, ())?;
+        if let Some(ref mut trails) = self.trails {
+            let latest = trails.entry(*uuid).or_insert(timestamp);
+            if timestamp < *latest {
+                if self.out_of_order == 0 {
+                    warn!("traildb: event of trail {} at {} added after one at {}; \
+                           events are buffered and sorted on finalize",
+                          uuid_hex(uuid), timestamp, *latest);
+                }
+                self.out_of_order += 1;
+            } else {
+                *latest = timestamp;
+            }
+        }
+        if let (Some(hash), Some(ref mut last_events)) = (hash, &mut self.last_events) {
+            let last = last_events.entry(*uuid).or_default();
+            last.hash = hash;
+            last.timestamp = timestamp;
+            last.values.clear();
+            last.values.extend(values.iter().map(|v| v.to_string()));
+        }
+        self.num_events += 1;
+        Ok(())
+    }
+
+    /// The number of distinct UUIDs added so far, if the constructor keeps
+    /// track of trails; see `ConstructorBuilder::track_order`.
+    pub fn num_trails(&self) -> Option<u64> {
+        self.trails.as_ref().map(|trails| trails.len() as u64)
+    }
+
+    /// The number of events added so far.
+    pub fn num_events(&self) -> u64 {
+        self.num_events
+    }
+
+    /// The number of events added with a timestamp earlier than one
+    /// already added to their trail. libtraildb sorts the events of every
+    /// trail on finalize, so these end up in order, but adding events in
+    /// order is cheaper. Only counted if the constructor keeps track of
+    /// trails; see `ConstructorBuilder::track_order`.
+    pub fn out_of_order(&self) -> u64 {
+        self.out_of_order
+    }
+
+    /// The number of events dropped as duplicates with
+    /// `ConstructorBuilder::dedup`.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// The event `add` last rejected with `Error::OutOfOrder`.
+    pub fn last_violation(&self) -> Option<&OrderViolation> {
+        self.last_violation.as_ref()
+    }
+
+    /// The path the constructor was opened with. A packaged database is
+    /// finalized into `<path>.tdb`.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The names of the fields, in the order `add` expects their values.
+    pub fn field_names(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// The size hints this constructor was built with.
+    pub fn size_hints(&self) -> SizeHints {
+        self.hints
     }
 
-    /// Close a constructor without writing it to disk.
+    /// Close a constructor without writing it to disk. Constructors are
+    /// closed when dropped, finalized or not; closing twice does nothing.
     pub fn close(&mut self) {
-        unsafe { ffi::tdb_cons_close(self.obj) };
+        if !self.obj.is_null() {
+            unsafe { ffi::tdb_cons_close(self.obj) };
+            self.obj = ptr::null_mut();
+        }
     }
 
     /// Write the TrailDB to disk and close it.
+    #[cfg_attr(feature = "tracing",
+               tracing::instrument(level = "debug",
+                                   skip_all,
+                                   fields(path = %self.path.display(),
+                                          trails = self.num_trails(),
+                                          events = self.num_events)))]
     pub fn finalize(&mut self) -> Result<(), Error> {
+        if self.out_of_order > 0 {
+            warn!("traildb: {} of {} events in {} were added out of order",
+                  self.out_of_order, self.num_events, self.path.display());
+        }
+        let start = Instant::now();
         let ret = unsafe { ffi::tdb_cons_finalize(self.obj) };
-        wrap_tdb_err(ret, ())
+        wrap_tdb_err(ret, ())?;
+        stats::finalized(start.elapsed(), self.num_events);
+        #[cfg(feature = "zstd")]
+        {
+            if let Some(level) = self.zstd {
+                compress_package(&self.path, level).map_err(|_| Error::IoWrite)?;
+            }
+        }
+        if !self.metadata.is_empty() {
+            write_metadata(&self.path, &self.metadata).map_err(|_| Error::IoWrite)?;
+        }
+        #[cfg(feature = "checksums")]
+        {
+            if self.checksums {
+                write_checksums(&self.path).map_err(|_| Error::IoWrite)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the metadata `key` to `value`, to be stored next to the database
+    /// when it is finalized; see `Db::metadata`.
+    pub fn set_metadata(&mut self, key: &str, value: &str) {
+        self.metadata.insert(key.to_string(), value.to_string());
     }
 
     /// Combine an alread finalized TrailDB with a constructor.
+    ///
+    /// A constructor keeping track of trails (with `strict`, `dedup` or
+    /// `track_order`) first reads the last event of every trail of `db`, to
+    /// check the events added later against; this decodes all of `db`.
     pub fn append(&mut self, db: &Db) -> Result<(), Error> {
-        let ret = unsafe { ffi::tdb_cons_append(self.obj, transmute(db)) };
-        wrap_tdb_err(ret, ())
+        // Read before appending, so that a failure leaves the constructor
+        // as it was.
+        let mut lasts = Vec::new();
+        if self.trails.is_some() {
+            let mut cursor = db.cursor();
+            let mut items = Vec::new();
+            for trail_id in 0..db.num_trails() {
+                let uuid = *db.get_uuid(trail_id).ok_or(Error::InvalidTrailId)?;
+                cursor.get_trail(trail_id)?;
+                // Events of a trail are sorted, so its last one is its latest.
+                let mut last = None;
+                for event in &mut cursor {
+                    last = Some(event.timestamp);
+                    items.clear();
+                    items.extend_from_slice(event.items);
+                }
+                if let Some(timestamp) = last {
+                    let values: Vec<String> = if self.last_events.is_some() {
+                        items.iter().map(|&item| db.get_item_value(item).to_string()).collect()
+                    } else {
+                        Vec::new()
+                    };
+                    lasts.push((uuid, timestamp, values));
+                }
+            }
+        }
+
+        let ret = unsafe { ffi::tdb_cons_append(self.obj, db.obj) };
+        wrap_tdb_err(ret, ())?;
+        if let Some(ref mut trails) = self.trails {
+            for (uuid, timestamp, values) in lasts {
+                let latest = trails.entry(uuid).or_insert(timestamp);
+                if timestamp < *latest {
+                    continue;
+                }
+                *latest = timestamp;
+                if let Some(ref mut last_events) = self.last_events {
+                    let refs: Vec<&str> = values.iter().map(|v| v.as_str()).collect();
+                    let last = last_events.entry(uuid).or_default();
+                    last.hash = event_hash(timestamp, &refs);
+                    last.timestamp = timestamp;
+                    last.values = values;
+                }
+            }
+        }
+        self.num_events += db.num_events();
+        Ok(())
+    }
+}
+
+/// An event rejected by a strict constructor, with the timestamp of the
+/// last event of its trail.
+#[derive(Debug,Clone,PartialEq)]
+pub struct OrderViolation {
+    pub uuid: Uuid,
+    pub timestamp: Timestamp,
+    pub previous: Timestamp,
+}
+
+impl OrderViolation {
+    /// Whether the event had the same timestamp as the previous one, rather
+    /// than an earlier one.
+    pub fn is_duplicate(&self) -> bool {
+        self.timestamp == self.previous
+    }
+}
+
+impl fmt::Display for OrderViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.is_duplicate() { "duplicate timestamp" } else { "out of order" };
+        write!(f,
+               "trail {}: event at {} after one at {} ({})",
+               uuid_hex(&self.uuid),
+               self.timestamp,
+               self.previous,
+               kind)
+    }
+}
+
+/// The event last added to a trail, kept by a constructor built with
+/// `ConstructorBuilder::dedup`.
+#[derive(Default)]
+struct LastEvent {
+    /// The `event_hash` of the event, compared first.
+    hash: u64,
+    timestamp: Timestamp,
+    values: Vec<String>,
+}
+
+impl LastEvent {
+    /// Whether the event with `timestamp`, `values` and the hash `hash` is
+    /// this one. The hashes are compared first and the events only if they
+    /// match, so that a collision can't drop a distinct event.
+    fn is(&self, hash: u64, timestamp: Timestamp, values: &[&str]) -> bool {
+        self.hash == hash && self.timestamp == timestamp && self.values.len() == values.len() &&
+        self.values.iter().zip(values).all(|(last, value)| last == value)
+    }
+}
+
+/// The hash `dedup` compares events by before comparing them.
+fn event_hash(timestamp: Timestamp, values: &[&str]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    timestamp.hash(&mut hasher);
+    values.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Drop for Constructor {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+// libtraildb keeps no thread-local state, so a constructor can move between
+// threads; it can't be used from two at once, which `&mut self` prevents.
+unsafe impl Send for Constructor {}
+
+
+/// Expected sizes of an ingest, given to a `ConstructorBuilder`.
+#[derive(Debug,Clone,Copy,Default)]
+pub struct SizeHints {
+    /// The number of distinct UUIDs expected.
+    pub trails: usize,
+    /// The total number of events expected.
+    pub events: usize,
+}
+
+impl SizeHints {
+    /// The number of trails to reserve room for: `trails`, but no more than
+    /// `events` when that is given, as every trail has an event.
+    pub fn trail_capacity(&self) -> usize {
+        if self.events > 0 {
+            self.trails.min(self.events)
+        } else {
+            self.trails
+        }
+    }
+}
+
+/// Configures and opens a `Constructor`.
+///
+/// libtraildb grows its own buffers and has no sizing knob, so the hints
+/// pre-size the per-trail bookkeeping kept on the Rust side, if any (see
+/// `track_order`), and are handed on to wrappers that buffer events, such as
+/// an import sorting its rows (see `Constructor::size_hints`).
+///
+/// # Examples
+///
+/// ```
+/// use traildb::ConstructorBuilder;
+/// use std::path::Path;
+///
+/// let mut cons = ConstructorBuilder::new(Path::new("my_big_traildb"), &["user", "action"])
+///     .expected_trails(1_000_000)
+///     .expected_events(50_000_000)
+///     .build()
+///     .unwrap();
+/// assert!(cons.finalize().is_ok());
+/// ```
+pub struct ConstructorBuilder<'a> {
+    path: &'a Path,
+    fields: Vec<String>,
+    hints: SizeHints,
+    metadata: BTreeMap<String, String>,
+    dedup: bool,
+    strict: bool,
+    track_order: bool,
+    #[cfg(feature = "checksums")]
+    checksums: bool,
+    #[cfg(feature = "zstd")]
+    zstd: Option<i32>,
+}
+
+impl<'a> ConstructorBuilder<'a> {
+    /// Start configuring a constructor writing to `path` with the given fields.
+    pub fn new(path: &'a Path, fields: &[&str]) -> Self {
+        ConstructorBuilder {
+            path: path,
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+            hints: SizeHints::default(),
+            metadata: BTreeMap::new(),
+            dedup: false,
+            strict: false,
+            track_order: false,
+            #[cfg(feature = "checksums")]
+            checksums: false,
+            #[cfg(feature = "zstd")]
+            zstd: None,
+        }
+    }
+
+    /// Hint at the number of distinct UUIDs that will be added.
+    pub fn expected_trails(mut self, n: usize) -> Self {
+        self.hints.trails = n;
+        self
+    }
+
+    /// Hint at the total number of events that will be added.
+    pub fn expected_events(mut self, n: usize) -> Self {
+        self.hints.events = n;
+        self
+    }
+
+    /// Drop events identical to the event last added to their trail, same
+    /// timestamp and values, as redelivered by at-least-once pipelines.
+    /// The constructor keeps a copy of the last event of every trail, its
+    /// values included, to compare events exactly, so memory grows with the
+    /// number of trails times the size of an event.
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Reject events not later than the last event of their trail, out of
+    /// order or with the same timestamp, with `Error::OutOfOrder`, for
+    /// pipelines where ordering bugs must not go unnoticed. With `dedup`,
+    /// exact duplicates are still dropped first.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Keep the latest timestamp of every trail, to count the events added
+    /// out of order (`Constructor::out_of_order`) and the distinct trails
+    /// (`Constructor::num_trails`). `strict` and `dedup` keep it anyway;
+    /// otherwise constructors hold no state per trail.
+    pub fn track_order(mut self, track_order: bool) -> Self {
+        self.track_order = track_order;
+        self
+    }
+
+    /// Store the metadata `key` with `value` next to the database once it is
+    /// finalized; see `Db::metadata`.
+    pub fn metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Write the checksums of the database to its sidecar `CHECKSUM_EXT`
+    /// once it is finalized, for `Db::verify_checksums`.
+    #[cfg(feature = "checksums")]
+    pub fn checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// Compress the package with zstd at `level` once it is finalized,
+    /// leaving `<path>.tdb.zst`; see `compress_package`.
+    #[cfg(feature = "zstd")]
+    pub fn zstd(mut self, level: i32) -> Self {
+        self.zstd = Some(level);
+        self
+    }
+
+    /// Open the constructor.
+    pub fn build(self) -> Result<Constructor, Error> {
+        let field_names: Vec<CString> = self.fields
+            .iter()
+            .map(|f| CString::new(f.as_str()).map_err(|_| Error::InvalidFieldname))
+            .collect::<Result<_, _>>()?;
+        let field_ptrs: Vec<*const i8> = field_names.iter().map(|f| f.as_ptr()).collect();
+        let ptr = unsafe { ffi::tdb_cons_init() };
+        let ret = unsafe {
+            ffi::tdb_cons_open(ptr,
+                               path_cstr(self.path).as_ptr(),
+                               field_ptrs.as_slice().as_ptr() as *mut *const i8,
+                               field_ptrs.len() as u64)
+        };
+        wrap_tdb_err(ret,
+                     Constructor {
+                         obj: ptr,
+                         path: self.path.to_path_buf(),
+                         fields: self.fields,
+                         hints: self.hints,
+                         trails: if self.strict || self.dedup || self.track_order {
+                             Some(HashMap::with_capacity(self.hints.trail_capacity()))
+                         } else {
+                             None
+                         },
+                         num_events: 0,
+                         out_of_order: 0,
+                         last_events: if self.dedup {
+                             Some(HashMap::with_capacity(self.hints.trail_capacity()))
+                         } else {
+                             None
+                         },
+                         duplicates: 0,
+                         strict: self.strict,
+                         last_violation: None,
+                         metadata: self.metadata,
+                         #[cfg(feature = "checksums")]
+                         checksums: self.checksums,
+                         #[cfg(feature = "zstd")]
+                         zstd: self.zstd,
+                     })
     }
 }
 
@@ -211,13 +904,72 @@ impl Constructor {
 
 pub struct Db<'a> {
     obj: &'a mut ffi::tdb,
+    path: PathBuf,
+    counters: Arc<DbCounters>,
+    /// Loaded by `uuid_bloom`.
+    bloom: OnceLock<Option<UuidBloom>>,
+    /// Loaded by the first scan with a filter.
+    #[cfg(feature = "index")]
+    index: OnceLock<Option<index::InvertedIndex>>,
+    /// The decompressed copy of a compressed package, removed with the `Db`.
+    #[cfg(feature = "zstd")]
+    _unpacked: Option<compress::Unpacked>,
+    /// The copy of a package opened with `open_storage`, removed with the
+    /// `Db`.
+    _staged: Option<storage::Staged>,
 }
 
 impl<'a> Db<'a> {
+    #[cfg_attr(feature = "tracing",
+               tracing::instrument(level = "debug",
+                                   skip_all,
+                                   fields(path = %path.display(),
+                                          trails = tracing::field::Empty,
+                                          events = tracing::field::Empty)))]
     pub fn open(path: &Path) -> Result<Self, Error> {
+        let start = Instant::now();
+        #[cfg(feature = "zstd")]
+        let unpacked = match compress::compressed_package(path) {
+            Some(packed) => Some(compress::Unpacked::new(&packed).map_err(|_| Error::IoOpen)?),
+            None => None,
+        };
+        #[cfg(feature = "zstd")]
+        let mapped = unpacked.as_ref().map_or(path, |unpacked| unpacked.path());
+        #[cfg(not(feature = "zstd"))]
+        let mapped = path;
         let ptr = unsafe { ffi::tdb_init() };
-        let ret = unsafe { ffi::tdb_open(ptr, path_cstr(path).as_ptr()) };
-        unsafe { wrap_tdb_err(ret, Db { obj: transmute(ptr) }) }
+        let ret = unsafe { ffi::tdb_open(ptr, path_cstr(mapped).as_ptr()) };
+        let db = unsafe {
+            wrap_tdb_err(ret,
+                         Db {
+                             obj: transmute(ptr),
+                             path: path.to_path_buf(),
+                             counters: Arc::new(DbCounters::opened(mapped)),
+                             bloom: OnceLock::new(),
+                             #[cfg(feature = "index")]
+                             index: OnceLock::new(),
+                             #[cfg(feature = "zstd")]
+                             _unpacked: unpacked,
+                             _staged: None,
+                         })
+        }?;
+        stats::opened(start.elapsed());
+        record_span!("trails", db.num_trails());
+        record_span!("events", db.num_events());
+        Ok(db)
+    }
+
+    /// Open the package in `storage`: in place if it is a local file,
+    /// otherwise from a temporary copy, removed with the `Db`, which is
+    /// then its `path`. Such a copy has no sidecars.
+    pub fn open_storage(storage: &dyn Storage) -> Result<Self, Error> {
+        if let Some(path) = storage.local_path() {
+            return Db::open(path);
+        }
+        let staged = storage::Staged::new(storage).map_err(|_| Error::IoOpen)?;
+        let mut db = Db::open(staged.path())?;
+        db._staged = Some(staged);
+        Ok(db)
     }
 
     pub fn close(&mut self) {
@@ -226,6 +978,17 @@ impl<'a> Db<'a> {
         }
     }
 
+    /// The path the database was opened with.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Where the sidecar file `ext` of the database goes; see
+    /// `sidecar_path`.
+    pub fn sidecar_path(&self, ext: &str) -> PathBuf {
+        sidecar_path(&self.path, ext)
+    }
+
     pub fn num_trails(&self) -> u64 {
         unsafe { ffi::tdb_num_trails(self.obj) }
     }
@@ -250,6 +1013,18 @@ impl<'a> Db<'a> {
         unsafe { ffi::tdb_version(self.obj) }
     }
 
+    /// Collect the database's metadata.
+    pub fn info(&'a self) -> DbInfo {
+        DbInfo {
+            num_trails: self.num_trails(),
+            num_events: self.num_events(),
+            min_timestamp: self.min_timestamp(),
+            max_timestamp: self.max_timestamp(),
+            version: self.version(),
+            fields: self.field_names().iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
     pub fn will_need(&self) {
         unsafe { ffi::tdb_willneed(self.obj) };
     }
@@ -269,7 +1044,12 @@ impl<'a> Db<'a> {
         })
     }
 
+    /// The id of the trail of `uuid`. UUIDs ruled out by the database's
+    /// bloom filter, if it has one, aren't looked up.
     pub fn get_trail_id(&self, uuid: &Uuid) -> Option<TrailId> {
+        if !self.uuid_bloom().map_or(true, |bloom| bloom.may_contain(uuid)) {
+            return None;
+        }
         let mut id: TrailId = 0;
         let ret = unsafe {
             ffi::tdb_get_trail_id(self.obj, uuid.as_ptr() as *mut u8, &mut id as *mut TrailId)
@@ -287,17 +1067,79 @@ impl<'a> Db<'a> {
         }
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all))]
     pub fn cursor(&self) -> Cursor<'a> {
         unsafe {
             let ptr = ffi::tdb_cursor_new(self.obj);
-            Cursor { obj: transmute(ptr) }
+            self.counters.cursors.fetch_add(1, Ordering::Relaxed);
+            Cursor {
+                obj: transmute(ptr),
+                counters: self.counters.clone(),
+                decoded: 0,
+            }
         }
     }
 
+    /// Create a pool handing out at most `n` reusable cursors at a time.
+    pub fn cursor_pool(&'a self, n: usize) -> CursorPool<'a> {
+        CursorPool::new(self, n)
+    }
+
     pub fn iter(&'a self) -> DbIter<'a> {
         DbIter { pos: 0, db: self }
     }
 
+    /// Resolve the items of `event` to their values.
+    pub fn resolve_event(&self, event: &Event) -> ResolvedEvent {
+        ResolvedEvent {
+            timestamp: event.timestamp,
+            values: event.items.iter().map(|item| self.get_item_value(*item).to_string()).collect(),
+        }
+    }
+
+    /// Read a whole trail into a `TrailBatch`.
+    pub fn trail_batch(&self, trail_id: TrailId) -> Result<TrailBatch, Error> {
+        let uuid = *self.get_uuid(trail_id).ok_or(Error::InvalidTrailId)?;
+        let mut cursor = self.cursor();
+        cursor.get_trail(trail_id)?;
+        let mut events = Vec::with_capacity(cursor.len() as usize);
+        for event in &mut cursor {
+            events.push(self.resolve_event(&event));
+        }
+        Ok(TrailBatch {
+            uuid: uuid,
+            events: events,
+        })
+    }
+
+    /// The number of events matching `filter`.
+    pub fn count_events(&self, filter: &EventFilter) -> Result<u64, Error> {
+        let mut cursor = self.cursor();
+        cursor.set_event_filter(filter)?;
+        let mut count = 0;
+        for trail_id in self.scan_trails(Some(filter)) {
+            cursor.get_trail(trail_id)?;
+            count += (&mut cursor).count() as u64;
+        }
+        Ok(count)
+    }
+
+    /// The trails a scan for events matching `filter` has to visit: with
+    /// the `index` feature, only those the database's index says can hold
+    /// one, if it has an index that narrows the filter down; otherwise
+    /// every trail.
+    pub(crate) fn scan_trails(&self, filter: Option<&EventFilter>) -> Box<dyn Iterator<Item = TrailId>> {
+        #[cfg(feature = "index")]
+        {
+            if let Some(trails) = filter.and_then(|filter| index::candidate_trails(self, filter)) {
+                return Box::new(trails.into_iter());
+            }
+        }
+        #[cfg(not(feature = "index"))]
+        let _ = filter;
+        Box::new(0..self.num_trails())
+    }
+
     pub fn get_item_value(&'a self, item: Item) -> &'a str {
         unsafe {
             let mut len = 0u64;
@@ -310,12 +1152,99 @@ impl<'a> Db<'a> {
     pub fn get_field_name(&'a self, field: Field) -> Option<&'a str> {
         unsafe {
             let ptr = ffi::tdb_get_field_name(self.obj, field);
+            if ptr.is_null() {
+                return None;
+            }
             match std::ffi::CStr::from_ptr(ptr).to_str() {
                 Ok(s) => Some(s),
                 Err(_) => None,
             }
         }
     }
+
+    /// Look up a field by name.
+    pub fn get_field(&self, name: &str) -> Option<Field> {
+        let name = match CString::new(name) {
+            Ok(name) => name,
+            Err(_) => return None,
+        };
+        let mut field: Field = 0;
+        let ret = unsafe { ffi::tdb_get_field(self.obj, name.as_ptr(), &mut field) };
+        match ret {
+            ffi::tdb_error::TDB_ERR_OK => Some(field),
+            _ => None,
+        }
+    }
+
+    /// Look up the item for `value` in `field`, if it occurs in the lexicon.
+    pub fn get_item(&self, field: Field, value: &str) -> Option<Item> {
+        let item = unsafe {
+            ffi::tdb_get_item(self.obj,
+                              field,
+                              value.as_ptr() as *const i8,
+                              value.len() as u64)
+        };
+        match item {
+            0 => None,
+            item => Some(Item(item)),
+        }
+    }
+
+    /// The number of distinct values of `field`, including the empty value.
+    pub fn lexicon_size(&self, field: Field) -> u64 {
+        unsafe { ffi::tdb_lexicon_size(self.obj, field) }
+    }
+
+    /// Look up value id `val` in the lexicon of `field`. Values that aren't
+    /// UTF-8, which TrailDB allows, are `None`; see `get_value_bytes`.
+    pub fn get_value(&'a self, field: Field, val: Value) -> Option<&'a str> {
+        self.get_value_bytes(field, val).and_then(|s| std::str::from_utf8(s).ok())
+    }
+
+    /// Look up value id `val` in the lexicon of `field`, as stored.
+    pub fn get_value_bytes(&'a self, field: Field, val: Value) -> Option<&'a [u8]> {
+        unsafe {
+            let mut len = 0u64;
+            let ptr = ffi::tdb_get_value(self.obj, field, val, &mut len);
+            if ptr.is_null() {
+                return None;
+            }
+            Some(std::slice::from_raw_parts(ptr as *const u8, len as usize))
+        }
+    }
+
+    /// The names of all fields except the implicit `time` field, in item order.
+    pub fn field_names(&'a self) -> Vec<&'a str> {
+        (1..self.num_fields() as Field)
+            .map(|field| self.get_field_name(field).unwrap_or(""))
+            .collect()
+    }
+}
+
+// SAFETY: libtraildb never writes to a `tdb` once it is opened: metadata,
+// lexicons and UUIDs are read from memory-mapped files, and decoding state
+// lives in cursors, which only decode through `&mut self`. `close`, the one
+// call that frees the handle, takes `&mut self`. A `Db` can therefore be
+// moved to and read from several threads at once. These are spelled out
+// rather than left to the bindgen type of `obj`, so that a bindgen upgrade
+// can't change them.
+unsafe impl<'a> Send for Db<'a> {}
+unsafe impl<'a> Sync for Db<'a> {}
+
+
+
+
+/// A summary of a `Db`'s metadata, returned by `Db::info`.
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DbInfo {
+    pub num_trails: u64,
+    pub num_events: u64,
+    pub min_timestamp: Timestamp,
+    pub max_timestamp: Timestamp,
+    pub version: Version,
+    /// Field names, excluding the implicit `time` field.
+    pub fields: Vec<String>,
 }
 
 
@@ -351,21 +1280,53 @@ impl<'a> Iterator for DbIter<'a> {
 
 pub struct Cursor<'a> {
     obj: &'a mut ffi::tdb_cursor,
+    counters: Arc<DbCounters>,
+    /// Events returned since the last trail was loaded, published in one
+    /// go rather than per event.
+    decoded: u64,
 }
 
 impl<'a> Cursor<'a> {
     pub fn get_trail(&mut self, trail_id: TrailId) -> Result<(), Error> {
+        self.flush_decoded();
         let ret = unsafe { ffi::tdb_get_trail(self.obj, trail_id) };
-        wrap_tdb_err(ret, ())
+        wrap_tdb_err(ret, ())?;
+        self.counters.trails.fetch_add(1, Ordering::Relaxed);
+        stats::trails_scanned(1);
+        Ok(())
     }
 
     pub fn len(&mut self) -> u64 {
         unsafe { ffi::tdb_get_trail_length(self.obj) }
     }
+
+    /// Only return events matching `filter` from now on.
+    pub fn set_event_filter(&mut self, filter: &'a EventFilter) -> Result<(), Error> {
+        let ret = unsafe { ffi::tdb_cursor_set_event_filter(self.obj, filter.obj) };
+        wrap_tdb_err(ret, ())
+    }
+
+    /// Return all events again.
+    pub fn unset_event_filter(&mut self) {
+        unsafe { ffi::tdb_cursor_unset_event_filter(self.obj) };
+    }
+
+    fn flush_decoded(&mut self) {
+        if self.decoded > 0 {
+            self.counters.events.fetch_add(self.decoded, Ordering::Relaxed);
+            stats::events_decoded(self.decoded);
+            self.decoded = 0;
+        }
+    }
 }
 
+// A cursor owns its decoding state and only reads from the (immutable)
+// database, so handing it to another thread is fine; sharing one is not.
+unsafe impl<'a> Send for Cursor<'a> {}
+
 impl<'a> Drop for Cursor<'a> {
     fn drop(&mut self) {
+        self.flush_decoded();
         unsafe { ffi::tdb_cursor_free(self.obj) };
     }
 }
@@ -374,16 +1335,167 @@ impl<'a> Iterator for Cursor<'a> {
     type Item = Event<'a>;
 
     fn next(&mut self) -> Option<Event<'a>> {
-        unsafe {
+        let event = unsafe {
             let e = ffi::tdb_cursor_next(self.obj);
             Event::from_tdb_event(e)
+        };
+        if event.is_some() {
+            self.decoded += 1;
         }
+        event
     }
 }
 
 
 
 
+/// A single term of an `EventFilter` clause.
+#[derive(Debug,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Term {
+    pub item: Item,
+    pub negative: bool,
+}
+
+/// A filter in conjunctive normal form, evaluated by libtraildb as a cursor
+/// decodes events.
+///
+/// Terms within a clause are OR'ed together and clauses are AND'ed.
+/// A new filter starts with one empty clause.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::{Db, EventFilter};
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let action = db.get_field("action").unwrap();
+/// let mut filter = EventFilter::new();
+/// filter.add_term(db.get_item(action, "login").unwrap(), false).unwrap();
+/// let mut cursor = db.cursor();
+/// cursor.set_event_filter(&filter).unwrap();
+/// ```
+pub struct EventFilter {
+    obj: *mut ffi::tdb_event_filter,
+    clauses: Vec<Vec<Term>>,
+}
+
+impl EventFilter {
+    pub fn new() -> Self {
+        EventFilter {
+            obj: unsafe { ffi::tdb_event_filter_new() },
+            clauses: vec![Vec::new()],
+        }
+    }
+
+    /// Add a term to the current clause. A negative term matches events
+    /// that do not contain `item`.
+    pub fn add_term(&mut self, item: Item, negative: bool) -> Result<(), Error> {
+        let ret = unsafe { ffi::tdb_event_filter_add_term(self.obj, item.0, negative as i32) };
+        wrap_tdb_err(ret, ())?;
+        self.clauses.last_mut().unwrap().push(Term {
+            item: item,
+            negative: negative,
+        });
+        Ok(())
+    }
+
+    /// Start a new clause, AND'ed with the previous ones.
+    pub fn new_clause(&mut self) -> Result<(), Error> {
+        let ret = unsafe { ffi::tdb_event_filter_new_clause(self.obj) };
+        wrap_tdb_err(ret, ())?;
+        self.clauses.push(Vec::new());
+        Ok(())
+    }
+
+    /// The clauses added so far.
+    pub fn clauses(&self) -> &[Vec<Term>] {
+        &self.clauses
+    }
+
+    /// Whether a cursor with this filter would return `event`: whether
+    /// every clause has a term matching it. A filter with an empty clause,
+    /// such as a new one, matches nothing.
+    ///
+    /// Like with cursors, the filter must have been built against the `Db`
+    /// the event comes from.
+    pub fn matches(&self, event: &Event) -> bool {
+        self.matches_items(event.items)
+    }
+
+    /// Like `matches`, for the items of an event, e.g. of an `EventBuf`.
+    pub fn matches_items(&self, items: &[Item]) -> bool {
+        self.evaluate(|item| {
+            let field = item.field() as usize;
+            field > 0 && items.get(field - 1) == Some(&item)
+        })
+    }
+
+    /// Like `matches`, for an event resolved from `db`.
+    pub fn matches_resolved(&self, db: &Db, event: &ResolvedEvent) -> bool {
+        self.evaluate(|item| {
+            let field = item.field() as usize;
+            field > 0 &&
+            event.values.get(field - 1).map(|v| v.as_str()) == Some(db.get_item_value(item))
+        })
+    }
+
+    /// Evaluate the clauses, given whether an event holds each item.
+    fn evaluate<F: Fn(Item) -> bool>(&self, holds: F) -> bool {
+        self.clauses.iter().all(|clause| {
+            clause.iter().any(|term| holds(term.item) != term.negative)
+        })
+    }
+}
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for EventFilter {
+    fn drop(&mut self) {
+        unsafe { ffi::tdb_event_filter_free(self.obj) };
+    }
+}
+
+/// Filters serialize as their list of clauses. Terms refer to items, which
+/// are only meaningful for the `Db` the filter was built against.
+#[cfg(feature = "serde")]
+impl Serialize for EventFilter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.clauses.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for EventFilter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error as DeError;
+
+        let clauses: Vec<Vec<Term>> = Vec::deserialize(deserializer)?;
+        let mut filter = EventFilter::new();
+        for (i, clause) in clauses.iter().enumerate() {
+            if i > 0 {
+                filter.new_clause().map_err(D::Error::custom)?;
+            }
+            for term in clause {
+                filter.add_term(term.item, term.negative).map_err(D::Error::custom)?;
+            }
+        }
+        Ok(filter)
+    }
+}
+
+// The filter is only read by cursors once built.
+unsafe impl Send for EventFilter {}
+unsafe impl Sync for EventFilter {}
+
+
+
+
 pub struct Trail<'a> {
     pub id: TrailId,
     cursor: Cursor<'a>,
@@ -427,6 +1539,38 @@ impl<'a> Event<'a> {
             }
         }
     }
+
+    /// Copy the event out of the cursor's buffer.
+    pub fn to_event_buf(&self) -> EventBuf {
+        EventBuf {
+            timestamp: self.timestamp,
+            items: self.items.to_vec(),
+        }
+    }
+}
+
+/// An owned `Event`, which stays valid after its cursor moves on.
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EventBuf {
+    pub timestamp: Timestamp,
+    pub items: Vec<Item>,
+}
+
+/// An event with its items resolved to their values, in field order.
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ResolvedEvent {
+    pub timestamp: Timestamp,
+    pub values: Vec<String>,
+}
+
+/// The UUID and resolved events of one trail, detached from any `Db`.
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TrailBatch {
+    pub uuid: Uuid,
+    pub events: Vec<ResolvedEvent>,
 }
 
 
@@ -435,7 +1579,7 @@ impl<'a> Event<'a> {
 #[cfg(test)]
 mod test_traildb {
     extern crate uuid;
-    use super::{Constructor, Db};
+    use super::{Constructor, ConstructorBuilder, Db, Error, EventFilter};
     use std::path::Path;
 
     #[test]
@@ -523,4 +1667,161 @@ mod test_traildb {
             }
         }
     }
+
+    #[test]
+    fn test_constructor_builder() {
+        let db_path = Path::new("test_builder");
+        let mut cons = ConstructorBuilder::new(db_path, &["field1"])
+            .expected_trails(2)
+            .expected_events(4)
+            .track_order(true)
+            .build()
+            .unwrap();
+        assert_eq!(cons.size_hints().trails, 2);
+        assert_eq!(cons.size_hints().events, 4);
+
+        for (i, uuid) in [[1u8; 16], [2u8; 16]].iter().enumerate() {
+            assert!(cons.add(uuid, i as u64, &["a"]).is_ok());
+            assert!(cons.add(uuid, i as u64 + 1, &["b"]).is_ok());
+        }
+        assert_eq!(cons.num_trails(), Some(2));
+        assert_eq!(cons.num_events(), 4);
+        assert!(cons.finalize().is_ok());
+        assert_eq!(Constructor::new(Path::new("test_builder_untracked"), &["field1"])
+                       .unwrap()
+                       .num_trails(),
+                   None);
+
+        let db = Db::open(db_path).unwrap();
+        assert_eq!(db.num_trails(), 2);
+        assert_eq!(db.num_events(), 4);
+    }
+
+    #[test]
+    fn test_constructor_dedup() {
+        let db_path = Path::new("test_constructor_dedup");
+        let mut cons = ConstructorBuilder::new(db_path, &["action"]).dedup(true).build().unwrap();
+        for &(uuid, timestamp, action) in &[(1u8, 1, "view"),
+                                            (1, 1, "view"),
+                                            (2, 1, "view"),
+                                            (1, 1, "click"),
+                                            (1, 1, "view"),
+                                            (1, 2, "view"),
+                                            (1, 2, "view")] {
+            assert!(cons.add(&[uuid; 16], timestamp, &[action]).is_ok());
+        }
+        assert_eq!(cons.num_events(), 5);
+        assert_eq!(cons.duplicates(), 2);
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        assert_eq!(db.num_events(), 5);
+    }
+
+    #[test]
+    fn test_constructor_strict() {
+        let db_path = Path::new("test_constructor_strict");
+        let mut cons = ConstructorBuilder::new(db_path, &["action"]).strict(true).build().unwrap();
+        assert!(cons.add(&[1u8; 16], 5, &["view"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 1, &["view"]).is_ok());
+        assert_eq!(cons.add(&[1u8; 16], 3, &["view"]), Err(Error::OutOfOrder));
+        let violation = cons.last_violation().unwrap().clone();
+        assert_eq!((violation.uuid, violation.timestamp, violation.previous), ([1u8; 16], 3, 5));
+        assert!(!violation.is_duplicate());
+        assert_eq!(cons.add(&[1u8; 16], 5, &["click"]), Err(Error::OutOfOrder));
+        assert!(cons.last_violation().unwrap().is_duplicate());
+        assert!(cons.add(&[1u8; 16], 6, &["view"]).is_ok());
+        assert_eq!(cons.num_events(), 3);
+        assert!(cons.finalize().is_ok());
+
+        // Appended trails are checked against their last events.
+        let db = Db::open(db_path).unwrap();
+        let appended_path = Path::new("test_constructor_strict_appended");
+        let mut cons = ConstructorBuilder::new(appended_path, &["action"]).strict(true).build().unwrap();
+        assert!(cons.append(&db).is_ok());
+        assert_eq!(cons.add(&[1u8; 16], 6, &["view"]), Err(Error::OutOfOrder));
+        assert_eq!(cons.add(&[2u8; 16], 0, &["view"]), Err(Error::OutOfOrder));
+        assert!(cons.add(&[1u8; 16], 7, &["view"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        // Redelivered copies of appended events are dropped.
+        let dedup_path = Path::new("test_constructor_strict_dedup");
+        let mut cons = ConstructorBuilder::new(dedup_path, &["action"]).dedup(true).build().unwrap();
+        assert!(cons.append(&db).is_ok());
+        assert!(cons.add(&[1u8; 16], 6, &["view"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 1, &["click"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 1, &["click"]).is_ok());
+        assert_eq!(cons.duplicates(), 2);
+        assert_eq!(cons.num_events(), 4);
+        assert!(cons.finalize().is_ok());
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let db_path = Path::new("test_filter_matches");
+        let mut cons = Constructor::new(db_path, &["action", "page"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["view", "home"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["click", "home"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 3, &["view", ""]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let action = db.get_field("action").unwrap();
+        let page = db.get_field("page").unwrap();
+        // action = view AND page != home
+        let mut filter = EventFilter::new();
+        assert!(!filter.matches_items(&[]));
+        filter.add_term(db.get_item(action, "view").unwrap(), false).unwrap();
+        filter.new_clause().unwrap();
+        filter.add_term(db.get_item(page, "home").unwrap(), true).unwrap();
+
+        let mut cursor = db.cursor();
+        cursor.get_trail(0).unwrap();
+        let matched: Vec<u64> = (&mut cursor)
+            .filter(|e| filter.matches(e))
+            .map(|e| e.timestamp)
+            .collect();
+        assert_eq!(matched, vec![3]);
+
+        cursor.get_trail(0).unwrap();
+        let resolved: Vec<bool> = (&mut cursor)
+            .map(|e| filter.matches_resolved(&db, &db.resolve_event(&e)))
+            .collect();
+        assert_eq!(resolved, vec![false, false, true]);
+
+        cursor.set_event_filter(&filter).unwrap();
+        cursor.get_trail(0).unwrap();
+        assert_eq!(cursor.map(|e| e.timestamp).collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[test]
+    fn test_filter_matches_empty_clause() {
+        let db_path = Path::new("test_filter_matches_empty_clause");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["view"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["click"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let view = db.get_item(db.get_field("action").unwrap(), "view").unwrap();
+        let empty = EventFilter::new();
+        let mut trailing = EventFilter::new();
+        trailing.add_term(view, false).unwrap();
+        trailing.new_clause().unwrap();
+
+        // `matches` must agree with the filter libtraildb applies in cursors.
+        for filter in &[empty, trailing] {
+            let mut cursor = db.cursor();
+            cursor.get_trail(0).unwrap();
+            let matched: Vec<u64> = (&mut cursor)
+                .filter(|e| filter.matches(e))
+                .map(|e| e.timestamp)
+                .collect();
+            cursor.set_event_filter(filter).unwrap();
+            cursor.get_trail(0).unwrap();
+            let filtered: Vec<u64> = (&mut cursor).map(|e| e.timestamp).collect();
+            assert_eq!(matched, filtered);
+            assert!(matched.is_empty());
+        }
+    }
 }
@@ -0,0 +1,96 @@
+//! Opening packaged databases that aren't on the filesystem.
+//!
+//! libtraildb only opens paths, so the package is written to a scratch file,
+//! on `/dev/shm` where there is one, opened, and removed right away. The
+//! opened database keeps the file mapped, so it stays readable until the
+//! database is closed, and nothing is left behind to clean up.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Db, Error};
+
+/// Distinguishes the scratch files of one process.
+static SCRATCH_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+impl<'a> Db<'a> {
+    /// Open a packaged (`.tdb`) database held in memory, e.g. fetched from
+    /// object storage or embedded in a test.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    ///
+    /// let package = std::fs::read("events.tdb").unwrap();
+    /// let db = Db::open_bytes(&package).unwrap();
+    /// println!("{} trails", db.num_trails());
+    /// ```
+    pub fn open_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Self::open_package_reader(bytes)
+    }
+
+    /// Open a packaged (`.tdb`) database read from `reader`.
+    ///
+    /// Fails with `Error::IoOpen` if no scratch file can be created and
+    /// `Error::IoWrite` if reading the package into it fails.
+    pub fn open_package_reader<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let (path, mut file) = scratch_file().map_err(|_| Error::IoOpen)?;
+        let copied = io::copy(&mut reader, &mut file).map_err(|_| Error::IoWrite);
+        drop(file);
+        let db = copied.and_then(|_| Db::open(&path));
+        let _ = fs::remove_file(&path);
+        db
+    }
+}
+
+/// Create a new scratch file for a package.
+fn scratch_file() -> io::Result<(PathBuf, fs::File)> {
+    let shm = PathBuf::from("/dev/shm");
+    let dir = if shm.is_dir() { shm } else { env::temp_dir() };
+    loop {
+        let path = dir.join(format!("traildb-{}-{}.tdb",
+                                    process::id(),
+                                    SCRATCH_SEQ.fetch_add(1, Ordering::Relaxed)));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_memory {
+    use super::super::{Constructor, Db};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_open_bytes() {
+        let db_path = Path::new("test_memory_open_bytes");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["view"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 2, &["buy"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let package = fs::read("test_memory_open_bytes.tdb").unwrap();
+        let db = Db::open_bytes(&package).unwrap();
+        assert_eq!(db.num_trails(), 2);
+        assert_eq!(db.num_events(), 2);
+        assert_eq!(db.get_trail_id(&[2u8; 16]), Some(1));
+        assert_eq!(db.field_names(), vec!["action"]);
+
+        let db = Db::open_package_reader(&package[..]).unwrap();
+        assert_eq!(db.num_events(), 2);
+
+        assert!(Db::open_bytes(b"not a package").is_err());
+    }
+}
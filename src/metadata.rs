@@ -0,0 +1,155 @@
+//! Key-value metadata kept next to a database, such as its schema version,
+//! source, time unit or owner, which the format has no place for.
+//!
+//! Metadata is written to the sidecar `METADATA_EXT` of the database (see
+//! `sidecar_path`) by a constructor given some with
+//! `ConstructorBuilder::metadata` or `Constructor::set_metadata` when it
+//! finalizes, or by `write_metadata`, and read back with `Db::metadata`.
+//! The sidecar holds a line per key, sorted, with the key and the value
+//! separated by a tab; backslashes, tabs and line breaks in either are
+//! escaped.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::durable::sync_dir;
+use super::{sidecar_path, Db};
+
+/// The extension of metadata sidecars.
+pub const METADATA_EXT: &str = "meta";
+
+/// Write `metadata` to the sidecar of the database at `db_path`, replacing
+/// any metadata it had, and return the sidecar's path.
+pub fn write_metadata(db_path: &Path, metadata: &BTreeMap<String, String>) -> io::Result<PathBuf> {
+    let path = sidecar_path(db_path, METADATA_EXT);
+    let mut contents = String::new();
+    for (key, value) in metadata {
+        contents.push_str(&format!("{}\t{}\n", escape(key), escape(value)));
+    }
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let tmp = dir.join(format!(".{}.tmp", name));
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, &path)?;
+    sync_dir(dir)?;
+    Ok(path)
+}
+
+/// Read the metadata sidecar at `path`.
+pub fn read_metadata(path: &Path) -> io::Result<BTreeMap<String, String>> {
+    let mut metadata = BTreeMap::new();
+    for line in fs::read_to_string(path)?.lines().filter(|line| !line.is_empty()) {
+        match line.find('\t') {
+            Some(at) => {
+                metadata.insert(unescape(&line[..at]), unescape(&line[at + 1..]));
+            }
+            None => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("malformed metadata line {:?}", line)));
+            }
+        }
+    }
+    Ok(metadata)
+}
+
+impl<'a> Db<'a> {
+    /// The metadata stored next to the database, empty if there is none.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::{ConstructorBuilder, Db};
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("clicks");
+    /// let mut cons = ConstructorBuilder::new(path, &["page"])
+    ///     .metadata("schema_version", "3")
+    ///     .metadata("time_unit", "ms")
+    ///     .build()
+    ///     .unwrap();
+    /// cons.add(&[1u8; 16], 1_700_000_000_000, &["/"]).unwrap();
+    /// cons.finalize().unwrap();
+    ///
+    /// let db = Db::open(path).unwrap();
+    /// assert_eq!(db.metadata().unwrap()["time_unit"], "ms");
+    /// ```
+    pub fn metadata(&self) -> io::Result<BTreeMap<String, String>> {
+        match read_metadata(&self.sidecar_path(METADATA_EXT)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            result => result,
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+
+
+
+#[cfg(test)]
+mod test_metadata {
+    use super::{write_metadata, METADATA_EXT};
+    use super::super::{ConstructorBuilder, Db};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_metadata() {
+        let db_path = Path::new("test_metadata");
+        let mut cons = ConstructorBuilder::new(db_path, &["action"])
+            .metadata("owner", "growth\tteam")
+            .build()
+            .unwrap();
+        cons.set_metadata("source", "kafka://events\\clicks\n");
+        assert!(cons.add(&[1u8; 16], 1, &["view"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let mut metadata = db.metadata().unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata["owner"], "growth\tteam");
+        assert_eq!(metadata["source"], "kafka://events\\clicks\n");
+
+        metadata.insert("schema_version".to_string(), "2".to_string());
+        write_metadata(db_path, &metadata).unwrap();
+        assert_eq!(db.metadata().unwrap(), metadata);
+
+        fs::remove_file(db.sidecar_path(METADATA_EXT)).unwrap();
+        assert!(db.metadata().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+//! Compact MessagePack encoding of resolved events and trail batches, for
+//! services exchanging trail slices.
+//!
+//! Field names are not encoded; both sides are expected to agree on the
+//! schema. The layout is positional:
+//!
+//! ```text
+//! ResolvedEvent: [timestamp, [value, ...]]
+//! TrailBatch:    [uuid (bin 16), [ResolvedEvent, ...]]
+//! ```
+
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use rmp::{decode, encode};
+
+use super::{ResolvedEvent, TrailBatch, Uuid};
+
+/// The most elements or bytes reserved ahead of reading them. Lengths come
+/// from the input, so larger buffers only grow as the data arrives, and a
+/// message claiming gigabytes it doesn't hold fails at its end instead of
+/// exhausting memory.
+const MAX_RESERVE: usize = 4096;
+
+fn invalid<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Encode one event.
+pub fn write_event<W: Write>(out: &mut W, event: &ResolvedEvent) -> io::Result<()> {
+    encode::write_array_len(out, 2).map_err(invalid)?;
+    encode::write_uint(out, event.timestamp).map_err(invalid)?;
+    encode::write_array_len(out, event.values.len() as u32).map_err(invalid)?;
+    for value in &event.values {
+        encode::write_str(out, value).map_err(invalid)?;
+    }
+    Ok(())
+}
+
+/// Decode one event written by `write_event`.
+pub fn read_event<R: Read>(rd: &mut R) -> io::Result<ResolvedEvent> {
+    expect_array(rd, 2)?;
+    let timestamp = decode::read_int(rd).map_err(invalid)?;
+    let len = decode::read_array_len(rd).map_err(invalid)?;
+    let mut values = Vec::with_capacity(cmp::min(len as usize, MAX_RESERVE));
+    for _ in 0..len {
+        let len = decode::read_str_len(rd).map_err(invalid)?;
+        let mut buf = Vec::with_capacity(cmp::min(len as usize, MAX_RESERVE));
+        if rd.take(len as u64).read_to_end(&mut buf)? < len as usize {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "string cut short"));
+        }
+        values.push(String::from_utf8(buf).map_err(invalid)?);
+    }
+    Ok(ResolvedEvent {
+        timestamp: timestamp,
+        values: values,
+    })
+}
+
+/// Encode a trail batch.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::{msgpack, Db};
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let mut buf = Vec::new();
+/// msgpack::write_trail_batch(&mut buf, &db.trail_batch(0).unwrap()).unwrap();
+/// let batch = msgpack::read_trail_batch(&mut &buf[..]).unwrap();
+/// assert_eq!(batch.uuid, *db.get_uuid(0).unwrap());
+/// ```
+pub fn write_trail_batch<W: Write>(out: &mut W, batch: &TrailBatch) -> io::Result<()> {
+    encode::write_array_len(out, 2).map_err(invalid)?;
+    encode::write_bin(out, &batch.uuid).map_err(invalid)?;
+    encode::write_array_len(out, batch.events.len() as u32).map_err(invalid)?;
+    for event in &batch.events {
+        write_event(out, event)?;
+    }
+    Ok(())
+}
+
+/// Decode a trail batch written by `write_trail_batch`.
+pub fn read_trail_batch<R: Read>(rd: &mut R) -> io::Result<TrailBatch> {
+    expect_array(rd, 2)?;
+    if decode::read_bin_len(rd).map_err(invalid)? != 16 {
+        return Err(invalid("UUID is not 16 bytes"));
+    }
+    let mut uuid: Uuid = [0u8; 16];
+    rd.read_exact(&mut uuid)?;
+    let len = decode::read_array_len(rd).map_err(invalid)?;
+    let mut events = Vec::with_capacity(cmp::min(len as usize, MAX_RESERVE));
+    for _ in 0..len {
+        events.push(read_event(rd)?);
+    }
+    Ok(TrailBatch {
+        uuid: uuid,
+        events: events,
+    })
+}
+
+fn expect_array<R: Read>(rd: &mut R, len: u32) -> io::Result<()> {
+    match decode::read_array_len(rd).map_err(invalid)? {
+        n if n == len => Ok(()),
+        n => Err(invalid(format!("expected an array of {} elements, found {}", len, n))),
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_msgpack {
+    use super::{read_event, read_trail_batch, write_trail_batch};
+    use super::super::{ResolvedEvent, TrailBatch};
+
+    #[test]
+    fn test_trail_batch_round_trip() {
+        let batch = TrailBatch {
+            uuid: [7u8; 16],
+            events: vec![ResolvedEvent {
+                             timestamp: 1,
+                             values: vec!["alice".to_string(), "login".to_string()],
+                         },
+                         ResolvedEvent {
+                             timestamp: 1 << 40,
+                             values: vec!["alice".to_string(), "".to_string()],
+                         }],
+        };
+        let mut buf = Vec::new();
+        write_trail_batch(&mut buf, &batch).unwrap();
+        write_trail_batch(&mut buf, &batch).unwrap();
+        let mut rd = &buf[..];
+        assert_eq!(read_trail_batch(&mut rd).unwrap(), batch);
+        assert_eq!(read_trail_batch(&mut rd).unwrap(), batch);
+        assert!(rd.is_empty());
+    }
+
+    #[test]
+    fn test_huge_lengths() {
+        // An event claiming 2^32 - 1 values, and one with a 4 GiB string.
+        assert!(read_event(&mut &[0x92, 0x01, 0xdd, 0xff, 0xff, 0xff, 0xff][..]).is_err());
+        assert!(read_event(&mut &[0x92, 0x01, 0x91, 0xdb, 0xff, 0xff, 0xff, 0xff, b'a'][..]).is_err());
+
+        let mut batch = vec![0x92, 0xc4, 16];
+        batch.extend_from_slice(&[7u8; 16]);
+        batch.extend_from_slice(&[0xdd, 0xff, 0xff, 0xff, 0xff]);
+        assert!(read_trail_batch(&mut &batch[..]).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+//! Async versions of the calls that block on disk, for tokio runtimes.
+//!
+//! Opening a database and finalizing a constructor can take from
+//! milliseconds to minutes; run on a runtime worker they would stall every
+//! other task scheduled on it. These run the FFI call on tokio's blocking
+//! pool instead.
+
+use std::panic;
+use std::path::Path;
+
+use tokio::task;
+
+use super::{Constructor, Db, Error};
+
+impl Constructor {
+    /// Like `finalize`, on tokio's blocking pool.
+    ///
+    /// Once started, finalizing runs to completion even if the returned
+    /// future is dropped, and a runtime being shut down waits for it, so
+    /// shutting down a service never leaves a half-written database
+    /// behind. The constructor is consumed, as it can't take any more
+    /// events.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Constructor;
+    /// use std::path::Path;
+    ///
+    /// # async fn run() {
+    /// let mut cons = Constructor::new(Path::new("my_traildb"), &["action"]).unwrap();
+    /// cons.add(&[0u8; 16], 1, &["login"]).unwrap();
+    /// cons.finalize_async().await.unwrap();
+    /// # }
+    /// ```
+    pub async fn finalize_async(mut self) -> Result<(), Error> {
+        blocking(move || self.finalize()).await
+    }
+}
+
+impl Db<'static> {
+    /// Like `open`, on tokio's blocking pool.
+    pub async fn open_async(path: &Path) -> Result<Self, Error> {
+        let path = path.to_path_buf();
+        blocking(move || Db::open(&path)).await
+    }
+}
+
+/// Run `f` on the blocking pool, passing on its panics.
+pub(crate) async fn blocking<T, F>(f: F) -> T
+    where T: Send + 'static,
+          F: FnOnce() -> T + Send + 'static
+{
+    match task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        // Blocking tasks are only cancelled before they start, by a runtime
+        // shutting down, which drops the task awaiting them as well.
+        Err(e) => panic!("blocking task cancelled: {}", e),
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_nonblocking {
+    use super::super::{Constructor, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_open_finalize_async() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let db_path = Path::new("test_nonblocking");
+        runtime.block_on(async {
+            let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+            assert!(cons.add(&[1u8; 16], 1, &["view"]).is_ok());
+            assert!(cons.finalize_async().await.is_ok());
+
+            let db = Db::open_async(db_path).await.unwrap();
+            assert_eq!(db.num_events(), 1);
+            assert!(Db::open_async(Path::new("test_nonblocking_missing")).await.is_err());
+        });
+    }
+}
@@ -0,0 +1,269 @@
+use std::cmp;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+
+use super::{Db, Error, Trail, TrailId};
+
+/// The number of trails a worker claims at a time.
+const CHUNK_TRAILS: u64 = 1024;
+
+/// A range of consecutive trails, created by `Db::partitions`.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Partition {
+    pub trails: Range<TrailId>,
+    /// The number of events in the trails.
+    pub events: u64,
+}
+
+impl<'a> Db<'a> {
+    /// Run `map` over every trail on all available cores and combine the
+    /// results with `reduce`. Returns `None` for a database without trails.
+    ///
+    /// Each worker thread claims chunks of consecutive trail ids and reuses
+    /// one cursor for all of them, so `map` gets the trail by reference.
+    /// Results are combined in no particular order: `reduce` should be
+    /// associative and commutative.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// // The length of the longest trail.
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// let longest = db.map_reduce(|trail| trail.count(), |a, b| a.max(b)).unwrap();
+    /// println!("{:?}", longest);
+    /// ```
+    pub fn map_reduce<T, M, R>(&self, map: M, reduce: R) -> Result<Option<T>, Error>
+        where T: Send,
+              M: Fn(&mut Trail<'a>) -> T + Sync,
+              R: Fn(T, T) -> T + Sync
+    {
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        self.map_reduce_with(threads, map, reduce)
+    }
+
+    /// Like `map_reduce`, with `threads` worker threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is 0.
+    pub fn map_reduce_with<T, M, R>(&self, threads: usize, map: M, reduce: R) -> Result<Option<T>, Error>
+        where T: Send,
+              M: Fn(&mut Trail<'a>) -> T + Sync,
+              R: Fn(T, T) -> T + Sync
+    {
+        self.map_reduce_cancellable(threads, map, reduce, &AtomicBool::new(false))
+    }
+
+    /// Like `map_reduce_with`, giving up with `Error::Cancelled` once
+    /// `cancel` is set. Workers check it before every chunk of trails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is 0.
+    #[cfg_attr(feature = "tracing",
+               tracing::instrument(level = "debug",
+                                   skip_all,
+                                   fields(threads = threads, trails = self.num_trails())))]
+    pub fn map_reduce_cancellable<T, M, R>(&self,
+                                           threads: usize,
+                                           map: M,
+                                           reduce: R,
+                                           cancel: &AtomicBool)
+                                           -> Result<Option<T>, Error>
+        where T: Send,
+              M: Fn(&mut Trail<'a>) -> T + Sync,
+              R: Fn(T, T) -> T + Sync
+    {
+        assert!(threads > 0, "threads must be at least 1");
+        let num_trails = self.num_trails();
+        let threads = cmp::min(threads as u64, num_trails.div_ceil(CHUNK_TRAILS)) as usize;
+        let next = AtomicU64::new(0);
+        let worker = || -> Result<Option<T>, Error> {
+            let mut trail = Trail {
+                id: 0,
+                cursor: self.cursor(),
+            };
+            let mut acc: Option<T> = None;
+            loop {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(Error::Cancelled);
+                }
+                let start = next.fetch_add(CHUNK_TRAILS, Ordering::Relaxed);
+                if start >= num_trails {
+                    return Ok(acc);
+                }
+                for trail_id in start..cmp::min(start + CHUNK_TRAILS, num_trails) {
+                    acc = Some(fold(acc, map_trail(&mut trail, trail_id, &map)?, &reduce));
+                }
+            }
+        };
+        let results: Vec<Result<Option<T>, Error>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..threads).map(|_| s.spawn(&worker)).collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        let mut acc = None;
+        for result in results {
+            if let Some(value) = result? {
+                acc = Some(fold(acc, value, &reduce));
+            }
+        }
+        Ok(acc)
+    }
+}
+
+impl<'a> Db<'a> {
+    /// Split the trails into up to `n` ranges of consecutive trail ids
+    /// holding about as many events each, for handing out a scan to `n`
+    /// workers. Panics if `n` is 0.
+    ///
+    /// Ranges are in trail id order and cover every trail; there are fewer
+    /// than `n` if there are fewer trails. Counting events reads the length
+    /// of every trail, which decodes it, so this costs about as much as a
+    /// scan that doesn't look at the events.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// for partition in db.partitions(8).unwrap() {
+    ///     println!("trails {:?}: {} events", partition.trails, partition.events);
+    /// }
+    /// ```
+    #[cfg_attr(feature = "tracing",
+               tracing::instrument(level = "debug", skip_all, fields(n = n, trails = self.num_trails())))]
+    pub fn partitions(&self, n: usize) -> Result<Vec<Partition>, Error> {
+        assert!(n > 0, "n must be at least 1");
+        let num_trails = self.num_trails();
+        let mut lengths = Vec::with_capacity(num_trails as usize);
+        let mut cursor = self.cursor();
+        for trail_id in 0..num_trails {
+            cursor.get_trail(trail_id)?;
+            lengths.push(cursor.len());
+        }
+        Ok(split_balanced(&lengths, n))
+    }
+}
+
+/// Cut `lengths` into up to `n` non-empty ranges of about equal sums.
+fn split_balanced(lengths: &[u64], n: usize) -> Vec<Partition> {
+    let total: u64 = lengths.iter().sum();
+    let n = cmp::min(n, lengths.len()) as u64;
+    let mut partitions = Vec::with_capacity(n as usize);
+    let mut start = 0;
+    let mut events = 0;
+    let mut seen = 0;
+    for (i, &len) in lengths.iter().enumerate() {
+        events += len;
+        seen += len;
+        let left = lengths.len() - i - 1;
+        let cuts_left = n - partitions.len() as u64 - 1;
+        // Cut once this range reaches its share of the events, or when each
+        // remaining partition needs one of the remaining trails.
+        let target = (total as u128 * (partitions.len() as u128 + 1) / n as u128) as u64;
+        if cuts_left > 0 && (seen >= target || left as u64 == cuts_left) {
+            partitions.push(Partition {
+                trails: start..i as TrailId + 1,
+                events: events,
+            });
+            start = i as TrailId + 1;
+            events = 0;
+        }
+    }
+    if start < lengths.len() as TrailId {
+        partitions.push(Partition {
+            trails: start..lengths.len() as TrailId,
+            events: events,
+        });
+    }
+    partitions
+}
+
+fn map_trail<'a, T, M>(trail: &mut Trail<'a>, trail_id: TrailId, map: &M) -> Result<T, Error>
+    where M: Fn(&mut Trail<'a>) -> T
+{
+    trail.cursor.get_trail(trail_id)?;
+    trail.id = trail_id;
+    Ok(map(trail))
+}
+
+fn fold<T, R: Fn(T, T) -> T>(acc: Option<T>, value: T, reduce: &R) -> T {
+    match acc {
+        Some(acc) => reduce(acc, value),
+        None => value,
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_parallel {
+    use super::super::{Constructor, Db};
+    use super::{split_balanced, Partition};
+    use std::path::Path;
+
+    #[test]
+    fn test_map_reduce() {
+        let db_path = Path::new("test_map_reduce");
+        let mut cons = Constructor::new(db_path, &["field1"]).unwrap();
+        for i in 0..3000u32 {
+            let mut uuid = [0u8; 16];
+            uuid[..4].copy_from_slice(&i.to_le_bytes());
+            for ts in 0..(i % 3 + 1) {
+                assert!(cons.add(&uuid, ts as u64, &["a"]).is_ok());
+            }
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let events = db.map_reduce_with(4, |trail| trail.count() as u64, |a, b| a + b).unwrap();
+        assert_eq!(events, Some(db.num_events()));
+        let longest = db.map_reduce(|trail| trail.count(), |a, b| a.max(b)).unwrap();
+        assert_eq!(longest, Some(3));
+    }
+
+    #[test]
+    fn test_split_balanced() {
+        let ranges = |lengths: &[u64], n| {
+            split_balanced(lengths, n)
+                .into_iter()
+                .map(|Partition { trails, events }| (trails.start, trails.end, events))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranges(&[1, 1, 1, 1], 2), vec![(0, 2, 2), (2, 4, 2)]);
+        assert_eq!(ranges(&[10, 1, 1, 1, 1], 2), vec![(0, 1, 10), (1, 5, 4)]);
+        assert_eq!(ranges(&[1, 1, 1, 1, 10], 2), vec![(0, 4, 4), (4, 5, 10)]);
+        assert_eq!(ranges(&[5, 5], 4), vec![(0, 1, 5), (1, 2, 5)]);
+        assert_eq!(ranges(&[100, 0, 0], 3), vec![(0, 1, 100), (1, 2, 0), (2, 3, 0)]);
+        assert!(ranges(&[], 3).is_empty());
+    }
+
+    #[test]
+    fn test_partitions() {
+        let db_path = Path::new("test_partitions");
+        let mut cons = Constructor::new(db_path, &["field1"]).unwrap();
+        for i in 0..100u8 {
+            for ts in 0..(i % 5 + 1) {
+                assert!(cons.add(&[i; 16], ts as u64, &["a"]).is_ok());
+            }
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let partitions = db.partitions(4).unwrap();
+        assert_eq!(partitions.len(), 4);
+        assert_eq!(partitions[0].trails.start, 0);
+        assert_eq!(partitions[3].trails.end, db.num_trails());
+        for pair in partitions.windows(2) {
+            assert_eq!(pair[0].trails.end, pair[1].trails.start);
+        }
+        assert_eq!(partitions.iter().map(|p| p.events).sum::<u64>(), db.num_events());
+    }
+}
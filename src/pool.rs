@@ -0,0 +1,382 @@
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{stats, Cursor, Db, Error};
+
+/// A bounded pool of reusable cursors over one `Db`.
+///
+/// Cursors are created lazily, up to the pool's capacity, and are handed
+/// back to the pool when the `PooledCursor` guard is dropped. The pool is
+/// `Sync`, so worker threads can share it by reference.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use std::path::Path;
+/// use std::thread;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let pool = db.cursor_pool(4);
+/// thread::scope(|s| {
+///     for worker in 0..4 {
+///         let pool = &pool;
+///         s.spawn(move || {
+///             let mut cursor = pool.get();
+///             cursor.get_trail(worker).unwrap();
+///             println!("trail {} has {} events", worker, cursor.len());
+///         });
+///     }
+/// });
+/// ```
+pub struct CursorPool<'a> {
+    db: &'a Db<'a>,
+    capacity: usize,
+    state: Mutex<PoolState<'a>>,
+    returned: Condvar,
+}
+
+struct PoolState<'a> {
+    idle: Vec<Cursor<'a>>,
+    created: usize,
+}
+
+impl<'a> CursorPool<'a> {
+    pub(crate) fn new(db: &'a Db<'a>, capacity: usize) -> Self {
+        CursorPool {
+            db: db,
+            capacity: capacity,
+            state: Mutex::new(PoolState {
+                idle: Vec::with_capacity(capacity),
+                created: 0,
+            }),
+            returned: Condvar::new(),
+        }
+    }
+
+    /// The maximum number of cursors this pool hands out at once.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Take a cursor from the pool, blocking until one is available.
+    pub fn get(&self) -> PooledCursor<'_, 'a> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(cursor) = self.take(&mut state) {
+                return PooledCursor {
+                    pool: self,
+                    cursor: Some(cursor),
+                };
+            }
+            state = self.returned.wait(state).unwrap();
+        }
+    }
+
+    /// Take a cursor from the pool if one is available without blocking.
+    pub fn try_get(&self) -> Option<PooledCursor<'_, 'a>> {
+        let mut state = self.state.lock().unwrap();
+        self.take(&mut state).map(|cursor| {
+            PooledCursor {
+                pool: self,
+                cursor: Some(cursor),
+            }
+        })
+    }
+
+    fn take(&self, state: &mut PoolState<'a>) -> Option<Cursor<'a>> {
+        if let Some(cursor) = state.idle.pop() {
+            stats::cursor_pool(true);
+            return Some(cursor);
+        }
+        if state.created < self.capacity {
+            state.created += 1;
+            stats::cursor_pool(false);
+            return Some(self.db.cursor());
+        }
+        None
+    }
+
+    fn put(&self, cursor: Cursor<'a>) {
+        self.state.lock().unwrap().idle.push(cursor);
+        self.returned.notify_one();
+    }
+}
+
+/// A cursor borrowed from a `CursorPool`, returned to it on drop.
+pub struct PooledCursor<'p, 'a: 'p> {
+    pool: &'p CursorPool<'a>,
+    cursor: Option<Cursor<'a>>,
+}
+
+impl<'p, 'a> Deref for PooledCursor<'p, 'a> {
+    type Target = Cursor<'a>;
+
+    fn deref(&self) -> &Cursor<'a> {
+        self.cursor.as_ref().unwrap()
+    }
+}
+
+impl<'p, 'a> DerefMut for PooledCursor<'p, 'a> {
+    fn deref_mut(&mut self) -> &mut Cursor<'a> {
+        self.cursor.as_mut().unwrap()
+    }
+}
+
+impl<'p, 'a> Drop for PooledCursor<'p, 'a> {
+    fn drop(&mut self) {
+        if let Some(cursor) = self.cursor.take() {
+            self.pool.put(cursor);
+        }
+    }
+}
+
+
+/// An error running a job on a `DbPool`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum PoolError {
+    /// No database became free within the pool's timeout.
+    Timeout,
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PoolError::Timeout => write!(f, "PoolError::Timeout"),
+        }
+    }
+}
+
+impl error::Error for PoolError {}
+
+/// A fixed set of handles to one database, lent out one job at a time.
+///
+/// For services answering many concurrent lookups: the number of handles
+/// caps how many jobs run at once, jobs waiting for a handle are served in
+/// the order they arrived, and with a timeout an overloaded pool turns
+/// jobs away instead of queueing them without bound. The handles are
+/// closed when the pool is dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::DbPool;
+/// use std::path::Path;
+/// use std::time::Duration;
+///
+/// let pool = DbPool::open(Path::new("my_traildb"), 8)
+///     .unwrap()
+///     .timeout(Duration::from_millis(500));
+/// let events = pool.run(|db| db.trail_batch(0).map(|batch| batch.events.len()));
+/// println!("{:?}", events);
+/// ```
+pub struct DbPool {
+    state: Mutex<DbPoolState>,
+    returned: Condvar,
+    timeout: Option<Duration>,
+}
+
+struct DbPoolState {
+    idle: Vec<Db<'static>>,
+    /// The tickets of the jobs waiting for a handle, oldest first.
+    waiting: VecDeque<u64>,
+    next_ticket: u64,
+}
+
+impl DbPool {
+    /// Open the database at `path` `n` times. Panics if `n` is 0.
+    pub fn open(path: &Path, n: usize) -> Result<Self, Error> {
+        assert!(n > 0, "a pool needs at least one handle");
+        let mut idle = Vec::with_capacity(n);
+        for _ in 0..n {
+            match Db::open(path) {
+                Ok(db) => idle.push(db),
+                Err(e) => {
+                    for mut db in idle {
+                        db.close();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(DbPool {
+            state: Mutex::new(DbPoolState {
+                idle: idle,
+                waiting: VecDeque::new(),
+                next_ticket: 0,
+            }),
+            returned: Condvar::new(),
+            timeout: None,
+        })
+    }
+
+    /// Give up on jobs that wait longer than `timeout` for a handle.
+    /// Without a timeout, jobs wait as long as it takes.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Run `job` on a free handle, waiting for one if all are busy.
+    pub fn run<T, F>(&self, job: F) -> Result<T, PoolError>
+        where F: FnOnce(&Db) -> T
+    {
+        let db = self.take()?;
+        let lent = Lent {
+            pool: self,
+            db: Some(db),
+        };
+        Ok(job(lent.db.as_ref().unwrap()))
+    }
+
+    fn take(&self) -> Result<Db<'static>, PoolError> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting.push_back(ticket);
+        loop {
+            if state.waiting.front() == Some(&ticket) && !state.idle.is_empty() {
+                state.waiting.pop_front();
+                let db = state.idle.pop().unwrap();
+                // The next job in line may be able to go too.
+                self.returned.notify_all();
+                return Ok(db);
+            }
+            state = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        state.waiting.retain(|&t| t != ticket);
+                        self.returned.notify_all();
+                        return Err(PoolError::Timeout);
+                    }
+                    self.returned.wait_timeout(state, deadline - now).unwrap().0
+                }
+                None => self.returned.wait(state).unwrap(),
+            };
+        }
+    }
+
+    fn put(&self, db: Db<'static>) {
+        self.state.lock().unwrap().idle.push(db);
+        self.returned.notify_all();
+    }
+}
+
+impl Drop for DbPool {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap_or_else(|e| e.into_inner());
+        for db in &mut state.idle {
+            db.close();
+        }
+    }
+}
+
+/// A handle lent to a job, returned to the pool even if the job panics.
+struct Lent<'p> {
+    pool: &'p DbPool,
+    db: Option<Db<'static>>,
+}
+
+impl<'p> Drop for Lent<'p> {
+    fn drop(&mut self) {
+        if let Some(db) = self.db.take() {
+            self.pool.put(db);
+        }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_pool {
+    use super::{DbPool, PoolError};
+    use super::super::{Constructor, Db};
+    use std::path::Path;
+    use std::sync::{mpsc, RwLock};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_cursor_pool() {
+        let db_path = Path::new("test_pool");
+        let mut cons = Constructor::new(db_path, &["field1"]).unwrap();
+        for i in 0..8u8 {
+            assert!(cons.add(&[i; 16], 0, &["a"]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let pool = db.cursor_pool(2);
+        {
+            let _a = pool.get();
+            let _b = pool.get();
+            assert!(pool.try_get().is_none());
+        }
+        assert!(pool.try_get().is_some());
+
+        thread::scope(|s| {
+            for worker in 0..4 {
+                let pool = &pool;
+                s.spawn(move || {
+                    for trail_id in (worker..8).step_by(4) {
+                        let mut cursor = pool.get();
+                        cursor.get_trail(trail_id).unwrap();
+                        assert_eq!(cursor.len(), 1);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_db_pool() {
+        let db_path = Path::new("test_db_pool");
+        let mut cons = Constructor::new(db_path, &["field1"]).unwrap();
+        for i in 0..8u8 {
+            assert!(cons.add(&[i; 16], 0, &["a"]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let pool = DbPool::open(db_path, 2).unwrap().timeout(Duration::from_millis(50));
+        assert_eq!(pool.run(|db| db.num_trails()), Ok(8));
+
+        // Hold both handles until the third job has timed out.
+        let gate = RwLock::new(());
+        let closed = gate.write().unwrap();
+        thread::scope(|s| {
+            let (held, holding) = mpsc::channel();
+            for _ in 0..2 {
+                let (held, pool, gate) = (held.clone(), &pool, &gate);
+                s.spawn(move || {
+                    pool.run(|_| {
+                        held.send(()).unwrap();
+                        drop(gate.read().unwrap());
+                    })
+                });
+                holding.recv().unwrap();
+            }
+            assert_eq!(pool.run(|db| db.num_trails()), Err(PoolError::Timeout));
+            drop(closed);
+        });
+
+        thread::scope(|s| {
+            for worker in 0..4 {
+                let pool = &pool;
+                s.spawn(move || {
+                    for trail_id in (worker..8).step_by(4) {
+                        let events = pool.run(|db| db.trail_batch(trail_id).unwrap().events.len());
+                        assert_eq!(events, Ok(1));
+                    }
+                });
+            }
+        });
+    }
+}
@@ -0,0 +1,161 @@
+//! Copying databases with their UUIDs replaced, so they can be shared or
+//! kept in pseudonymized form.
+//!
+//! `Db::copy_pseudonymized` maps every UUID through a function: a lookup in
+//! a mapping kept elsewhere, or, with the `pseudonymize` feature, a
+//! `UuidKey`, which derives UUIDs with a keyed hash. The same key gives the
+//! same UUIDs across databases, so they can still be joined, and without
+//! the key the original UUIDs can't be recovered.
+
+use std::path::Path;
+
+#[cfg(feature = "pseudonymize")]
+use ::sha2::{Digest, Sha256};
+
+use super::copy::rewrite_cancellable;
+use super::{Db, Error, Field, Uuid};
+
+/// A key deriving pseudonymous UUIDs, as the first 16 bytes of the
+/// HMAC-SHA256 of the UUID under the key. Keep the key secret: anyone with
+/// it can check whether a pseudonym belongs to a given UUID.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::{Db, UuidKey};
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("events")).unwrap();
+/// let key = UuidKey::new(b"kept in the secret store");
+/// db.copy_pseudonymized(Path::new("events_shared"), |uuid| Some(key.pseudonym(uuid)))
+///     .unwrap();
+/// ```
+#[cfg(feature = "pseudonymize")]
+#[derive(Clone)]
+pub struct UuidKey {
+    /// SHA-256 states after the key, padded and combined with the inner
+    /// and outer HMAC pads.
+    inner: Sha256,
+    outer: Sha256,
+}
+
+#[cfg(feature = "pseudonymize")]
+impl UuidKey {
+    pub fn new(key: &[u8]) -> Self {
+        let mut block = [0u8; 64];
+        if key.len() > block.len() {
+            block[..32].copy_from_slice(&Sha256::digest(key));
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+        let pad = |byte: u8| {
+            let padded: Vec<u8> = block.iter().map(|b| b ^ byte).collect();
+            let mut state = Sha256::new();
+            state.update(&padded);
+            state
+        };
+        UuidKey {
+            inner: pad(0x36),
+            outer: pad(0x5c),
+        }
+    }
+
+    /// The pseudonym of `uuid`.
+    pub fn pseudonym(&self, uuid: &Uuid) -> Uuid {
+        let mut pseudonym = [0u8; 16];
+        pseudonym.copy_from_slice(&self.hmac(uuid)[..16]);
+        pseudonym
+    }
+
+    fn hmac(&self, message: &[u8]) -> [u8; 32] {
+        let mut inner = self.inner.clone();
+        inner.update(message);
+        let mut outer = self.outer.clone();
+        outer.update(inner.finalize());
+        outer.finalize().into()
+    }
+}
+
+impl<'a> Db<'a> {
+    /// Write the database into a new one at `dst_path` with the UUID of
+    /// every trail replaced by `map`, returning the number of events
+    /// written. Trails `map` returns `None` for are left out, and trails
+    /// mapped to the same UUID are merged.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::{Db, Uuid};
+    /// use std::collections::HashMap;
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("events")).unwrap();
+    /// let consented: HashMap<Uuid, Uuid> = HashMap::new();
+    /// db.copy_pseudonymized(Path::new("events_shared"), |uuid| consented.get(uuid).cloned())
+    ///     .unwrap();
+    /// ```
+    pub fn copy_pseudonymized<F>(&self, dst_path: &Path, map: F) -> Result<u64, Error>
+        where F: FnMut(&Uuid) -> Option<Uuid>
+    {
+        let fields = self.field_names();
+        let sources: Vec<Field> = (1..self.num_fields() as Field).collect();
+        rewrite_cancellable(self,
+                            dst_path,
+                            &fields,
+                            &sources,
+                            None,
+                            0..self.num_trails(),
+                            |_| true,
+                            map,
+                            None)
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_pseudonym {
+    use super::super::copy::test_copy::source;
+    use super::super::Db;
+    use std::path::Path;
+
+    #[test]
+    fn test_copy_pseudonymized() {
+        let src = source(Path::new("test_copy_pseudonymized_src"));
+        let dst_path = Path::new("test_copy_pseudonymized_dst");
+        let written = src.copy_pseudonymized(dst_path, |uuid| {
+                if uuid[0] == 2 {
+                    None
+                } else {
+                    Some([uuid[0] + 100; 16])
+                }
+            })
+            .unwrap();
+        assert_eq!(written, 3);
+
+        let dst = Db::open(dst_path).unwrap();
+        assert_eq!(dst.num_trails(), 2);
+        assert_eq!(dst.get_trail_id(&[1u8; 16]), None);
+        let trail_id = dst.get_trail_id(&[101u8; 16]).unwrap();
+        assert_eq!(dst.trail_batch(trail_id).unwrap().events.len(), 2);
+    }
+
+    #[cfg(feature = "pseudonymize")]
+    #[test]
+    fn test_uuid_key() {
+        use super::UuidKey;
+
+        // RFC 4231, test case 2.
+        let key = UuidKey::new(b"Jefe");
+        let mac: String = key.hmac(b"what do ya want for nothing?")
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(mac, "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+
+        assert_eq!(key.pseudonym(&[1u8; 16]), UuidKey::new(b"Jefe").pseudonym(&[1u8; 16]));
+        assert_ne!(key.pseudonym(&[1u8; 16]), UuidKey::new(b"Joe").pseudonym(&[1u8; 16]));
+        assert_ne!(key.pseudonym(&[1u8; 16]), key.pseudonym(&[2u8; 16]));
+    }
+}
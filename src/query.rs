@@ -0,0 +1,289 @@
+//! A small text syntax for event filters.
+//!
+//! A query is a conjunction of clauses, each a disjunction of terms, which
+//! is exactly the shape of an `EventFilter`:
+//!
+//! ```text
+//! action="purchase" AND (country="de" OR country="fr") AND plan!="free"
+//! ```
+//!
+//! A term compares a field with a value, with `=` or `!=`. Values, and field
+//! names, are either double-quoted strings, with `\"` and `\\` escapes, or
+//! bare words. `AND` and `OR` are case-insensitive; parentheses around a
+//! clause are optional.
+
+use std::error;
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::{Db, Error, EventFilter};
+
+/// An error raised while parsing a query.
+#[derive(Debug,PartialEq)]
+pub enum QueryError {
+    /// The query isn't well-formed.
+    Syntax(String),
+    /// The query names a field the database doesn't have.
+    UnknownField(String),
+    /// The query compares a field with a value that never occurs in it.
+    UnknownValue(String, String),
+    /// Building the filter failed.
+    Db(Error),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QueryError::Syntax(ref e) => write!(f, "QueryError::Syntax({})", e),
+            QueryError::UnknownField(ref name) => write!(f, "QueryError::UnknownField({})", name),
+            QueryError::UnknownValue(ref field, ref value) => {
+                write!(f, "QueryError::UnknownValue({}={:?})", field, value)
+            }
+            QueryError::Db(ref e) => write!(f, "QueryError::Db({})", e),
+        }
+    }
+}
+
+impl error::Error for QueryError {}
+
+impl From<Error> for QueryError {
+    fn from(e: Error) -> Self {
+        QueryError::Db(e)
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Eq,
+    NotEq,
+    Open,
+    Close,
+}
+
+/// A filter term naming its field and value, resolved against a `Db` by
+/// `build_filter`.
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NamedTerm {
+    pub field: String,
+    pub value: String,
+    /// Match events that do not hold the value.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub negative: bool,
+}
+
+/// Parse `query` into an `EventFilter` over the fields and values of `db`.
+///
+/// Values are looked up in the lexicon, so a value that never occurs in its
+/// field is reported as `QueryError::UnknownValue`, which usually means a
+/// typo.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::query::parse_filter;
+/// use std::path::Path;
+///
+/// let db = Db::open(Path::new("my_traildb")).unwrap();
+/// let filter = parse_filter(&db, r#"action="purchase" AND country!="us""#).unwrap();
+/// let purchases = db.value_counts(1, Some(&filter)).unwrap();
+/// ```
+pub fn parse_filter(db: &Db, query: &str) -> Result<EventFilter, QueryError> {
+    build_filter(db, &parse(&tokenize(query)?)?)
+}
+
+/// Build an `EventFilter` over the fields and values of `db` from clauses of
+/// named terms, AND'ed and OR'ed like the filter's own clauses. Fails like
+/// `parse_filter` on unknown fields and values.
+pub fn build_filter(db: &Db, clauses: &[Vec<NamedTerm>]) -> Result<EventFilter, QueryError> {
+    let mut filter = EventFilter::new();
+    for (i, clause) in clauses.iter().enumerate() {
+        if i > 0 {
+            filter.new_clause()?;
+        }
+        for term in clause {
+            let field = db.get_field(&term.field)
+                .ok_or_else(|| QueryError::UnknownField(term.field.clone()))?;
+            let item = db.get_item(field, &term.value)
+                .ok_or_else(|| QueryError::UnknownValue(term.field.clone(), term.value.clone()))?;
+            filter.add_term(item, term.negative)?;
+        }
+    }
+    Ok(filter)
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '=' => {
+                chars.next();
+                tokens.push(Token::Eq);
+            }
+            '!' => {
+                chars.next();
+                if chars.next() != Some('=') {
+                    return Err(QueryError::Syntax("expected `=` after `!`".to_string()));
+                }
+                tokens.push(Token::NotEq);
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') if chars.peek().is_some() => s.push(chars.next().unwrap()),
+                        Some(c) if c != '\\' => s.push(c),
+                        _ => return Err(QueryError::Syntax("unterminated string".to_string())),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "()=!\"".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_keyword(token: Option<&Token>, keyword: &str) -> bool {
+    match token {
+        Some(&Token::Word(ref w)) => w.eq_ignore_ascii_case(keyword),
+        _ => false,
+    }
+}
+
+fn parse(tokens: &[Token]) -> Result<Vec<Vec<NamedTerm>>, QueryError> {
+    let syntax = |msg: &str| QueryError::Syntax(msg.to_string());
+    let mut pos = 0;
+    let mut clauses = Vec::new();
+    loop {
+        let parens = tokens.get(pos) == Some(&Token::Open);
+        if parens {
+            pos += 1;
+        }
+        let mut clause = Vec::new();
+        loop {
+            let name = match tokens.get(pos) {
+                Some(&Token::Word(ref s)) | Some(&Token::Str(ref s)) => s.clone(),
+                _ => return Err(syntax("expected a field name")),
+            };
+            let negative = match tokens.get(pos + 1) {
+                Some(&Token::Eq) => false,
+                Some(&Token::NotEq) => true,
+                _ => return Err(syntax("expected `=` or `!=` after a field name")),
+            };
+            let value = match tokens.get(pos + 2) {
+                Some(&Token::Word(ref s)) | Some(&Token::Str(ref s)) => s.clone(),
+                _ => return Err(syntax("expected a value")),
+            };
+            clause.push(NamedTerm {
+                field: name,
+                value: value,
+                negative: negative,
+            });
+            pos += 3;
+            if !is_keyword(tokens.get(pos), "or") {
+                break;
+            }
+            pos += 1;
+        }
+        if parens {
+            if tokens.get(pos) != Some(&Token::Close) {
+                return Err(syntax("expected `)`"));
+            }
+            pos += 1;
+        }
+        clauses.push(clause);
+        if pos == tokens.len() {
+            return Ok(clauses);
+        }
+        if !is_keyword(tokens.get(pos), "and") {
+            return Err(syntax("expected `AND`, `OR` or the end of the query"));
+        }
+        pos += 1;
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_query {
+    use super::{build_filter, parse, parse_filter, tokenize, NamedTerm, QueryError};
+    use super::super::{Constructor, Db};
+    use std::path::Path;
+
+    fn clauses(query: &str) -> Result<Vec<Vec<NamedTerm>>, QueryError> {
+        parse(&tokenize(query)?)
+    }
+
+    fn term(field: &str, value: &str, negative: bool) -> NamedTerm {
+        NamedTerm {
+            field: field.to_string(),
+            value: value.to_string(),
+            negative: negative,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let parsed = clauses(r#"action="buy it" and (country=de OR "the country"!="fr")"#).unwrap();
+        assert_eq!(parsed,
+                   vec![vec![term("action", "buy it", false)],
+                        vec![term("country", "de", false), term("the country", "fr", true)]]);
+        assert!(clauses(r#"action="a\"b""#).is_ok());
+        assert!(clauses("action=").is_err());
+        assert!(clauses("action=a country=b").is_err());
+        assert!(clauses("(action=a").is_err());
+        assert!(clauses(r#"action="a"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        let db_path = Path::new("test_parse_filter");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["buy"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let filter = parse_filter(&db, r#"action="buy" AND action!="""#).unwrap();
+        assert_eq!(filter.clauses().len(), 2);
+        assert_eq!(parse_filter(&db, "user=x").err(),
+                   Some(QueryError::UnknownField("user".to_string())));
+        assert_eq!(parse_filter(&db, "action=sell").err(),
+                   Some(QueryError::UnknownValue("action".to_string(), "sell".to_string())));
+        let clauses = vec![vec![term("action", "buy", false), term("action", "sell", true)]];
+        assert_eq!(build_filter(&db, &clauses).err(),
+                   Some(QueryError::UnknownValue("action".to_string(), "sell".to_string())));
+        let clauses = vec![vec![term("action", "buy", true)], vec![term("action", "", true)]];
+        assert_eq!(build_filter(&db, &clauses).unwrap().clauses().len(), 2);
+    }
+}
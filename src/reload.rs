@@ -0,0 +1,355 @@
+//! A set of shards that follows a directory or a manifest.
+//!
+//! Serving processes pick up shards as ingesters finalize them by calling
+//! `ReloadableDb::reload`, or by running `ReloadableDb::watch` on a thread
+//! of its own. A reload opens the new shards first and then swaps in a new
+//! snapshot at once; queries holding the previous snapshot finish on it,
+//! and shards that were dropped are closed when the last snapshot holding
+//! them goes away.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::durable::sync_dir;
+use super::{Error, SharedDb};
+
+/// Where a `ReloadableDb` finds its shards.
+#[derive(Debug,Clone)]
+enum Source {
+    Dir(PathBuf),
+    Manifest(PathBuf),
+}
+
+/// An opened shard.
+#[derive(Clone)]
+pub struct Shard {
+    path: PathBuf,
+    db: SharedDb,
+    /// The modification time of the shard when it was opened, to tell
+    /// whether it has been replaced since.
+    modified: Option<SystemTime>,
+}
+
+impl Shard {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn db(&self) -> &SharedDb {
+        &self.db
+    }
+}
+
+/// What a reload changed.
+#[derive(Debug,Default)]
+pub struct ReloadReport {
+    /// Shards opened for the first time, or reopened after being replaced.
+    pub added: Vec<PathBuf>,
+    /// Shards that are gone from the directory or the manifest.
+    pub removed: Vec<PathBuf>,
+    /// Shards that could not be opened, tried again on the next reload.
+    /// New ones are left out of the snapshot; replaced ones keep being
+    /// served as they were.
+    pub failed: Vec<(PathBuf, Error)>,
+}
+
+impl ReloadReport {
+    /// Whether the reload swapped in a new snapshot.
+    pub fn changed(&self) -> bool {
+        !self.added.is_empty() || !self.removed.is_empty()
+    }
+}
+
+/// A set of shards that can be reloaded while it is being read.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::ReloadableDb;
+/// use std::path::Path;
+/// use std::sync::atomic::AtomicBool;
+/// use std::sync::Arc;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// let shards = Arc::new(ReloadableDb::dir(Path::new("ingest")));
+/// shards.reload().unwrap();
+///
+/// let watcher = shards.clone();
+/// thread::spawn(move || {
+///     let stop = AtomicBool::new(false);
+///     watcher.watch(Duration::from_secs(10), &stop, |report| {
+///         println!("{} shards added", report.added.len());
+///     })
+/// });
+///
+/// let events: u64 = shards.shards().iter().map(|shard| shard.db().num_events()).sum();
+/// println!("{} events", events);
+/// ```
+pub struct ReloadableDb {
+    source: Source,
+    current: RwLock<Arc<Vec<Shard>>>,
+    /// Held for the length of a reload, so that two reloads don't race to
+    /// swap in their snapshot.
+    reloading: Mutex<()>,
+}
+
+impl ReloadableDb {
+    /// Follow the shards in `dir`: `.tdb` packages and database
+    /// directories. Names starting with a `.` are skipped, so shards can be
+    /// built under a hidden name and renamed into place once finalized.
+    ///
+    /// Nothing is opened until the first `reload`.
+    pub fn dir(dir: &Path) -> Self {
+        Self::new(Source::Dir(dir.to_path_buf()))
+    }
+
+    /// Follow the shards listed in the file `manifest`, one path per line,
+    /// relative to the manifest's directory. Blank lines and lines starting
+    /// with `#` are skipped. Replace the manifest by renaming a new one
+    /// over it, so that a reload never reads a partly written list.
+    ///
+    /// Nothing is opened until the first `reload`.
+    pub fn manifest(manifest: &Path) -> Self {
+        Self::new(Source::Manifest(manifest.to_path_buf()))
+    }
+
+    fn new(source: Source) -> Self {
+        ReloadableDb {
+            source: source,
+            current: RwLock::new(Arc::new(Vec::new())),
+            reloading: Mutex::new(()),
+        }
+    }
+
+    /// The current snapshot of shards, ordered by path. It stays usable
+    /// however many reloads happen while it is held.
+    pub fn shards(&self) -> Arc<Vec<Shard>> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Open new and replaced shards and swap in a snapshot without the
+    /// ones that are gone. Shards that didn't change are carried over
+    /// without being reopened.
+    pub fn reload(&self) -> io::Result<ReloadReport> {
+        let _reloading = self.reloading.lock().unwrap();
+        let listed = self.list()?;
+        let current = self.shards();
+        let mut previous: HashMap<&Path, &Shard> =
+            current.iter().map(|shard| (shard.path.as_path(), shard)).collect();
+        let mut report = ReloadReport::default();
+        let mut shards = Vec::with_capacity(listed.len());
+        for path in listed {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            let old = previous.remove(path.as_path());
+            if let Some(shard) = old.filter(|shard| shard.modified == modified) {
+                shards.push(shard.clone());
+                continue;
+            }
+            match SharedDb::open(&path) {
+                Ok(db) => {
+                    report.added.push(path.clone());
+                    shards.push(Shard {
+                        path: path,
+                        db: db,
+                        modified: modified,
+                    });
+                }
+                Err(e) => {
+                    // A replaced shard that can't be opened yet is served
+                    // as it was.
+                    if let Some(shard) = old {
+                        shards.push(shard.clone());
+                    }
+                    report.failed.push((path, e));
+                }
+            }
+        }
+        report.removed.extend(previous.keys().map(|path| path.to_path_buf()));
+        report.removed.sort();
+        if report.changed() {
+            *self.current.write().unwrap() = Arc::new(shards);
+        }
+        Ok(report)
+    }
+
+    /// Reload every `interval` until `stop` is set. `on_reload` is called
+    /// after every reload that changed the snapshot or failed to open a
+    /// shard.
+    pub fn watch<F>(&self, interval: Duration, stop: &AtomicBool, mut on_reload: F) -> io::Result<()>
+        where F: FnMut(&ReloadReport)
+    {
+        while !stop.load(Ordering::Relaxed) {
+            let report = self.reload()?;
+            if report.changed() || !report.failed.is_empty() {
+                on_reload(&report);
+            }
+            thread::sleep(interval);
+        }
+        Ok(())
+    }
+
+    /// The paths of the shards that should be open, sorted.
+    fn list(&self) -> io::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        match self.source {
+            Source::Dir(ref dir) => {
+                for entry in fs::read_dir(dir)? {
+                    let entry = entry?;
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if name.starts_with('.') {
+                        continue;
+                    }
+                    let path = entry.path();
+                    let is_shard = if entry.file_type()?.is_dir() {
+                        path.join("info").exists()
+                    } else {
+                        name.ends_with(".tdb")
+                    };
+                    if is_shard {
+                        paths.push(path);
+                    }
+                }
+            }
+            Source::Manifest(ref manifest) => {
+                let base = manifest.parent().unwrap_or_else(|| Path::new(""));
+                for line in fs::read_to_string(manifest)?.lines() {
+                    let line = line.trim();
+                    if !line.is_empty() && !line.starts_with('#') {
+                        paths.push(base.join(line));
+                    }
+                }
+            }
+        }
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
+}
+
+/// Add `shard` to the manifest `manifest`, in the format read by
+/// `ReloadableDb::manifest`, creating the manifest if there is none.
+/// Returns false if the shard was already listed.
+///
+/// The new manifest is written and flushed under a hidden name and renamed
+/// over the old one, so readers see either list in full and the shard
+/// stays listed across a crash. Calls for the same manifest must not run
+/// concurrently.
+pub fn append_manifest(manifest: &Path, shard: &Path) -> io::Result<bool> {
+    let base = manifest.parent().unwrap_or_else(|| Path::new(""));
+    let entry = match shard.strip_prefix(base) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => {
+            let shard = fs::canonicalize(shard)?;
+            match fs::canonicalize(base) {
+                Ok(ref base) if shard.starts_with(base) => shard.strip_prefix(base).unwrap().to_path_buf(),
+                _ => shard,
+            }
+        }
+    };
+    let entry = entry.to_string_lossy().into_owned();
+    let mut contents = match fs::read_to_string(manifest) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    if contents.lines().any(|line| line.trim() == entry) {
+        return Ok(false);
+    }
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents.push_str(&entry);
+    contents.push('\n');
+
+    let name = manifest.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let tmp = base.join(format!(".{}.tmp", name));
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, manifest)?;
+    sync_dir(base)?;
+    Ok(true)
+}
+
+
+
+
+#[cfg(test)]
+mod test_reload {
+    use super::{append_manifest, ReloadableDb};
+    use super::super::Constructor;
+    use std::fs;
+    use std::path::Path;
+
+    fn shard(path: &Path, events: u64) {
+        let mut cons = Constructor::new(path, &["action"]).unwrap();
+        for t in 0..events {
+            assert!(cons.add(&[1u8; 16], t, &["view"]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+    }
+
+    #[test]
+    fn test_reload_dir() {
+        let dir = Path::new("test_reload_dir");
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        shard(&dir.join("a"), 1);
+
+        let shards = ReloadableDb::dir(dir);
+        assert!(shards.shards().is_empty());
+        let report = shards.reload().unwrap();
+        assert_eq!(report.added, vec![dir.join("a.tdb")]);
+        let before = shards.shards();
+
+        shard(&dir.join("b"), 2);
+        shard(&dir.join(".c"), 3);
+        let report = shards.reload().unwrap();
+        assert_eq!(report.added, vec![dir.join("b.tdb")]);
+        assert!(report.removed.is_empty());
+        assert!(!shards.reload().unwrap().changed());
+
+        fs::remove_file(dir.join("a.tdb")).unwrap();
+        let report = shards.reload().unwrap();
+        assert_eq!(report.removed, vec![dir.join("a.tdb")]);
+        let after = shards.shards();
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].db().num_events(), 2);
+
+        // The old snapshot still reads the removed shard.
+        assert_eq!(before.len(), 1);
+        assert_eq!(before[0].db().num_events(), 1);
+    }
+
+    #[test]
+    fn test_reload_manifest() {
+        let dir = Path::new("test_reload_manifest");
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        shard(&dir.join("a"), 1);
+        shard(&dir.join("b"), 2);
+        let manifest = dir.join("MANIFEST");
+        fs::write(&manifest, "# shards\na.tdb\n\nmissing.tdb\n").unwrap();
+
+        let shards = ReloadableDb::manifest(&manifest);
+        let report = shards.reload().unwrap();
+        assert_eq!(report.added, vec![dir.join("a.tdb")]);
+        assert_eq!(report.failed.len(), 1);
+
+        fs::write(&manifest, "").unwrap();
+        assert!(append_manifest(&manifest, &dir.join("b.tdb")).unwrap());
+        assert!(!append_manifest(&manifest, &dir.join("b.tdb")).unwrap());
+        assert_eq!(fs::read_to_string(&manifest).unwrap(), "b.tdb\n");
+        let report = shards.reload().unwrap();
+        assert_eq!(report.added, vec![dir.join("b.tdb")]);
+        assert_eq!(report.removed, vec![dir.join("a.tdb")]);
+        assert_eq!(shards.shards()[0].db().num_events(), 2);
+    }
+}
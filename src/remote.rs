@@ -0,0 +1,325 @@
+//! Packaged databases fetched from object storage.
+//!
+//! `ShardCache` downloads `.tdb` packages from any `object_store` backend
+//! (S3 and GCS with the `s3` and `gcs` features) into a local directory the
+//! first time they are opened, and opens them from there afterwards. Once
+//! the cached packages take more than the configured size, the least
+//! recently opened ones are removed. A removed package stays readable
+//! through any `Db` that already has it open, as the database keeps it
+//! mapped.
+//!
+//! The cache directory can be reused across restarts: packages already in
+//! it are picked up, oldest first, when the cache is created.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use futures::StreamExt;
+use object_store::path::Path as ObjectPath;
+use object_store::ObjectStore;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::task;
+
+use super::{stats, Db, Error};
+
+/// Marks a package that is still being downloaded.
+const PARTIAL_PREFIX: &str = ".partial-";
+
+/// An error fetching or opening a remote database.
+#[derive(Debug)]
+pub enum RemoteError {
+    /// Talking to the object store failed.
+    Store(object_store::Error),
+    /// Writing to or reading the cache directory failed.
+    Io(io::Error),
+    /// The downloaded package could not be opened.
+    Db(Error),
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RemoteError::Store(ref e) => write!(f, "RemoteError::Store({})", e),
+            RemoteError::Io(ref e) => write!(f, "RemoteError::Io({})", e),
+            RemoteError::Db(ref e) => write!(f, "RemoteError::Db({})", e),
+        }
+    }
+}
+
+impl error::Error for RemoteError {}
+
+impl From<object_store::Error> for RemoteError {
+    fn from(e: object_store::Error) -> Self {
+        RemoteError::Store(e)
+    }
+}
+
+impl From<io::Error> for RemoteError {
+    fn from(e: io::Error) -> Self {
+        RemoteError::Io(e)
+    }
+}
+
+impl From<Error> for RemoteError {
+    fn from(e: Error) -> Self {
+        RemoteError::Db(e)
+    }
+}
+
+/// A cached package.
+#[derive(Debug,Clone,Copy)]
+struct Entry {
+    size: u64,
+    /// When the package was last opened, as a tick of the cache.
+    used: u64,
+}
+
+/// What the cache holds, keyed by object key.
+#[derive(Debug,Default)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    size: u64,
+    tick: u64,
+}
+
+impl Index {
+    fn touch(&mut self, key: &str) -> bool {
+        self.tick += 1;
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                entry.used = self.tick;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, key: &str, size: u64) {
+        self.tick += 1;
+        let entry = Entry {
+            size: size,
+            used: self.tick,
+        };
+        if let Some(old) = self.entries.insert(key.to_string(), entry) {
+            self.size -= old.size;
+        }
+        self.size += size;
+    }
+
+    /// Remove least recently used entries, other than `keep`, until the
+    /// cache holds at most `max_size` bytes. Returns the removed keys.
+    fn evict(&mut self, max_size: u64, keep: &str) -> Vec<String> {
+        let mut removed = Vec::new();
+        while self.size > max_size {
+            let oldest = self.entries
+                .iter()
+                .filter(|&(key, _)| key != keep)
+                .min_by_key(|&(_, entry)| entry.used)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(key) => {
+                    self.size -= self.entries.remove(&key).unwrap().size;
+                    removed.push(key);
+                }
+                None => break,
+            }
+        }
+        removed
+    }
+}
+
+/// A local cache of packaged databases kept in object storage.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::path::Path;
+/// use traildb::remote::ShardCache;
+///
+/// # async fn run() -> Result<(), traildb::remote::RemoteError> {
+/// let cache = ShardCache::s3("events-archive", Path::new("/var/cache/traildb"), 50 << 30)?;
+/// let db = cache.open("daily/2024-01-01.tdb").await?;
+/// println!("{} trails", db.num_trails());
+/// # Ok(())
+/// # }
+/// ```
+pub struct ShardCache {
+    store: Arc<dyn ObjectStore>,
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<Index>,
+    seq: AtomicUsize,
+}
+
+impl ShardCache {
+    /// Cache packages from `store` in `dir`, keeping at most about
+    /// `max_size` bytes. A package larger than that is still cached while
+    /// it is the most recently opened one.
+    pub fn new(store: Arc<dyn ObjectStore>, dir: &Path, max_size: u64) -> Result<Self, RemoteError> {
+        fs::create_dir_all(dir)?;
+        let mut cached = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(PARTIAL_PREFIX) {
+                fs::remove_file(entry.path())?;
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if let Some(key) = decode_key(&name) {
+                cached.push((metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                             key,
+                             metadata.len()));
+            }
+        }
+        cached.sort();
+        let mut index = Index::default();
+        for (_, key, size) in cached {
+            index.insert(&key, size);
+        }
+        Ok(ShardCache {
+            store: store,
+            dir: dir.to_path_buf(),
+            max_size: max_size,
+            index: Mutex::new(index),
+            seq: AtomicUsize::new(0),
+        })
+    }
+
+    /// Cache packages from the S3 bucket `bucket`, configured from the
+    /// `AWS_*` environment variables.
+    #[cfg(feature = "s3")]
+    pub fn s3(bucket: &str, dir: &Path, max_size: u64) -> Result<Self, RemoteError> {
+        let store = object_store::aws::AmazonS3Builder::from_env().with_bucket_name(bucket).build()?;
+        Self::new(Arc::new(store), dir, max_size)
+    }
+
+    /// Cache packages from the GCS bucket `bucket`, configured from the
+    /// `GOOGLE_*` environment variables.
+    #[cfg(feature = "gcs")]
+    pub fn gcs(bucket: &str, dir: &Path, max_size: u64) -> Result<Self, RemoteError> {
+        let store = object_store::gcp::GoogleCloudStorageBuilder::from_env()
+            .with_bucket_name(bucket)
+            .build()?;
+        Self::new(Arc::new(store), dir, max_size)
+    }
+
+    /// The number of bytes of packages in the cache.
+    pub fn size(&self) -> u64 {
+        self.index.lock().unwrap().size
+    }
+
+    /// Whether the package `key` is in the cache.
+    pub fn contains(&self, key: &str) -> bool {
+        self.index.lock().unwrap().entries.contains_key(key)
+    }
+
+    /// The local path of the package `key`, downloading it first if it
+    /// isn't cached.
+    pub async fn fetch(&self, key: &str) -> Result<PathBuf, RemoteError> {
+        let path = self.dir.join(encode_key(key));
+        if self.index.lock().unwrap().touch(key) {
+            stats::shard_cache(true);
+            return Ok(path);
+        }
+        stats::shard_cache(false);
+        let partial = self.dir.join(format!("{}{}-{}",
+                                            PARTIAL_PREFIX,
+                                            self.seq.fetch_add(1, Ordering::Relaxed),
+                                            encode_key(key)));
+        let size = match self.download(key, &partial).await {
+            Ok(size) => size,
+            Err(e) => {
+                let _ = fs::remove_file(&partial);
+                return Err(e);
+            }
+        };
+        fs::rename(&partial, &path)?;
+        let removed = {
+            let mut index = self.index.lock().unwrap();
+            index.insert(key, size);
+            index.evict(self.max_size, key)
+        };
+        for key in removed {
+            let _ = fs::remove_file(self.dir.join(encode_key(&key)));
+        }
+        Ok(path)
+    }
+
+    /// Open the package `key`, downloading it first if it isn't cached.
+    pub async fn open(&self, key: &str) -> Result<Db<'static>, RemoteError> {
+        let path = self.fetch(key).await?;
+        task::spawn_blocking(move || Db::open(&path))
+            .await
+            .map_err(|e| RemoteError::Io(io::Error::other(e)))?
+            .map_err(RemoteError::Db)
+    }
+
+    async fn download(&self, key: &str, dst: &Path) -> Result<u64, RemoteError> {
+        let mut stream = self.store.get(&ObjectPath::from(key)).await?.into_stream();
+        let mut file = File::create(dst).await?;
+        let mut size = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await?;
+            size += chunk.len() as u64;
+        }
+        file.sync_all().await?;
+        Ok(size)
+    }
+}
+
+/// The file name a package is cached under: its key, with `%` and `/`
+/// escaped.
+fn encode_key(key: &str) -> String {
+    key.replace('%', "%25").replace('/', "%2F")
+}
+
+fn decode_key(name: &str) -> Option<String> {
+    if name.starts_with('.') {
+        return None;
+    }
+    Some(name.replace("%2F", "/").replace("%25", "%"))
+}
+
+
+
+
+#[cfg(test)]
+mod test_remote {
+    use super::{decode_key, encode_key, Index};
+
+    #[test]
+    fn test_encode_key() {
+        for key in &["a.tdb", "daily/2024-01-01.tdb", "odd%2Fname/x"] {
+            assert_eq!(decode_key(&encode_key(key)).unwrap(), *key);
+        }
+        assert_eq!(encode_key("a/b.tdb"), "a%2Fb.tdb");
+        assert_eq!(decode_key(".partial-0-a.tdb"), None);
+    }
+
+    #[test]
+    fn test_evict() {
+        let mut index = Index::default();
+        index.insert("a", 10);
+        index.insert("b", 10);
+        index.insert("c", 10);
+        assert!(index.touch("a"));
+        assert!(!index.touch("d"));
+        assert_eq!(index.evict(20, "c"), vec!["b".to_string()]);
+        assert_eq!(index.size, 20);
+        index.insert("d", 50);
+        let mut removed = index.evict(20, "d");
+        removed.sort();
+        assert_eq!(removed, vec!["a".to_string(), "c".to_string()]);
+        assert_eq!(index.size, 50);
+    }
+}
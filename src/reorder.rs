@@ -0,0 +1,180 @@
+//! Sorting the events of roughly ordered sources before they reach a
+//! constructor.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use super::{Constructor, Error, Timestamp, Uuid};
+
+/// An event held by a `ReorderBuffer`.
+#[derive(Debug)]
+struct Pending {
+    timestamp: Timestamp,
+    /// The order events were given in, to keep events with the same
+    /// timestamp in that order.
+    seq: u64,
+    uuid: Uuid,
+    values: Vec<String>,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        (self.timestamp, self.seq) == (other.timestamp, other.seq)
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.timestamp, self.seq).cmp(&(other.timestamp, other.seq))
+    }
+}
+
+/// A constructor wrapper for sources that are only roughly ordered.
+///
+/// Events are held until an event `window` later has been seen, and are
+/// then added to the constructor in timestamp order, so every trail gets
+/// its events in order as long as none arrives more than `window` late.
+/// Events arriving later than that are added right away and counted by
+/// `late`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::{Constructor, ReorderBuffer};
+/// use std::path::Path;
+///
+/// let cons = Constructor::new(Path::new("clicks"), &["page"]).unwrap();
+/// // Events arrive up to a minute out of order.
+/// let mut buffer = ReorderBuffer::new(cons, 60);
+/// buffer.add(&[1u8; 16], 1_000, &["/cart"]).unwrap();
+/// buffer.add(&[1u8; 16], 990, &["/"]).unwrap();
+/// buffer.finalize().unwrap();
+/// ```
+pub struct ReorderBuffer {
+    cons: Constructor,
+    window: Timestamp,
+    pending: BinaryHeap<Reverse<Pending>>,
+    seq: u64,
+    /// The latest timestamp given.
+    latest: Timestamp,
+    /// The timestamp of the event last added to the constructor.
+    released: Option<Timestamp>,
+    late: u64,
+}
+
+impl ReorderBuffer {
+    /// Buffer the events for `cons`, sorting those less than `window` apart.
+    pub fn new(cons: Constructor, window: Timestamp) -> Self {
+        ReorderBuffer {
+            cons: cons,
+            window: window,
+            pending: BinaryHeap::new(),
+            seq: 0,
+            latest: 0,
+            released: None,
+            late: 0,
+        }
+    }
+
+    /// Buffer an event, adding the events it moves out of the window to the
+    /// constructor.
+    pub fn add(&mut self, uuid: &Uuid, timestamp: Timestamp, values: &[&str]) -> Result<(), Error> {
+        if self.released.is_some_and(|released| timestamp < released) {
+            self.late += 1;
+            return self.cons.add(uuid, timestamp, values);
+        }
+        self.pending.push(Reverse(Pending {
+            timestamp: timestamp,
+            seq: self.seq,
+            uuid: *uuid,
+            values: values.iter().map(|v| v.to_string()).collect(),
+        }));
+        self.seq += 1;
+        self.latest = self.latest.max(timestamp);
+        let horizon = self.latest.saturating_sub(self.window);
+        self.release(|pending| pending.timestamp < horizon)
+    }
+
+    /// Add every buffered event to the constructor.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.release(|_| true)
+    }
+
+    /// Flush and finalize the constructor.
+    pub fn finalize(&mut self) -> Result<(), Error> {
+        self.flush()?;
+        self.cons.finalize()
+    }
+
+    /// Flush and return the constructor.
+    pub fn into_inner(mut self) -> Result<Constructor, Error> {
+        self.flush()?;
+        Ok(self.cons)
+    }
+
+    pub fn constructor(&self) -> &Constructor {
+        &self.cons
+    }
+
+    /// The number of events buffered.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The number of events that arrived after an event more than `window`
+    /// later had been added, and were added out of order.
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+
+    /// Add the buffered events, earliest first, while `ready` holds.
+    fn release<F>(&mut self, ready: F) -> Result<(), Error>
+        where F: Fn(&Pending) -> bool
+    {
+        while self.pending.peek().is_some_and(|&Reverse(ref next)| ready(next)) {
+            let Reverse(next) = self.pending.pop().unwrap();
+            let values: Vec<&str> = next.values.iter().map(|v| v.as_str()).collect();
+            self.cons.add(&next.uuid, next.timestamp, &values)?;
+            self.released = Some(next.timestamp);
+        }
+        Ok(())
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_reorder {
+    use super::ReorderBuffer;
+    use super::super::{ConstructorBuilder, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_reorder_buffer() {
+        let db_path = Path::new("test_reorder_buffer");
+        let cons = ConstructorBuilder::new(db_path, &["seq"]).track_order(true).build().unwrap();
+        let mut buffer = ReorderBuffer::new(cons, 10);
+        for &(timestamp, seq) in &[(100, "a"), (95, "b"), (105, "c"), (102, "d"), (120, "e"), (101, "f")] {
+            assert!(buffer.add(&[1u8; 16], timestamp, &[seq]).is_ok());
+        }
+        assert_eq!(buffer.late(), 1);
+        assert_eq!(buffer.constructor().num_events(), 5);
+        assert_eq!(buffer.constructor().out_of_order(), 1);
+        assert_eq!(buffer.pending(), 1);
+        assert!(buffer.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let batch = db.trail_batch(0).unwrap();
+        let seqs: Vec<&str> = batch.events.iter().map(|e| e.values[0].as_str()).collect();
+        assert_eq!(seqs, vec!["b", "a", "f", "d", "c", "e"]);
+    }
+}
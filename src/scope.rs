@@ -0,0 +1,114 @@
+use std::thread::{self, Scope, ScopedJoinHandle};
+
+use super::{Cursor, Db};
+
+impl<'a> Db<'a> {
+    /// Run `f` with a scope for spawning worker threads that read the
+    /// database, like `std::thread::scope`.
+    ///
+    /// Every worker spawned through the scope gets a cursor of its own, so
+    /// no cursor is ever shared between threads. Cursors are tied to the
+    /// scope, and all workers are joined before `scoped` returns, so
+    /// neither can outlive the database. A panic in a worker that wasn't
+    /// joined is passed on once the others are done.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// // Count the events of the even and the odd trails in parallel.
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// let counts: Vec<u64> = db.scoped(|scope| {
+    ///     let workers: Vec<_> = (0..2)
+    ///         .map(|parity| {
+    ///             scope.spawn(move |mut cursor| {
+    ///                 let mut count = 0;
+    ///                 for trail_id in (parity..scope.db().num_trails()).step_by(2) {
+    ///                     cursor.get_trail(trail_id).unwrap();
+    ///                     count += cursor.len();
+    ///                 }
+    ///                 count
+    ///             })
+    ///         })
+    ///         .collect();
+    ///     workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+    /// });
+    /// println!("{:?}", counts);
+    /// ```
+    pub fn scoped<'env, T, F>(&'env self, f: F) -> T
+        where F: for<'scope> FnOnce(DbScope<'scope, 'env>) -> T
+    {
+        thread::scope(|scope| {
+            f(DbScope {
+                db: self,
+                scope: scope,
+            })
+        })
+    }
+}
+
+/// A scope for spawning threads that read a database, created by
+/// `Db::scoped`. It is `Copy`, so workers can take it along to spawn more
+/// workers or to look up values.
+#[derive(Clone,Copy)]
+pub struct DbScope<'scope, 'env: 'scope> {
+    db: &'env Db<'env>,
+    scope: &'scope Scope<'scope, 'env>,
+}
+
+impl<'scope, 'env> DbScope<'scope, 'env> {
+    /// The database the scope reads.
+    pub fn db(&self) -> &'env Db<'env> {
+        self.db
+    }
+
+    /// Spawn a worker thread that runs `f` with a new cursor.
+    pub fn spawn<T, F>(&self, f: F) -> ScopedJoinHandle<'scope, T>
+        where T: Send + 'scope,
+              F: FnOnce(Cursor<'scope>) -> T + Send + 'scope
+    {
+        let cursor = self.db.cursor();
+        self.scope.spawn(move || f(cursor))
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_scope {
+    use super::super::{Constructor, Db};
+    use std::path::Path;
+
+    #[test]
+    fn test_scoped() {
+        let db_path = Path::new("test_scoped");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        for i in 0..10u8 {
+            for t in 0..i as u64 {
+                assert!(cons.add(&[i; 16], t, &["view"]).is_ok());
+            }
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let total: u64 = db.scoped(|scope| {
+            let workers: Vec<_> = (0..3)
+                .map(|worker| {
+                    scope.spawn(move |mut cursor| {
+                        let mut count = 0;
+                        for trail_id in (worker..scope.db().num_trails()).step_by(3) {
+                            cursor.get_trail(trail_id).unwrap();
+                            count += cursor.len();
+                        }
+                        count
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).sum()
+        });
+        assert_eq!(total, db.num_events());
+    }
+}
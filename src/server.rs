@@ -0,0 +1,361 @@
+//! A read-only HTTP API over opened databases.
+//!
+//! `Server` routes:
+//!
+//! - `GET /info`: the database's `DbInfo`, as JSON.
+//! - `GET /trails/{uuid}`: the events of the trail with the given hex UUID,
+//!   as a JSON object holding the `uuid` and a list of `events`.
+//! - `POST /query`: the events matching a filter, as newline-delimited JSON
+//!   in the format of `export::jsonl`.
+//!
+//! The body of `/query` is a list of clauses, each a list of terms, AND'ed
+//! and OR'ed like an `EventFilter`; an empty list matches every event:
+//!
+//! ```text
+//! [[{"field": "action", "value": "purchase"}],
+//!  [{"field": "country", "value": "us", "negative": true}]]
+//! ```
+//!
+//! A server can hold several databases. Requests go to the first one added
+//! unless they name another with a `db` parameter, e.g. `/info?db=events`.
+//! `/query` responses are streamed as the events are read; an error while
+//! streaming one cuts it short.
+
+use std::io::{self, Write};
+use std::mem;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{Map, Value as JsonValue};
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task;
+
+use super::export::jsonl::JsonlEncoder;
+use super::export::{export_to, ExportError, ExportOptions};
+use super::query::{build_filter, NamedTerm, QueryError};
+use super::{uuid_hex, uuid_raw, Db, DbInfo, Error, TrailId};
+
+/// The size of the chunks `/query` responses are sent in.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// The number of chunks read ahead of a slow client.
+const CHUNKS_AHEAD: usize = 4;
+
+/// An HTTP server over a set of named databases.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::Db;
+/// use traildb::server::Server;
+/// use std::path::Path;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let server = Server::new()
+///     .database("events", Db::open(Path::new("events")).unwrap())
+///     .database("archive", Db::open(Path::new("archive")).unwrap());
+/// server.serve("127.0.0.1:8080").await
+/// # }
+/// ```
+#[derive(Default)]
+pub struct Server {
+    dbs: Vec<(String, Db<'static>)>,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `db` as `name`.
+    pub fn database(mut self, name: &str, db: Db<'static>) -> Self {
+        self.dbs.push((name.to_string(), db));
+        self
+    }
+
+    /// The routes of the API, to be served as is or nested in a larger
+    /// application.
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/info", get(info))
+            .route("/trails/{uuid}", get(trail))
+            .route("/query", post(query))
+            .with_state(Arc::new(self))
+    }
+
+    /// Listen on `addr` and serve requests until an error occurs.
+    pub async fn serve<A: ToSocketAddrs>(self, addr: A) -> io::Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        axum::serve(listener, self.router()).await
+    }
+
+    /// The database a request asked for.
+    fn select(&self, name: Option<&str>) -> Result<&Db<'static>, ApiError> {
+        let found = match name {
+            Some(name) => self.dbs.iter().find(|&&(ref n, _)| n == name),
+            None => self.dbs.first(),
+        };
+        found.map(|&(_, ref db)| db).ok_or_else(|| {
+            ApiError(StatusCode::NOT_FOUND,
+                     format!("no database {:?}", name.unwrap_or("")))
+        })
+    }
+}
+
+/// A failed request, sent as a status code and a JSON `{"error": ...}` body.
+#[derive(Debug)]
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut body = Map::new();
+        body.insert("error".to_string(), JsonValue::String(self.1));
+        (self.0, Json(JsonValue::Object(body))).into_response()
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+impl From<ExportError> for ApiError {
+    fn from(e: ExportError) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+impl From<QueryError> for ApiError {
+    fn from(e: QueryError) -> Self {
+        match e {
+            QueryError::Db(e) => e.into(),
+            e => ApiError(StatusCode::BAD_REQUEST, e.to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Params {
+    db: Option<String>,
+}
+
+/// Run `f` on the requested database on the blocking thread pool, as
+/// reading a database may fault in pages from disk.
+async fn blocking<T, F>(server: Arc<Server>, name: Option<String>, f: F) -> Result<T, ApiError>
+    where T: Send + 'static,
+          F: FnOnce(&Db) -> Result<T, ApiError> + Send + 'static
+{
+    task::spawn_blocking(move || f(server.select(name.as_deref())?))
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+}
+
+async fn info(State(server): State<Arc<Server>>,
+              Query(params): Query<Params>)
+              -> Result<Json<DbInfo>, ApiError> {
+    Ok(Json(blocking(server, params.db, |db| Ok(db.info())).await?))
+}
+
+async fn trail(State(server): State<Arc<Server>>,
+               Path(uuid): Path<String>,
+               Query(params): Query<Params>)
+               -> Result<Json<JsonValue>, ApiError> {
+    let hex: String = uuid.chars().filter(|&c| c != '-').collect();
+    let raw = uuid_raw(&hex.to_lowercase())
+        .ok_or_else(|| ApiError(StatusCode::BAD_REQUEST, format!("invalid UUID {:?}", uuid)))?;
+    let trail = blocking(server, params.db, move |db| {
+            let trail_id = db.get_trail_id(&raw)
+                .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("no trail {}", uuid)))?;
+            Ok(trail_json(db, trail_id)?)
+        })
+        .await?;
+    Ok(Json(trail))
+}
+
+async fn query(State(server): State<Arc<Server>>,
+               Query(params): Query<Params>,
+               Json(clauses): Json<Vec<Vec<NamedTerm>>>)
+               -> Result<Response, ApiError> {
+    // The filter is built before answering, so that a bad one gets an
+    // error status; the events are then sent as they are exported.
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let (chunks_tx, chunks_rx) = mpsc::channel(CHUNKS_AHEAD);
+    task::spawn_blocking(move || {
+        let filter = server.select(params.db.as_deref()).and_then(|db| {
+            if clauses.is_empty() {
+                Ok((db, None))
+            } else {
+                Ok((db, Some(build_filter(db, &clauses)?)))
+            }
+        });
+        let (db, filter) = match filter {
+            Ok(found) => found,
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+                return;
+            }
+        };
+        if ready_tx.send(Ok(())).is_err() {
+            return;
+        }
+        let options = match filter {
+            Some(ref filter) => ExportOptions::new().filter(filter),
+            None => ExportOptions::new(),
+        };
+        let mut out = ChunkWriter::new(chunks_tx.clone());
+        if let Err(e) = export_to(db, &mut JsonlEncoder::new(), &mut out, options) {
+            let _ = chunks_tx.blocking_send(Err(io::Error::other(e.to_string())));
+        }
+    });
+    ready_rx.await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+    let chunks = futures::stream::unfold(chunks_rx,
+                                         |mut rx| async move { rx.recv().await.map(|chunk| (chunk, rx)) });
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(chunks)).into_response())
+}
+
+/// Sends what is written to it to a response body, in chunks of about
+/// `CHUNK_SIZE` bytes. Writes block while the client is behind, and fail
+/// once it has gone away.
+struct ChunkWriter {
+    tx: mpsc::Sender<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+}
+
+impl ChunkWriter {
+    fn new(tx: mpsc::Sender<io::Result<Vec<u8>>>) -> Self {
+        ChunkWriter {
+            tx: tx,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the client went away"))
+    }
+}
+
+/// The events of a trail as a JSON object, with one string member per field
+/// in every event.
+fn trail_json(db: &Db, trail_id: TrailId) -> Result<JsonValue, Error> {
+    let batch = db.trail_batch(trail_id)?;
+    let fields = db.field_names();
+    let events = batch.events
+        .into_iter()
+        .map(|event| {
+            let mut object = Map::new();
+            object.insert("timestamp".to_string(), event.timestamp.into());
+            for (name, value) in fields.iter().zip(event.values) {
+                object.insert(name.to_string(), JsonValue::String(value));
+            }
+            JsonValue::Object(object)
+        })
+        .collect();
+    let mut trail = Map::new();
+    trail.insert("uuid".to_string(), JsonValue::String(uuid_hex(&batch.uuid)));
+    trail.insert("events".to_string(), JsonValue::Array(events));
+    Ok(JsonValue::Object(trail))
+}
+
+
+
+
+#[cfg(test)]
+mod test_server {
+    use super::{info, query, trail, trail_json, ApiError, Params, Server};
+    use super::super::{Constructor, Db};
+    use axum::body::to_bytes;
+    use axum::extract::{Path as UrlPath, Query, State};
+    use axum::http::StatusCode;
+    use axum::Json;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_trail_json() {
+        let db_path = Path::new("test_server_trail_json");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["view"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["buy"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let trail = trail_json(&db, 0).unwrap();
+        assert_eq!(trail["uuid"], "01010101010101010101010101010101");
+        assert_eq!(trail["events"][1]["timestamp"], 2);
+        assert_eq!(trail["events"][1]["action"], "buy");
+
+        let server = Server::new().database("a", db).database("b", Db::open(db_path).unwrap());
+        assert!(server.select(None).is_ok());
+        assert!(server.select(Some("b")).is_ok());
+        assert!(server.select(Some("c")).is_err());
+    }
+    #[test]
+    fn test_handlers() {
+        let db_path = Path::new("test_server_handlers");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["view"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["buy"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 1, &["view"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let server = Arc::new(Server::new().database("events", Db::open(db_path).unwrap()));
+        let params = |db: Option<&str>| Query(Params { db: db.map(|db| db.to_string()) });
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let Json(db_info) = info(State(server.clone()), params(None)).await.unwrap();
+            assert_eq!(db_info.num_events, 3);
+            match info(State(server.clone()), params(Some("other"))).await {
+                Err(ApiError(StatusCode::NOT_FOUND, _)) => {}
+                _ => panic!("expected an unknown database"),
+            }
+
+            let uuid = "02020202-0202-0202-0202-020202020202".to_string();
+            let Json(found) = trail(State(server.clone()), UrlPath(uuid), params(None)).await.unwrap();
+            assert_eq!(found["events"][0]["action"], "view");
+            match trail(State(server.clone()), UrlPath("03".repeat(16)), params(None)).await {
+                Err(ApiError(StatusCode::NOT_FOUND, _)) => {}
+                _ => panic!("expected an unknown trail"),
+            }
+
+            let lines = |clauses: &str| {
+                let server = server.clone();
+                let clauses = serde_json::from_str(clauses).unwrap();
+                async move {
+                    let response = query(State(server), params(None), Json(clauses)).await?;
+                    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                    Ok::<_, ApiError>(String::from_utf8(body.to_vec()).unwrap().lines().count())
+                }
+            };
+            assert_eq!(lines("[]").await.unwrap(), 3);
+            assert_eq!(lines(r#"[[{"field": "action", "value": "buy"}]]"#).await.unwrap(), 1);
+            match lines(r#"[[{"field": "country", "value": "DE"}]]"#).await {
+                Err(ApiError(StatusCode::BAD_REQUEST, _)) => {}
+                _ => panic!("expected a bad request"),
+            }
+        });
+    }
+}
@@ -0,0 +1,185 @@
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use super::{Db, Error};
+
+/// A database of a set that could not be opened.
+#[derive(Debug,PartialEq)]
+pub struct OpenError {
+    pub path: PathBuf,
+    pub error: Error,
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OpenError({}: {})", self.path.display(), self.error)
+    }
+}
+
+impl error::Error for OpenError {}
+
+/// A set of opened databases, such as the daily shards of a dataset, in
+/// the order they were given to `Db::open_many`. They are closed when the
+/// set is dropped.
+pub struct DbSet {
+    dbs: Vec<(PathBuf, Db<'static>)>,
+}
+
+impl DbSet {
+    pub fn len(&self) -> usize {
+        self.dbs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dbs.is_empty()
+    }
+
+    /// The `i`th database.
+    pub fn get(&self, i: usize) -> Option<&Db<'_>> {
+        self.dbs.get(i).map(|&(_, ref db)| db)
+    }
+
+    /// The databases with their paths.
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &Db<'_>)> {
+        self.dbs.iter().map(|&(ref path, ref db)| (path.as_path(), db))
+    }
+
+    /// The total number of trails. A UUID with events in several databases
+    /// is counted once per database.
+    pub fn num_trails(&self) -> u64 {
+        self.dbs.iter().map(|&(_, ref db)| db.num_trails()).sum()
+    }
+
+    /// The total number of events.
+    pub fn num_events(&self) -> u64 {
+        self.dbs.iter().map(|&(_, ref db)| db.num_events()).sum()
+    }
+}
+
+impl Drop for DbSet {
+    fn drop(&mut self) {
+        for &mut (_, ref mut db) in &mut self.dbs {
+            db.close();
+        }
+    }
+}
+
+impl Db<'static> {
+    /// Open every database in `paths` on all available cores.
+    ///
+    /// Opening reads and maps each database's metadata and lexicons, which
+    /// adds up to most of the startup time of a service reading hundreds
+    /// of shards when done one at a time. If any database fails to open,
+    /// the ones already opened are closed and the first failure, in the
+    /// order of `paths`, is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Db;
+    /// use std::path::PathBuf;
+    ///
+    /// let paths: Vec<PathBuf> = (1..=31)
+    ///     .map(|day| PathBuf::from(format!("2024-01-{:02}", day)))
+    ///     .collect();
+    /// let shards = Db::open_many(&paths).unwrap();
+    /// println!("{} events in {} shards", shards.num_events(), shards.len());
+    /// ```
+    pub fn open_many<P: AsRef<Path> + Sync>(paths: &[P]) -> Result<DbSet, OpenError> {
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        Self::open_many_with(paths, threads)
+    }
+
+    /// Like `open_many`, with up to `threads` threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is 0.
+    #[cfg_attr(feature = "tracing",
+               tracing::instrument(level = "debug",
+                                   skip_all,
+                                   fields(shards = paths.len(), threads = threads)))]
+    pub fn open_many_with<P>(paths: &[P], threads: usize) -> Result<DbSet, OpenError>
+        where P: AsRef<Path> + Sync
+    {
+        assert!(threads > 0, "threads must be at least 1");
+        let next = AtomicUsize::new(0);
+        let worker = || {
+            let mut opened = Vec::new();
+            loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                match paths.get(i) {
+                    Some(path) => opened.push((i, Db::open(path.as_ref()))),
+                    None => return opened,
+                }
+            }
+        };
+        let mut results: Vec<(usize, Result<Db<'static>, Error>)> = thread::scope(|s| {
+            let handles: Vec<_> = (0..cmp::min(threads, paths.len()))
+                .map(|_| s.spawn(worker))
+                .collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        });
+        results.sort_by_key(|&(i, _)| i);
+
+        let mut dbs = DbSet { dbs: Vec::with_capacity(paths.len()) };
+        let mut failed = None;
+        for (i, result) in results {
+            match result {
+                Ok(db) => dbs.dbs.push((paths[i].as_ref().to_path_buf(), db)),
+                Err(e) => {
+                    if failed.is_none() {
+                        failed = Some(OpenError {
+                            path: paths[i].as_ref().to_path_buf(),
+                            error: e,
+                        });
+                    }
+                }
+            }
+        }
+        match failed {
+            Some(e) => Err(e),
+            None => Ok(dbs),
+        }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_set {
+    use super::super::{Constructor, Db};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_open_many() {
+        let paths: Vec<PathBuf> = (0..5u8)
+            .map(|i| {
+                let path = PathBuf::from(format!("test_open_many_{}", i));
+                let mut cons = Constructor::new(&path, &["action"]).unwrap();
+                for t in 0..i as u64 {
+                    assert!(cons.add(&[i; 16], t, &["view"]).is_ok());
+                }
+                assert!(cons.finalize().is_ok());
+                path
+            })
+            .collect();
+
+        let set = Db::open_many_with(&paths, 2).unwrap();
+        assert_eq!(set.len(), 5);
+        assert_eq!(set.num_events(), 10);
+        let order: Vec<&Path> = set.iter().map(|(path, _)| path).collect();
+        assert_eq!(order, paths.iter().map(|p| p.as_path()).collect::<Vec<_>>());
+        assert_eq!(set.get(3).unwrap().num_events(), 3);
+
+        let mut bad = paths.clone();
+        bad.insert(1, PathBuf::from("test_open_many_missing"));
+        let e = Db::open_many(&bad).err().unwrap();
+        assert_eq!(e.path, Path::new("test_open_many_missing"));
+    }
+}
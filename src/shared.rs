@@ -0,0 +1,152 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{Cursor, Db, DbInfo, Error, TrailBatch, TrailId, Uuid};
+
+/// A reference-counted, thread-safe read handle to a database.
+///
+/// Clones share one opened database, which is closed when the last clone is
+/// dropped. Everything a `SharedDb` offers can be called from any number of
+/// threads at once:
+///
+/// - Once opened, libtraildb never writes to a `tdb` handle: metadata,
+///   lexicons and UUIDs are read from memory-mapped files, so concurrent
+///   lookups only share immutable data.
+/// - Decoding a trail needs mutable state, which lives in a cursor. Every
+///   cursor belongs to one caller, either created for the call
+///   (`trail_batch`) or handed out to the caller (`cursor`), and cursors
+///   only decode through `&mut self`, so one is never used from two
+///   threads at once.
+/// - The only mutating operation on a `Db`, `close`, takes `&mut self` and
+///   is out of reach, as the handle is only ever lent out shared.
+/// - Cursors and values borrowed through `db` or `cursor` are tied to the
+///   borrow of the `SharedDb`, so they can't outlive the database they
+///   read from.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::SharedDb;
+/// use std::path::Path;
+/// use std::thread;
+///
+/// let db = SharedDb::open(Path::new("my_traildb")).unwrap();
+/// let workers: Vec<_> = (0..4)
+///     .map(|worker| {
+///         let db = db.clone();
+///         thread::spawn(move || db.trail_batch(worker).map(|batch| batch.events.len()))
+///     })
+///     .collect();
+/// for worker in workers {
+///     println!("{:?} events", worker.join().unwrap());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SharedDb {
+    inner: Arc<Owned>,
+}
+
+// `SharedDb` is only useful if it is `Send` and `Sync`; fail to compile
+// rather than lose either.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Db<'static>>();
+    assert_send_sync::<SharedDb>();
+};
+
+/// The database behind every clone of a `SharedDb`.
+struct Owned(Db<'static>);
+
+impl Drop for Owned {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+impl SharedDb {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Db::open(path).map(SharedDb::from)
+    }
+
+    /// The database, for the read operations without a shortcut here.
+    pub fn db(&self) -> &Db<'_> {
+        &self.inner.0
+    }
+
+    /// A new cursor, owned by the caller.
+    pub fn cursor(&self) -> Cursor<'_> {
+        self.db().cursor()
+    }
+
+    /// Read a whole trail with a cursor of its own.
+    pub fn trail_batch(&self, trail_id: TrailId) -> Result<TrailBatch, Error> {
+        self.db().trail_batch(trail_id)
+    }
+
+    pub fn get_trail_id(&self, uuid: &Uuid) -> Option<TrailId> {
+        self.db().get_trail_id(uuid)
+    }
+
+    pub fn get_uuid(&self, trail_id: TrailId) -> Option<Uuid> {
+        self.db().get_uuid(trail_id).cloned()
+    }
+
+    pub fn num_trails(&self) -> u64 {
+        self.db().num_trails()
+    }
+
+    pub fn num_events(&self) -> u64 {
+        self.db().num_events()
+    }
+
+    pub fn info(&self) -> DbInfo {
+        self.db().info()
+    }
+}
+
+impl From<Db<'static>> for SharedDb {
+    fn from(db: Db<'static>) -> Self {
+        SharedDb { inner: Arc::new(Owned(db)) }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_shared {
+    use super::SharedDb;
+    use super::super::Constructor;
+    use std::path::Path;
+    use std::thread;
+
+    #[test]
+    fn test_shared_db() {
+        let db_path = Path::new("test_shared_db");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        for i in 0..8u8 {
+            assert!(cons.add(&[i; 16], i as u64, &["view"]).is_ok());
+            assert!(cons.add(&[i; 16], i as u64 + 1, &["buy"]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = SharedDb::open(db_path).unwrap();
+        let workers: Vec<_> = (0..8)
+            .map(|trail_id| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    let uuid = db.get_uuid(trail_id).unwrap();
+                    assert_eq!(db.get_trail_id(&uuid), Some(trail_id));
+                    let mut cursor = db.cursor();
+                    cursor.get_trail(trail_id).unwrap();
+                    assert_eq!(cursor.len(), 2);
+                    db.trail_batch(trail_id).unwrap().events.len()
+                })
+            })
+            .collect();
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), 2);
+        }
+        assert_eq!(db.num_events(), 16);
+    }
+}
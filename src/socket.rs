@@ -0,0 +1,464 @@
+//! Building TrailDBs from events sent over TCP or Unix sockets.
+//!
+//! `SocketIngest` accepts connections from any number of producers, each
+//! writing newline-delimited JSON objects in the format read by
+//! `Constructor::import_jsonl`, and adds the events to a constructor that
+//! finalizes a new database under its output directory every
+//! `roll_every`. Nothing is written back to producers.
+//!
+//! Decoded events queue up in a bounded channel; while the channel is full,
+//! and while a database is being finalized, connections stop being read
+//! and producers block on their socket buffers.
+//!
+//! Databases are built under a hidden name and published, flushed to disk,
+//! under `<dst_dir>/<unix seconds>-<sequence>` once finalized, then listed
+//! in the manifest if there is one.
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::future::{self, Future};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::net::{TcpListener, ToSocketAddrs};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::mpsc::{self, Sender};
+use tokio::task::{JoinError, JoinSet};
+use tokio::time::{self, Instant};
+
+use super::durable::publish;
+use super::import::{jsonl, ColumnMapping, ImportReport, RowError, RowErrorKind};
+use super::nonblocking::blocking;
+use super::{Constructor, ConstructorBuilder, Error, Timestamp, Uuid};
+
+/// The number of decoded events waiting to be added before connections
+/// stop being read.
+pub const SOCKET_BUFFER: usize = 4096;
+
+/// How long to wait before accepting again after accepting failed.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// An error that stops an ingest.
+#[derive(Debug)]
+pub enum SocketError {
+    /// Creating or finalizing a database failed.
+    Db(Error),
+    /// Binding the socket or creating the output directory failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for SocketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SocketError::Db(ref e) => write!(f, "SocketError::Db({})", e),
+            SocketError::Io(ref e) => write!(f, "SocketError::Io({})", e),
+        }
+    }
+}
+
+impl error::Error for SocketError {}
+
+impl From<Error> for SocketError {
+    fn from(e: Error) -> Self {
+        SocketError::Db(e)
+    }
+}
+
+impl From<io::Error> for SocketError {
+    fn from(e: io::Error) -> Self {
+        SocketError::Io(e)
+    }
+}
+
+type Decoded = Result<(Uuid, Timestamp, Vec<String>), RowErrorKind>;
+
+/// A listener writing rolling TrailDBs.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::path::Path;
+/// use std::time::Duration;
+/// use traildb::socket::SocketIngest;
+///
+/// # async fn run(shutdown: impl std::future::Future) -> Result<(), traildb::socket::SocketError> {
+/// SocketIngest::new(Path::new("ingest"), &["user", "action"])
+///     .roll_every(Duration::from_secs(600))
+///     .serve_tcp("127.0.0.1:7070", shutdown, |path, report| {
+///         println!("{}: {} events", path.display(), report.imported);
+///     })
+///     .await
+/// # }
+/// ```
+pub struct SocketIngest {
+    dst_dir: PathBuf,
+    fields: Vec<String>,
+    mapping: ColumnMapping,
+    roll_every: Duration,
+    drain_timeout: Duration,
+    manifest: Option<PathBuf>,
+    rolled: u64,
+}
+
+/// The database currently being filled.
+struct Segment {
+    name: String,
+    cons: Constructor,
+    report: ImportReport,
+    deadline: Instant,
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl SocketIngest {
+    /// Ingest into databases with `fields` under `dst_dir`.
+    ///
+    /// Defaults to objects with `uuid` and `timestamp` keys, rolling every
+    /// hour.
+    pub fn new(dst_dir: &Path, fields: &[&str]) -> Self {
+        SocketIngest {
+            dst_dir: dst_dir.to_path_buf(),
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+            mapping: ColumnMapping::new("uuid", "timestamp"),
+            roll_every: Duration::from_secs(3600),
+            drain_timeout: Duration::from_secs(5),
+            manifest: None,
+            rolled: 0,
+        }
+    }
+
+    /// Where objects keep the UUID, the timestamp and the fields.
+    pub fn mapping(mut self, mapping: ColumnMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    /// How long a database collects events before it is finalized.
+    pub fn roll_every(mut self, interval: Duration) -> Self {
+        self.roll_every = interval;
+        self
+    }
+
+    /// How long producers get to close their connections on shutdown
+    /// before the remaining ones are cut off. Defaults to 5 seconds.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// List every published database in `manifest`, for a
+    /// `ReloadableDb::manifest` to pick up.
+    pub fn manifest(mut self, manifest: &Path) -> Self {
+        self.manifest = Some(manifest.to_path_buf());
+        self
+    }
+
+    /// Listen on `addr` until `shutdown` completes, then finalize the
+    /// current database.
+    ///
+    /// `on_roll` is called with the path and report of every finalized
+    /// database. Lines that can't be added are skipped and show up in the
+    /// report, numbered in the order they were received. Connections that
+    /// can't be accepted or read are logged and dropped; the ingest goes on.
+    ///
+    /// On shutdown, no more connections are accepted and open ones are
+    /// read until their producers close them, for up to `drain_timeout`.
+    /// Connections still open then are cut off: the events already read
+    /// from them are added, anything still in flight on them is lost.
+    pub async fn serve_tcp<A, S, F>(&mut self,
+                                    addr: A,
+                                    shutdown: S,
+                                    on_roll: F)
+                                    -> Result<(), SocketError>
+        where A: ToSocketAddrs,
+              S: Future,
+              F: FnMut(&Path, &ImportReport)
+    {
+        let listener = TcpListener::bind(addr).await?;
+        self.serve(Listener::Tcp(listener), shutdown, on_roll).await
+    }
+
+    /// Like `serve_tcp`, listening on the Unix socket `path`, which must
+    /// not exist yet.
+    #[cfg(unix)]
+    pub async fn serve_unix<S, F>(&mut self,
+                                  path: &Path,
+                                  shutdown: S,
+                                  on_roll: F)
+                                  -> Result<(), SocketError>
+        where S: Future,
+              F: FnMut(&Path, &ImportReport)
+    {
+        let listener = UnixListener::bind(path)?;
+        self.serve(Listener::Unix(listener), shutdown, on_roll).await
+    }
+
+    async fn serve<S, F>(&mut self,
+                         listener: Listener,
+                         shutdown: S,
+                         mut on_roll: F)
+                         -> Result<(), SocketError>
+        where S: Future,
+              F: FnMut(&Path, &ImportReport)
+    {
+        fs::create_dir_all(&self.dst_dir)?;
+        let decoder = Arc::new((self.mapping.clone(), self.fields.clone()));
+        let (tx, mut rx) = mpsc::channel::<Decoded>(SOCKET_BUFFER);
+        let mut connections = JoinSet::new();
+        let mut segment: Option<Segment> = None;
+        tokio::pin!(shutdown);
+        loop {
+            let deadline = segment.as_ref().map(|s| s.deadline);
+            tokio::select! {
+                _ = &mut shutdown => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(stream) => {
+                        connections.spawn(read_lines(stream, decoder.clone(), tx.clone()));
+                    }
+                    // Out of file descriptors, or a connection reset before
+                    // it was accepted: no reason to lose the current
+                    // database.
+                    Err(e) => {
+                        warn!("traildb: failed to accept a connection: {}", e);
+                        time::sleep(ACCEPT_BACKOFF).await;
+                    }
+                },
+                Some(joined) = connections.join_next(), if !connections.is_empty() => closed(joined),
+                Some(decoded) = rx.recv() => {
+                    if segment.is_none() {
+                        segment = Some(self.open_segment()?);
+                    }
+                    add(segment.as_mut().unwrap(), decoded);
+                }
+                _ = roll_at(deadline) => {
+                    self.roll(segment.take().unwrap(), &mut on_roll).await?;
+                }
+            }
+        }
+        drop(listener);
+        let drain = time::sleep(self.drain_timeout);
+        tokio::pin!(drain);
+        while !connections.is_empty() {
+            tokio::select! {
+                _ = &mut drain => break,
+                Some(joined) = connections.join_next() => closed(joined),
+                Some(decoded) = rx.recv() => {
+                    if segment.is_none() {
+                        segment = Some(self.open_segment()?);
+                    }
+                    add(segment.as_mut().unwrap(), decoded);
+                }
+            }
+        }
+        connections.abort_all();
+        drop(tx);
+        rx.close();
+        while let Some(decoded) = rx.recv().await {
+            if segment.is_none() {
+                segment = Some(self.open_segment()?);
+            }
+            add(segment.as_mut().unwrap(), decoded);
+        }
+        if let Some(segment) = segment {
+            self.roll(segment, &mut on_roll).await?;
+        }
+        Ok(())
+    }
+
+    fn open_segment(&mut self) -> Result<Segment, SocketError> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let name = format!("{}-{:05}", secs, self.rolled);
+        self.rolled += 1;
+        let fields: Vec<&str> = self.fields.iter().map(|f| f.as_str()).collect();
+        Ok(Segment {
+            cons: ConstructorBuilder::new(&self.dst_dir.join(format!(".{}", name)), &fields)
+                .build()?,
+            name: name,
+            report: ImportReport::default(),
+            deadline: Instant::now() + self.roll_every,
+        })
+    }
+
+    async fn roll<F>(&self, segment: Segment, on_roll: &mut F) -> Result<(), SocketError>
+        where F: FnMut(&Path, &ImportReport)
+    {
+        segment.cons.finalize_async().await?;
+        let dst_dir = self.dst_dir.clone();
+        let manifest = self.manifest.clone();
+        let name = segment.name;
+        let path = blocking(move || publish(&dst_dir, &name, manifest.as_deref())).await?;
+        on_roll(&path, &segment.report);
+        Ok(())
+    }
+}
+
+impl Listener {
+    async fn accept(&self) -> io::Result<Box<dyn AsyncRead + Unpin + Send>> {
+        match *self {
+            Listener::Tcp(ref listener) => Ok(Box::new(listener.accept().await?.0)),
+            #[cfg(unix)]
+            Listener::Unix(ref listener) => Ok(Box::new(listener.accept().await?.0)),
+        }
+    }
+}
+
+/// Log why a connection stopped being read, unless its producer closed it
+/// or it was cut off on shutdown.
+fn closed(joined: Result<io::Result<()>, JoinError>) {
+    match joined {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            warn!("traildb: failed to read a connection: {}", e);
+        }
+        Err(ref e) if e.is_cancelled() => {}
+        Err(e) => {
+            warn!("traildb: connection reader panicked: {}", e);
+        }
+    }
+}
+
+/// Decode the lines of a connection until it is closed.
+async fn read_lines<R>(stream: R,
+                       decoder: Arc<(ColumnMapping, Vec<String>)>,
+                       tx: Sender<Decoded>)
+                       -> io::Result<()>
+    where R: AsyncRead + Unpin
+{
+    let (ref mapping, ref fields) = *decoder;
+    let mut lines = BufReader::new(stream).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if tx.send(jsonl::decode(line.as_bytes(), mapping, fields)).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn add(segment: &mut Segment, decoded: Decoded) {
+    segment.report.rows += 1;
+    let row = segment.report.rows;
+    let added = decoded.and_then(|(uuid, timestamp, values)| {
+        let values: Vec<&str> = values.iter().map(|v| v.as_str()).collect();
+        segment.cons.add(&uuid, timestamp, &values).map_err(|e| RowErrorKind::rejected(&segment.cons, e))
+    });
+    match added {
+        Ok(()) => segment.report.imported += 1,
+        Err(kind) => {
+            segment.report.errors.push(RowError {
+                row: row,
+                kind: kind,
+            })
+        }
+    }
+}
+
+/// Wait until `deadline`, or forever without one.
+async fn roll_at(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => future::pending().await,
+    }
+}
+
+
+
+
+#[cfg(all(test, unix))]
+mod test_socket {
+    use super::SocketIngest;
+    use super::super::Db;
+    use std::fs;
+    use std::path::Path;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::UnixStream;
+    use tokio::time;
+
+    #[test]
+    fn test_socket_ingest() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let socket = Path::new("test_socket_ingest.sock");
+        let dst_dir = Path::new("test_socket_ingest");
+        let _ = fs::remove_file(socket);
+        let _ = fs::remove_dir_all(dst_dir);
+
+        let producer = async {
+            time::sleep(Duration::from_millis(50)).await;
+            let mut stream = UnixStream::connect(socket).await.unwrap();
+            stream.write_all(b"{\"uuid\": \"01010101010101010101010101010101\", \
+                               \"timestamp\": 1, \"action\": \"view\"}\n\
+                               not json\n\
+                               \n\
+                               {\"uuid\": \"01010101010101010101010101010101\", \
+                               \"timestamp\": 2, \"action\": \"buy\"}\n")
+                .await
+                .unwrap();
+            stream.shutdown().await.unwrap();
+            time::sleep(Duration::from_millis(200)).await;
+        };
+        let mut rolled = Vec::new();
+        runtime.block_on(SocketIngest::new(dst_dir, &["action"])
+                .serve_unix(socket, producer, |path, report| {
+                    rolled.push((path.to_path_buf(), report.rows, report.imported));
+                }))
+            .unwrap();
+        fs::remove_file(socket).unwrap();
+
+        assert_eq!(rolled.len(), 1);
+        let (ref path, rows, imported) = rolled[0];
+        assert_eq!((rows, imported), (3, 2));
+        let db = Db::open(path).unwrap();
+        assert_eq!(db.num_events(), 2);
+    }
+
+    #[test]
+    fn test_socket_ingest_bad_connection() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let socket = Path::new("test_socket_ingest_bad_connection.sock");
+        let dst_dir = Path::new("test_socket_ingest_bad_connection");
+        let _ = fs::remove_file(socket);
+        let _ = fs::remove_dir_all(dst_dir);
+
+        let producer = async {
+            time::sleep(Duration::from_millis(50)).await;
+            // Not UTF-8: reading the connection fails after the first line.
+            let mut bad = UnixStream::connect(socket).await.unwrap();
+            bad.write_all(b"{\"uuid\": \"01010101010101010101010101010101\", \
+                            \"timestamp\": 1, \"action\": \"view\"}\n\
+                            \xff\xfe\n")
+                .await
+                .unwrap();
+            time::sleep(Duration::from_millis(50)).await;
+            let mut good = UnixStream::connect(socket).await.unwrap();
+            good.write_all(b"{\"uuid\": \"02020202020202020202020202020202\", \
+                             \"timestamp\": 2, \"action\": \"buy\"}\n")
+                .await
+                .unwrap();
+            good.shutdown().await.unwrap();
+            time::sleep(Duration::from_millis(200)).await;
+        };
+        let mut rolled = Vec::new();
+        runtime.block_on(SocketIngest::new(dst_dir, &["action"])
+                .serve_unix(socket, producer, |path, report| {
+                    rolled.push((path.to_path_buf(), report.imported));
+                }))
+            .unwrap();
+        fs::remove_file(socket).unwrap();
+
+        assert_eq!(rolled.len(), 1);
+        let db = Db::open(&rolled[0].0).unwrap();
+        assert_eq!((rolled[0].1, db.num_events()), (2, 2));
+    }
+}
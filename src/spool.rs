@@ -0,0 +1,433 @@
+//! Building TrailDBs from files dropped into a spool directory.
+//!
+//! `SpoolIngest` watches a directory for event files, adds their events to
+//! a constructor and finalizes a new database, a shard, under its output
+//! directory every `roll_every`. Files are read as newline-delimited JSON
+//! if they end in `.jsonl` or `.ndjson` and, with the `csv` feature, as CSV
+//! if they end in `.csv`. Other files, and files whose names start with a
+//! dot, are left alone, so producers should write under another name and
+//! rename complete files into the spool.
+//!
+//! Handoff is crash-safe. A file is moved to `.inflight/<shard>` in the
+//! spool when it is picked up, the shard is built under a hidden name and
+//! renamed into place once finalized, and only then are its files moved to
+//! `done/<shard>`. On start, files of shards that were never published are
+//! moved back into the spool to be read again, so a crash neither loses
+//! nor duplicates events.
+//!
+//! To shut down, set the `stop` flag given to `SpoolIngest::run`, e.g. from
+//! a SIGTERM handler. The file being read is finished, the current shard
+//! is finalized, flushed to disk and published, and listed in the
+//! manifest if there is one, before `run` returns.
+
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::durable::{publish, sync_db};
+use super::import::{ColumnMapping, ImportError, ImportReport};
+use super::reload::append_manifest;
+use super::{Constructor, ConstructorBuilder, Error};
+
+/// The spool subdirectory holding the files of unpublished shards.
+const INFLIGHT_DIR: &str = ".inflight";
+/// The spool subdirectory holding the files of published shards.
+const DONE_DIR: &str = "done";
+
+/// An error that stops an ingest.
+#[derive(Debug)]
+pub enum SpoolError {
+    /// Creating or finalizing a shard failed.
+    Db(Error),
+    /// Listing, moving or opening files failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for SpoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SpoolError::Db(ref e) => write!(f, "SpoolError::Db({})", e),
+            SpoolError::Io(ref e) => write!(f, "SpoolError::Io({})", e),
+        }
+    }
+}
+
+impl error::Error for SpoolError {}
+
+impl From<Error> for SpoolError {
+    fn from(e: Error) -> Self {
+        SpoolError::Db(e)
+    }
+}
+
+impl From<io::Error> for SpoolError {
+    fn from(e: io::Error) -> Self {
+        SpoolError::Io(e)
+    }
+}
+
+/// The outcome of reading one spool file into a shard.
+#[derive(Debug)]
+pub struct FileReport {
+    /// The name of the file in the spool.
+    pub name: String,
+    /// The rows read and skipped or, if the file could not be read at all,
+    /// why. Events added before a read error are kept.
+    pub result: Result<ImportReport, ImportError>,
+}
+
+/// The files that went into a published shard.
+#[derive(Debug,Default)]
+pub struct ShardReport {
+    pub files: Vec<FileReport>,
+}
+
+impl ShardReport {
+    /// The number of rows read from all files.
+    pub fn rows(&self) -> u64 {
+        self.reports().map(|r| r.rows).sum()
+    }
+
+    /// The number of events added from all files.
+    pub fn imported(&self) -> u64 {
+        self.reports().map(|r| r.imported).sum()
+    }
+
+    fn reports(&self) -> impl Iterator<Item = &ImportReport> {
+        self.files.iter().filter_map(|f| f.result.as_ref().ok())
+    }
+}
+
+/// A loop turning spooled event files into rolling TrailDBs.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::path::Path;
+/// use std::sync::atomic::AtomicBool;
+/// use std::time::Duration;
+/// use traildb::import::ColumnMapping;
+/// use traildb::spool::SpoolIngest;
+///
+/// let stop = AtomicBool::new(false);
+/// SpoolIngest::new(Path::new("spool"), Path::new("shards"), &["user", "action"])
+///     .mapping(ColumnMapping::new("session", "ts"))
+///     .roll_every(Duration::from_secs(3600))
+///     .run(&stop, |path, report| {
+///         println!("{}: {} events", path.display(), report.imported());
+///     })
+///     .unwrap();
+/// ```
+pub struct SpoolIngest {
+    spool_dir: PathBuf,
+    dst_dir: PathBuf,
+    fields: Vec<String>,
+    mapping: ColumnMapping,
+    roll_every: Duration,
+    poll_interval: Duration,
+    manifest: Option<PathBuf>,
+}
+
+/// The shard currently being filled.
+struct Shard {
+    name: String,
+    cons: Constructor,
+    report: ShardReport,
+    opened: Instant,
+}
+
+impl SpoolIngest {
+    /// Ingest files appearing in `spool_dir` into databases with `fields`
+    /// under `dst_dir`.
+    ///
+    /// Defaults to `uuid` and `timestamp` columns, rolling every hour and
+    /// looking for new files every second.
+    pub fn new(spool_dir: &Path, dst_dir: &Path, fields: &[&str]) -> Self {
+        SpoolIngest {
+            spool_dir: spool_dir.to_path_buf(),
+            dst_dir: dst_dir.to_path_buf(),
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+            mapping: ColumnMapping::new("uuid", "timestamp"),
+            roll_every: Duration::from_secs(3600),
+            poll_interval: Duration::from_secs(1),
+            manifest: None,
+        }
+    }
+
+    /// Where files keep the UUID, the timestamp and the fields.
+    pub fn mapping(mut self, mapping: ColumnMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    /// How long a shard collects events before it is finalized.
+    pub fn roll_every(mut self, interval: Duration) -> Self {
+        self.roll_every = interval;
+        self
+    }
+
+    /// How long to wait before listing the spool again when it is empty.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// List every published shard in `manifest`, for a
+    /// `ReloadableDb::manifest` to pick up.
+    pub fn manifest(mut self, manifest: &Path) -> Self {
+        self.manifest = Some(manifest.to_path_buf());
+        self
+    }
+
+    /// Consume files until `stop` is set, then publish the current shard.
+    ///
+    /// Files left in flight by an earlier run are recovered first. Shards
+    /// are published as `<dst_dir>/<unix seconds>-<sequence>`; `on_roll` is
+    /// called with the path and report of every one. A shard is only
+    /// opened once a file arrives, so an idle spool publishes nothing.
+    pub fn run<F>(&self, stop: &AtomicBool, mut on_roll: F) -> Result<(), SpoolError>
+        where F: FnMut(&Path, &ShardReport)
+    {
+        fs::create_dir_all(&self.dst_dir)?;
+        fs::create_dir_all(self.spool_dir.join(INFLIGHT_DIR))?;
+        fs::create_dir_all(self.spool_dir.join(DONE_DIR))?;
+        self.recover()?;
+        let mut shard: Option<Shard> = None;
+        while !stop.load(Ordering::Relaxed) {
+            let pending = self.pending()?;
+            for name in &pending {
+                if shard.is_none() {
+                    shard = Some(self.open_shard()?);
+                }
+                self.ingest_file(shard.as_mut().unwrap(), name)?;
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+            }
+            if shard.as_ref().map_or(false, |s| s.opened.elapsed() >= self.roll_every) {
+                self.roll(shard.take().unwrap(), &mut on_roll)?;
+            }
+            if pending.is_empty() && !stop.load(Ordering::Relaxed) {
+                thread::sleep(self.poll_interval);
+            }
+        }
+        if let Some(shard) = shard {
+            self.roll(shard, &mut on_roll)?;
+        }
+        Ok(())
+    }
+
+    /// Settle the in-flight files of an earlier run: those of published
+    /// shards are done, the others go back into the spool, and the partial
+    /// output of unpublished shards is removed.
+    fn recover(&self) -> Result<(), SpoolError> {
+        let inflight = self.spool_dir.join(INFLIGHT_DIR);
+        for name in sorted_names(&inflight)? {
+            let files = inflight.join(&name);
+            if let Some(path) = self.published(&name) {
+                sync_db(&path)?;
+                if let Some(ref manifest) = self.manifest {
+                    append_manifest(manifest, &path)?;
+                }
+                fs::rename(&files, self.spool_dir.join(DONE_DIR).join(&name))?;
+                continue;
+            }
+            for file in sorted_names(&files)? {
+                fs::rename(files.join(&file), self.spool_dir.join(&file))?;
+            }
+            fs::remove_dir(&files)?;
+            let partial = format!(".{}", name);
+            for entry in fs::read_dir(&self.dst_dir)? {
+                let entry = entry?;
+                if entry.file_name().to_string_lossy().starts_with(&partial) {
+                    remove_path(&entry.path())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The names of the files in the spool waiting to be read, oldest name
+    /// first.
+    fn pending(&self) -> Result<Vec<String>, SpoolError> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.spool_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                if !name.starts_with('.') && file_format(name).is_some() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn open_shard(&self) -> Result<Shard, SpoolError> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let mut seq = 0;
+        let mut name = format!("{}-{:05}", secs, seq);
+        while self.published(&name).is_some() ||
+              self.spool_dir.join(DONE_DIR).join(&name).exists() {
+            seq += 1;
+            name = format!("{}-{:05}", secs, seq);
+        }
+        fs::create_dir_all(self.spool_dir.join(INFLIGHT_DIR).join(&name))?;
+        let fields: Vec<&str> = self.fields.iter().map(|f| f.as_str()).collect();
+        Ok(Shard {
+            cons: ConstructorBuilder::new(&self.dst_dir.join(format!(".{}", name)), &fields)
+                .build()?,
+            name: name,
+            report: ShardReport::default(),
+            opened: Instant::now(),
+        })
+    }
+
+    /// Claim the spool file `name` for `shard` and add its events.
+    fn ingest_file(&self, shard: &mut Shard, name: &str) -> Result<(), SpoolError> {
+        let claimed = self.spool_dir.join(INFLIGHT_DIR).join(&shard.name).join(name);
+        fs::rename(self.spool_dir.join(name), &claimed)?;
+        let file = File::open(&claimed)?;
+        let result = match file_format(name) {
+            Some(FileFormat::Jsonl) => shard.cons.import_jsonl(file, &self.mapping),
+            #[cfg(feature = "csv")]
+            Some(FileFormat::Csv) => shard.cons.import_csv(file, &self.mapping),
+            None => unreachable!(),
+        };
+        shard.report.files.push(FileReport {
+            name: name.to_string(),
+            result: result,
+        });
+        Ok(())
+    }
+
+    fn roll<F>(&self, mut shard: Shard, on_roll: &mut F) -> Result<(), SpoolError>
+        where F: FnMut(&Path, &ShardReport)
+    {
+        shard.cons.finalize()?;
+        let path = publish(&self.dst_dir, &shard.name, self.manifest.as_deref())?;
+        fs::rename(self.spool_dir.join(INFLIGHT_DIR).join(&shard.name),
+                   self.spool_dir.join(DONE_DIR).join(&shard.name))?;
+        on_roll(&path, &shard.report);
+        Ok(())
+    }
+
+    /// The path of the published shard `name`, if there is one.
+    fn published(&self, name: &str) -> Option<PathBuf> {
+        let packaged = self.dst_dir.join(format!("{}.tdb", name));
+        let dir = self.dst_dir.join(name);
+        if packaged.exists() {
+            Some(packaged)
+        } else if dir.exists() {
+            Some(dir)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum FileFormat {
+    Jsonl,
+    #[cfg(feature = "csv")]
+    Csv,
+}
+
+/// The format of a spool file, by its extension.
+fn file_format(name: &str) -> Option<FileFormat> {
+    match Path::new(name).extension().and_then(|e| e.to_str()) {
+        Some("jsonl") | Some("ndjson") => Some(FileFormat::Jsonl),
+        #[cfg(feature = "csv")]
+        Some("csv") => Some(FileFormat::Csv),
+        _ => None,
+    }
+}
+
+/// The names of the entries of `dir`, sorted.
+fn sorted_names(dir: &Path) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    Ok(names)
+}
+
+fn remove_path(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+#[cfg(test)]
+mod test_spool {
+    use super::{file_format, FileFormat, ShardReport, SpoolIngest, INFLIGHT_DIR};
+    use super::super::Db;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_file_format() {
+        assert_eq!(file_format("events.jsonl"), Some(FileFormat::Jsonl));
+        assert_eq!(file_format("events.ndjson"), Some(FileFormat::Jsonl));
+        assert_eq!(file_format("events.jsonl.part"), None);
+        assert_eq!(file_format("events"), None);
+    }
+
+    #[test]
+    fn test_spool_ingest() {
+        let spool = Path::new("test_spool_ingest_spool");
+        let dst = Path::new("test_spool_ingest_dst");
+        let _ = fs::remove_dir_all(spool);
+        let _ = fs::remove_dir_all(dst);
+        fs::create_dir_all(spool.join(INFLIGHT_DIR).join("1-00000")).unwrap();
+        // Left in flight by a run that crashed before publishing.
+        fs::write(spool.join(INFLIGHT_DIR).join("1-00000").join("a.jsonl"),
+                  "{\"uuid\":\"00000000000000000000000000000001\",\"timestamp\":1,\"action\":\"view\"}\n")
+            .unwrap();
+        fs::write(spool.join("b.jsonl"),
+                  "{\"uuid\":\"00000000000000000000000000000001\",\"timestamp\":2,\"action\":\"buy\"}\nnot json\n")
+            .unwrap();
+        fs::write(spool.join("c.jsonl.part"), "").unwrap();
+
+        // Already set, so the run only recovers the in-flight file.
+        let stop = AtomicBool::new(true);
+        let ingest = SpoolIngest::new(spool, dst, &["action"]).manifest(&dst.join("MANIFEST"));
+        ingest.run(&stop, |_, _| panic!("no shard to roll")).unwrap();
+        assert!(spool.join("a.jsonl").exists());
+        assert_eq!(ingest.pending().unwrap(), vec!["a.jsonl", "b.jsonl"]);
+
+        let mut shard = ingest.open_shard().unwrap();
+        for name in ingest.pending().unwrap() {
+            ingest.ingest_file(&mut shard, &name).unwrap();
+        }
+        let mut shards = Vec::new();
+        ingest.roll(shard, &mut |path: &Path, report: &ShardReport| {
+                shards.push((path.to_path_buf(), report.rows(), report.imported()));
+            })
+            .unwrap();
+
+        assert_eq!(shards.len(), 1);
+        assert_eq!(shards[0].1, 3);
+        assert_eq!(shards[0].2, 2);
+        let db = Db::open(&shards[0].0).unwrap();
+        assert_eq!(db.num_events(), 2);
+        assert!(spool.join("c.jsonl.part").exists());
+        assert!(!spool.join("a.jsonl").exists());
+        assert_eq!(fs::read_dir(spool.join(INFLIGHT_DIR)).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(spool.join("done")).unwrap().count(), 1);
+        let listed = fs::read_to_string(dst.join("MANIFEST")).unwrap();
+        assert_eq!(dst.join(listed.trim()), shards[0].0);
+    }
+}
@@ -0,0 +1,63 @@
+//! Counters and histograms published through the `metrics` facade with the
+//! `metrics` feature. Without it these calls compile to nothing.
+//!
+//! | Name                                   | Type      | What                                  |
+//! |----------------------------------------|-----------|---------------------------------------|
+//! | `traildb_trails_scanned_total`         | counter   | trails loaded into a cursor           |
+//! | `traildb_events_decoded_total`         | counter   | events returned by cursors            |
+//! | `traildb_open_seconds`                 | histogram | time taken by `Db::open`              |
+//! | `traildb_finalize_seconds`             | histogram | time taken by `Constructor::finalize` |
+//! | `traildb_events_finalized_total`       | counter   | events in finalized databases         |
+//! | `traildb_cursor_pool_hits_total`       | counter   | cursors reused by a `CursorPool`      |
+//! | `traildb_cursor_pool_misses_total`     | counter   | cursors created by a `CursorPool`     |
+//! | `traildb_shard_cache_hits_total`       | counter   | packages found in a `ShardCache`      |
+//! | `traildb_shard_cache_misses_total`     | counter   | packages downloaded by a `ShardCache` |
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+
+use std::time::Duration;
+
+pub(crate) fn trails_scanned(n: u64) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("traildb_trails_scanned_total").increment(n);
+}
+
+pub(crate) fn events_decoded(n: u64) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("traildb_events_decoded_total").increment(n);
+}
+
+pub(crate) fn opened(took: Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!("traildb_open_seconds").record(took.as_secs_f64());
+}
+
+pub(crate) fn finalized(took: Duration, events: u64) {
+    #[cfg(feature = "metrics")]
+    {
+        ::metrics::histogram!("traildb_finalize_seconds").record(took.as_secs_f64());
+        ::metrics::counter!("traildb_events_finalized_total").increment(events);
+    }
+}
+
+pub(crate) fn cursor_pool(hit: bool) {
+    #[cfg(feature = "metrics")]
+    {
+        if hit {
+            ::metrics::counter!("traildb_cursor_pool_hits_total").increment(1);
+        } else {
+            ::metrics::counter!("traildb_cursor_pool_misses_total").increment(1);
+        }
+    }
+}
+
+#[cfg(feature = "remote")]
+pub(crate) fn shard_cache(hit: bool) {
+    #[cfg(feature = "metrics")]
+    {
+        if hit {
+            ::metrics::counter!("traildb_shard_cache_hits_total").increment(1);
+        } else {
+            ::metrics::counter!("traildb_shard_cache_misses_total").increment(1);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Databases read from pluggable storage.
+//!
+//! A `Storage` is a readable blob of known length holding a TrailDB
+//! package, such as an encrypted volume, a buffer in memory or an object
+//! behind a network client. `Db::open_storage` opens one without the
+//! decoding code knowing where the bytes come from.
+//!
+//! libtraildb can only map a package from a file, so a storage with a file
+//! of its own (`Storage::local_path`) is opened in place, and any other is
+//! first copied to a temporary file, removed once the `Db` is dropped.
+//! Storages that hold the package in memory can hand it over whole with
+//! `Storage::bytes` rather than being read piece by piece.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The size of the pieces a storage is read in.
+const STAGING_CHUNK: usize = 1 << 20;
+
+/// Distinguishes the staged packages of one process.
+static STAGED_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// A readable blob holding a TrailDB package.
+pub trait Storage {
+    /// The length of the package in bytes.
+    fn len(&self) -> io::Result<u64>;
+
+    /// Read bytes from `offset` into `buf`, returning how many were read,
+    /// 0 only at the end of the package.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Whether the package is empty.
+    fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// The package as a file on the local file system, if the storage has
+    /// one that can be opened directly.
+    fn local_path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// The whole package, if the storage holds it in memory.
+    fn bytes(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl Storage for [u8] {
+    fn len(&self) -> io::Result<u64> {
+        Ok(<[u8]>::len(self) as u64)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let start = clamp_offset(offset, <[u8]>::len(self));
+        let n = buf.len().min(<[u8]>::len(self) - start);
+        buf[..n].copy_from_slice(&self[start..start + n]);
+        Ok(n)
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+impl Storage for Vec<u8> {
+    fn len(&self) -> io::Result<u64> {
+        Storage::len(self.as_slice())
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.as_slice().read_at(offset, buf)
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+/// `offset` as a position in a buffer of `len` bytes, at most its end.
+fn clamp_offset(offset: u64, len: usize) -> usize {
+    if offset >= len as u64 {
+        len
+    } else {
+        offset as usize
+    }
+}
+
+/// A package in a file on the local file system.
+#[derive(Debug)]
+pub struct FileStorage {
+    path: PathBuf,
+    file: File,
+}
+
+impl FileStorage {
+    /// Open the package at `path`.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(FileStorage {
+            path: path.to_path_buf(),
+            file: File::open(path)?,
+        })
+    }
+}
+
+impl Storage for FileStorage {
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    #[cfg(unix)]
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        use std::os::unix::fs::FileExt;
+        self.file.read_at(buf, offset)
+    }
+
+    #[cfg(windows)]
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        use std::os::windows::fs::FileExt;
+        self.file.seek_read(buf, offset)
+    }
+
+    fn local_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+/// A package copied from a storage to a temporary file, removed on drop.
+#[derive(Debug)]
+pub(crate) struct Staged {
+    path: PathBuf,
+}
+
+impl Staged {
+    /// Copy the package in `storage`.
+    pub(crate) fn new(storage: &dyn Storage) -> io::Result<Self> {
+        let path = env::temp_dir().join(format!("traildb-storage-{}-{}.tdb",
+                                                process::id(),
+                                                STAGED_SEQ.fetch_add(1, Ordering::Relaxed)));
+        let staged = Staged { path: path };
+        let mut dst = BufWriter::new(File::create(&staged.path)?);
+        match storage.bytes() {
+            Some(bytes) => dst.write_all(bytes)?,
+            None => {
+                let len = storage.len()?;
+                let mut buf = vec![0u8; STAGING_CHUNK];
+                let mut offset = 0;
+                while offset < len {
+                    let n = storage.read_at(offset, &mut buf)?;
+                    if n == 0 {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                  format!("storage ended at {} of {} bytes", offset, len)));
+                    }
+                    dst.write_all(&buf[..n])?;
+                    offset += n as u64;
+                }
+            }
+        }
+        dst.flush()?;
+        Ok(staged)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_storage {
+    use super::{FileStorage, Storage};
+    use super::super::{Constructor, Db};
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    /// A package stored with every byte flipped, read back through
+    /// `read_at` only.
+    struct Flipped(Vec<u8>);
+
+    impl Storage for Flipped {
+        fn len(&self) -> io::Result<u64> {
+            Ok(self.0.len() as u64)
+        }
+
+        fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.as_slice().read_at(offset, buf)?;
+            for byte in &mut buf[..n] {
+                *byte = !*byte;
+            }
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_open_storage() {
+        let db_path = Path::new("test_open_storage");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["view"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 2, &["buy"]).is_ok());
+        assert!(cons.finalize().is_ok());
+        let package = Path::new("test_open_storage.tdb");
+        let bytes = fs::read(package).unwrap();
+
+        let mut buf = [0u8; 4];
+        assert_eq!(bytes.read_at(bytes.len() as u64 - 2, &mut buf).unwrap(), 2);
+        assert_eq!(bytes.read_at(bytes.len() as u64 + 10, &mut buf).unwrap(), 0);
+
+        let file = FileStorage::open(package).unwrap();
+        assert_eq!(Storage::len(&file).unwrap(), bytes.len() as u64);
+        let db = Db::open_storage(&file).unwrap();
+        assert_eq!(db.path(), package);
+        assert_eq!(db.num_events(), 2);
+
+        let db = Db::open_storage(&bytes).unwrap();
+        let staged = db.path().to_path_buf();
+        assert!(staged.exists());
+        assert_eq!(db.num_trails(), 2);
+        drop(db);
+        assert!(!staged.exists());
+
+        let flipped = Flipped(bytes.iter().map(|byte| !byte).collect());
+        let db = Db::open_storage(&flipped).unwrap();
+        let trail_id = db.get_trail_id(&[2u8; 16]).unwrap();
+        assert_eq!(db.trail_batch(trail_id).unwrap().events[0].values, vec!["buy"]);
+    }
+}
@@ -0,0 +1,311 @@
+//! Trails as a `futures::Stream`.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{Stream, TryStreamExt};
+
+use super::{Cursor, Db, Error, EventBuf, Trail, TrailBatch, TrailId};
+
+/// The number of trails a `TrailStream` decodes at a time by default.
+pub const DEFAULT_STREAM_BUFFER: usize = 64;
+
+impl<'a> Db<'a> {
+    /// Stream every trail, in trail id order, as an owned `TrailBatch`.
+    ///
+    /// Trails are decoded in the polling task, a buffer's worth at a time,
+    /// and the stream yields to the executor before decoding the next
+    /// buffer so that a long scan doesn't starve other tasks. Decoding
+    /// reads memory-mapped data and may fault in pages, so keep buffers
+    /// small on databases that don't fit in memory.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use futures::StreamExt;
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// # async fn run() {
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// let mut trails = db.stream().buffer(16);
+    /// while let Some(batch) = trails.next().await {
+    ///     let batch = batch.unwrap();
+    ///     println!("{} events", batch.events.len());
+    /// }
+    /// # }
+    /// ```
+    pub fn stream(&'a self) -> TrailStream<'a> {
+        TrailStream {
+            db: self,
+            cursor: self.cursor(),
+            next: 0,
+            end: self.num_trails(),
+            buffer: VecDeque::new(),
+            capacity: DEFAULT_STREAM_BUFFER,
+            yielded: true,
+        }
+    }
+
+    /// Run `f` on every trail, with up to `limit` calls in flight at once.
+    /// Panics if `limit` is 0.
+    ///
+    /// Trails are decoded from `stream` as calls finish, so no more than
+    /// about `limit` trails plus a stream buffer are held in memory. Stops
+    /// at the first error, from decoding or from `f`, and returns it; calls
+    /// still in flight are dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::{Db, Error, TrailBatch};
+    /// use std::path::Path;
+    ///
+    /// # async fn upload(batch: TrailBatch) -> Result<(), Error> { Ok(()) }
+    /// # async fn run() -> Result<(), Error> {
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// db.for_each_concurrent(16, upload).await
+    /// # }
+    /// ```
+    #[cfg_attr(feature = "tracing",
+               tracing::instrument(level = "debug",
+                                   skip_all,
+                                   fields(limit = limit, trails = self.num_trails())))]
+    pub async fn for_each_concurrent<E, F, Fut>(&'a self, limit: usize, f: F) -> Result<(), E>
+        where E: From<Error>,
+              F: FnMut(TrailBatch) -> Fut,
+              Fut: Future<Output = Result<(), E>>
+    {
+        assert!(limit > 0, "at least one call has to be in flight");
+        self.stream().map_err(E::from).try_for_each_concurrent(limit, f).await
+    }
+}
+
+/// A stream of the trails of a `Db`, created by `Db::stream`.
+pub struct TrailStream<'a> {
+    db: &'a Db<'a>,
+    cursor: Cursor<'a>,
+    next: TrailId,
+    end: TrailId,
+    buffer: VecDeque<Result<TrailBatch, Error>>,
+    capacity: usize,
+    /// Whether the stream has given the executor a turn since it last
+    /// filled the buffer.
+    yielded: bool,
+}
+
+impl<'a> TrailStream<'a> {
+    /// Decode up to `n` trails at a time. Panics if `n` is 0.
+    pub fn buffer(mut self, n: usize) -> Self {
+        assert!(n > 0, "a stream buffer holds at least one trail");
+        self.capacity = n;
+        self
+    }
+
+    fn fill(&mut self) {
+        while self.buffer.len() < self.capacity && self.next < self.end {
+            let trail_id = self.next;
+            self.next += 1;
+            let batch = self.decode(trail_id);
+            self.buffer.push_back(batch);
+        }
+    }
+
+    fn decode(&mut self, trail_id: TrailId) -> Result<TrailBatch, Error> {
+        let uuid = *self.db.get_uuid(trail_id).ok_or(Error::InvalidTrailId)?;
+        self.cursor.get_trail(trail_id)?;
+        let mut events = Vec::with_capacity(self.cursor.len() as usize);
+        for event in &mut self.cursor {
+            events.push(self.db.resolve_event(&event));
+        }
+        Ok(TrailBatch {
+            uuid: uuid,
+            events: events,
+        })
+    }
+}
+
+impl<'a> Stream for TrailStream<'a> {
+    type Item = Result<TrailBatch, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(batch) = this.buffer.pop_front() {
+            return Poll::Ready(Some(batch));
+        }
+        if this.next >= this.end {
+            return Poll::Ready(None);
+        }
+        if !this.yielded {
+            this.yielded = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        this.yielded = false;
+        this.fill();
+        Poll::Ready(this.buffer.pop_front())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.buffer.len() + (self.end - self.next) as usize;
+        (left, Some(left))
+    }
+}
+
+impl<'a> Trail<'a> {
+    /// Stream the events of the trail in chunks of up to `chunk_size`
+    /// owned events. Panics if `chunk_size` is 0.
+    ///
+    /// Events are decoded only as chunks are polled for, so a slow
+    /// consumer holds back decoding rather than having the whole trail
+    /// pile up in memory, and the stream yields to the executor between
+    /// chunks. Items can be resolved with `Db::get_item_value`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use futures::StreamExt;
+    /// use traildb::Db;
+    /// use std::path::Path;
+    ///
+    /// # async fn run() {
+    /// let db = Db::open(Path::new("my_traildb")).unwrap();
+    /// let mut chunks = db.get_trail(0).unwrap().into_stream(1000);
+    /// while let Some(chunk) = chunks.next().await {
+    ///     println!("{} events", chunk.len());
+    /// }
+    /// # }
+    /// ```
+    pub fn into_stream(self, chunk_size: usize) -> EventStream<'a> {
+        assert!(chunk_size > 0, "a chunk holds at least one event");
+        EventStream {
+            trail: self,
+            chunk_size: chunk_size,
+            done: false,
+            yielded: true,
+        }
+    }
+}
+
+/// A stream of chunks of the events of a trail, created by
+/// `Trail::into_stream`.
+pub struct EventStream<'a> {
+    trail: Trail<'a>,
+    chunk_size: usize,
+    done: bool,
+    yielded: bool,
+}
+
+impl<'a> Stream for EventStream<'a> {
+    type Item = Vec<EventBuf>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        if !this.yielded {
+            this.yielded = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        this.yielded = false;
+        let mut chunk = Vec::with_capacity(this.chunk_size);
+        while chunk.len() < this.chunk_size {
+            match this.trail.next() {
+                Some(event) => chunk.push(event.to_event_buf()),
+                None => {
+                    this.done = true;
+                    break;
+                }
+            }
+        }
+        if chunk.is_empty() {
+            return Poll::Ready(None);
+        }
+        Poll::Ready(Some(chunk))
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_stream {
+    use super::super::{Constructor, Db, Error};
+    use futures::executor::block_on;
+    use futures::{Stream, StreamExt};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_stream() {
+        let db_path = Path::new("test_stream");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        for i in 0..5u8 {
+            for t in 0..i as u64 {
+                assert!(cons.add(&[i; 16], t, &["view"]).is_ok());
+            }
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let stream = db.stream().buffer(2);
+        assert_eq!(stream.size_hint(), (4, Some(4)));
+        let batches: Vec<_> = block_on(stream.collect());
+        assert_eq!(batches.len(), 4);
+        let mut lengths: Vec<usize> =
+            batches.into_iter().map(|batch| batch.unwrap().events.len()).collect();
+        lengths.sort();
+        assert_eq!(lengths, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_event_stream() {
+        let db_path = Path::new("test_event_stream");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        for t in 0..5 {
+            assert!(cons.add(&[1u8; 16], t, &["view"]).is_ok());
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let chunks: Vec<_> = block_on(db.get_trail(0).unwrap().into_stream(2).collect());
+        let lengths: Vec<usize> = chunks.iter().map(|chunk| chunk.len()).collect();
+        assert_eq!(lengths, vec![2, 2, 1]);
+        assert_eq!(chunks[2][0].timestamp, 4);
+        assert_eq!(db.get_item_value(chunks[0][0].items[0]), "view");
+    }
+
+    #[test]
+    fn test_for_each_concurrent() {
+        let db_path = Path::new("test_for_each_concurrent");
+        let mut cons = Constructor::new(db_path, &["action"]).unwrap();
+        for i in 0..20u8 {
+            for t in 0..3 {
+                assert!(cons.add(&[i; 16], t, &["view"]).is_ok());
+            }
+        }
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let events = AtomicUsize::new(0);
+        let result: Result<(), Error> = block_on(db.for_each_concurrent(4, |batch| {
+            events.fetch_add(batch.events.len(), Ordering::Relaxed);
+            async { Ok(()) }
+        }));
+        assert!(result.is_ok());
+        assert_eq!(events.load(Ordering::Relaxed), 60);
+
+        let result = block_on(db.for_each_concurrent(4, |batch| async move {
+            if batch.uuid == [7u8; 16] {
+                Err(Error::InvalidTrailId)
+            } else {
+                Ok(())
+            }
+        }));
+        assert_eq!(result, Err(Error::InvalidTrailId));
+    }
+}
@@ -0,0 +1,262 @@
+//! Generators for property tests of code handling TrailDBs, with the
+//! `proptest` feature.
+//!
+//! `DbSpec` describes a small database, events and all; `any::<DbSpec>()`
+//! generates one and `DbSpec::build` writes it to a temporary directory,
+//! so a test can check what it reads from the database against the spec.
+//! Values are drawn from a small alphabet, so that trails share values the
+//! way they do in real data.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use proptest::prelude::*;
+//! use traildb::testing::DbSpec;
+//!
+//! proptest! {
+//!     #[test]
+//!     fn counts_every_event(spec in any::<DbSpec>()) {
+//!         let built = spec.build().unwrap();
+//!         let mut db = built.open().unwrap();
+//!         prop_assert_eq!(db.num_events(), spec.num_events());
+//!         db.close();
+//!     }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ::proptest::arbitrary::{any, Arbitrary};
+use ::proptest::collection::{btree_map, hash_set, vec};
+use ::proptest::prop_oneof;
+use ::proptest::strategy::{BoxedStrategy, Just, Strategy};
+
+use super::{Constructor, Db, Error, ResolvedEvent, Timestamp, TrailBatch};
+
+/// Distinguishes the directories of one process.
+static TEMP_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// The field names of a database, distinct and valid for libtraildb.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Schema(pub Vec<String>);
+
+impl Arbitrary for Schema {
+    /// The most fields to generate.
+    type Parameters = usize;
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(max_fields: usize) -> Self::Strategy {
+        let max_fields = if max_fields == 0 { DbParams::default().max_fields } else { max_fields };
+        hash_set(field_name(), 1..=max_fields)
+            .prop_map(|names| {
+                let mut names: Vec<String> = names.into_iter().collect();
+                names.sort();
+                Schema(names)
+            })
+            .boxed()
+    }
+}
+
+/// How large the databases generated by `any::<DbSpec>()` get.
+#[derive(Debug,Clone,Copy)]
+pub struct DbParams {
+    pub max_fields: usize,
+    pub max_trails: usize,
+    pub max_events: usize,
+}
+
+impl Default for DbParams {
+    fn default() -> Self {
+        DbParams {
+            max_fields: 4,
+            max_trails: 16,
+            max_events: 16,
+        }
+    }
+}
+
+/// A database to build: its fields and its trails, ordered by UUID, each
+/// with at least one event and strictly increasing timestamps, so that
+/// they read back exactly as they are.
+#[derive(Debug,Clone,PartialEq)]
+pub struct DbSpec {
+    pub fields: Vec<String>,
+    pub trails: Vec<TrailBatch>,
+}
+
+impl DbSpec {
+    pub fn num_events(&self) -> u64 {
+        self.trails.iter().map(|trail| trail.events.len() as u64).sum()
+    }
+
+    /// Write the database to a new temporary directory.
+    pub fn build(&self) -> Result<TempDb, Error> {
+        let dir = temp_dir().map_err(|_| Error::IoOpen)?;
+        let temp = TempDb { dir: dir };
+        let fields: Vec<&str> = self.fields.iter().map(|f| f.as_str()).collect();
+        let mut cons = Constructor::new(&temp.dir.join("db"), &fields)?;
+        for trail in &self.trails {
+            for event in &trail.events {
+                let values: Vec<&str> = event.values.iter().map(|v| v.as_str()).collect();
+                if let Err(e) = cons.add(&trail.uuid, event.timestamp, &values) {
+                    cons.close();
+                    return Err(e);
+                }
+            }
+        }
+        cons.finalize()?;
+        Ok(temp)
+    }
+}
+
+impl Arbitrary for DbSpec {
+    type Parameters = DbParams;
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(params: DbParams) -> Self::Strategy {
+        Schema::arbitrary_with(params.max_fields)
+            .prop_flat_map(move |Schema(fields)| {
+                let trails = trails(fields.len(), params.max_trails, params.max_events);
+                (Just(fields), trails)
+            })
+            .prop_map(|(fields, trails)| {
+                DbSpec {
+                    fields: fields,
+                    trails: trails,
+                }
+            })
+            .boxed()
+    }
+}
+
+/// A database written by `DbSpec::build`, removed with its directory on
+/// drop. Close any `Db` opened from it first.
+#[derive(Debug)]
+pub struct TempDb {
+    dir: PathBuf,
+}
+
+impl TempDb {
+    /// The path to open the database with.
+    pub fn path(&self) -> PathBuf {
+        self.dir.join("db")
+    }
+
+    pub fn open(&self) -> Result<Db<'static>, Error> {
+        Db::open(&self.path())
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A field name valid for libtraildb, other than `time`.
+pub fn field_name() -> impl Strategy<Value = String> {
+    "[a-z][a-z0-9_]{0,7}".prop_filter("time is reserved", |name| name != "time")
+}
+
+/// A value, often empty and otherwise one of a few short strings.
+pub fn value() -> impl Strategy<Value = String> {
+    prop_oneof![
+        2 => Just(String::new()),
+        3 => "[a-c]{1,2}",
+        1 => "[a-z]{1,8}",
+    ]
+}
+
+/// An event with `fields` values.
+pub fn event(fields: usize) -> impl Strategy<Value = ResolvedEvent> {
+    (any::<Timestamp>(), vec(value(), fields)).prop_map(|(timestamp, values)| {
+        ResolvedEvent {
+            timestamp: timestamp,
+            values: values,
+        }
+    })
+}
+
+/// Between 1 and `max_events` events with `fields` values each and strictly
+/// increasing timestamps.
+pub fn events(fields: usize, max_events: usize) -> impl Strategy<Value = Vec<ResolvedEvent>> {
+    let start = 1_600_000_000 as Timestamp;
+    vec((1..3600 as Timestamp, vec(value(), fields)), 1..=max_events).prop_map(move |deltas| {
+        let mut timestamp = start;
+        deltas.into_iter()
+            .map(|(delta, values)| {
+                timestamp += delta;
+                ResolvedEvent {
+                    timestamp: timestamp,
+                    values: values,
+                }
+            })
+            .collect()
+    })
+}
+
+/// Up to `max_trails` trails with distinct UUIDs, ordered by UUID, of
+/// between 1 and `max_events` events with `fields` values each.
+pub fn trails(fields: usize,
+              max_trails: usize,
+              max_events: usize)
+              -> impl Strategy<Value = Vec<TrailBatch>> {
+    btree_map(any::<[u8; 16]>(), events(fields, max_events), 0..=max_trails)
+        .prop_map(|trails: BTreeMap<_, _>| {
+            trails.into_iter()
+                .map(|(uuid, events)| {
+                    TrailBatch {
+                        uuid: uuid,
+                        events: events,
+                    }
+                })
+                .collect()
+        })
+}
+
+/// Create a new directory for a database.
+fn temp_dir() -> io::Result<PathBuf> {
+    let base = env::temp_dir();
+    loop {
+        let dir = base.join(format!("traildb-proptest-{}-{}",
+                                    process::id(),
+                                    TEMP_SEQ.fetch_add(1, Ordering::Relaxed)));
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod test_testing {
+    use super::DbSpec;
+    use ::proptest::prelude::*;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn test_build_reads_back(spec in any::<DbSpec>()) {
+            let built = spec.build().unwrap();
+            let mut db = built.open().unwrap();
+            prop_assert_eq!(db.num_trails(), spec.trails.len() as u64);
+            prop_assert_eq!(db.num_events(), spec.num_events());
+            for trail in &spec.trails {
+                let id = db.get_trail_id(&trail.uuid).unwrap();
+                prop_assert_eq!(&db.trail_batch(id).unwrap(), trail);
+            }
+            db.close();
+        }
+    }
+}
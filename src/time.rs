@@ -0,0 +1,158 @@
+//! Calendar conversions for timestamps that count time since the UNIX
+//! epoch, used when formatting, parsing and partitioning by time.
+
+use std::time::Duration;
+
+use super::Timestamp;
+
+/// The unit of a database's timestamps. TrailDB doesn't care, but anything
+/// that maps timestamps onto calendar time has to be told.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+    Microseconds,
+}
+
+impl TimeUnit {
+    /// Split a timestamp in this unit into whole seconds and the remaining
+    /// milliseconds.
+    pub fn split(&self, timestamp: Timestamp) -> (u64, u32) {
+        match *self {
+            TimeUnit::Seconds => (timestamp, 0),
+            TimeUnit::Milliseconds => (timestamp / 1000, (timestamp % 1000) as u32),
+            TimeUnit::Microseconds => (timestamp / 1_000_000, (timestamp % 1_000_000 / 1000) as u32),
+        }
+    }
+
+    /// The length of `duration` in this unit, rounded down.
+    pub fn from_duration(&self, duration: Duration) -> Timestamp {
+        match *self {
+            TimeUnit::Seconds => duration.as_secs(),
+            TimeUnit::Milliseconds => duration.as_millis() as Timestamp,
+            TimeUnit::Microseconds => duration.as_micros() as Timestamp,
+        }
+    }
+}
+
+/// Days since 1970-01-01 to a (year, month, day) civil date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// A (year, month, day) civil date to days since 1970-01-01.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Format seconds since the epoch as a UTC date, e.g. `2017-03-01`.
+pub fn format_date(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Format seconds (plus milliseconds) since the epoch as an RFC 3339 UTC
+/// timestamp, e.g. `2017-03-01T12:00:00Z` or `2017-03-01T12:00:00.250Z`.
+pub fn format_rfc3339(secs: u64, millis: Option<u32>) -> String {
+    let rem = secs % 86_400;
+    let mut s = format!("{}T{:02}:{:02}:{:02}",
+                        format_date(secs),
+                        rem / 3600,
+                        rem % 3600 / 60,
+                        rem % 60);
+    if let Some(millis) = millis {
+        s.push_str(&format!(".{:03}", millis));
+    }
+    s.push('Z');
+    s
+}
+
+/// Parse an RFC 3339 UTC timestamp such as `2017-03-01T12:00:00Z`, or a bare
+/// date such as `2017-03-01` meaning its midnight, into seconds since the
+/// epoch. Fractional seconds are dropped. Returns `None` for anything else,
+/// including dates before the epoch.
+pub fn parse_rfc3339(s: &str) -> Option<u64> {
+    let (date, time) = match s.find(|c| c == 'T' || c == 't' || c == ' ') {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parse_digits(parts.next()?, 4)?;
+    let month: u32 = parse_digits(parts.next()?, 2)?;
+    let day: u32 = parse_digits(parts.next()?, 2)?;
+    if month < 1 || month > 12 || day < 1 || day > 31 {
+        return None;
+    }
+    let mut secs = days_from_civil(year, month, day) * 86_400;
+    if civil_from_days(secs / 86_400) != (year, month, day) {
+        // E.g. February 30th.
+        return None;
+    }
+    if let Some(time) = time {
+        let time = time.strip_suffix('Z').or_else(|| time.strip_suffix('z'))?;
+        let time = time.split('.').next()?;
+        let mut parts = time.splitn(3, ':');
+        let hour: i64 = parse_digits(parts.next()?, 2)?;
+        let minute: i64 = parse_digits(parts.next()?, 2)?;
+        let second: i64 = parse_digits(parts.next()?, 2)?;
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        secs += hour * 3600 + minute * 60 + second;
+    }
+    if secs < 0 { None } else { Some(secs as u64) }
+}
+
+fn parse_digits<T: ::std::str::FromStr>(s: &str, len: usize) -> Option<T> {
+    if s.len() != len || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+
+
+
+#[cfg(test)]
+mod test_time {
+    use super::{format_rfc3339, parse_rfc3339, TimeUnit};
+
+    #[test]
+    fn test_format_rfc3339() {
+        assert_eq!(format_rfc3339(0, None), "1970-01-01T00:00:00Z");
+        assert_eq!(format_rfc3339(951_782_400, None), "2000-02-29T00:00:00Z");
+        assert_eq!(format_rfc3339(1_488_369_600, Some(250)), "2017-03-01T12:00:00.250Z");
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        assert_eq!(parse_rfc3339("1970-01-01"), Some(0));
+        assert_eq!(parse_rfc3339("2000-02-29T00:00:00Z"), Some(951_782_400));
+        assert_eq!(parse_rfc3339("2017-03-01T12:00:00.250Z"), Some(1_488_369_600));
+        assert_eq!(parse_rfc3339("2017-02-30"), None);
+        assert_eq!(parse_rfc3339("2017-03-01T12:00:00"), None);
+        assert_eq!(parse_rfc3339("1969-12-31"), None);
+    }
+
+    #[test]
+    fn test_time_unit_split() {
+        assert_eq!(TimeUnit::Seconds.split(1_250), (1_250, 0));
+        assert_eq!(TimeUnit::Milliseconds.split(1_250), (1, 250));
+        assert_eq!(TimeUnit::Microseconds.split(1_250_000), (1, 250));
+    }
+}
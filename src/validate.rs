@@ -0,0 +1,561 @@
+//! Checking a database's structure, and salvaging what's left of a damaged
+//! one.
+//!
+//! libtraildb verifies the table of contents when a database is opened.
+//! `Db::validate` checks what the table of contents points at: field names,
+//! lexicons, the UUID index and, at the `Full` level, every event. `repair`
+//! copies the trails that pass these checks into a new database.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::str;
+
+use super::{ffi, ConstructorBuilder, Db, Error, Event, Field, Item, Timestamp, TrailId, Uuid,
+            Value};
+
+/// How thoroughly `Db::validate` checks a database. Each level includes the
+/// checks of the previous ones.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
+pub enum ValidationLevel {
+    /// Field names, timestamp bounds and the UUID index. Reads one entry
+    /// per field and per trail.
+    Metadata,
+    /// Every lexicon value, which must resolve to valid UTF-8.
+    Lexicons,
+    /// Every event of every trail.
+    Full,
+}
+
+/// A problem found by `Db::validate`.
+#[derive(Debug,PartialEq)]
+pub enum Finding {
+    /// The smallest timestamp is larger than the largest.
+    TimestampBounds { min: Timestamp, max: Timestamp },
+    /// A field name is missing or isn't valid UTF-8.
+    FieldName(Field),
+    /// Two fields share a name.
+    DuplicateFieldName(Field, String),
+    /// A lexicon doesn't even hold the empty value.
+    EmptyLexicon(Field),
+    /// A lexicon entry can't be read.
+    MissingValue { field: Field, value: Value },
+    /// A lexicon entry isn't valid UTF-8.
+    InvalidUtf8 { field: Field, value: Value },
+    /// A trail has no UUID.
+    MissingUuid(TrailId),
+    /// A UUID doesn't sort after the one of the previous trail.
+    UnsortedUuid(TrailId),
+    /// Looking a trail's UUID up finds another trail.
+    UuidLookup { trail_id: TrailId, found: Option<TrailId> },
+    /// A trail can't be decoded.
+    TrailDecode { trail_id: TrailId, error: Error },
+    /// An event is older than the one before it in its trail.
+    TimestampOrder { trail_id: TrailId, event: u64, timestamp: Timestamp, previous: Timestamp },
+    /// An event's timestamp is outside the database's bounds.
+    TimestampOutOfRange { trail_id: TrailId, event: u64, timestamp: Timestamp },
+    /// An event's item at `position` belongs to another field.
+    ItemField { trail_id: TrailId, event: u64, position: usize, field: Field },
+    /// An event's item refers to a value past the end of the lexicon.
+    ItemValue { trail_id: TrailId, event: u64, field: Field, value: Value },
+    /// The trails hold another number of events than the header says.
+    EventCount { expected: u64, found: u64 },
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Finding::TimestampBounds { min, max } => {
+                write!(f, "min timestamp {} is larger than max timestamp {}", min, max)
+            }
+            Finding::FieldName(field) => write!(f, "field {}: unreadable name", field),
+            Finding::DuplicateFieldName(field, ref name) => {
+                write!(f, "field {}: duplicate name {:?}", field, name)
+            }
+            Finding::EmptyLexicon(field) => write!(f, "field {}: empty lexicon", field),
+            Finding::MissingValue { field, value } => {
+                write!(f, "field {}: value {} can't be read", field, value)
+            }
+            Finding::InvalidUtf8 { field, value } => {
+                write!(f, "field {}: value {} isn't valid UTF-8", field, value)
+            }
+            Finding::MissingUuid(trail_id) => write!(f, "trail {}: no UUID", trail_id),
+            Finding::UnsortedUuid(trail_id) => {
+                write!(f, "trail {}: UUID doesn't sort after trail {}", trail_id, trail_id - 1)
+            }
+            Finding::UuidLookup { trail_id, found: Some(found) } => {
+                write!(f, "trail {}: UUID lookup finds trail {}", trail_id, found)
+            }
+            Finding::UuidLookup { trail_id, found: None } => {
+                write!(f, "trail {}: UUID lookup finds nothing", trail_id)
+            }
+            Finding::TrailDecode { trail_id, ref error } => {
+                write!(f, "trail {}: can't be decoded: {}", trail_id, error)
+            }
+            Finding::TimestampOrder { trail_id, event, timestamp, previous } => {
+                write!(f,
+                       "trail {}, event {}: timestamp {} is before the previous one, {}",
+                       trail_id,
+                       event,
+                       timestamp,
+                       previous)
+            }
+            Finding::TimestampOutOfRange { trail_id, event, timestamp } => {
+                write!(f,
+                       "trail {}, event {}: timestamp {} is outside the database's bounds",
+                       trail_id,
+                       event,
+                       timestamp)
+            }
+            Finding::ItemField { trail_id, event, position, field } => {
+                write!(f,
+                       "trail {}, event {}: item {} belongs to field {}",
+                       trail_id,
+                       event,
+                       position,
+                       field)
+            }
+            Finding::ItemValue { trail_id, event, field, value } => {
+                write!(f,
+                       "trail {}, event {}: value {} is past the end of the lexicon of field {}",
+                       trail_id,
+                       event,
+                       value,
+                       field)
+            }
+            Finding::EventCount { expected, found } => {
+                write!(f, "the header counts {} events, the trails hold {}", expected, found)
+            }
+        }
+    }
+}
+
+/// The outcome of `Db::validate`.
+#[derive(Debug,PartialEq)]
+pub struct ValidationReport {
+    pub level: ValidationLevel,
+    /// The number of trails whose events were checked; 0 below `Full`.
+    pub trails_checked: u64,
+    /// The number of events checked; 0 below `Full`.
+    pub events_checked: u64,
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    /// Whether no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.findings.is_empty()
+    }
+}
+
+impl<'a> Db<'a> {
+    /// Check the database's structure up to `level`, returning every problem
+    /// found rather than stopping at the first.
+    ///
+    /// A trail with a problem in its events is reported once, at its first
+    /// bad event.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::{Db, ValidationLevel};
+    /// use std::path::Path;
+    ///
+    /// let db = Db::open(Path::new("nightly")).unwrap();
+    /// let report = db.validate(ValidationLevel::Full);
+    /// for finding in &report.findings {
+    ///     eprintln!("{}", finding);
+    /// }
+    /// assert!(report.is_ok());
+    /// ```
+    pub fn validate(&self, level: ValidationLevel) -> ValidationReport {
+        let mut report = ValidationReport {
+            level: level,
+            trails_checked: 0,
+            events_checked: 0,
+            findings: Vec::new(),
+        };
+        self.validate_metadata(&mut report.findings);
+        if level >= ValidationLevel::Lexicons {
+            self.validate_lexicons(&mut report.findings);
+        }
+        if level >= ValidationLevel::Full {
+            self.validate_events(&mut report);
+        }
+        report
+    }
+
+    fn validate_metadata(&self, findings: &mut Vec<Finding>) {
+        let (min, max) = (self.min_timestamp(), self.max_timestamp());
+        if self.num_events() > 0 && min > max {
+            findings.push(Finding::TimestampBounds { min: min, max: max });
+        }
+
+        let mut names = HashSet::new();
+        for field in 1..self.num_fields() as Field {
+            match self.get_field_name(field) {
+                None => findings.push(Finding::FieldName(field)),
+                Some(name) => {
+                    if !names.insert(name) {
+                        findings.push(Finding::DuplicateFieldName(field, name.to_string()));
+                    }
+                }
+            }
+        }
+
+        let mut previous = None;
+        for trail_id in 0..self.num_trails() {
+            let uuid = match self.get_uuid(trail_id) {
+                Some(uuid) => uuid,
+                None => {
+                    findings.push(Finding::MissingUuid(trail_id));
+                    previous = None;
+                    continue;
+                }
+            };
+            if previous.map_or(false, |previous| uuid <= previous) {
+                findings.push(Finding::UnsortedUuid(trail_id));
+            }
+            let found = self.get_trail_id(uuid);
+            if found != Some(trail_id) {
+                findings.push(Finding::UuidLookup {
+                    trail_id: trail_id,
+                    found: found,
+                });
+            }
+            previous = Some(uuid);
+        }
+    }
+
+    fn validate_lexicons(&self, findings: &mut Vec<Finding>) {
+        for field in 1..self.num_fields() as Field {
+            let size = self.lexicon_size(field);
+            if size == 0 {
+                findings.push(Finding::EmptyLexicon(field));
+            }
+            for value in 1..size {
+                let bytes = unsafe {
+                    let mut len = 0u64;
+                    let ptr = ffi::tdb_get_value(self.obj, field, value, &mut len);
+                    if ptr.is_null() {
+                        None
+                    } else {
+                        Some(std::slice::from_raw_parts(ptr as *const u8, len as usize))
+                    }
+                };
+                match bytes {
+                    None => {
+                        findings.push(Finding::MissingValue {
+                            field: field,
+                            value: value,
+                        })
+                    }
+                    Some(bytes) => {
+                        if str::from_utf8(bytes).is_err() {
+                            findings.push(Finding::InvalidUtf8 {
+                                field: field,
+                                value: value,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn validate_events(&self, report: &mut ValidationReport) {
+        let (min, max) = (self.min_timestamp(), self.max_timestamp());
+        let lexicon_sizes: Vec<u64> = (1..self.num_fields() as Field)
+            .map(|field| self.lexicon_size(field))
+            .collect();
+        let mut cursor = self.cursor();
+        for trail_id in 0..self.num_trails() {
+            if let Err(e) = cursor.get_trail(trail_id) {
+                report.findings.push(Finding::TrailDecode {
+                    trail_id: trail_id,
+                    error: e,
+                });
+                continue;
+            }
+            report.trails_checked += 1;
+            let mut previous = None;
+            let mut reported = false;
+            for (i, event) in (&mut cursor).enumerate() {
+                report.events_checked += 1;
+                if reported {
+                    continue;
+                }
+                if let Some(finding) = check_event(trail_id,
+                                                   i as u64,
+                                                   &event,
+                                                   previous,
+                                                   (min, max),
+                                                   &lexicon_sizes) {
+                    report.findings.push(finding);
+                    reported = true;
+                }
+                previous = Some(event.timestamp);
+            }
+        }
+        if report.events_checked != self.num_events() {
+            report.findings.push(Finding::EventCount {
+                expected: self.num_events(),
+                found: report.events_checked,
+            });
+        }
+    }
+}
+
+/// A trail `repair` left out.
+#[derive(Debug,PartialEq)]
+pub struct SkippedTrail {
+    pub trail_id: TrailId,
+    /// The trail's UUID, if it could be read.
+    pub uuid: Option<Uuid>,
+    /// The number of events decoded before giving up on the trail.
+    pub events: u64,
+    /// Why the trail was left out.
+    pub reason: Finding,
+}
+
+/// The outcome of `repair`.
+#[derive(Debug,Default,PartialEq)]
+pub struct RepairReport {
+    pub trails_copied: u64,
+    pub events_copied: u64,
+    pub skipped: Vec<SkippedTrail>,
+}
+
+/// Copy every trail of the database at `src` that decodes cleanly into a new
+/// database at `dst`, reporting the trails that had to be left out.
+///
+/// A trail is left out if its UUID can't be read, it can't be decoded, its
+/// events are out of order, or an event refers to a field or lexicon value
+/// that is out of range or unreadable. Fails only if `src` can't be opened
+/// or `dst` can't be written.
+///
+/// # Examples
+///
+/// ```no_run
+/// use traildb::repair;
+/// use std::path::Path;
+///
+/// let report = repair(Path::new("shard-17"), Path::new("shard-17-salvaged")).unwrap();
+/// for skipped in &report.skipped {
+///     eprintln!("lost {} events: {}", skipped.events, skipped.reason);
+/// }
+/// ```
+pub fn repair(src: &Path, dst: &Path) -> Result<RepairReport, Error> {
+    let db = Db::open(src)?;
+    let fields = db.field_names();
+    let mut cons = ConstructorBuilder::new(dst, &fields)
+        .expected_trails(db.num_trails() as usize)
+        .expected_events(db.num_events() as usize)
+        .build()?;
+
+    let mut lexicon_findings = Vec::new();
+    db.validate_lexicons(&mut lexicon_findings);
+    let bad_values: HashSet<(Field, Value)> = lexicon_findings.into_iter()
+        .filter_map(|finding| match finding {
+            Finding::MissingValue { field, value } |
+            Finding::InvalidUtf8 { field, value } => Some((field, value)),
+            _ => None,
+        })
+        .collect();
+    let lexicon_sizes: Vec<u64> = (1..db.num_fields() as Field)
+        .map(|field| db.lexicon_size(field))
+        .collect();
+
+    let mut report = RepairReport::default();
+    let mut cursor = db.cursor();
+    let mut events: Vec<(Timestamp, Vec<Item>)> = Vec::new();
+    let mut values: Vec<&str> = Vec::with_capacity(fields.len());
+    for trail_id in 0..db.num_trails() {
+        let skip = |uuid, events, reason| {
+            SkippedTrail {
+                trail_id: trail_id,
+                uuid: uuid,
+                events: events,
+                reason: reason,
+            }
+        };
+        let uuid = match db.get_uuid(trail_id) {
+            Some(uuid) => *uuid,
+            None => {
+                report.skipped.push(skip(None, 0, Finding::MissingUuid(trail_id)));
+                continue;
+            }
+        };
+        if let Err(e) = cursor.get_trail(trail_id) {
+            let reason = Finding::TrailDecode {
+                trail_id: trail_id,
+                error: e,
+            };
+            report.skipped.push(skip(Some(uuid), 0, reason));
+            continue;
+        }
+
+        events.clear();
+        let mut decoded = 0;
+        let mut problem = None;
+        for (i, event) in (&mut cursor).enumerate() {
+            decoded += 1;
+            if problem.is_some() {
+                continue;
+            }
+            let previous = events.last().map(|&(timestamp, _)| timestamp);
+            problem = check_event(trail_id,
+                                  i as u64,
+                                  &event,
+                                  previous,
+                                  (0, Timestamp::MAX),
+                                  &lexicon_sizes)
+                .or_else(|| {
+                    event.items
+                        .iter()
+                        .find(|item| bad_values.contains(&(item.field(), item.value())))
+                        .map(|item| {
+                            Finding::InvalidUtf8 {
+                                field: item.field(),
+                                value: item.value(),
+                            }
+                        })
+                });
+            events.push((event.timestamp, event.items.to_vec()));
+        }
+        if let Some(reason) = problem {
+            report.skipped.push(skip(Some(uuid), decoded, reason));
+            continue;
+        }
+
+        for &(timestamp, ref items) in &events {
+            values.clear();
+            values.extend(items.iter().map(|&item| db.get_item_value(item)));
+            cons.add(&uuid, timestamp, &values)?;
+        }
+        report.trails_copied += 1;
+        report.events_copied += decoded;
+    }
+    cons.finalize()?;
+    Ok(report)
+}
+
+/// The first problem with the `index`th event of a trail, given the
+/// timestamp of the event before it.
+fn check_event(trail_id: TrailId,
+               index: u64,
+               event: &Event,
+               previous: Option<Timestamp>,
+               (min, max): (Timestamp, Timestamp),
+               lexicon_sizes: &[u64])
+               -> Option<Finding> {
+    if let Some(previous) = previous {
+        if event.timestamp < previous {
+            return Some(Finding::TimestampOrder {
+                trail_id: trail_id,
+                event: index,
+                timestamp: event.timestamp,
+                previous: previous,
+            });
+        }
+    }
+    if event.timestamp < min || event.timestamp > max {
+        return Some(Finding::TimestampOutOfRange {
+            trail_id: trail_id,
+            event: index,
+            timestamp: event.timestamp,
+        });
+    }
+    for (position, item) in event.items.iter().enumerate() {
+        let field = item.field();
+        if field as usize != position + 1 {
+            return Some(Finding::ItemField {
+                trail_id: trail_id,
+                event: index,
+                position: position,
+                field: field,
+            });
+        }
+        if lexicon_sizes.get(position).map_or(true, |&size| item.value() >= size) {
+            return Some(Finding::ItemValue {
+                trail_id: trail_id,
+                event: index,
+                field: field,
+                value: item.value(),
+            });
+        }
+    }
+    None
+}
+
+
+
+
+#[cfg(test)]
+mod test_validate {
+    use super::{check_event, repair, Finding, ValidationLevel};
+    use super::super::{Constructor, Db, Event, Item};
+    use std::path::Path;
+
+    #[test]
+    fn test_validate() {
+        let db_path = Path::new("test_validate");
+        let mut cons = Constructor::new(db_path, &["action", "page"]).unwrap();
+        assert!(cons.add(&[2u8; 16], 1, &["view", "/"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 3, &["buy", "/a"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["view", ""]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let db = Db::open(db_path).unwrap();
+        let report = db.validate(ValidationLevel::Full);
+        assert!(report.is_ok(), "{:?}", report.findings);
+        assert_eq!((report.trails_checked, report.events_checked), (2, 3));
+        let report = db.validate(ValidationLevel::Metadata);
+        assert!(report.is_ok());
+        assert_eq!(report.events_checked, 0);
+    }
+
+    #[test]
+    fn test_check_event() {
+        let page = |value: u64| Item(1 | value << 8);
+        let items = [page(1)];
+        let event = Event {
+            timestamp: 5,
+            items: &items,
+        };
+        assert_eq!(check_event(0, 1, &event, Some(4), (0, 10), &[2]), None);
+        assert_eq!(check_event(0, 1, &event, Some(6), (0, 10), &[2]),
+                   Some(Finding::TimestampOrder {
+                       trail_id: 0,
+                       event: 1,
+                       timestamp: 5,
+                       previous: 6,
+                   }));
+        assert!(check_event(0, 1, &event, None, (6, 10), &[2]).is_some());
+        assert_eq!(check_event(0, 1, &event, None, (0, 10), &[1]),
+                   Some(Finding::ItemValue {
+                       trail_id: 0,
+                       event: 1,
+                       field: 1,
+                       value: 1,
+                   }));
+    }
+
+    #[test]
+    fn test_repair() {
+        let src = Path::new("test_repair_src");
+        let mut cons = Constructor::new(src, &["action"]).unwrap();
+        assert!(cons.add(&[1u8; 16], 1, &["view"]).is_ok());
+        assert!(cons.add(&[1u8; 16], 2, &["buy"]).is_ok());
+        assert!(cons.add(&[2u8; 16], 1, &["view"]).is_ok());
+        assert!(cons.finalize().is_ok());
+
+        let dst = Path::new("test_repair_dst");
+        let report = repair(src, dst).unwrap();
+        assert_eq!((report.trails_copied, report.events_copied), (2, 3));
+        assert!(report.skipped.is_empty());
+        let db = Db::open(dst).unwrap();
+        assert_eq!(db.num_events(), 3);
+        assert!(db.validate(ValidationLevel::Full).is_ok());
+    }
+}
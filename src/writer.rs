@@ -0,0 +1,188 @@
+use std::panic;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::durable::{finalized_path, sync_db};
+use super::{Constructor, Error, Timestamp, Uuid};
+
+/// The number of events a writer's channel holds before senders block.
+pub const WRITER_BUFFER: usize = 4096;
+
+/// How often an idle writer checks whether it was asked to shut down.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(50);
+
+/// An event sent to a writer thread: the UUID, the timestamp and the
+/// values, in field order.
+pub type WriterEvent = (Uuid, Timestamp, Vec<String>);
+
+/// What a writer thread added.
+#[derive(Debug,Default)]
+pub struct WriterReport {
+    /// Where the database was finalized.
+    pub path: PathBuf,
+    /// The number of events added.
+    pub added: u64,
+    /// The events the constructor rejected, with the reason.
+    pub rejected: Vec<(Uuid, Timestamp, Error)>,
+}
+
+/// The writer thread started by `Constructor::spawn_writer`.
+pub struct WriterHandle {
+    thread: JoinHandle<Result<WriterReport, Error>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl WriterHandle {
+    /// Wait until every sender has been dropped and the writer has
+    /// finalized the database. A panic on the writer thread is passed on.
+    pub fn join(self) -> Result<WriterReport, Error> {
+        match self.thread.join() {
+            Ok(result) => result,
+            Err(e) => panic::resume_unwind(e),
+        }
+    }
+
+    /// Finalize the database without waiting for the senders to be
+    /// dropped, e.g. on SIGTERM, and wait for the writer like `join`.
+    ///
+    /// The events already in the channel are added first. Sending fails
+    /// once the writer is done; stop producers before calling this, as an
+    /// event sent while the writer drains the channel may still be
+    /// accepted and then dropped.
+    pub fn shutdown(self) -> Result<WriterReport, Error> {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.join()
+    }
+}
+
+impl Constructor {
+    /// Move the constructor to a thread of its own, fed by a channel, for
+    /// adding events from several threads at once.
+    ///
+    /// The constructor isn't thread-safe, so the writer thread is the only
+    /// one touching it. The channel holds up to `WRITER_BUFFER` events;
+    /// senders block while it is full. Once every sender is dropped, or
+    /// `WriterHandle::shutdown` is called, the writer finalizes the
+    /// database and flushes it to disk, and `WriterHandle::join` returns
+    /// what it added.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use traildb::Constructor;
+    /// use std::path::Path;
+    /// use std::thread;
+    ///
+    /// let cons = Constructor::new(Path::new("my_traildb"), &["action"]).unwrap();
+    /// let (tx, writer) = cons.spawn_writer();
+    /// let producers: Vec<_> = (0..4u8)
+    ///     .map(|i| {
+    ///         let tx = tx.clone();
+    ///         thread::spawn(move || tx.send(([i; 16], 1, vec!["login".to_string()])).unwrap())
+    ///     })
+    ///     .collect();
+    /// drop(tx);
+    /// for producer in producers {
+    ///     producer.join().unwrap();
+    /// }
+    /// println!("added {} events", writer.join().unwrap().added);
+    /// ```
+    pub fn spawn_writer(mut self) -> (SyncSender<WriterEvent>, WriterHandle) {
+        let (tx, rx) = mpsc::sync_channel::<WriterEvent>(WRITER_BUFFER);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = shutdown.clone();
+        let thread = thread::Builder::new()
+            .name("traildb-writer".to_string())
+            .spawn(move || {
+                let mut report = WriterReport::default();
+                loop {
+                    let event = match rx.recv_timeout(SHUTDOWN_POLL) {
+                        Ok(event) => event,
+                        Err(RecvTimeoutError::Timeout) if stop.load(Ordering::Relaxed) => break,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    };
+                    self.add_event(event, &mut report);
+                    if stop.load(Ordering::Relaxed) {
+                        for event in rx.try_iter() {
+                            self.add_event(event, &mut report);
+                        }
+                        break;
+                    }
+                }
+                drop(rx);
+                self.finalize()?;
+                report.path = finalized_path(self.path());
+                sync_db(&report.path).map_err(|_| Error::IoWrite)?;
+                Ok(report)
+            })
+            .expect("failed to spawn the writer thread");
+        let handle = WriterHandle {
+            thread: thread,
+            shutdown: shutdown,
+        };
+        (tx, handle)
+    }
+
+    fn add_event(&mut self, (uuid, timestamp, owned): WriterEvent, report: &mut WriterReport) {
+        let values: Vec<&str> = owned.iter().map(|v| v.as_str()).collect();
+        match self.add(&uuid, timestamp, &values) {
+            Ok(()) => report.added += 1,
+            Err(e) => report.rejected.push((uuid, timestamp, e)),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test_writer {
+    use super::super::{Constructor, Db};
+    use std::path::Path;
+    use std::thread;
+
+    #[test]
+    fn test_spawn_writer() {
+        let db_path = Path::new("test_spawn_writer");
+        let cons = Constructor::new(db_path, &["action"]).unwrap();
+        let (tx, writer) = cons.spawn_writer();
+        let producers: Vec<_> = (0..4u8)
+            .map(|i| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for t in 0..10 {
+                        tx.send(([i; 16], t, vec!["view".to_string()])).unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let report = writer.join().unwrap();
+        assert_eq!(report.added, 40);
+        assert!(report.rejected.is_empty());
+
+        let db = Db::open(&report.path).unwrap();
+        assert_eq!(db.num_trails(), 4);
+        assert_eq!(db.num_events(), 40);
+    }
+
+    #[test]
+    fn test_writer_shutdown() {
+        let cons = Constructor::new(Path::new("test_writer_shutdown"), &["action"]).unwrap();
+        let (tx, writer) = cons.spawn_writer();
+        for t in 0..10 {
+            tx.send(([1u8; 16], t, vec!["view".to_string()])).unwrap();
+        }
+        let report = writer.shutdown().unwrap();
+        assert_eq!(report.added, 10);
+        assert!(tx.send(([1u8; 16], 10, vec!["view".to_string()])).is_err());
+        assert_eq!(Db::open(&report.path).unwrap().num_events(), 10);
+    }
+}